@@ -5,6 +5,8 @@ use serde_json::Value;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 
+use autoorganize_ingestion::DocumentChunk;
+
 use crate::{DocumentInfo, Entity, SearchResult};
 
 pub struct Database {
@@ -14,6 +16,8 @@ pub struct Database {
 impl Database {
     pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
         let conn = Connection::open(db_path)?;
+        // The search engine writes through a connection of its own
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
         Ok(Self { conn })
     }
     
@@ -51,6 +55,8 @@ impl Database {
                 document_id TEXT NOT NULL,
                 content TEXT NOT NULL,
                 chunk_index INTEGER NOT NULL,
+                start_position INTEGER NOT NULL DEFAULT 0,
+                end_position INTEGER NOT NULL DEFAULT 0,
                 embedding BLOB,
                 FOREIGN KEY (document_id) REFERENCES documents (id) ON DELETE CASCADE
             )
@@ -58,6 +64,19 @@ impl Database {
             [],
         )?;
         
+        // Chunk offsets were added after the table; older databases lack them
+        let has_offsets: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM pragma_table_info('document_chunks') WHERE name = 'start_position')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_offsets {
+            self.conn.execute_batch(
+                "ALTER TABLE document_chunks ADD COLUMN start_position INTEGER NOT NULL DEFAULT 0;
+                 ALTER TABLE document_chunks ADD COLUMN end_position INTEGER NOT NULL DEFAULT 0;",
+            )?;
+        }
+        
         // Entities table
         self.conn.execute(
             r#"
@@ -136,6 +155,9 @@ impl Database {
         self.conn.execute("CREATE INDEX IF NOT EXISTS idx_entities_name ON entities (name)", [])?;
         self.conn.execute("CREATE INDEX IF NOT EXISTS idx_entities_created_at ON entities (created_at)", [])?;
         
+        // Chunk indexes
+        self.conn.execute("CREATE INDEX IF NOT EXISTS idx_document_chunks_document_id ON document_chunks (document_id)", [])?;
+        
         // Entity mention indexes
         self.conn.execute("CREATE INDEX IF NOT EXISTS idx_entity_mentions_entity_id ON entity_mentions (entity_id)", [])?;
         self.conn.execute("CREATE INDEX IF NOT EXISTS idx_entity_mentions_document_id ON entity_mentions (document_id)", [])?;
@@ -193,11 +215,67 @@ impl Database {
             [],
         )?;
         
+        // Chunks are searched on their own, so long documents can be
+        // matched and shown by passage
+        let chunks_indexed: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'document_chunks_fts')",
+            [],
+            |row| row.get(0),
+        )?;
+        self.conn.execute(
+            r#"
+            CREATE VIRTUAL TABLE IF NOT EXISTS document_chunks_fts USING fts5(
+                content,
+                chunk_id UNINDEXED
+            )
+            "#,
+            [],
+        )?;
+        if !chunks_indexed {
+            self.conn.execute(
+                "INSERT INTO document_chunks_fts(content, chunk_id) SELECT content, id FROM document_chunks",
+                [],
+            )?;
+        }
+        
+        self.conn.execute(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS document_chunks_fts_insert AFTER INSERT ON document_chunks BEGIN
+                INSERT INTO document_chunks_fts(content, chunk_id) VALUES (new.content, new.id);
+            END
+            "#,
+            [],
+        )?;
+        
+        self.conn.execute(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS document_chunks_fts_update AFTER UPDATE ON document_chunks BEGIN
+                UPDATE document_chunks_fts SET content = new.content WHERE chunk_id = new.id;
+            END
+            "#,
+            [],
+        )?;
+        
+        self.conn.execute(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS document_chunks_fts_delete AFTER DELETE ON document_chunks BEGIN
+                DELETE FROM document_chunks_fts WHERE chunk_id = old.id;
+            END
+            "#,
+            [],
+        )?;
+        
         Ok(())
     }
     
     pub fn insert_document(&self, document: &DocumentInfo) -> Result<()> {
-        self.conn.execute(
+        Self::write_document(&self.conn, document)
+    }
+    
+    /// Write a document row on any connection, e.g. inside the search
+    /// engine's indexing transaction
+    pub fn write_document(conn: &Connection, document: &DocumentInfo) -> Result<()> {
+        conn.execute(
             r#"
             INSERT OR REPLACE INTO documents 
             (id, source_type, file_path, content_hash, ingested_at, modified_at, metadata, title, content)
@@ -218,6 +296,38 @@ impl Database {
         Ok(())
     }
     
+    /// Write an ingested document's row and replace its chunks
+    pub fn store_document(conn: &Connection, document: &DocumentInfo, chunks: &[DocumentChunk]) -> Result<()> {
+        Self::write_document(conn, document)?;
+        Self::write_chunks(conn, &document.id, chunks)
+    }
+    
+    /// Replace a document's chunks
+    pub fn insert_chunks(&self, document_id: &str, chunks: &[DocumentChunk]) -> Result<()> {
+        Self::write_chunks(&self.conn, document_id, chunks)
+    }
+    
+    fn write_chunks(conn: &Connection, document_id: &str, chunks: &[DocumentChunk]) -> Result<()> {
+        conn.execute("DELETE FROM document_chunks WHERE document_id = ?1", [document_id])?;
+        for chunk in chunks {
+            conn.execute(
+                r#"
+                INSERT INTO document_chunks (id, document_id, content, chunk_index, start_position, end_position)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                "#,
+                params![
+                    chunk.id,
+                    document_id,
+                    chunk.content,
+                    chunk.chunk_index,
+                    chunk.start_position,
+                    chunk.end_position
+                ],
+            )?;
+        }
+        Ok(())
+    }
+    
     pub fn get_document_by_id(&self, id: &str) -> Result<Option<DocumentInfo>> {
         let mut stmt = self.conn.prepare(
             "SELECT id, source_type, file_path, content_hash, ingested_at, modified_at, metadata, title, content 
@@ -298,6 +408,7 @@ impl Database {
                 relevance_score: row.get::<_, f64>(3)?,
                 source_json: row.get(4)?,
                 metadata_json: row.get(5)?,
+                passages: Vec::new(),
             })
         })?;
         
@@ -390,4 +501,60 @@ impl Database {
         )?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &str, chunk_index: u32, content: &str) -> DocumentChunk {
+        DocumentChunk {
+            id: id.to_string(),
+            content: content.to_string(),
+            chunk_index,
+            start_position: chunk_index * 100,
+            end_position: chunk_index * 100 + content.len() as u32,
+        }
+    }
+
+    #[test]
+    fn test_store_document_replaces_chunks() {
+        let mut db = Database::new(":memory:").unwrap();
+        db.initialize().unwrap();
+
+        let document = DocumentInfo {
+            id: "doc-1".to_string(),
+            source_type: "file_system".to_string(),
+            file_path: "/notes/budget.txt".to_string(),
+            content_hash: "hash-1".to_string(),
+            ingested_at: 1,
+            modified_at: 1,
+            metadata_json: "{}".to_string(),
+            title: "Budget".to_string(),
+            content: Some("annual budget review and quarterly forecast".to_string()),
+        };
+        let chunks = [chunk("c1", 0, "annual budget review"), chunk("c2", 1, "quarterly forecast")];
+        {
+            let tx = db.conn.transaction().unwrap();
+            Database::store_document(&tx, &document, &chunks).unwrap();
+            tx.commit().unwrap();
+        }
+
+        let count = |db: &Database, sql: &str| -> i64 { db.conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM document_chunks WHERE document_id = 'doc-1'"), 2);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM document_chunks_fts WHERE document_chunks_fts MATCH 'forecast'"), 1);
+
+        // Re-ingesting the document replaces its chunks
+        let document = DocumentInfo {
+            content_hash: "hash-2".to_string(),
+            modified_at: 2,
+            content: Some("annual budget review".to_string()),
+            ..document
+        };
+        Database::store_document(&db.conn, &document, &chunks[..1]).unwrap();
+
+        assert_eq!(db.get_document_count().unwrap(), 1);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM document_chunks WHERE document_id = 'doc-1'"), 1);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM document_chunks_fts WHERE document_chunks_fts MATCH 'forecast'"), 0);
+    }
+}
@@ -5,7 +5,14 @@ use tokio::sync::RwLock;
 use crate::{
     AutoOrganizeCore, CoreConfig, DocumentInfo, Entity, SearchResult, FileEvent,
     AutoOrganizeError, FileWatcherCallback, IngestionCallback, SearchCallback,
+    QuerySuggestion, Completion, CompletionKind, SynonymSet, DuplicateGroup,
+    FeedbackAction, RankingEvaluation, SavedSearch, SavedSearchCallback, SearchHistoryEntry,
+    SearchStage, SearchPassage, IndexTerm,
 };
+use autoorganize_search::feedback::FeedbackAction as SearchFeedbackAction;
+use autoorganize_search::saved_searches::{SavedSearch as StoredSearch, SavedSearchAlert};
+use autoorganize_search::stream::{SearchHandle as StreamHandle, SearchStage as StreamStage};
+use autoorganize_search::synonyms::{SynonymEntry, SynonymRule};
 
 // FFI implementation for the AutoOrganizeCore
 impl AutoOrganizeCore {
@@ -54,8 +61,7 @@ impl AutoOrganizeCore {
         callback: Box<dyn IngestionCallback + Send + Sync>,
     ) -> Result<(), AutoOrganizeError> {
         self.runtime.block_on(async {
-            self.ingestion_engine.ingest_directory(&dir_path, callback).await
-                .map_err(|e| AutoOrganizeError::IngestionError(e.to_string()))
+            self.ingest_directory(dir_path, callback).await
         })
     }
     
@@ -69,6 +75,32 @@ impl AutoOrganizeCore {
         })
     }
     
+    /// Runs the search on its own thread and returns at once. Results
+    /// reach the callback stage by stage until it completes, fails or is
+    /// cancelled through the handle.
+    pub fn start_search(
+        &self,
+        query_json: String,
+        callback: Box<dyn SearchCallback + Send + Sync>,
+    ) -> Result<Arc<SearchHandle>, AutoOrganizeError> {
+        let query: autoorganize_search::SearchQuery = serde_json::from_str(&query_json)
+            .map_err(|e| AutoOrganizeError::SearchError(format!("Invalid search query: {}", e)))?;
+
+        let handle = StreamHandle::new();
+        let search = handle.clone();
+        let search_engine = self.search_engine.clone();
+        let runtime = self.runtime.clone();
+        std::thread::spawn(move || {
+            runtime.block_on(async {
+                // Failures are reported to the callback as well
+                let results = StreamedResults(callback);
+                let _ = search_engine.stream_search(&query, &results, &search).await;
+            });
+        });
+
+        Ok(Arc::new(SearchHandle(handle)))
+    }
+    
     pub fn search_entities(
         &self,
         query: String,
@@ -80,6 +112,241 @@ impl AutoOrganizeCore {
         })
     }
     
+    pub fn suggest_queries(&self, query: String, limit: u32) -> Result<Vec<QuerySuggestion>, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            let suggestions = self.search_engine.suggest_queries(&query, limit as usize).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+
+            Ok(suggestions
+                .into_iter()
+                .map(|suggestion| QuerySuggestion {
+                    text: suggestion.text,
+                    score: suggestion.score,
+                })
+                .collect())
+        })
+    }
+    
+    pub fn autocomplete(&self, text: String, limit: u32) -> Result<Vec<Completion>, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            let completions = self.search_engine.autocomplete(&text, limit as usize).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+
+            Ok(completions
+                .into_iter()
+                .map(|completion| Completion {
+                    text: completion.text,
+                    kind: match completion.kind {
+                        autoorganize_search::suggest::CompletionKind::Entity => CompletionKind::Entity,
+                        autoorganize_search::suggest::CompletionKind::Title => CompletionKind::Title,
+                        autoorganize_search::suggest::CompletionKind::Term => CompletionKind::Term,
+                    },
+                    score: completion.score,
+                })
+                .collect())
+        })
+    }
+    
+    pub fn find_similar(&self, document_id: String, limit: u32) -> Result<Vec<SearchResult>, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            let results = self.search_engine.find_similar(&document_id, limit as usize).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+
+            Ok(results.into_iter().map(search_result).collect())
+        })
+    }
+    
+    pub fn get_duplicate_groups(&self, threshold: Option<f64>) -> Vec<DuplicateGroup> {
+        self.runtime.block_on(async {
+            self.search_engine
+                .duplicate_groups(threshold)
+                .await
+                .into_iter()
+                .map(|group| DuplicateGroup {
+                    document_ids: group.document_ids,
+                    similarity: group.similarity,
+                })
+                .collect()
+        })
+    }
+    
+    pub fn add_synonyms(&self, source: Option<String>, terms: Vec<String>) -> Result<SynonymSet, AutoOrganizeError> {
+        let rule = match source {
+            Some(from) => SynonymRule::OneWay { from, to: terms },
+            None => SynonymRule::Equivalent { terms },
+        };
+
+        self.runtime.block_on(async {
+            let entry = self.search_engine.add_synonyms(rule).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+            Ok(synonym_set(entry))
+        })
+    }
+    
+    pub fn remove_synonyms(&self, id: String) -> Result<bool, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            self.search_engine.remove_synonyms(&id).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))
+        })
+    }
+    
+    pub fn list_synonyms(&self) -> Vec<SynonymSet> {
+        self.runtime.block_on(async {
+            self.search_engine.list_synonyms().await.into_iter().map(synonym_set).collect()
+        })
+    }
+    
+    pub fn save_search(&self, name: String, query_json: String) -> Result<SavedSearch, AutoOrganizeError> {
+        let query = serde_json::from_str(&query_json)
+            .map_err(|e| AutoOrganizeError::SearchError(format!("Invalid search query: {}", e)))?;
+
+        self.runtime.block_on(async {
+            let saved = self.search_engine.save_search(&name, &query).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+            Ok(saved_search(saved))
+        })
+    }
+    
+    pub fn list_saved_searches(&self) -> Result<Vec<SavedSearch>, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            let saved = self.search_engine.list_saved_searches().await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+            Ok(saved.into_iter().map(saved_search).collect())
+        })
+    }
+    
+    pub fn delete_saved_search(&self, id: String) -> Result<bool, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            self.search_engine.delete_saved_search(&id).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))
+        })
+    }
+    
+    pub fn set_saved_search_callback(&self, callback: Box<dyn SavedSearchCallback + Send + Sync>) {
+        self.runtime.block_on(async {
+            self.search_engine
+                .set_saved_search_callback(Box::new(SavedSearchAlerts(callback)))
+                .await
+        });
+    }
+    
+    pub fn get_search_history(
+        &self,
+        profile_id: Option<String>,
+        limit: u32,
+    ) -> Result<Vec<SearchHistoryEntry>, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            let history = self.search_engine.search_history(profile_id.as_deref(), limit as usize).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+
+            Ok(history.into_iter().map(|entry| SearchHistoryEntry {
+                query: entry.query,
+                profile_id: entry.profile_id,
+                result_count: entry.result_count as u32,
+                latency_ms: entry.latency_ms,
+                searched_at: entry.searched_at,
+            }).collect())
+        })
+    }
+    
+    pub fn clear_search_history(&self, profile_id: Option<String>) -> Result<u32, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            let removed = self.search_engine.clear_search_history(profile_id.as_deref()).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+            Ok(removed as u32)
+        })
+    }
+    
+    pub fn get_search_statistics(&self) -> Result<String, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            let statistics = self.search_engine.get_search_statistics().await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+            Ok(statistics.to_string())
+        })
+    }
+    
+    pub fn get_index_stats(&self, top_terms: u32) -> Result<String, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            let stats = self.search_engine.index_stats(top_terms as usize).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+            serde_json::to_string(&stats).map_err(|e| AutoOrganizeError::SearchError(e.to_string()))
+        })
+    }
+    
+    pub fn explore_index_terms(&self, prefix: String, limit: u32) -> Vec<IndexTerm> {
+        self.runtime.block_on(async {
+            self.search_engine
+                .explore_terms(&prefix, limit as usize)
+                .await
+                .into_iter()
+                .map(|term| IndexTerm {
+                    term: term.term,
+                    document_frequency: term.document_frequency as u32,
+                    idf: term.idf,
+                    title_frequency: term.title_frequency as u32,
+                    content_frequency: term.content_frequency as u32,
+                })
+                .collect()
+        })
+    }
+    
+    pub fn explain_search(&self, query_json: String, document_id: String) -> Result<String, AutoOrganizeError> {
+        let query: autoorganize_search::SearchQuery = serde_json::from_str(&query_json)
+            .map_err(|e| AutoOrganizeError::SearchError(format!("Invalid search query: {}", e)))?;
+
+        self.runtime.block_on(async {
+            let explanation = self.search_engine.explain(&query, &document_id).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+            serde_json::to_string(&explanation).map_err(|e| AutoOrganizeError::SearchError(e.to_string()))
+        })
+    }
+    
+    pub fn set_query_cache_limit(&self, max_bytes: u64) {
+        self.runtime.block_on(async {
+            self.search_engine.set_query_cache_limit(max_bytes as usize).await
+        });
+    }
+    
+    pub fn record_search_feedback(
+        &self,
+        profile_id: String,
+        query: String,
+        document_id: String,
+        position: u32,
+        action: FeedbackAction,
+    ) -> Result<(), AutoOrganizeError> {
+        let action = match action {
+            FeedbackAction::Shown => SearchFeedbackAction::Shown,
+            FeedbackAction::Clicked => SearchFeedbackAction::Clicked,
+            FeedbackAction::Opened => SearchFeedbackAction::Opened,
+            FeedbackAction::Dismissed => SearchFeedbackAction::Dismissed,
+        };
+
+        self.runtime.block_on(async {
+            self.search_engine
+                .record_feedback(&profile_id, &query, &document_id, position as usize, action)
+                .await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))
+        })
+    }
+    
+    pub fn train_ranking(&self, profile_id: String) -> Result<Option<RankingEvaluation>, AutoOrganizeError> {
+        self.runtime.block_on(async {
+            let evaluation = self.search_engine.train_ranking(&profile_id).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?;
+
+            Ok(evaluation.map(|evaluation| RankingEvaluation {
+                training_sessions: evaluation.training_sessions as u32,
+                evaluation_sessions: evaluation.evaluation_sessions as u32,
+                baseline_accuracy: evaluation.baseline.pairwise_accuracy,
+                tuned_accuracy: evaluation.tuned.pairwise_accuracy,
+                baseline_mrr: evaluation.baseline.mrr,
+                tuned_mrr: evaluation.tuned.mrr,
+                applied: evaluation.applied,
+            }))
+        })
+    }
+    
     pub fn get_entities(
         &self,
         entity_type: Option<String>,
@@ -122,9 +389,15 @@ impl AutoOrganizeCore {
     
     pub fn delete_document(&self, document_id: String) -> Result<(), AutoOrganizeError> {
         self.runtime.block_on(async {
-            let db = self.database.read().await;
-            db.delete_document(&document_id)
-                .map_err(|e| AutoOrganizeError::DatabaseError(e.to_string()))
+            {
+                let db = self.database.read().await;
+                db.delete_document(&document_id)
+                    .map_err(|e| AutoOrganizeError::DatabaseError(e.to_string()))?;
+            }
+            
+            // Also drops cached searches that returned the document
+            self.search_engine.remove_document(&document_id).await
+                .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))
         })
     }
     
@@ -141,11 +414,110 @@ impl AutoOrganizeCore {
     }
 }
 
+fn search_result(result: autoorganize_search::SearchResult) -> SearchResult {
+    SearchResult {
+        id: result.id,
+        result_type: "document".to_string(),
+        title: result.title,
+        snippet: result.snippet.unwrap_or_default(),
+        relevance_score: result.score,
+        source_json: "{}".to_string(),
+        metadata_json: result.metadata.to_string(),
+        passages: result
+            .passages
+            .into_iter()
+            .map(|passage| SearchPassage {
+                chunk_id: passage.chunk_id,
+                chunk_index: passage.chunk_index,
+                start_position: passage.start_position,
+                end_position: passage.end_position,
+                snippet: passage.fragment.map(|fragment| fragment.text).unwrap_or_default(),
+            })
+            .collect(),
+    }
+}
+
+fn saved_search(saved: StoredSearch) -> SavedSearch {
+    SavedSearch {
+        query_json: serde_json::to_string(&saved.query).unwrap_or_default(),
+        id: saved.id,
+        name: saved.name,
+        created_at: saved.created_at,
+        last_run_at: saved.last_run_at,
+    }
+}
+
+/// Cancels a search started with `start_search`
+pub struct SearchHandle(StreamHandle);
+
+impl SearchHandle {
+    pub fn cancel(&self) {
+        self.0.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
+
+/// Hands a streamed search's results to the foreign callback
+struct StreamedResults(Box<dyn SearchCallback + Send + Sync>);
+
+impl autoorganize_search::SearchCallback for StreamedResults {
+    fn on_search_results(&self, results: Vec<autoorganize_search::SearchResult>) {
+        self.0.on_search_results(results.into_iter().map(search_result).collect());
+    }
+
+    fn on_search_error(&self, error: String) {
+        self.0.on_search_error(error);
+    }
+
+    fn on_partial_results(&self, stage: StreamStage, results: Vec<autoorganize_search::SearchResult>) {
+        let stage = match stage {
+            StreamStage::Keyword => SearchStage::Keyword,
+            StreamStage::Fuzzy => SearchStage::Fuzzy,
+            StreamStage::Semantic => SearchStage::Semantic,
+        };
+        self.0.on_partial_results(stage, results.into_iter().map(search_result).collect());
+    }
+
+    fn on_search_complete(&self, total_hits: usize) {
+        self.0.on_search_complete(total_hits as u32);
+    }
+
+    fn on_search_cancelled(&self) {
+        self.0.on_search_cancelled();
+    }
+}
+
+/// Hands saved search alerts to the foreign callback
+struct SavedSearchAlerts(Box<dyn SavedSearchCallback + Send + Sync>);
+
+impl autoorganize_search::SavedSearchCallback for SavedSearchAlerts {
+    fn on_saved_search_matches(&self, alert: SavedSearchAlert) {
+        let results = alert.results.into_iter().map(search_result).collect();
+        self.0.on_saved_search_matches(alert.saved_search_id, alert.name, results);
+    }
+
+    fn on_saved_search_error(&self, error: String) {
+        self.0.on_saved_search_error(error);
+    }
+}
+
+fn synonym_set(entry: SynonymEntry) -> SynonymSet {
+    match entry.rule {
+        SynonymRule::Equivalent { terms } => SynonymSet { id: entry.id, source: None, terms },
+        SynonymRule::OneWay { from, to } => SynonymSet { id: entry.id, source: Some(from), terms: to },
+    }
+}
+
 // Uniffi requires these to be defined at the crate level
 uniffi::export!(AutoOrganizeCore);
 uniffi::export!(AutoOrganizeError);
+uniffi::export!(SearchHandle);
 
 // Export callback interfaces
 uniffi::export!(FileWatcherCallback);
 uniffi::export!(IngestionCallback);
-uniffi::export!(SearchCallback);
\ No newline at end of file
+uniffi::export!(SearchCallback);
+uniffi::export!(SavedSearchCallback);
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 use std::collections::HashMap;
-use tokio::sync::RwLock;
+use std::path::{Path, PathBuf};
+use tokio::sync::{Mutex, RwLock};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -8,8 +9,8 @@ use chrono::{DateTime, Utc};
 
 use autoorganize_file_watcher::{FileWatcher, FileWatcherEvent};
 use autoorganize_encryption::EncryptionEngine;
-use autoorganize_ingestion::IngestionEngine;
-use autoorganize_search::SearchEngine;
+use autoorganize_ingestion::{IngestionEngine, ProcessedDocument};
+use autoorganize_search::{IndexedDocument, IndexedEntity, SearchEngine};
 
 pub mod database;
 pub mod ffi;
@@ -51,6 +52,102 @@ pub struct SearchResult {
     pub relevance_score: f64,
     pub source_json: String,
     pub metadata_json: String,
+    pub passages: Vec<SearchPassage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchPassage {
+    pub chunk_id: String,
+    pub chunk_index: u32,
+    pub start_position: u32,
+    pub end_position: u32,
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuerySuggestion {
+    pub text: String,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompletionKind {
+    Entity,
+    Title,
+    Term,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Completion {
+    pub text: String,
+    pub kind: CompletionKind,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SynonymSet {
+    pub id: String,
+    pub source: Option<String>, // one-way rule from this term when set
+    pub terms: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub document_ids: Vec<String>,
+    pub similarity: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSearch {
+    pub id: String,
+    pub name: String,
+    pub query_json: String,
+    pub created_at: i64,
+    pub last_run_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHistoryEntry {
+    pub query: String,
+    pub profile_id: Option<String>,
+    pub result_count: u32,
+    pub latency_ms: f64,
+    pub searched_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexTerm {
+    pub term: String,
+    pub document_frequency: u32,
+    pub idf: f64,
+    pub title_frequency: u32,
+    pub content_frequency: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchStage {
+    Keyword,
+    Fuzzy,
+    Semantic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeedbackAction {
+    Shown,
+    Clicked,
+    Opened,
+    Dismissed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankingEvaluation {
+    pub training_sessions: u32,
+    pub evaluation_sessions: u32,
+    pub baseline_accuracy: f64, // pairwise accuracy on held-out searches
+    pub tuned_accuracy: f64,
+    pub baseline_mrr: f64,
+    pub tuned_mrr: f64,
+    pub applied: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -84,6 +181,16 @@ pub struct CoreConfig {
     pub encryption_config: Option<EncryptionConfig>,
 }
 
+impl CoreConfig {
+    /// The persisted search index lives next to the database
+    pub fn search_index_path(&self) -> PathBuf {
+        Path::new(&self.db_path)
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("search-index")
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum AutoOrganizeError {
     #[error("Invalid configuration: {0}")]
@@ -112,33 +219,43 @@ pub struct AutoOrganizeCore {
 }
 
 impl AutoOrganizeCore {
-    pub fn new(config: CoreConfig) -> Self {
+    pub fn new(config: CoreConfig) -> Result<Self, AutoOrganizeError> {
         let runtime = Arc::new(
             tokio::runtime::Runtime::new()
-                .expect("Failed to create async runtime")
+                .map_err(|e| AutoOrganizeError::InvalidConfig(format!("Failed to create async runtime: {}", e)))?
         );
         
         let database = Arc::new(RwLock::new(
             database::Database::new(&config.db_path)
-                .expect("Failed to initialize database")
+                .map_err(|e| AutoOrganizeError::DatabaseError(e.to_string()))?
         ));
         
-        let encryption_engine = config.encryption_config.as_ref().map(|enc_config| {
-            Arc::new(EncryptionEngine::new(enc_config.clone())
-                .expect("Failed to initialize encryption engine"))
-        });
+        let encryption_engine = match config.encryption_config.as_ref() {
+            Some(enc_config) => Some(Arc::new(
+                EncryptionEngine::new(enc_config.clone())
+                    .map_err(|e| AutoOrganizeError::EncryptionError(e.to_string()))?
+            )),
+            None => None,
+        };
         
         let ingestion_engine = Arc::new(
             IngestionEngine::new(database.clone(), encryption_engine.clone())
-                .expect("Failed to initialize ingestion engine")
+                .map_err(|e| AutoOrganizeError::IngestionError(e.to_string()))?
         );
         
+        // The search engine writes documents and its index manifest in
+        // transactions of its own, so it gets its own connection
+        let search_connection = Self::open_search_connection(&config.db_path)
+            .map_err(|e| AutoOrganizeError::DatabaseError(e.to_string()))?;
         let search_engine = Arc::new(
-            SearchEngine::new(database.clone())
-                .expect("Failed to initialize search engine")
+            SearchEngine::with_index_path(
+                Arc::new(Mutex::new(search_connection)),
+                config.search_index_path(),
+            )
+            .map_err(|e| AutoOrganizeError::SearchError(e.to_string()))?
         );
         
-        Self {
+        Ok(Self {
             config,
             database,
             file_watcher: None,
@@ -147,7 +264,16 @@ impl AutoOrganizeCore {
             search_engine,
             runtime,
             initialized: Arc::new(RwLock::new(false)),
-        }
+        })
+    }
+
+    /// Writes on the other connection make this one wait rather than fail
+    /// with SQLITE_BUSY, and WAL lets searches read while a batch is written
+    fn open_search_connection(db_path: &str) -> rusqlite::Result<rusqlite::Connection> {
+        let connection = rusqlite::Connection::open(db_path)?;
+        connection.busy_timeout(std::time::Duration::from_secs(5))?;
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        Ok(connection)
     }
     
     pub async fn initialize(&self) -> Result<(), AutoOrganizeError> {
@@ -207,8 +333,65 @@ impl AutoOrganizeCore {
         file_path: String,
         callback: Box<dyn IngestionCallback + Send + Sync>,
     ) -> Result<(), AutoOrganizeError> {
-        self.ingestion_engine.ingest_file(&file_path, callback).await
-            .map_err(|e| AutoOrganizeError::IngestionError(e.to_string()))
+        let document = self.ingestion_engine.ingest_file(&file_path, callback).await
+            .map_err(|e| AutoOrganizeError::IngestionError(e.to_string()))?;
+        self.store_documents(&[document]).await
+    }
+    
+    /// Ingest a directory and store it as one batch, so saved searches run
+    /// once for the whole directory
+    pub async fn ingest_directory(
+        &self,
+        dir_path: String,
+        callback: Box<dyn IngestionCallback + Send + Sync>,
+    ) -> Result<(), AutoOrganizeError> {
+        let documents = self.ingestion_engine.ingest_directory(&dir_path, callback).await
+            .map_err(|e| AutoOrganizeError::IngestionError(e.to_string()))?;
+        self.store_documents(&documents).await
+    }
+    
+    /// Write ingested documents and their chunks and index them in one
+    /// transaction, so the persisted index always agrees with the documents
+    /// table. Saved searches are run afterwards and alert on new matches.
+    async fn store_documents(&self, documents: &[ProcessedDocument]) -> Result<(), AutoOrganizeError> {
+        let rows: Vec<DocumentInfo> = documents.iter().map(document_info).collect();
+        let indexed: Vec<IndexedDocument> = documents
+            .iter()
+            .map(|document| self.indexed_document(document))
+            .collect();
+        
+        self.search_engine
+            .index_documents_with(&indexed, |tx| {
+                for (row, document) in rows.iter().zip(documents) {
+                    database::Database::store_document(tx, row, &document.chunks)?;
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|e| AutoOrganizeError::DatabaseError(e.to_string()))
+    }
+    
+    fn indexed_document(&self, document: &ProcessedDocument) -> IndexedDocument {
+        // Analyzed in the language detected at ingestion, as on rebuild
+        let language = document.metadata.language.as_deref();
+        IndexedDocument {
+            id: document.id.clone(),
+            title: document.title.clone(),
+            content: document.content.clone(),
+            title_tokens: self.search_engine.analyze(&document.title, language),
+            tokens: self.search_engine.analyze(&document.content, language),
+            entities: document.entities
+                .iter()
+                .map(|entity| IndexedEntity {
+                    id: entity.id.clone(),
+                    entity_type: entity.entity_type.clone(),
+                    name: entity.name.clone(),
+                    properties: entity.properties.clone(),
+                })
+                .collect(),
+            metadata: serde_json::to_value(&document.metadata).unwrap_or_default(),
+            embedding: None,
+        }
     }
     
     pub async fn search_documents(
@@ -244,6 +427,20 @@ impl AutoOrganizeCore {
     }
 }
 
+fn document_info(document: &ProcessedDocument) -> DocumentInfo {
+    DocumentInfo {
+        id: document.id.clone(),
+        source_type: document.source_type.clone(),
+        file_path: document.file_path.to_string_lossy().into_owned(),
+        content_hash: document.content_hash.clone(),
+        ingested_at: Utc::now().timestamp(),
+        modified_at: document.metadata.modified_at.timestamp(),
+        metadata_json: serde_json::to_string(&document.metadata).unwrap_or_else(|_| "{}".to_string()),
+        title: document.title.clone(),
+        content: Some(document.content.clone()),
+    }
+}
+
 // Callback trait definitions
 pub trait FileWatcherCallback: Send + Sync {
     fn on_file_event(&self, event: FileEvent);
@@ -257,4 +454,12 @@ pub trait IngestionCallback: Send + Sync {
 pub trait SearchCallback: Send + Sync {
     fn on_search_results(&self, results: Vec<SearchResult>);
     fn on_search_error(&self, error_message: String);
+    fn on_partial_results(&self, stage: SearchStage, results: Vec<SearchResult>);
+    fn on_search_complete(&self, total_hits: u32);
+    fn on_search_cancelled(&self);
+}
+
+pub trait SavedSearchCallback: Send + Sync {
+    fn on_saved_search_matches(&self, saved_search_id: String, name: String, results: Vec<SearchResult>);
+    fn on_saved_search_error(&self, error_message: String);
 }
\ No newline at end of file
@@ -366,6 +366,79 @@ impl FileTypeDetector {
     }
 }
 
+// Lightweight language detection for choosing a search analyzer
+pub struct LanguageDetector;
+
+impl LanguageDetector {
+    // Frequent function words that rarely appear in the other languages
+    const MARKERS: &'static [(&'static str, &'static [&'static str])] = &[
+        ("en", &["the", "and", "of", "to", "is", "that", "with", "for", "this", "are"]),
+        ("de", &["der", "die", "und", "das", "ist", "nicht", "mit", "ein", "eine", "auf"]),
+        ("fr", &["le", "les", "et", "est", "des", "une", "dans", "pour", "pas", "que"]),
+        ("es", &["el", "los", "las", "y", "es", "del", "una", "por", "para", "que"]),
+    ];
+
+    /// ISO 639-1 code of the dominant language, or None when the text is
+    /// too short or mixed to tell
+    pub fn detect(text: &str) -> Option<String> {
+        let sample: String = text.chars().take(10_000).collect();
+
+        // Scripts settle CJK languages without looking at words
+        let mut letters = 0usize;
+        let mut han = 0usize;
+        let mut kana = 0usize;
+        let mut hangul = 0usize;
+        for c in sample.chars().filter(|c| c.is_alphabetic()) {
+            letters += 1;
+            match c as u32 {
+                0x3040..=0x30FF => kana += 1,
+                0xAC00..=0xD7AF | 0x1100..=0x11FF | 0x3130..=0x318F => hangul += 1,
+                0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => han += 1,
+                _ => {}
+            }
+        }
+
+        if letters > 0 && (han + kana + hangul) * 3 >= letters {
+            let code = if kana > 0 {
+                "ja"
+            } else if hangul >= han {
+                "ko"
+            } else {
+                "zh"
+            };
+            return Some(code.to_string());
+        }
+
+        let words: Vec<String> = sample
+            .split(|c: char| !c.is_alphabetic())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .collect();
+
+        let scores: Vec<(&str, usize)> = Self::MARKERS
+            .iter()
+            .map(|(code, markers)| {
+                let hits = words.iter().filter(|word| markers.contains(&word.as_str())).count();
+                (*code, hits)
+            })
+            .collect();
+
+        let (best_code, best_hits) = scores.iter().max_by_key(|(_, hits)| *hits).copied()?;
+        let runner_up = scores
+            .iter()
+            .filter(|(code, _)| *code != best_code)
+            .map(|(_, hits)| *hits)
+            .max()
+            .unwrap_or(0);
+
+        if best_hits >= 2 && best_hits > runner_up {
+            Some(best_code.to_string())
+        } else {
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -453,4 +526,15 @@ mod tests {
         assert!(!chunks.is_empty());
         assert!(chunks.len() > 1);
     }
+
+    #[test]
+    fn test_language_detection() {
+        assert_eq!(LanguageDetector::detect("The report is ready and the team is happy with it").as_deref(), Some("en"));
+        assert_eq!(LanguageDetector::detect("Die Stadt ist nicht groß und das Wetter ist schön").as_deref(), Some("de"));
+        assert_eq!(LanguageDetector::detect("Le rapport est prêt et les résultats sont dans le dossier").as_deref(), Some("fr"));
+        assert_eq!(LanguageDetector::detect("El informe está listo y los resultados son para el equipo").as_deref(), Some("es"));
+        assert_eq!(LanguageDetector::detect("北京大学的图书馆").as_deref(), Some("zh"));
+        assert_eq!(LanguageDetector::detect("東京の天気").as_deref(), Some("ja"));
+        assert_eq!(LanguageDetector::detect("12345"), None);
+    }
 }
\ No newline at end of file
@@ -4,7 +4,7 @@ use async_trait::async_trait;
 use chrono::Utc;
 use uuid::Uuid;
 
-use crate::{ProcessedDocument, DocumentMetadata, IngestionConfig, FileTypeDetector};
+use crate::{ProcessedDocument, DocumentMetadata, IngestionConfig, FileTypeDetector, LanguageDetector};
 
 #[async_trait]
 pub trait DocumentProcessor: Send + Sync {
@@ -44,7 +44,7 @@ impl DocumentProcessor for TextProcessor {
             mime_type: FileTypeDetector::detect_mime_type(file_path),
             created_at: metadata.created().ok().map(|time| time.into()),
             modified_at: metadata.modified().unwrap_or(std::time::SystemTime::now()).into(),
-            language: Some("en".to_string()), // TODO: Detect language
+            language: LanguageDetector::detect(&content),
             encoding: Some("utf-8".to_string()),
             word_count: Some(content.split_whitespace().count() as u32),
             char_count: Some(content.chars().count() as u32),
@@ -97,7 +97,7 @@ impl DocumentProcessor for PdfProcessor {
             mime_type: "application/pdf".to_string(),
             created_at: metadata.created().ok().map(|time| time.into()),
             modified_at: metadata.modified().unwrap_or(std::time::SystemTime::now()).into(),
-            language: Some("en".to_string()),
+            language: LanguageDetector::detect(&content),
             encoding: Some("utf-8".to_string()),
             word_count: Some(content.split_whitespace().count() as u32),
             char_count: Some(content.chars().count() as u32),
@@ -160,7 +160,7 @@ impl DocumentProcessor for DocxProcessor {
             mime_type: "application/vnd.openxmlformats-officedocument.wordprocessingml.document".to_string(),
             created_at: metadata.created().ok().map(|time| time.into()),
             modified_at: metadata.modified().unwrap_or(std::time::SystemTime::now()).into(),
-            language: Some("en".to_string()),
+            language: LanguageDetector::detect(&content),
             encoding: Some("utf-8".to_string()),
             word_count: Some(content.split_whitespace().count() as u32),
             char_count: Some(content.chars().count() as u32),
@@ -261,7 +261,7 @@ impl DocumentProcessor for HtmlProcessor {
             mime_type: "text/html".to_string(),
             created_at: metadata.created().ok().map(|time| time.into()),
             modified_at: metadata.modified().unwrap_or(std::time::SystemTime::now()).into(),
-            language: Some("en".to_string()),
+            language: LanguageDetector::detect(&content),
             encoding: Some("utf-8".to_string()),
             word_count: Some(content.split_whitespace().count() as u32),
             char_count: Some(content.chars().count() as u32),
@@ -359,7 +359,7 @@ impl DocumentProcessor for CsvProcessor {
             mime_type: "text/csv".to_string(),
             created_at: metadata.created().ok().map(|time| time.into()),
             modified_at: metadata.modified().unwrap_or(std::time::SystemTime::now()).into(),
-            language: Some("en".to_string()),
+            language: LanguageDetector::detect(&content),
             encoding: Some("utf-8".to_string()),
             word_count: Some(content.split_whitespace().count() as u32),
             char_count: Some(content.chars().count() as u32),
@@ -413,7 +413,7 @@ impl DocumentProcessor for JsonProcessor {
             mime_type: "application/json".to_string(),
             created_at: metadata.created().ok().map(|time| time.into()),
             modified_at: metadata.modified().unwrap_or(std::time::SystemTime::now()).into(),
-            language: Some("en".to_string()),
+            language: LanguageDetector::detect(&content),
             encoding: Some("utf-8".to_string()),
             word_count: Some(content.split_whitespace().count() as u32),
             char_count: Some(content.chars().count() as u32),
@@ -0,0 +1,359 @@
+use std::collections::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+use rust_stemmers::{Algorithm, Stemmer};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use unicode_segmentation::UnicodeSegmentation;
+
+const ENGLISH_STOP_WORDS: &[&str] = &[
+    "the", "a", "an", "and", "or", "but", "in", "on", "at", "to", "for", "of", "with",
+    "by", "is", "are", "was", "were", "be", "been", "being", "have", "has", "had",
+    "do", "does", "did", "will", "would", "could", "should", "may", "might", "must",
+    "this", "that", "these", "those", "i", "you", "he", "she", "it", "we", "they",
+];
+
+const GERMAN_STOP_WORDS: &[&str] = &[
+    "der", "die", "das", "den", "dem", "des", "ein", "eine", "einer", "eines", "einem", "einen",
+    "und", "oder", "aber", "in", "im", "an", "am", "auf", "aus", "bei", "mit", "nach", "von",
+    "vom", "zu", "zum", "zur", "für", "über", "unter", "ist", "sind", "war", "waren", "sein",
+    "hat", "haben", "hatte", "wird", "werden", "wurde", "nicht", "auch", "als", "wie", "so",
+    "es", "er", "sie", "wir", "ihr", "ich", "du", "dass", "sich", "noch", "nur",
+];
+
+const FRENCH_STOP_WORDS: &[&str] = &[
+    "le", "la", "les", "l", "un", "une", "des", "du", "de", "d", "et", "ou", "mais", "dans",
+    "en", "sur", "au", "aux", "avec", "par", "pour", "sans", "sous", "est", "sont", "était",
+    "été", "être", "a", "ont", "avait", "ce", "cet", "cette", "ces", "il", "elle", "ils",
+    "elles", "nous", "vous", "je", "tu", "on", "qui", "que", "qu", "ne", "pas", "se", "son",
+    "sa", "ses", "leur", "leurs", "c", "s", "n", "j", "m", "t",
+];
+
+const SPANISH_STOP_WORDS: &[&str] = &[
+    "el", "la", "los", "las", "un", "una", "unos", "unas", "y", "o", "pero", "en", "de",
+    "del", "al", "a", "con", "por", "para", "sin", "sobre", "es", "son", "era", "eran",
+    "fue", "ser", "ha", "han", "había", "este", "esta", "estos", "estas", "ese", "esa",
+    "él", "ella", "ellos", "ellas", "nosotros", "yo", "tú", "que", "qué", "no", "se",
+    "su", "sus", "lo", "le", "les", "como", "más",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Language {
+    English,
+    German,
+    French,
+    Spanish,
+    Chinese,
+    Japanese,
+    Korean,
+}
+
+impl Language {
+    pub const ALL: [Language; 7] = [
+        Language::English,
+        Language::German,
+        Language::French,
+        Language::Spanish,
+        Language::Chinese,
+        Language::Japanese,
+        Language::Korean,
+    ];
+
+    /// Parse an ISO 639-1 code, optionally with a region ("de", "en-US")
+    pub fn from_code(code: &str) -> Option<Self> {
+        let primary = code.split(['-', '_']).next()?.to_lowercase();
+
+        match primary.as_str() {
+            "en" => Some(Language::English),
+            "de" => Some(Language::German),
+            "fr" => Some(Language::French),
+            "es" => Some(Language::Spanish),
+            "zh" => Some(Language::Chinese),
+            "ja" => Some(Language::Japanese),
+            "ko" => Some(Language::Korean),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::German => "de",
+            Language::French => "fr",
+            Language::Spanish => "es",
+            Language::Chinese => "zh",
+            Language::Japanese => "ja",
+            Language::Korean => "ko",
+        }
+    }
+
+    pub fn stop_words(&self) -> &'static [&'static str] {
+        match self {
+            Language::English => ENGLISH_STOP_WORDS,
+            Language::German => GERMAN_STOP_WORDS,
+            Language::French => FRENCH_STOP_WORDS,
+            Language::Spanish => SPANISH_STOP_WORDS,
+            // CJK text is indexed as bigrams, which stop lists do not apply to
+            Language::Chinese | Language::Japanese | Language::Korean => &[],
+        }
+    }
+
+    fn stemmer(&self) -> Option<Stemmer> {
+        let algorithm = match self {
+            Language::English => Algorithm::English,
+            Language::German => Algorithm::German,
+            Language::French => Algorithm::French,
+            Language::Spanish => Algorithm::Spanish,
+            Language::Chinese | Language::Japanese | Language::Korean => return None,
+        };
+        Some(Stemmer::create(algorithm))
+    }
+}
+
+/// An index term and the byte range of the text it was produced from
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyzedToken {
+    pub term: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Turns text into index terms for one language: word segmentation,
+/// lowercasing, stopword removal, stemming and accent folding. Runs of CJK
+/// characters become overlapping bigrams whatever the language, since they
+/// carry no word boundaries.
+pub struct Analyzer {
+    language: Language,
+    stemmer: Option<Stemmer>,
+    stop_words: HashSet<&'static str>,
+}
+
+impl Analyzer {
+    pub fn new(language: Language) -> Self {
+        Self {
+            language,
+            stemmer: language.stemmer(),
+            stop_words: language.stop_words().iter().copied().collect(),
+        }
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    pub fn is_stop_word(&self, word: &str) -> bool {
+        self.stop_words.contains(word)
+    }
+
+    pub fn analyze(&self, text: &str) -> Vec<String> {
+        self.analyze_with_offsets(text).into_iter().map(|token| token.term).collect()
+    }
+
+    /// Like `analyze`, keeping where in `text` each term came from
+    pub fn analyze_with_offsets(&self, text: &str) -> Vec<AnalyzedToken> {
+        let mut tokens = Vec::new();
+        let mut cjk_run: Vec<(usize, char)> = Vec::new();
+
+        for (offset, segment) in text.split_word_bound_indices() {
+            // Word segmentation splits ideographs apart, so adjacent CJK
+            // segments are collected back into one run
+            if segment.chars().any(is_cjk) {
+                cjk_run.extend(segment.char_indices().map(|(i, c)| (offset + i, c)));
+                continue;
+            }
+            push_bigrams(&cjk_run, &mut tokens);
+            cjk_run.clear();
+
+            if !segment.chars().any(char::is_alphanumeric) {
+                continue;
+            }
+
+            let lowercase = segment.to_lowercase();
+            let word = self.strip_elision(&lowercase);
+            if self.is_stop_word(word) {
+                continue;
+            }
+
+            // Stem before folding: the Snowball stemmers expect accented suffixes
+            let stemmed = match &self.stemmer {
+                Some(stemmer) => stemmer.stem(word).to_string(),
+                None => word.to_string(),
+            };
+            tokens.push(AnalyzedToken {
+                term: fold(&stemmed),
+                start: offset,
+                end: offset + segment.len(),
+            });
+        }
+
+        push_bigrams(&cjk_run, &mut tokens);
+        tokens
+    }
+
+    /// Count the words of `text`, lowercased, under the term each one
+    /// analyzes to, so a term can be shown as a word rather than a stem
+    pub fn count_surface_forms(&self, text: &str, forms: &mut HashMap<(String, String), usize>) {
+        for token in self.analyze_with_offsets(text) {
+            let surface = text[token.start..token.end].to_lowercase();
+            *forms.entry((token.term, surface)).or_insert(0) += 1;
+        }
+    }
+
+    /// French elides articles and pronouns into the next word ("l'école")
+    fn strip_elision<'a>(&self, word: &'a str) -> &'a str {
+        if self.language != Language::French {
+            return word;
+        }
+
+        match word.split_once(['\'', '\u{2019}']) {
+            Some((head, tail)) if self.is_stop_word(head) && !tail.is_empty() => tail,
+            _ => word,
+        }
+    }
+}
+
+fn push_bigrams(run: &[(usize, char)], tokens: &mut Vec<AnalyzedToken>) {
+    let chars: Vec<(usize, char)> = run.iter().copied().filter(|(_, c)| is_cjk(*c)).collect();
+    let token = |chars: &[(usize, char)]| {
+        let (start, _) = chars[0];
+        let (last, last_char) = chars[chars.len() - 1];
+        AnalyzedToken {
+            term: chars.iter().map(|(_, c)| c).collect(),
+            start,
+            end: last + last_char.len_utf8(),
+        }
+    };
+
+    match chars.len() {
+        0 => {}
+        1 => tokens.push(token(&chars)),
+        _ => tokens.extend(chars.windows(2).map(token)),
+    }
+}
+
+/// Strip diacritics and expand ligatures so "Résumé" and "resume" meet
+pub fn fold(word: &str) -> String {
+    let mut folded = String::with_capacity(word.len());
+
+    for c in word.nfkd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'æ' => folded.push_str("ae"),
+            'œ' => folded.push_str("oe"),
+            'ø' => folded.push('o'),
+            'đ' => folded.push('d'),
+            'ł' => folded.push('l'),
+            _ => folded.push(c),
+        }
+    }
+
+    folded
+}
+
+pub fn is_cjk(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x3040..=0x30FF     // Hiragana, Katakana
+        | 0x3400..=0x4DBF   // CJK Extension A
+        | 0x4E00..=0x9FFF   // CJK Unified Ideographs
+        | 0xF900..=0xFAFF   // CJK Compatibility Ideographs
+        | 0x1100..=0x11FF   // Hangul Jamo
+        | 0x3130..=0x318F   // Hangul Compatibility Jamo
+        | 0xAC00..=0xD7AF   // Hangul Syllables
+        | 0x20000..=0x2A6DF // CJK Extension B
+    )
+}
+
+/// One analyzer per supported language, picked by language code with a
+/// fallback for documents and queries whose language is unknown
+pub struct AnalyzerRegistry {
+    analyzers: HashMap<Language, Analyzer>,
+    default_language: Language,
+}
+
+impl Default for AnalyzerRegistry {
+    fn default() -> Self {
+        Self {
+            analyzers: Language::ALL.iter().map(|&language| (language, Analyzer::new(language))).collect(),
+            default_language: Language::English,
+        }
+    }
+}
+
+impl AnalyzerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn analyzer(&self, language_code: Option<&str>) -> &Analyzer {
+        let language = language_code
+            .and_then(Language::from_code)
+            .unwrap_or(self.default_language);
+        &self.analyzers[&language]
+    }
+
+    pub fn default_analyzer(&self) -> &Analyzer {
+        &self.analyzers[&self.default_language]
+    }
+
+    pub fn set_default_language(&mut self, language: Language) {
+        self.default_language = language;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_codes() {
+        assert_eq!(Language::from_code("de"), Some(Language::German));
+        assert_eq!(Language::from_code("en-US"), Some(Language::English));
+        assert_eq!(Language::from_code("pt_BR"), None);
+        assert_eq!(Language::Spanish.code(), "es");
+    }
+
+    #[test]
+    fn test_european_analyzers() {
+        let english = Analyzer::new(Language::English);
+        assert_eq!(english.analyze("The running of the Bulls"), vec!["run", "bull"]);
+
+        let german = Analyzer::new(Language::German);
+        assert_eq!(german.analyze("Die Häuser und der Straße"), vec!["haus", "strass"]);
+
+        let french = Analyzer::new(Language::French);
+        assert_eq!(french.analyze("Les élèves de l'école"), vec!["elev", "ecol"]);
+
+        let spanish = Analyzer::new(Language::Spanish);
+        assert_eq!(spanish.analyze("Las canciones del año"), vec!["cancion", "ano"]);
+    }
+
+    #[test]
+    fn test_cjk_bigrams() {
+        let analyzer = Analyzer::new(Language::Chinese);
+        assert_eq!(analyzer.analyze("北京大学"), vec!["北京", "京大", "大学"]);
+
+        // Runs are split by punctuation and other scripts, and the
+        // bigrams are produced by every analyzer
+        let english = Analyzer::new(Language::English);
+        assert_eq!(english.analyze("東京 reports, 日本"), vec!["東京", "report", "日本"]);
+        assert_eq!(english.analyze("猫"), vec!["猫"]);
+    }
+
+    #[test]
+    fn test_token_offsets() {
+        let text = "Große 北京大学";
+        let tokens = Analyzer::new(Language::German).analyze_with_offsets(text);
+
+        let spans: Vec<(&str, &str)> = tokens
+            .iter()
+            .map(|token| (token.term.as_str(), &text[token.start..token.end]))
+            .collect();
+        assert_eq!(spans, vec![("gross", "Große"), ("北京", "北京"), ("京大", "京大"), ("大学", "大学")]);
+    }
+
+    #[test]
+    fn test_folding() {
+        assert_eq!(fold("résumé"), "resume");
+        assert_eq!(fold("œuvre"), "oeuvre");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+
+use crate::{SearchQuery, SearchResponse};
+
+// Serialized size of the cached responses, not counting bookkeeping
+const DEFAULT_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// What a cached response was computed from, so a change to a document
+/// only drops the responses it could alter
+#[derive(Debug, Clone, Default)]
+pub struct CacheDependencies {
+    pub terms: HashSet<String>, // analyzed query terms and their expansions
+    pub broad: bool,            // fuzzy, semantic or boolean matching, which any document can change
+    pub profile_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+}
+
+struct CachedSearch {
+    response: SearchResponse,
+    document_ids: HashSet<String>,
+    dependencies: CacheDependencies,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// Least recently used search responses, keyed by the normalized query and
+/// bounded by their serialized size
+pub struct QueryCache {
+    entries: HashMap<String, CachedSearch>,
+    recency: BTreeMap<u64, String>, // last use to key, oldest first
+    tick: u64,
+    generation: u64, // bumped by every invalidation
+    bytes: usize,
+    max_bytes: usize,
+    hits: u64,
+    misses: u64,
+    invalidations: u64,
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        Self::with_max_bytes(DEFAULT_MAX_BYTES)
+    }
+}
+
+impl QueryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_bytes(max_bytes: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            generation: 0,
+            bytes: 0,
+            max_bytes,
+            hits: 0,
+            misses: 0,
+            invalidations: 0,
+        }
+    }
+
+    /// Queries that differ only in whitespace or in the order of filter
+    /// values share a key
+    pub fn key(query: &SearchQuery) -> String {
+        let mut query = query.clone();
+        query.text = query.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let filters = &mut query.filters;
+        for values in [
+            &mut filters.entity_types,
+            &mut filters.document_types,
+            &mut filters.file_types,
+            &mut filters.source_types,
+            &mut filters.document_ids,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            values.sort();
+            values.dedup();
+        }
+        serde_json::to_string(&query).unwrap_or_default()
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<SearchResponse> {
+        let tick = self.next_tick();
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                self.recency.remove(&entry.last_used);
+                self.recency.insert(tick, key.to_string());
+                entry.last_used = tick;
+                self.hits += 1;
+                Some(entry.response.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Invalidations since the cache was created. A response computed
+    /// while this changed may be stale and is not cached.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn insert(&mut self, key: String, response: &SearchResponse, dependencies: CacheDependencies, generation: u64) {
+        if generation != self.generation {
+            return;
+        }
+        let bytes = key.len() + serde_json::to_vec(response).map(|json| json.len()).unwrap_or(usize::MAX - key.len());
+        if bytes > self.max_bytes {
+            return;
+        }
+
+        self.remove(&key);
+        while self.bytes + bytes > self.max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else { break };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.bytes -= entry.bytes;
+            }
+        }
+
+        let tick = self.next_tick();
+        self.recency.insert(tick, key.clone());
+        self.bytes += bytes;
+        self.entries.insert(key, CachedSearch {
+            document_ids: response.results.iter().map(|result| result.id.clone()).collect(),
+            response: response.clone(),
+            dependencies,
+            bytes,
+            last_used: tick,
+        });
+    }
+
+    /// Drop the responses that the given documents were in, or that they
+    /// could be matched by: `has_term(term, document_id)` tells whether a
+    /// document contains an analyzed term. Call it before and after a
+    /// change, so both the old and the new text are checked.
+    pub fn invalidate_documents<F>(&mut self, document_ids: &[String], has_term: F) -> usize
+    where
+        F: Fn(&str, &str) -> bool,
+    {
+        self.invalidate_where(|entry| {
+            entry.dependencies.broad
+                || document_ids.iter().any(|id| {
+                    entry.document_ids.contains(id)
+                        || entry.dependencies.terms.iter().any(|term| has_term(term, id))
+                })
+        })
+    }
+
+    /// Drop the responses ranked with a profile's weights and preferences
+    pub fn invalidate_profile(&mut self, profile_id: &str) -> usize {
+        self.invalidate_where(|entry| entry.dependencies.profile_id.as_deref() == Some(profile_id))
+    }
+
+    pub fn clear(&mut self) -> usize {
+        self.invalidate_where(|_| true)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            bytes: self.bytes,
+            max_bytes: self.max_bytes,
+            hits: self.hits,
+            misses: self.misses,
+            invalidations: self.invalidations,
+        }
+    }
+
+    fn invalidate_where<F>(&mut self, stale: F) -> usize
+    where
+        F: Fn(&CachedSearch) -> bool,
+    {
+        self.generation += 1;
+        let keys: Vec<String> = self.entries.iter().filter(|(_, entry)| stale(entry)).map(|(key, _)| key.clone()).collect();
+        for key in &keys {
+            self.remove(key);
+        }
+        self.invalidations += keys.len() as u64;
+        keys.len()
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+            self.bytes -= entry.bytes;
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SearchFilters, SearchOptions, SearchResult, SearchResultType};
+
+    fn query(text: &str, source_types: &[&str]) -> SearchQuery {
+        SearchQuery {
+            text: text.to_string(),
+            filters: SearchFilters {
+                entity_types: None,
+                document_types: None,
+                date_range: None,
+                file_types: None,
+                source_types: Some(source_types.iter().map(|s| s.to_string()).collect()),
+                document_ids: None,
+                mentions: None,
+            },
+            options: SearchOptions::default(),
+        }
+    }
+
+    fn response(ids: &[&str]) -> SearchResponse {
+        SearchResponse {
+            results: ids
+                .iter()
+                .map(|id| SearchResult {
+                    id: id.to_string(),
+                    result_type: SearchResultType::Document,
+                    title: format!("Document {}", id),
+                    content: None,
+                    snippet: None,
+                    score: 1.0,
+                    metadata: serde_json::json!({}),
+                    highlights: Vec::new(),
+                    fragments: Vec::new(),
+                    passages: Vec::new(),
+                    sort_values: Vec::new(),
+                })
+                .collect(),
+            total_hits: ids.len(),
+            total_hits_exact: true,
+            next_cursor: None,
+            facets: None,
+            did_you_mean: None,
+            timed_out: false,
+            entities: Vec::new(),
+        }
+    }
+
+    fn depends_on(terms: &[&str]) -> CacheDependencies {
+        CacheDependencies {
+            terms: terms.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_key_ignores_whitespace_and_filter_order() {
+        assert_eq!(
+            QueryCache::key(&query("  budget   review ", &["email", "pdf"])),
+            QueryCache::key(&query("budget review", &["pdf", "email", "pdf"])),
+        );
+        assert_ne!(QueryCache::key(&query("budget", &[])), QueryCache::key(&query("Budget OR", &[])));
+    }
+
+    #[test]
+    fn test_least_recently_used_are_evicted_first() {
+        let key_size = |text: &str| {
+            let key = QueryCache::key(&query(text, &[]));
+            key.len() + serde_json::to_vec(&response(&["1"])).unwrap().len()
+        };
+        let mut cache = QueryCache::with_max_bytes(key_size("aaa") * 2);
+
+        for text in ["aaa", "bbb"] {
+            cache.insert(QueryCache::key(&query(text, &[])), &response(&["1"]), depends_on(&[]), cache.generation());
+        }
+        assert!(cache.get(&QueryCache::key(&query("aaa", &[]))).is_some());
+        cache.insert(QueryCache::key(&query("ccc", &[])), &response(&["1"]), depends_on(&[]), cache.generation());
+
+        assert!(cache.get(&QueryCache::key(&query("bbb", &[]))).is_none());
+        assert!(cache.get(&QueryCache::key(&query("aaa", &[]))).is_some());
+        assert!(cache.get(&QueryCache::key(&query("ccc", &[]))).is_some());
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 3, 1));
+        assert!(stats.bytes <= stats.max_bytes);
+    }
+
+    #[test]
+    fn test_invalidation_drops_only_affected_responses() {
+        let mut cache = QueryCache::new();
+        let generation = cache.generation();
+        cache.insert("budget".to_string(), &response(&["1"]), depends_on(&["budget"]), generation);
+        cache.insert("garden".to_string(), &response(&["2"]), depends_on(&["garden"]), generation);
+        cache.insert("fuzzy".to_string(), &response(&[]), CacheDependencies { broad: true, ..Default::default() }, generation);
+        cache.insert(
+            "mine".to_string(),
+            &response(&["2"]),
+            CacheDependencies { profile_id: Some("ana".to_string()), ..depends_on(&["garden"]) },
+            generation,
+        );
+
+        // Document 3 is new and mentions a budget
+        let has_term = |term: &str, id: &str| id == "3" && term == "budget";
+        assert_eq!(cache.invalidate_documents(&["3".to_string()], has_term), 2);
+        assert!(cache.get("budget").is_none() && cache.get("fuzzy").is_none());
+        assert!(cache.get("garden").is_some());
+
+        // A response computed before the invalidation is not cached
+        cache.insert("late".to_string(), &response(&["3"]), depends_on(&["budget"]), generation);
+        assert!(cache.get("late").is_none());
+
+        assert_eq!(cache.invalidate_profile("ana"), 1);
+        assert_eq!(cache.invalidate_documents(&["2".to_string()], |_, _| false), 1);
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().bytes, 0);
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+use crate::SnippetFragment;
+
+/// Passages per document when the query does not say
+pub const DEFAULT_MAX_PASSAGES: usize = 3;
+
+/// A chunk that matched the query text
+#[derive(Debug, Clone)]
+pub struct ChunkHit {
+    pub document_id: String,
+    pub chunk_id: String,
+    pub chunk_index: u32,
+    pub start_position: u32,
+    pub end_position: u32,
+    pub content: String,
+    pub score: f64, // negated FTS5 rank, higher is better
+}
+
+/// One of the best matching chunks of a result. Positions are offsets of
+/// the chunk in the document text; the fragment's offsets are relative to
+/// the chunk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkPassage {
+    pub chunk_id: String,
+    pub chunk_index: u32,
+    pub start_position: u32,
+    pub end_position: u32,
+    pub score: f64,
+    pub fragment: Option<SnippetFragment>,
+}
+
+/// The best `per_document` hits of each document, best first. Hits are
+/// expected ranked, as FTS returns them.
+pub fn group_by_document(hits: Vec<ChunkHit>, per_document: usize) -> HashMap<String, Vec<ChunkHit>> {
+    let mut groups: HashMap<String, Vec<ChunkHit>> = HashMap::new();
+    for hit in hits {
+        let group = groups.entry(hit.document_id.clone()).or_default();
+        if group.len() < per_document {
+            group.push(hit);
+        }
+    }
+    groups
+}
+
+/// Each document's best chunk score relative to the best chunk overall, so
+/// the strongest passage counts 1 whatever the scale of the FTS scores
+pub fn best_chunk_scores(hits: &[ChunkHit]) -> HashMap<String, f64> {
+    let mut best: HashMap<String, f64> = HashMap::new();
+    for hit in hits {
+        let score = best.entry(hit.document_id.clone()).or_insert(f64::MIN);
+        *score = score.max(hit.score);
+    }
+
+    let top = best.values().cloned().fold(f64::MIN, f64::max);
+    if top <= 0.0 {
+        return best.into_keys().map(|id| (id, 0.0)).collect();
+    }
+    best.into_iter().map(|(id, score)| (id, (score / top).max(0.0))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(document_id: &str, chunk_index: u32, score: f64) -> ChunkHit {
+        ChunkHit {
+            document_id: document_id.to_string(),
+            chunk_id: format!("{}-{}", document_id, chunk_index),
+            chunk_index,
+            start_position: chunk_index * 100,
+            end_position: chunk_index * 100 + 99,
+            content: String::new(),
+            score,
+        }
+    }
+
+    #[test]
+    fn test_group_by_document_keeps_best_chunks() {
+        let hits = vec![hit("a", 4, 3.0), hit("b", 0, 2.5), hit("a", 1, 2.0), hit("a", 7, 1.0)];
+        let groups = group_by_document(hits, 2);
+
+        let chunks: Vec<u32> = groups["a"].iter().map(|hit| hit.chunk_index).collect();
+        assert_eq!(chunks, vec![4, 1]);
+        assert_eq!(groups["b"].len(), 1);
+    }
+
+    #[test]
+    fn test_best_chunk_scores_are_relative_to_the_best() {
+        let scores = best_chunk_scores(&[hit("a", 0, 1.0), hit("a", 1, 4.0), hit("b", 0, 2.0)]);
+        assert_eq!(scores["a"], 1.0);
+        assert_eq!(scores["b"], 0.5);
+        assert!(best_chunk_scores(&[]).is_empty());
+    }
+}
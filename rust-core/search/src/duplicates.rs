@@ -0,0 +1,457 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use anyhow::Result;
+use rusqlite::{Connection, params};
+use serde::{Serialize, Deserialize};
+
+use crate::IndexedDocument;
+
+const NUM_HASHES: usize = 128;
+const BANDS: usize = 32;
+const ROWS_PER_BAND: usize = NUM_HASHES / BANDS;
+
+/// A set of documents that are near-copies of each other. `similarity`
+/// is the lowest estimated similarity of the pairs that joined the group.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub document_ids: Vec<String>,
+    pub similarity: f64,
+}
+
+/// MinHash signatures over word shingles. Hash functions are derived
+/// from fixed seeds, so signatures stay comparable across restarts.
+#[derive(Debug, Clone)]
+pub struct MinHasher {
+    seeds: Vec<u64>,
+    shingle_size: usize,
+}
+
+impl Default for MinHasher {
+    fn default() -> Self {
+        let mut state = 0x5eed_u64;
+        let seeds = (0..NUM_HASHES).map(|_| splitmix64(&mut state)).collect();
+
+        Self {
+            seeds,
+            shingle_size: 3,
+        }
+    }
+}
+
+impl MinHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signature of a token sequence; None when there are no tokens.
+    /// Texts shorter than one shingle are hashed word by word.
+    pub fn signature(&self, tokens: &[String]) -> Option<Vec<u64>> {
+        if tokens.is_empty() {
+            return None;
+        }
+
+        let shingles: HashSet<u64> = if tokens.len() < self.shingle_size {
+            tokens.iter().map(|token| fnv1a(token.as_bytes())).collect()
+        } else {
+            tokens.windows(self.shingle_size).map(|window| fnv1a(window.join(" ").as_bytes())).collect()
+        };
+
+        let signature = self
+            .seeds
+            .iter()
+            .map(|seed| {
+                shingles
+                    .iter()
+                    .map(|shingle| mix(shingle ^ seed))
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect();
+
+        Some(signature)
+    }
+
+    /// Estimated Jaccard similarity of the shingle sets
+    pub fn similarity(a: &[u64], b: &[u64]) -> f64 {
+        if a.is_empty() || a.len() != b.len() {
+            return 0.0;
+        }
+        a.iter().zip(b.iter()).filter(|(x, y)| x == y).count() as f64 / a.len() as f64
+    }
+}
+
+/// Signatures and save state of documents from before a change, see
+/// `DuplicateDetector::checkpoint`
+pub struct SignatureCheckpoint(Vec<(String, Option<Vec<u64>>, bool, bool)>); // (id, signature, unsaved, deleted)
+
+/// Finds near-duplicate documents from MinHash signatures computed at
+/// index time. Candidate pairs come from locality-sensitive hashing over
+/// bands of the signature, which reliably surfaces pairs above a
+/// similarity of about 0.5; thresholds below that may miss pairs.
+/// Signatures are saved in `search_document_signatures` alongside a
+/// persisted index.
+#[derive(Debug)]
+pub struct DuplicateDetector {
+    hasher: MinHasher,
+    threshold: f64,
+    signatures: HashMap<String, Vec<u64>>,
+    buckets: HashMap<(usize, u64), HashSet<String>>,
+    unsaved: HashSet<String>,
+    deleted: HashSet<String>,
+}
+
+impl Default for DuplicateDetector {
+    fn default() -> Self {
+        Self {
+            hasher: MinHasher::new(),
+            threshold: 0.8,
+            signatures: HashMap::new(),
+            buckets: HashMap::new(),
+            unsaved: HashSet::new(),
+            deleted: HashSet::new(),
+        }
+    }
+}
+
+impl DuplicateDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(db: &Connection) -> Result<Self> {
+        let mut detector = Self::new();
+
+        let exists: bool = db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'search_document_signatures')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(detector);
+        }
+
+        let mut stmt = db.prepare("SELECT document_id, signature FROM search_document_signatures")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+        for row in rows {
+            let (document_id, blob) = row?;
+            let signature: Vec<u64> = blob
+                .chunks_exact(8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap_or([0; 8])))
+                .collect();
+            // Signatures from a different configuration are recomputed
+            if signature.len() == NUM_HASHES {
+                detector.insert(document_id, signature);
+            }
+        }
+
+        Ok(detector)
+    }
+
+    /// Write signatures added or removed since the last save. Call
+    /// `mark_saved` once the surrounding transaction has committed.
+    pub fn save(&self, db: &Connection) -> Result<()> {
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS search_document_signatures (
+                document_id TEXT PRIMARY KEY,
+                signature BLOB NOT NULL
+            )",
+            [],
+        )?;
+
+        for document_id in &self.deleted {
+            db.execute("DELETE FROM search_document_signatures WHERE document_id = ?1", params![document_id])?;
+        }
+        for document_id in &self.unsaved {
+            if let Some(signature) = self.signatures.get(document_id) {
+                let blob: Vec<u8> = signature.iter().flat_map(|value| value.to_le_bytes()).collect();
+                db.execute(
+                    "INSERT OR REPLACE INTO search_document_signatures (document_id, signature) VALUES (?1, ?2)",
+                    params![document_id, blob],
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.unsaved.clear();
+        self.deleted.clear();
+    }
+
+    pub fn has_unsaved_changes(&self) -> bool {
+        !self.unsaved.is_empty() || !self.deleted.is_empty()
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    pub fn set_threshold(&mut self, threshold: f64) {
+        self.threshold = threshold.clamp(0.0, 1.0);
+    }
+
+    pub fn contains_document(&self, document_id: &str) -> bool {
+        self.signatures.contains_key(document_id)
+    }
+
+    pub fn add_document(&mut self, document: &IndexedDocument) {
+        self.remove_document(&document.id);
+
+        let tokens: Vec<String> = document.title_tokens.iter().chain(document.tokens.iter()).cloned().collect();
+        if let Some(signature) = self.hasher.signature(&tokens) {
+            self.deleted.remove(&document.id);
+            self.unsaved.insert(document.id.clone());
+            self.insert(document.id.clone(), signature);
+        }
+    }
+
+    pub fn remove_document(&mut self, document_id: &str) {
+        let signature = match self.signatures.remove(document_id) {
+            Some(signature) => signature,
+            None => return,
+        };
+
+        for (band, key) in band_keys(&signature) {
+            if let Some(bucket) = self.buckets.get_mut(&(band, key)) {
+                bucket.remove(document_id);
+                if bucket.is_empty() {
+                    self.buckets.remove(&(band, key));
+                }
+            }
+        }
+        self.unsaved.remove(document_id);
+        self.deleted.insert(document_id.to_string());
+    }
+
+    /// Remember the documents' signatures and save state before they
+    /// change, so that `restore` can undo the change
+    pub fn checkpoint(&self, document_ids: &[String]) -> SignatureCheckpoint {
+        SignatureCheckpoint(
+            document_ids
+                .iter()
+                .map(|id| (id.clone(), self.signatures.get(id).cloned(), self.unsaved.contains(id), self.deleted.contains(id)))
+                .collect(),
+        )
+    }
+
+    pub fn restore(&mut self, checkpoint: SignatureCheckpoint) {
+        for (document_id, signature, unsaved, deleted) in checkpoint.0 {
+            self.remove_document(&document_id);
+            if let Some(signature) = signature {
+                self.insert(document_id.clone(), signature);
+            }
+
+            if unsaved {
+                self.unsaved.insert(document_id.clone());
+            }
+            if !deleted {
+                self.deleted.remove(&document_id);
+            }
+        }
+    }
+
+    fn insert(&mut self, document_id: String, signature: Vec<u64>) {
+        for key in band_keys(&signature) {
+            self.buckets.entry(key).or_default().insert(document_id.clone());
+        }
+        self.signatures.insert(document_id, signature);
+    }
+
+    /// Estimated similarity of two indexed documents
+    pub fn similarity(&self, a: &str, b: &str) -> Option<f64> {
+        Some(MinHasher::similarity(self.signatures.get(a)?, self.signatures.get(b)?))
+    }
+
+    /// Documents at or above `threshold` (the configured one when None)
+    /// similarity to `document_id`, most similar first
+    pub fn duplicates_of(&self, document_id: &str, threshold: Option<f64>) -> Vec<(String, f64)> {
+        let threshold = threshold.unwrap_or(self.threshold);
+        let signature = match self.signatures.get(document_id) {
+            Some(signature) => signature,
+            None => return Vec::new(),
+        };
+
+        let candidates: HashSet<&String> = band_keys(signature)
+            .filter_map(|key| self.buckets.get(&key))
+            .flatten()
+            .filter(|candidate| candidate.as_str() != document_id)
+            .collect();
+
+        let mut duplicates: Vec<(String, f64)> = candidates
+            .into_iter()
+            .map(|candidate| (candidate.clone(), MinHasher::similarity(signature, &self.signatures[candidate])))
+            .filter(|(_, similarity)| *similarity >= threshold)
+            .collect();
+        duplicates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.0.cmp(&b.0)));
+        duplicates
+    }
+
+    /// Connected groups of documents linked by pairs at or above the
+    /// threshold, largest groups first
+    pub fn groups(&self, threshold: Option<f64>) -> Vec<DuplicateGroup> {
+        let threshold = threshold.unwrap_or(self.threshold);
+        let mut parent: HashMap<&str, &str> = HashMap::new();
+        let mut edges: Vec<(&str, &str, f64)> = Vec::new();
+        let mut checked: HashSet<(&str, &str)> = HashSet::new();
+
+        for bucket in self.buckets.values().filter(|bucket| bucket.len() > 1) {
+            let mut members: Vec<&str> = bucket.iter().map(String::as_str).collect();
+            members.sort();
+
+            for (i, a) in members.iter().enumerate() {
+                for b in &members[i + 1..] {
+                    if !checked.insert((a, b)) {
+                        continue;
+                    }
+                    let similarity = MinHasher::similarity(&self.signatures[*a], &self.signatures[*b]);
+                    if similarity >= threshold {
+                        edges.push((a, b, similarity));
+                    }
+                }
+            }
+        }
+
+        fn find<'a>(parent: &mut HashMap<&'a str, &'a str>, node: &'a str) -> &'a str {
+            let mut root = node;
+            while let Some(&next) = parent.get(root).filter(|&&next| next != root) {
+                root = next;
+            }
+            parent.insert(node, root);
+            root
+        }
+
+        for (a, b, _) in &edges {
+            let (root_a, root_b) = (find(&mut parent, a), find(&mut parent, b));
+            if root_a != root_b {
+                parent.insert(root_a.max(root_b), root_a.min(root_b));
+            }
+        }
+
+        let mut groups: BTreeMap<&str, DuplicateGroup> = BTreeMap::new();
+        for (a, b, similarity) in &edges {
+            let root = find(&mut parent, a);
+            let group = groups.entry(root).or_insert_with(|| DuplicateGroup {
+                document_ids: Vec::new(),
+                similarity: 1.0,
+            });
+            group.document_ids.extend([a.to_string(), b.to_string()]);
+            group.similarity = group.similarity.min(*similarity);
+        }
+
+        let mut groups: Vec<DuplicateGroup> = groups
+            .into_values()
+            .map(|mut group| {
+                group.document_ids.sort();
+                group.document_ids.dedup();
+                group
+            })
+            .collect();
+        groups.sort_by(|a, b| {
+            b.document_ids
+                .len()
+                .cmp(&a.document_ids.len())
+                .then_with(|| a.document_ids.cmp(&b.document_ids))
+        });
+        groups
+    }
+}
+
+fn band_keys(signature: &[u64]) -> impl Iterator<Item = (usize, u64)> + '_ {
+    signature
+        .chunks(ROWS_PER_BAND)
+        .enumerate()
+        .map(|(band, rows)| (band, rows.iter().fold(0xcbf2_9ce4_8422_2325, |hash, row| mix(hash ^ row))))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    mix(*state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, text: &str) -> IndexedDocument {
+        IndexedDocument {
+            id: id.to_string(),
+            title: String::new(),
+            content: text.to_string(),
+            title_tokens: Vec::new(),
+            tokens: text.split_whitespace().map(str::to_string).collect(),
+            entities: Vec::new(),
+            metadata: serde_json::Value::Null,
+            embedding: None,
+        }
+    }
+
+    const REPORT: &str = "the quarterly report covers revenue growth in every region with details on \
+        costs hiring plans supplier contracts marketing spend and the outlook for next year including \
+        risks and opportunities across all product lines";
+
+    #[test]
+    fn test_signature_similarity() {
+        let hasher = MinHasher::new();
+        let tokens = |text: &str| text.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+
+        let a = hasher.signature(&tokens(REPORT)).unwrap();
+        let edited = hasher.signature(&tokens(&REPORT.replace("every region", "each region"))).unwrap();
+        let other = hasher.signature(&tokens("minutes of the garden club meeting about tomato planting")).unwrap();
+
+        assert_eq!(MinHasher::similarity(&a, &a), 1.0);
+        assert!(MinHasher::similarity(&a, &edited) > 0.7);
+        assert!(MinHasher::similarity(&a, &other) < 0.1);
+        assert!(hasher.signature(&[]).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_groups() {
+        let mut detector = DuplicateDetector::new();
+        detector.add_document(&document("a", REPORT));
+        detector.add_document(&document("b", &REPORT.replace("every region", "each region")));
+        detector.add_document(&document("c", &format!("{} appendix", REPORT)));
+        detector.add_document(&document("d", "minutes of the garden club meeting about tomato planting"));
+
+        let groups = detector.groups(Some(0.7));
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].document_ids, vec!["a", "b", "c"]);
+        assert!(groups[0].similarity >= 0.7);
+
+        let duplicates: Vec<String> = detector.duplicates_of("a", Some(0.7)).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(duplicates.len(), 2);
+
+        detector.remove_document("b");
+        assert_eq!(detector.groups(Some(0.7))[0].document_ids, vec!["a", "c"]);
+        assert!(detector.groups(Some(1.0)).is_empty());
+    }
+
+    #[test]
+    fn test_signatures_persist() {
+        let db = Connection::open_in_memory().unwrap();
+        let mut detector = DuplicateDetector::new();
+        detector.add_document(&document("a", REPORT));
+        detector.add_document(&document("b", REPORT));
+        detector.save(&db).unwrap();
+        detector.mark_saved();
+
+        detector.remove_document("b");
+        detector.save(&db).unwrap();
+        detector.mark_saved();
+        assert!(!detector.has_unsaved_changes());
+
+        let reloaded = DuplicateDetector::load(&db).unwrap();
+        assert!(reloaded.contains_document("a"));
+        assert!(!reloaded.contains_document("b"));
+        assert_eq!(reloaded.signatures["a"], detector.signatures["a"]);
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use anyhow::Result;
+use chrono::Utc;
+use rusqlite::Connection;
+use serde::{Serialize, Deserialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{SearchResult, SearchResultType};
+use crate::analyzer::fold;
+use crate::fuzzy::max_edit_distance;
+use crate::suggest::escape_like;
+
+// Keep well below SQLite's bound parameter limit
+const ID_BATCH_SIZE: usize = 500;
+
+/// How an entity matched the query text, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntityMatch {
+    Exact,
+    Prefix,
+    Token,
+    Substring,
+    Fuzzy { distance: usize },
+    Property,
+}
+
+impl EntityMatch {
+    pub fn weight(&self) -> f64 {
+        match self {
+            EntityMatch::Exact => 1.0,
+            EntityMatch::Prefix => 0.85,
+            EntityMatch::Token => 0.7,
+            EntityMatch::Substring => 0.6,
+            EntityMatch::Fuzzy { distance } => 0.5 / (*distance).max(1) as f64,
+            EntityMatch::Property => 0.4,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EntityMatch::Exact => "exact",
+            EntityMatch::Prefix => "prefix",
+            EntityMatch::Token => "token",
+            EntityMatch::Substring => "substring",
+            EntityMatch::Fuzzy { .. } => "fuzzy",
+            EntityMatch::Property => "property",
+        }
+    }
+
+    /// Best name match of `query` against `name`, if any. Both are
+    /// compared lowercased and accent folded.
+    pub fn of_name(name: &str, query: &str) -> Option<Self> {
+        let name = normalize(name);
+        let query = normalize(query);
+        if query.trim().is_empty() {
+            return None;
+        }
+
+        if name == query {
+            return Some(EntityMatch::Exact);
+        }
+        if name.starts_with(&query) {
+            return Some(EntityMatch::Prefix);
+        }
+
+        let name_words: Vec<&str> = name.unicode_words().collect();
+        let query_words: Vec<&str> = query.unicode_words().collect();
+        if query_words.is_empty() {
+            return None;
+        }
+
+        let all_words_match = query_words
+            .iter()
+            .all(|word| name_words.iter().any(|name_word| name_word.starts_with(word)));
+        if all_words_match {
+            return Some(EntityMatch::Token);
+        }
+        if name.contains(&query) {
+            return Some(EntityMatch::Substring);
+        }
+
+        // Every query word close to some word of the name
+        let mut distance = 0;
+        for word in &query_words {
+            let limit = max_edit_distance(word);
+            let closest = name_words
+                .iter()
+                .map(|name_word| strsim::levenshtein(word, name_word))
+                .min()
+                .filter(|&closest| closest <= limit)?;
+            distance += closest;
+        }
+        Some(EntityMatch::Fuzzy { distance })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MentioningDocument {
+    pub id: String,
+    pub title: String,
+    pub mentions: usize,
+}
+
+#[derive(Debug)]
+struct EntityCandidate {
+    id: String,
+    name: String,
+    entity_type: String,
+    properties: serde_json::Value,
+    entity_match: EntityMatch,
+    confidence: f64,
+    mentions: usize,
+    last_seen: i64,
+    score: f64,
+}
+
+/// Ranks entities for a query by how well their name (or, failing that,
+/// one of their properties) matches, blended with how often they are
+/// mentioned, extraction confidence and how recently they were seen
+#[derive(Debug, Clone)]
+pub struct EntitySearcher {
+    documents_per_entity: usize,
+    recency_half_life_days: f64,
+}
+
+impl Default for EntitySearcher {
+    fn default() -> Self {
+        Self {
+            documents_per_entity: 5,
+            recency_half_life_days: 90.0,
+        }
+    }
+}
+
+impl EntitySearcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_documents_per_entity(&mut self, documents_per_entity: usize) {
+        self.documents_per_entity = documents_per_entity;
+    }
+
+    pub fn search(
+        &self,
+        db: &Connection,
+        text: &str,
+        entity_types: Option<&[String]>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let mut candidates = self.matching_entities(db, text, entity_types)?;
+        self.load_statistics(db, &mut candidates)?;
+
+        let max_mentions = candidates.iter().map(|candidate| candidate.mentions).max().unwrap_or(0);
+        let now = Utc::now().timestamp();
+        for candidate in &mut candidates {
+            candidate.score = self.score(candidate, max_mentions, now);
+        }
+        candidates.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        let mut results = Vec::new();
+        for candidate in candidates.into_iter().skip(offset).take(limit) {
+            let documents = self.mentioning_documents(db, &candidate.id)?;
+
+            results.push(SearchResult {
+                id: candidate.id,
+                result_type: SearchResultType::Entity,
+                title: candidate.name,
+                content: None,
+                snippet: None,
+                score: candidate.score,
+                metadata: serde_json::json!({
+                    "entity_type": candidate.entity_type,
+                    "properties": candidate.properties,
+                    "match": candidate.entity_match.label(),
+                    "mention_count": candidate.mentions,
+                    "documents": documents,
+                }),
+                highlights: Vec::new(),
+                fragments: Vec::new(),
+                passages: Vec::new(),
+                sort_values: Vec::new(),
+            });
+        }
+
+        Ok(results)
+    }
+
+    /// Match quality times a mix of popularity, confidence and recency, so
+    /// a better match wins unless the weaker one is far more prominent
+    fn score(&self, candidate: &EntityCandidate, max_mentions: usize, now: i64) -> f64 {
+        let popularity = if max_mentions == 0 {
+            0.0
+        } else {
+            (1.0 + candidate.mentions as f64).ln() / (1.0 + max_mentions as f64).ln()
+        };
+        let age_days = (now - candidate.last_seen).max(0) as f64 / 86_400.0;
+        let recency = 0.5f64.powf(age_days / self.recency_half_life_days);
+
+        candidate.entity_match.weight()
+            * (0.55 + 0.2 * popularity + 0.15 * candidate.confidence + 0.1 * recency)
+    }
+
+    /// Entities whose name matches, or whose property values contain
+    /// every query word. An empty query matches every entity.
+    fn matching_entities(
+        &self,
+        db: &Connection,
+        text: &str,
+        entity_types: Option<&[String]>,
+    ) -> Result<Vec<EntityCandidate>> {
+        let mut sql = "SELECT id, entity_type, name, properties, created_at, confidence FROM entities WHERE 1 = 1".to_string();
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+
+        if let Some(entity_types) = entity_types.filter(|types| !types.is_empty()) {
+            let placeholders = entity_types.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            sql.push_str(&format!(" AND entity_type IN ({})", placeholders));
+            for entity_type in entity_types {
+                params.push(Box::new(entity_type.clone()));
+            }
+        }
+
+        let query_words: Vec<String> = normalize(text).unicode_words().map(str::to_string).collect();
+        push_candidate_filter(&mut sql, &mut params, &query_words);
+
+        let mut stmt = db.prepare(&sql)?;
+        let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<f64>>(5)?,
+            ))
+        })?;
+
+        let mut candidates = Vec::new();
+        for row in rows {
+            let (id, entity_type, name, properties, created_at, confidence) = row?;
+
+            let entity_match = if query_words.is_empty() {
+                Some(EntityMatch::Exact)
+            } else {
+                EntityMatch::of_name(&name, text).or_else(|| {
+                    // Cheap check on the raw JSON before parsing it
+                    let raw = normalize(&properties);
+                    if !query_words.iter().all(|word| raw.contains(word.as_str())) {
+                        return None;
+                    }
+                    let values = normalize(&property_text(&serde_json::from_str(&properties).ok()?));
+                    query_words
+                        .iter()
+                        .all(|word| values.contains(word.as_str()))
+                        .then_some(EntityMatch::Property)
+                })
+            };
+
+            if let Some(entity_match) = entity_match {
+                candidates.push(EntityCandidate {
+                    id,
+                    name,
+                    entity_type,
+                    properties: serde_json::from_str(&properties).unwrap_or_default(),
+                    entity_match,
+                    // Entities added by hand carry no confidence
+                    confidence: confidence.unwrap_or(1.0).clamp(0.0, 1.0),
+                    mentions: 0,
+                    last_seen: created_at,
+                    score: 0.0,
+                });
+            }
+        }
+
+        Ok(candidates)
+    }
+
+    /// Mention counts and the ingestion time of the latest mentioning document
+    fn load_statistics(&self, db: &Connection, candidates: &mut [EntityCandidate]) -> Result<()> {
+        let mut statistics: HashMap<String, (usize, Option<i64>)> = HashMap::new();
+        let ids: Vec<&str> = candidates.iter().map(|candidate| candidate.id.as_str()).collect();
+
+        for batch in ids.chunks(ID_BATCH_SIZE) {
+            let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let sql = format!(
+                "SELECT m.entity_id, COUNT(*), MAX(d.ingested_at)
+                 FROM entity_mentions m
+                 LEFT JOIN documents d ON d.id = m.document_id
+                 WHERE m.entity_id IN ({})
+                 GROUP BY m.entity_id",
+                placeholders
+            );
+
+            let mut stmt = db.prepare(&sql)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(batch.iter()), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, Option<i64>>(2)?))
+            })?;
+            for row in rows {
+                let (entity_id, mentions, last_mentioned) = row?;
+                statistics.insert(entity_id, (mentions as usize, last_mentioned));
+            }
+        }
+
+        for candidate in candidates {
+            if let Some((mentions, last_mentioned)) = statistics.get(&candidate.id) {
+                candidate.mentions = *mentions;
+                candidate.last_seen = candidate.last_seen.max(last_mentioned.unwrap_or(0));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mentioning_documents(&self, db: &Connection, entity_id: &str) -> Result<Vec<MentioningDocument>> {
+        let mut stmt = db.prepare(
+            "SELECT d.id, d.title, COUNT(*) AS mentions
+             FROM entity_mentions m
+             JOIN documents d ON d.id = m.document_id
+             WHERE m.entity_id = ?1
+             GROUP BY d.id
+             ORDER BY mentions DESC, d.ingested_at DESC
+             LIMIT ?2",
+        )?;
+
+        let documents = stmt
+            .query_map(rusqlite::params![entity_id, self.documents_per_entity as i64], |row| {
+                Ok(MentioningDocument {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    mentions: row.get::<_, i64>(2)? as usize,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(documents)
+    }
+}
+
+/// Narrow the entities to those that could match every query word by
+/// name or property, so only they are matched in Rust. A word within the
+/// edit limit of a name word shares one of its limit + 1 pieces with it.
+/// LIKE only ignores ASCII case, so rows with other characters are kept.
+fn push_candidate_filter(sql: &mut String, params: &mut Vec<Box<dyn rusqlite::ToSql>>, query_words: &[String]) {
+    for word in query_words {
+        let chars: Vec<char> = word.chars().collect();
+        let piece_count = (max_edit_distance(word) + 1).min(chars.len()).max(1);
+
+        let mut alternatives = vec![
+            "name GLOB '*[^ -~]*'".to_string(),
+            "properties GLOB '*[^ -~]*'".to_string(),
+            "properties LIKE ? ESCAPE '\\'".to_string(),
+        ];
+        params.push(Box::new(format!("%{}%", escape_like(word))));
+
+        for piece in 0..piece_count {
+            let start = piece * chars.len() / piece_count;
+            let end = (piece + 1) * chars.len() / piece_count;
+            let text: String = chars[start..end].iter().collect();
+            alternatives.push("name LIKE ? ESCAPE '\\'".to_string());
+            params.push(Box::new(format!("%{}%", escape_like(&text))));
+        }
+
+        sql.push_str(&format!(" AND ({})", alternatives.join(" OR ")));
+    }
+}
+
+fn normalize(text: &str) -> String {
+    fold(&text.to_lowercase())
+}
+
+/// String and number values of a JSON document, keys left out
+fn property_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Number(number) => number.to_string(),
+        serde_json::Value::Array(values) => values.iter().map(property_text).collect::<Vec<_>>().join(" "),
+        serde_json::Value::Object(map) => map.values().map(property_text).collect::<Vec<_>>().join(" "),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_db() -> Connection {
+        let now = Utc::now().timestamp();
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(&format!(
+            "CREATE TABLE documents (id TEXT PRIMARY KEY, title TEXT NOT NULL, ingested_at INTEGER NOT NULL);
+             CREATE TABLE entities (
+                 id TEXT PRIMARY KEY, entity_type TEXT NOT NULL, name TEXT NOT NULL,
+                 properties TEXT NOT NULL DEFAULT '{{}}', created_at INTEGER NOT NULL, confidence REAL
+             );
+             CREATE TABLE entity_mentions (id TEXT PRIMARY KEY, entity_id TEXT NOT NULL, document_id TEXT NOT NULL);
+             INSERT INTO documents VALUES ('d1', 'Kickoff notes', {now});
+             INSERT INTO documents VALUES ('d2', 'Contract draft', {now});
+             INSERT INTO entities VALUES ('e1', 'person', 'Ann Smith', '{{\"email\": \"ann@example.com\"}}', {now}, 0.9);
+             INSERT INTO entities VALUES ('e2', 'person', 'Smith', '{{}}', {now}, 0.9);
+             INSERT INTO entities VALUES ('e3', 'organization', 'Smithson Labs', '{{}}', {now}, NULL);
+             INSERT INTO entities VALUES ('e4', 'organization', 'Acme', '{{\"domain\": \"acme.io\"}}', {now}, 0.5);
+             INSERT INTO entity_mentions VALUES ('m1', 'e1', 'd1');
+             INSERT INTO entity_mentions VALUES ('m2', 'e1', 'd2');
+             INSERT INTO entity_mentions VALUES ('m3', 'e1', 'd2');",
+        )).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_name_match_quality() {
+        assert_eq!(EntityMatch::of_name("Smith", "smith"), Some(EntityMatch::Exact));
+        assert_eq!(EntityMatch::of_name("Smithson Labs", "Smith"), Some(EntityMatch::Prefix));
+        assert_eq!(EntityMatch::of_name("Ann Smith", "smith ann"), Some(EntityMatch::Token));
+        assert_eq!(EntityMatch::of_name("Zoë Müller", "muller"), Some(EntityMatch::Token));
+        assert_eq!(EntityMatch::of_name("Anderson", "derso"), Some(EntityMatch::Substring));
+        assert_eq!(EntityMatch::of_name("Kubernetes", "kubernets"), Some(EntityMatch::Fuzzy { distance: 1 }));
+        assert_eq!(EntityMatch::of_name("Acme", "zebra"), None);
+    }
+
+    #[test]
+    fn test_entities_ranked_by_match_quality() {
+        let db = create_test_db();
+        let searcher = EntitySearcher::new();
+
+        let results = searcher.search(&db, "smith", None, 0, 10).unwrap();
+        let ids: Vec<&str> = results.iter().map(|r| r.id.as_str()).collect();
+        // The often mentioned token match overtakes the unmentioned prefix match
+        assert_eq!(ids, vec!["e2", "e1", "e3"]);
+        assert!(results.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        let types = vec!["organization".to_string()];
+        let results = searcher.search(&db, "smith", Some(&types), 0, 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "e3");
+    }
+
+    #[test]
+    fn test_sql_narrowing_keeps_fuzzy_and_accented_matches() {
+        let db = create_test_db();
+        let now = Utc::now().timestamp();
+        db.execute_batch(&format!(
+            "INSERT INTO entities VALUES ('e5', 'technology', 'Kubernetes', '{{}}', {now}, 0.8);
+             INSERT INTO entities VALUES ('e6', 'person', 'Zoë Müller', '{{}}', {now}, 0.8);
+             INSERT INTO entities VALUES ('e7', 'organization', '100% Labs', '{{}}', {now}, 0.8);",
+        )).unwrap();
+        let searcher = EntitySearcher::new();
+
+        let ids = |query: &str| -> Vec<String> {
+            searcher.search(&db, query, None, 0, 10).unwrap().into_iter().map(|r| r.id).collect()
+        };
+        assert_eq!(ids("kubernets"), vec!["e5"]);
+        assert_eq!(ids("ubernetes"), vec!["e5"]);
+        assert_eq!(ids("muller"), vec!["e6"]);
+        assert_eq!(ids("100%"), vec!["e7"]);
+        assert!(ids("zebra").is_empty());
+    }
+
+    #[test]
+    fn test_property_matches_and_mentioning_documents() {
+        let db = create_test_db();
+        let searcher = EntitySearcher::new();
+
+        let results = searcher.search(&db, "acme.io", None, 0, 10).unwrap();
+        assert_eq!(results[0].id, "e4");
+        assert_eq!(results[0].metadata["match"], "property");
+
+        // Keys are not searched
+        assert!(searcher.search(&db, "email", None, 0, 10).unwrap().is_empty());
+
+        let results = searcher.search(&db, "ann smith", None, 0, 10).unwrap();
+        assert_eq!(results[0].metadata["mention_count"], 3);
+        let documents = results[0].metadata["documents"].as_array().unwrap();
+        assert_eq!(documents[0]["id"], "d2");
+        assert_eq!(documents[0]["mentions"], 2);
+        assert_eq!(documents.len(), 2);
+    }
+}
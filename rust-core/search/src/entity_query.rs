@@ -0,0 +1,466 @@
+use std::collections::{HashMap, HashSet};
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::{Serialize, Deserialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::analyzer::fold;
+use crate::entities::EntityMatch;
+use crate::suggest::escape_like;
+
+// Keep well below SQLite's bound parameter limit
+const ID_BATCH_SIZE: usize = 500;
+
+/// Query text with its entity references taken out. A reference is
+/// `entity:word` or `entity:"several words"`, naming an entity or giving
+/// its id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntityQuery {
+    pub text: String,
+    pub references: Vec<String>,
+}
+
+impl EntityQuery {
+    pub fn parse(text: &str) -> Self {
+        let mut parsed = EntityQuery::default();
+        let mut rest = text;
+
+        while let Some(start) = rest.find("entity:") {
+            // Only at the start of a word, so "nonentity:" is left alone
+            let at_word_start = rest[..start].chars().next_back().is_none_or(char::is_whitespace);
+            let after = &rest[start + "entity:".len()..];
+            if !at_word_start {
+                parsed.text.push_str(&rest[..start + "entity:".len()]);
+                rest = after;
+                continue;
+            }
+
+            parsed.text.push_str(&rest[..start]);
+            let (reference, remainder) = match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted.find('"').unwrap_or(quoted.len());
+                    (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+                }
+                None => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            if !reference.trim().is_empty() {
+                parsed.references.push(reference.trim().to_string());
+            }
+            rest = remainder;
+        }
+        parsed.text.push_str(rest);
+        parsed.text = parsed.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        parsed
+    }
+
+    pub fn has_references(&self) -> bool {
+        !self.references.is_empty()
+    }
+}
+
+/// Whether and how far to follow relationships from referenced entities
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntityExpansion {
+    #[serde(default = "default_max_hops")]
+    pub max_hops: usize,
+    #[serde(default = "default_decay")]
+    pub decay: f64, // weight kept per hop, times the relationship's strength
+    #[serde(default)]
+    pub entity_types: Option<Vec<String>>, // only expand to entities of these types
+    #[serde(default)]
+    pub relationship_types: Option<Vec<String>>,
+    #[serde(default = "default_max_entities")]
+    pub max_entities: usize, // related entities kept per reference, heaviest first
+    #[serde(default)]
+    pub co_mentions: bool, // also follow entities mentioned in the same documents
+}
+
+fn default_max_hops() -> usize {
+    1
+}
+
+fn default_decay() -> f64 {
+    0.5
+}
+
+fn default_max_entities() -> usize {
+    50
+}
+
+impl Default for EntityExpansion {
+    fn default() -> Self {
+        Self {
+            max_hops: default_max_hops(),
+            decay: default_decay(),
+            entity_types: None,
+            relationship_types: None,
+            max_entities: default_max_entities(),
+            co_mentions: false,
+        }
+    }
+}
+
+/// An entity a reference resolved or expanded to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchedEntity {
+    pub id: String,
+    pub name: String,
+    pub entity_type: String,
+    pub weight: f64, // 1 for the referenced entity, decayed along relationships
+    pub hops: usize,
+}
+
+/// The entities one reference stands for. A document matches the
+/// reference by mentioning any of them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityGroup {
+    pub reference: String,
+    pub entities: Vec<MatchedEntity>,
+}
+
+impl EntityGroup {
+    pub fn entity_ids(&self) -> Vec<String> {
+        self.entities.iter().map(|entity| entity.id.clone()).collect()
+    }
+}
+
+/// Resolves entity references through `entities` and expands them along
+/// `relationships`
+#[derive(Debug, Clone)]
+pub struct EntityResolver;
+
+impl EntityResolver {
+    /// An id resolves to its entity. A name resolves to the entities it
+    /// matches best, as long as that is at least a token match, so
+    /// "Acme" finds "Acme Corp" but a misspelling finds nothing.
+    pub fn resolve(db: &Connection, reference: &str, expansion: Option<&EntityExpansion>) -> Result<EntityGroup> {
+        let mut stmt = db.prepare("SELECT id, name, entity_type FROM entities WHERE id = ?1")?;
+        let by_id: Option<MatchedEntity> = stmt
+            .query_map([reference], |row| Self::read_entity(row, 1.0, 0))?
+            .next()
+            .transpose()?;
+
+        let mut entities = match by_id {
+            Some(entity) => vec![entity],
+            None => Self::resolve_name(db, reference)?,
+        };
+        if let Some(expansion) = expansion {
+            let related = Self::expand(db, &entities, expansion)?;
+            entities.extend(related);
+        }
+
+        Ok(EntityGroup {
+            reference: reference.to_string(),
+            entities,
+        })
+    }
+
+    fn resolve_name(db: &Connection, reference: &str) -> Result<Vec<MatchedEntity>> {
+        // Every match this accepts has each word of the reference inside
+        // the name. LIKE only folds ASCII case, so names with other
+        // characters are always left to the matcher.
+        let mut sql = "SELECT id, name, entity_type FROM entities WHERE 1 = 1".to_string();
+        let words: Vec<String> = fold(&reference.to_lowercase())
+            .unicode_words()
+            .map(|word| format!("%{}%", escape_like(word)))
+            .collect();
+        for _ in &words {
+            sql.push_str(" AND (name GLOB '*[^ -~]*' OR name LIKE ? ESCAPE '\\')");
+        }
+
+        let mut stmt = db.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(words.iter()), |row| Self::read_entity(row, 1.0, 0))?;
+
+        let mut best_tier = None;
+        let mut entities = Vec::new();
+        for row in rows {
+            let entity = row?;
+            // Looser matches would let a typo stand for an unrelated entity
+            let tier = match EntityMatch::of_name(&entity.name, reference) {
+                Some(EntityMatch::Exact) => 0,
+                Some(EntityMatch::Prefix) => 1,
+                Some(EntityMatch::Token) => 2,
+                _ => continue,
+            };
+            if best_tier.is_some_and(|best| tier > best) {
+                continue;
+            }
+            if best_tier != Some(tier) {
+                best_tier = Some(tier);
+                entities.clear();
+            }
+            entities.push(entity);
+        }
+        entities.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(entities)
+    }
+
+    /// Entities reachable from the seeds in up to `max_hops` relationships,
+    /// either way round, or co-mentions when asked for. A co-mention's
+    /// strength is the share of the documents mentioning one entity that
+    /// also mention the other. Each keeps the heaviest path to it.
+    fn expand(db: &Connection, seeds: &[MatchedEntity], expansion: &EntityExpansion) -> Result<Vec<MatchedEntity>> {
+        let mut weights: HashMap<String, (f64, usize)> =
+            seeds.iter().map(|seed| (seed.id.clone(), (seed.weight, 0))).collect();
+        let seed_ids: HashSet<String> = weights.keys().cloned().collect();
+        let mut frontier: Vec<String> = seed_ids.iter().cloned().collect();
+
+        for hop in 1..=expansion.max_hops {
+            let mut next = HashSet::new();
+            for batch in frontier.chunks(ID_BATCH_SIZE) {
+                let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+                let mut sql = format!(
+                    "SELECT source_entity_id, target_entity_id, strength FROM relationships
+                     WHERE (source_entity_id IN ({0}) OR target_entity_id IN ({0}))",
+                    placeholders
+                );
+                let mut params: Vec<Box<dyn rusqlite::ToSql>> = batch
+                    .iter()
+                    .chain(batch.iter())
+                    .map(|id| Box::new(id.clone()) as Box<dyn rusqlite::ToSql>)
+                    .collect();
+                if let Some(types) = expansion.relationship_types.as_ref().filter(|types| !types.is_empty()) {
+                    sql.push_str(&format!(" AND relationship_type IN ({})", types.iter().map(|_| "?").collect::<Vec<_>>().join(",")));
+                    params.extend(types.iter().map(|t| Box::new(t.clone()) as Box<dyn rusqlite::ToSql>));
+                }
+
+                let mut stmt = db.prepare(&sql)?;
+                let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+                let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, f64>(2)?))
+                })?;
+
+                let frontier_ids: HashSet<&String> = batch.iter().collect();
+                let mut edges = Vec::new();
+                for row in rows {
+                    let (source, target, strength) = row?;
+                    // Follow the edge away from each frontier end
+                    if frontier_ids.contains(&source) {
+                        edges.push((source.clone(), target.clone(), strength));
+                    }
+                    if frontier_ids.contains(&target) {
+                        edges.push((target, source, strength));
+                    }
+                }
+                if expansion.co_mentions {
+                    edges.extend(Self::co_mentions(db, batch, &placeholders)?);
+                }
+
+                for (from, to, strength) in edges {
+                    let Some(&(from_weight, _)) = weights.get(&from) else { continue };
+                    let weight = from_weight * expansion.decay * strength.clamp(0.0, 1.0);
+                    if weight <= 0.0 {
+                        continue;
+                    }
+                    let known = weights.get(&to).map(|(known, _)| *known).unwrap_or(0.0);
+                    if weight > known {
+                        weights.insert(to.clone(), (weight, hop));
+                        next.insert(to);
+                    }
+                }
+            }
+            frontier = next.into_iter().collect();
+            if frontier.is_empty() {
+                break;
+            }
+        }
+
+        let related_ids: Vec<String> = weights.keys().filter(|id| !seed_ids.contains(*id)).cloned().collect();
+        let mut related = Vec::new();
+        for batch in related_ids.chunks(ID_BATCH_SIZE) {
+            let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let mut stmt = db.prepare(&format!("SELECT id, name, entity_type FROM entities WHERE id IN ({})", placeholders))?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(batch.iter()), |row| Self::read_entity(row, 0.0, 0))?;
+            for row in rows {
+                let mut entity = row?;
+                (entity.weight, entity.hops) = weights[&entity.id];
+                related.push(entity);
+            }
+        }
+
+        // Types only narrow what is added; paths may still run through others
+        if let Some(types) = expansion.entity_types.as_ref().filter(|types| !types.is_empty()) {
+            related.retain(|entity| types.contains(&entity.entity_type));
+        }
+        related.sort_by(|a, b| {
+            b.weight
+                .partial_cmp(&a.weight)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        related.truncate(expansion.max_entities);
+        Ok(related)
+    }
+
+    /// `(entity, co-mentioned entity, strength)` for each entity of `batch`
+    fn co_mentions(db: &Connection, batch: &[String], placeholders: &str) -> Result<Vec<(String, String, f64)>> {
+        let mut stmt = db.prepare(&format!(
+            "SELECT entity_id, COUNT(DISTINCT document_id) FROM entity_mentions
+             WHERE entity_id IN ({}) GROUP BY entity_id",
+            placeholders
+        ))?;
+        let mut documents: HashMap<String, f64> = HashMap::new();
+        for row in stmt.query_map(rusqlite::params_from_iter(batch.iter()), |row| Ok((row.get(0)?, row.get::<_, i64>(1)?)))? {
+            let (entity_id, count) = row?;
+            documents.insert(entity_id, count as f64);
+        }
+
+        let mut stmt = db.prepare(&format!(
+            "SELECT a.entity_id, b.entity_id, COUNT(DISTINCT a.document_id) FROM entity_mentions a
+             JOIN entity_mentions b ON b.document_id = a.document_id AND b.entity_id != a.entity_id
+             WHERE a.entity_id IN ({}) GROUP BY a.entity_id, b.entity_id",
+            placeholders
+        ))?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(batch.iter()), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+        })?;
+
+        let mut edges = Vec::new();
+        for row in rows {
+            let (from, to, shared) = row?;
+            if let Some(&total) = documents.get(&from) {
+                edges.push((from, to, shared as f64 / total));
+            }
+        }
+        Ok(edges)
+    }
+
+    fn read_entity(row: &rusqlite::Row<'_>, weight: f64, hops: usize) -> rusqlite::Result<MatchedEntity> {
+        Ok(MatchedEntity {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            entity_type: row.get(2)?,
+            weight,
+            hops,
+        })
+    }
+}
+
+/// How strongly each document matches the groups: per group the weight of
+/// the heaviest entity it mentions, summed. Documents missing a group are
+/// left out.
+pub fn document_entity_scores(mentions: &[(String, String)], groups: &[EntityGroup]) -> HashMap<String, f64> {
+    let mut mentioned: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for (document_id, entity_id) in mentions {
+        mentioned.entry(document_id.as_str()).or_default().insert(entity_id.as_str());
+    }
+
+    mentioned
+        .into_iter()
+        .filter_map(|(document_id, entity_ids)| {
+            let mut score = 0.0;
+            for group in groups {
+                score += group
+                    .entities
+                    .iter()
+                    .filter(|entity| entity_ids.contains(entity.id.as_str()))
+                    .map(|entity| entity.weight)
+                    .fold(None, |best: Option<f64>, weight| Some(best.map_or(weight, |best| best.max(weight))))?;
+            }
+            Some((document_id.to_string(), score))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_db() -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE entities (id TEXT PRIMARY KEY, entity_type TEXT NOT NULL, name TEXT NOT NULL);
+             CREATE TABLE relationships (
+                 id TEXT PRIMARY KEY, source_entity_id TEXT NOT NULL, target_entity_id TEXT NOT NULL,
+                 relationship_type TEXT NOT NULL, strength REAL NOT NULL DEFAULT 1.0
+             );
+             INSERT INTO entities VALUES ('acme', 'organization', 'Acme Corp');
+             INSERT INTO entities VALUES ('acme-labs', 'organization', 'Acme Labs');
+             INSERT INTO entities VALUES ('ann', 'person', 'Ann Smith');
+             INSERT INTO entities VALUES ('bob', 'person', 'Bob Jones');
+             INSERT INTO entities VALUES ('paris', 'location', 'Paris');
+             INSERT INTO entities VALUES ('jose', 'person', 'José Núñez');
+             INSERT INTO relationships VALUES ('r1', 'ann', 'acme', 'works_for', 1.0);
+             INSERT INTO relationships VALUES ('r2', 'acme', 'paris', 'located_in', 0.8);
+             INSERT INTO relationships VALUES ('r3', 'paris', 'bob', 'lives_in', 1.0);
+             CREATE TABLE entity_mentions (id TEXT PRIMARY KEY, entity_id TEXT NOT NULL, document_id TEXT NOT NULL);
+             INSERT INTO entity_mentions VALUES ('m1', 'acme', 'd1');
+             INSERT INTO entity_mentions VALUES ('m2', 'acme', 'd2');
+             INSERT INTO entity_mentions VALUES ('m3', 'acme', 'd3');
+             INSERT INTO entity_mentions VALUES ('m4', 'acme', 'd4');
+             INSERT INTO entity_mentions VALUES ('m5', 'ann', 'd1');
+             INSERT INTO entity_mentions VALUES ('m6', 'bob', 'd2');
+             INSERT INTO entity_mentions VALUES ('m7', 'bob', 'd3');",
+        ).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_parse_takes_out_references() {
+        let parsed = EntityQuery::parse(r#"budget entity:"Acme Corp" review entity:ann nonentity:x"#);
+        assert_eq!(parsed.references, vec!["Acme Corp", "ann"]);
+        assert_eq!(parsed.text, "budget review nonentity:x");
+        assert!(!EntityQuery::parse("entity:").has_references());
+    }
+
+    #[test]
+    fn test_resolve_by_id_or_best_name_match() {
+        let db = create_test_db();
+        let ids = |group: EntityGroup| group.entity_ids();
+
+        assert_eq!(ids(EntityResolver::resolve(&db, "ann", None).unwrap()), vec!["ann"]);
+        assert_eq!(ids(EntityResolver::resolve(&db, "acme corp", None).unwrap()), vec!["acme"]);
+        assert_eq!(ids(EntityResolver::resolve(&db, "Acme", None).unwrap()), vec!["acme", "acme-labs"]);
+        assert!(ids(EntityResolver::resolve(&db, "Acne", None).unwrap()).is_empty());
+        // Names SQL cannot fold are still matched without accents
+        assert_eq!(ids(EntityResolver::resolve(&db, "jose nunez", None).unwrap()), vec!["jose"]);
+        assert_eq!(ids(EntityResolver::resolve(&db, "100%", None).unwrap()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_expansion_decays_along_relationships() {
+        let db = create_test_db();
+        let two_hops = EntityExpansion { max_hops: 2, ..Default::default() };
+        let group = EntityResolver::resolve(&db, "acme corp", Some(&two_hops)).unwrap();
+        let weights: Vec<(&str, f64, usize)> =
+            group.entities.iter().map(|e| (e.id.as_str(), e.weight, e.hops)).collect();
+        assert_eq!(weights, vec![("acme", 1.0, 0), ("ann", 0.5, 1), ("paris", 0.4, 1), ("bob", 0.2, 2)]);
+
+        let people = EntityExpansion { max_hops: 2, entity_types: Some(vec!["person".to_string()]), ..Default::default() };
+        let group = EntityResolver::resolve(&db, "acme corp", Some(&people)).unwrap();
+        assert_eq!(group.entity_ids(), vec!["acme", "ann", "bob"]);
+
+        let mentions = vec![
+            ("d1".to_string(), "acme".to_string()),
+            ("d1".to_string(), "ann".to_string()),
+            ("d2".to_string(), "bob".to_string()),
+            ("d3".to_string(), "paris".to_string()),
+        ];
+        let scores = document_entity_scores(&mentions, &[group]);
+        assert_eq!(scores.get("d1"), Some(&1.0));
+        assert_eq!(scores.get("d2"), Some(&0.2));
+        assert_eq!(scores.get("d3"), None);
+    }
+
+    #[test]
+    fn test_expansion_follows_co_mentions() {
+        let db = create_test_db();
+        let people = || Some(vec!["person".to_string()]);
+
+        // Bob is only related through Paris, two hops away
+        let related = EntityExpansion { entity_types: people(), ..Default::default() };
+        let group = EntityResolver::resolve(&db, "acme corp", Some(&related)).unwrap();
+        assert_eq!(group.entity_ids(), vec!["acme", "ann"]);
+
+        // Bob shares half of Acme's documents, Ann a quarter but keeps her
+        // heavier relationship
+        let co_mentioned = EntityExpansion { entity_types: people(), co_mentions: true, ..Default::default() };
+        let group = EntityResolver::resolve(&db, "acme corp", Some(&co_mentioned)).unwrap();
+        let weights: Vec<(&str, f64, usize)> =
+            group.entities.iter().map(|e| (e.id.as_str(), e.weight, e.hops)).collect();
+        assert_eq!(weights, vec![("acme", 1.0, 0), ("ann", 0.5, 1), ("bob", 0.25, 1)]);
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use anyhow::{Result, Context};
+use rusqlite::{Connection, params};
+use serde::{Serialize, Deserialize};
+use tokio::sync::Mutex;
+
+use crate::{SearchEngine, SearchFilters, SearchOptions, SearchQuery};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorpusDocument {
+    pub id: String,
+    pub title: String,
+    pub content: String,
+    #[serde(default)]
+    pub metadata: serde_json::Value,
+}
+
+/// Documents to evaluate against, read from a JSON file of the form
+/// `{ "documents": [{ "id", "title", "content", "metadata"? }] }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvaluationCorpus {
+    pub documents: Vec<CorpusDocument>,
+}
+
+impl EvaluationCorpus {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path).with_context(|| format!("Reading corpus {}", path.display()))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// A search engine over an in-memory database holding the corpus
+    pub async fn search_engine(&self) -> Result<SearchEngine> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(
+            "CREATE TABLE documents (
+                id TEXT PRIMARY KEY,
+                source_type TEXT NOT NULL,
+                file_path TEXT NOT NULL UNIQUE,
+                content_hash TEXT NOT NULL,
+                ingested_at INTEGER NOT NULL,
+                modified_at INTEGER NOT NULL,
+                metadata TEXT NOT NULL DEFAULT '{}',
+                title TEXT NOT NULL,
+                content TEXT
+            );
+            CREATE VIRTUAL TABLE documents_fts USING fts5(title, content, content_id UNINDEXED);",
+        )?;
+
+        for document in &self.documents {
+            let metadata = if document.metadata.is_null() { serde_json::json!({}) } else { document.metadata.clone() };
+            conn.execute(
+                "INSERT INTO documents (id, source_type, file_path, content_hash, ingested_at, modified_at, metadata, title, content)
+                 VALUES (?1, 'file_system', ?1, '', 0, 0, ?2, ?3, ?4)",
+                params![document.id, metadata.to_string(), document.title, document.content],
+            )?;
+            conn.execute(
+                "INSERT INTO documents_fts (title, content, content_id) VALUES (?1, ?2, ?3)",
+                params![document.title, document.content, document.id],
+            )?;
+        }
+
+        let engine = SearchEngine::new(Arc::new(Mutex::new(conn)))?;
+        engine.initialize().await?;
+        Ok(engine)
+    }
+}
+
+/// Graded relevance of documents for one query; 0 or missing means not
+/// relevant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryJudgment {
+    pub query: String,
+    pub relevant: HashMap<String, u32>,
+}
+
+/// Judgments read from a JSON file of the form
+/// `{ "queries": [{ "query", "relevant": { "<document id>": grade } }] }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelevanceJudgments {
+    pub queries: Vec<QueryJudgment>,
+}
+
+impl RelevanceJudgments {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path).with_context(|| format!("Reading judgments {}", path.display()))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// Semantic search has no embeddings behind it yet, so it is not a mode
+/// until it can return results worth scoring
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    Keyword,
+    Fuzzy,
+}
+
+impl SearchMode {
+    pub fn all() -> Vec<SearchMode> {
+        vec![SearchMode::Keyword, SearchMode::Fuzzy]
+    }
+
+    fn options(&self, k: usize) -> SearchOptions {
+        SearchOptions {
+            limit: Some(k),
+            fuzzy_matching: *self == SearchMode::Fuzzy,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryReport {
+    pub query: String,
+    pub ndcg: f64,
+    pub reciprocal_rank: f64,
+    pub recall: f64,
+    pub latency_ms: f64,
+}
+
+/// Metrics of one search mode, averaged over the judged queries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModeReport {
+    pub mode: SearchMode,
+    pub ndcg: f64,
+    pub mrr: f64,
+    pub recall: f64,
+    #[serde(default)]
+    pub mean_latency_ms: f64,
+    #[serde(default)]
+    pub p95_latency_ms: f64,
+    #[serde(default)]
+    pub queries: Vec<QueryReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvaluationReport {
+    pub k: usize,
+    pub modes: Vec<ModeReport>,
+}
+
+impl EvaluationReport {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path).with_context(|| format!("Reading report {}", path.display()))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Quality metrics that fell more than `tolerance` below `baseline`.
+    /// Latency is left out, since it depends on the machine.
+    pub fn regressions(&self, baseline: &EvaluationReport, tolerance: f64) -> Vec<String> {
+        let mut regressions = Vec::new();
+
+        for expected in &baseline.modes {
+            let actual = match self.modes.iter().find(|report| report.mode == expected.mode) {
+                Some(actual) => actual,
+                None => {
+                    regressions.push(format!("{:?}: not evaluated", expected.mode));
+                    continue;
+                }
+            };
+
+            for (metric, value, floor) in [
+                ("nDCG", actual.ndcg, expected.ndcg),
+                ("MRR", actual.mrr, expected.mrr),
+                ("recall", actual.recall, expected.recall),
+            ] {
+                if value < floor - tolerance {
+                    regressions.push(format!("{:?} {}@{}: {:.3} < {:.3}", expected.mode, metric, self.k, value, floor));
+                }
+            }
+        }
+
+        regressions
+    }
+}
+
+/// Runs judged queries through each search mode and scores the rankings
+#[derive(Debug, Clone)]
+pub struct SearchEvaluator {
+    k: usize,
+    modes: Vec<SearchMode>,
+}
+
+impl Default for SearchEvaluator {
+    fn default() -> Self {
+        Self {
+            k: 10,
+            modes: SearchMode::all(),
+        }
+    }
+}
+
+impl SearchEvaluator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_k(&mut self, k: usize) {
+        self.k = k.max(1);
+    }
+
+    pub fn set_modes(&mut self, modes: Vec<SearchMode>) {
+        self.modes = modes;
+    }
+
+    pub async fn evaluate(&self, engine: &SearchEngine, judgments: &RelevanceJudgments) -> Result<EvaluationReport> {
+        let mut modes = Vec::with_capacity(self.modes.len());
+
+        for &mode in &self.modes {
+            let mut queries = Vec::with_capacity(judgments.queries.len());
+
+            for judgment in &judgments.queries {
+                let query = SearchQuery {
+                    text: judgment.query.clone(),
+                    filters: SearchFilters {
+                        entity_types: None,
+                        document_types: None,
+                        source_types: None,
+                        date_range: None,
+                        file_types: None,
+                        document_ids: None,
+                        mentions: None,
+                    },
+                    options: mode.options(self.k),
+                };
+
+                let started = Instant::now();
+                let response = engine.search(&query).await?;
+                let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+                let ranked: Vec<String> = response.results.into_iter().map(|result| result.id).collect();
+                queries.push(QueryReport {
+                    query: judgment.query.clone(),
+                    ndcg: ndcg_at(&ranked, &judgment.relevant, self.k),
+                    reciprocal_rank: reciprocal_rank(&ranked, &judgment.relevant),
+                    recall: recall_at(&ranked, &judgment.relevant, self.k),
+                    latency_ms,
+                });
+            }
+
+            let mean = |value: fn(&QueryReport) -> f64| {
+                if queries.is_empty() {
+                    0.0
+                } else {
+                    queries.iter().map(value).sum::<f64>() / queries.len() as f64
+                }
+            };
+            let mut latencies: Vec<f64> = queries.iter().map(|query| query.latency_ms).collect();
+            latencies.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+            modes.push(ModeReport {
+                mode,
+                ndcg: mean(|query| query.ndcg),
+                mrr: mean(|query| query.reciprocal_rank),
+                recall: mean(|query| query.recall),
+                mean_latency_ms: mean(|query| query.latency_ms),
+                p95_latency_ms: percentile(&latencies, 0.95),
+                queries,
+            });
+        }
+
+        Ok(EvaluationReport { k: self.k, modes })
+    }
+}
+
+/// Normalized discounted cumulative gain of the top `k` results, with
+/// gains of 2^grade - 1
+pub fn ndcg_at(ranked: &[String], relevant: &HashMap<String, u32>, k: usize) -> f64 {
+    let gain = |grade: u32| 2f64.powi(grade as i32) - 1.0;
+    let discount = |rank: usize| (rank as f64 + 2.0).log2();
+
+    let dcg: f64 = ranked
+        .iter()
+        .take(k)
+        .enumerate()
+        .map(|(rank, id)| gain(relevant.get(id).copied().unwrap_or(0)) / discount(rank))
+        .sum();
+
+    let mut grades: Vec<u32> = relevant.values().copied().filter(|&grade| grade > 0).collect();
+    grades.sort_unstable_by(|a, b| b.cmp(a));
+    let ideal: f64 = grades.iter().take(k).enumerate().map(|(rank, &grade)| gain(grade) / discount(rank)).sum();
+
+    if ideal > 0.0 { dcg / ideal } else { 0.0 }
+}
+
+/// 1 / rank of the first relevant result, 0 if none was returned
+pub fn reciprocal_rank(ranked: &[String], relevant: &HashMap<String, u32>) -> f64 {
+    ranked
+        .iter()
+        .position(|id| relevant.get(id).is_some_and(|&grade| grade > 0))
+        .map(|rank| 1.0 / (rank + 1) as f64)
+        .unwrap_or(0.0)
+}
+
+/// Share of the relevant documents found in the top `k` results
+pub fn recall_at(ranked: &[String], relevant: &HashMap<String, u32>, k: usize) -> f64 {
+    let total = relevant.values().filter(|&&grade| grade > 0).count();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let found = ranked
+        .iter()
+        .take(k)
+        .filter(|id| relevant.get(*id).is_some_and(|&grade| grade > 0))
+        .count();
+    found as f64 / total as f64
+}
+
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let index = ((sorted.len() as f64 * fraction).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn grades(grades: &[(&str, u32)]) -> HashMap<String, u32> {
+        grades.iter().map(|(id, grade)| (id.to_string(), *grade)).collect()
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("evaluation").join(name)
+    }
+
+    #[test]
+    fn test_metrics() {
+        let relevant = grades(&[("a", 3), ("b", 1), ("c", 0)]);
+
+        assert_eq!(ndcg_at(&ids(&["a", "b", "x"]), &relevant, 10), 1.0);
+        let swapped = ndcg_at(&ids(&["b", "a"]), &relevant, 10);
+        let expected = (1.0 + 7.0 / 3f64.log2()) / (7.0 + 1.0 / 3f64.log2());
+        assert!((swapped - expected).abs() < 1e-12);
+        assert_eq!(ndcg_at(&ids(&["x", "c"]), &relevant, 10), 0.0);
+
+        assert_eq!(reciprocal_rank(&ids(&["c", "x", "b"]), &relevant), 1.0 / 3.0);
+        assert_eq!(reciprocal_rank(&ids(&["x"]), &relevant), 0.0);
+
+        assert_eq!(recall_at(&ids(&["x", "b", "a"]), &relevant, 2), 0.5);
+        assert_eq!(recall_at(&ids(&["a", "b"]), &grades(&[]), 2), 0.0);
+    }
+
+    #[test]
+    fn test_regressions_compare_quality_only() {
+        let report = |ndcg: f64, latency: f64| EvaluationReport {
+            k: 10,
+            modes: vec![ModeReport {
+                mode: SearchMode::Keyword,
+                ndcg,
+                mrr: 1.0,
+                recall: 1.0,
+                mean_latency_ms: latency,
+                p95_latency_ms: latency,
+                queries: Vec::new(),
+            }],
+        };
+
+        assert!(report(0.795, 50.0).regressions(&report(0.8, 1.0), 0.01).is_empty());
+        assert_eq!(report(0.7, 1.0).regressions(&report(0.8, 1.0), 0.01).len(), 1);
+
+        let mut fuzzy = report(0.8, 1.0);
+        fuzzy.modes[0].mode = SearchMode::Fuzzy;
+        assert_eq!(report(0.8, 1.0).regressions(&fuzzy, 0.01), vec!["Fuzzy: not evaluated".to_string()]);
+    }
+
+    /// Fails when a ranking change makes the judged fixture queries worse
+    /// than `evaluation/baseline.json`. Set `UPDATE_SEARCH_BASELINE=1` to
+    /// record the current metrics as the new baseline.
+    #[tokio::test]
+    async fn test_fixture_corpus_meets_baseline() {
+        let corpus = EvaluationCorpus::load(&fixture("corpus.json")).unwrap();
+        let judgments = RelevanceJudgments::load(&fixture("judgments.json")).unwrap();
+        let engine = corpus.search_engine().await.unwrap();
+
+        let report = SearchEvaluator::new().evaluate(&engine, &judgments).await.unwrap();
+        assert_eq!(report.modes.len(), SearchMode::all().len());
+        assert!(report.modes.iter().all(|mode| mode.queries.len() == judgments.queries.len()));
+
+        if std::env::var_os("UPDATE_SEARCH_BASELINE").is_some() {
+            let mut baseline = report.clone();
+            for mode in &mut baseline.modes {
+                mode.queries.clear();
+                mode.mean_latency_ms = 0.0;
+                mode.p95_latency_ms = 0.0;
+            }
+            baseline.save(&fixture("baseline.json")).unwrap();
+        }
+
+        let baseline = EvaluationReport::load(&fixture("baseline.json")).unwrap();
+        let regressions = report.regressions(&baseline, 0.01);
+        assert!(regressions.is_empty(), "Search quality regressed: {:?}", regressions);
+    }
+}
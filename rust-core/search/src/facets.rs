@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use rusqlite::Connection;
+use serde::{Serialize, Deserialize};
+
+// Keep well below SQLite's bound parameter limit
+const ID_BATCH_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DateInterval {
+    Day,
+    Week,
+    #[default]
+    Month,
+    Year,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FacetBucket {
+    pub value: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DateBucket {
+    pub label: String,
+    pub start: i64, // Unix timestamp of the bucket start
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntityFacet {
+    pub entity_id: String,
+    pub name: String,
+    pub entity_type: String,
+    pub document_count: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchFacets {
+    pub source_types: Vec<FacetBucket>,
+    pub mime_types: Vec<FacetBucket>,
+    pub modified_dates: Vec<DateBucket>,
+    pub entity_types: Vec<FacetBucket>,
+    pub top_entities: Vec<EntityFacet>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FacetCollector {
+    top_entities_limit: usize,
+}
+
+impl Default for FacetCollector {
+    fn default() -> Self {
+        Self {
+            top_entities_limit: 10,
+        }
+    }
+}
+
+impl FacetCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count facet buckets over the given set of matching documents.
+    /// Every bucket counts documents, so a document mentioning two people
+    /// only adds one to the "person" entity type bucket.
+    pub fn collect(
+        &self,
+        db: &Connection,
+        document_ids: &[String],
+        date_interval: DateInterval,
+    ) -> Result<SearchFacets> {
+        let mut source_counts: HashMap<String, usize> = HashMap::new();
+        let mut mime_counts: HashMap<String, usize> = HashMap::new();
+        let mut date_counts: HashMap<i64, usize> = HashMap::new();
+        let mut entity_type_docs: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut entity_counts: HashMap<String, EntityFacet> = HashMap::new();
+
+        for batch in document_ids.chunks(ID_BATCH_SIZE) {
+            let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+
+            let sql = format!(
+                "SELECT source_type, json_extract(metadata, '$.mime_type'), modified_at
+                 FROM documents WHERE id IN ({})",
+                placeholders
+            );
+            let mut stmt = db.prepare(&sql)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(batch.iter()), |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<i64>>(2)?,
+                ))
+            })?;
+
+            for row in rows {
+                let (source_type, mime_type, modified_at) = row?;
+                *source_counts.entry(source_type.unwrap_or_else(|| "unknown".to_string())).or_insert(0) += 1;
+                *mime_counts.entry(mime_type.unwrap_or_else(|| "unknown".to_string())).or_insert(0) += 1;
+                if let Some(timestamp) = modified_at {
+                    if let Some(bucket_start) = Self::bucket_start(timestamp, date_interval) {
+                        *date_counts.entry(bucket_start).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            let sql = format!(
+                "SELECT DISTINCT m.document_id, e.id, e.entity_type, e.name
+                 FROM entity_mentions m
+                 JOIN entities e ON e.id = m.entity_id
+                 WHERE m.document_id IN ({})",
+                placeholders
+            );
+            let mut stmt = db.prepare(&sql)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(batch.iter()), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })?;
+
+            for row in rows {
+                let (document_id, entity_id, entity_type, name) = row?;
+                entity_type_docs
+                    .entry(entity_type.clone())
+                    .or_default()
+                    .insert(document_id);
+
+                entity_counts
+                    .entry(entity_id.clone())
+                    .or_insert_with(|| EntityFacet {
+                        entity_id,
+                        name,
+                        entity_type,
+                        document_count: 0,
+                    })
+                    .document_count += 1;
+            }
+        }
+
+        let mut modified_dates: Vec<DateBucket> = date_counts
+            .into_iter()
+            .map(|(start, count)| DateBucket {
+                label: Self::bucket_label(start, date_interval),
+                start,
+                count,
+            })
+            .collect();
+        modified_dates.sort_by_key(|bucket| bucket.start);
+
+        let mut top_entities: Vec<EntityFacet> = entity_counts.into_values().collect();
+        top_entities.sort_by(|a, b| {
+            b.document_count.cmp(&a.document_count).then_with(|| a.name.cmp(&b.name))
+        });
+        top_entities.truncate(self.top_entities_limit);
+
+        Ok(SearchFacets {
+            source_types: Self::sorted_buckets(source_counts),
+            mime_types: Self::sorted_buckets(mime_counts),
+            modified_dates,
+            entity_types: Self::sorted_buckets(
+                entity_type_docs
+                    .into_iter()
+                    .map(|(entity_type, docs)| (entity_type, docs.len()))
+                    .collect(),
+            ),
+            top_entities,
+        })
+    }
+
+    fn sorted_buckets(counts: HashMap<String, usize>) -> Vec<FacetBucket> {
+        let mut buckets: Vec<FacetBucket> = counts
+            .into_iter()
+            .map(|(value, count)| FacetBucket { value, count })
+            .collect();
+        // Highest count first, ties broken alphabetically for stable output
+        buckets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        buckets
+    }
+
+    fn bucket_start(timestamp: i64, interval: DateInterval) -> Option<i64> {
+        let date = DateTime::from_timestamp(timestamp, 0)?.date_naive();
+
+        let start = match interval {
+            DateInterval::Day => date,
+            DateInterval::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            DateInterval::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1)?,
+            DateInterval::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1)?,
+        };
+
+        Some(start.and_hms_opt(0, 0, 0)?.and_utc().timestamp())
+    }
+
+    fn bucket_label(start: i64, interval: DateInterval) -> String {
+        let date = match DateTime::<Utc>::from_timestamp(start, 0) {
+            Some(date) => date,
+            None => return start.to_string(),
+        };
+
+        match interval {
+            DateInterval::Day | DateInterval::Week => date.format("%Y-%m-%d").to_string(),
+            DateInterval::Month => date.format("%Y-%m").to_string(),
+            DateInterval::Year => date.format("%Y").to_string(),
+        }
+    }
+
+    pub fn set_top_entities_limit(&mut self, limit: usize) {
+        self.top_entities_limit = limit;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE documents (
+                id TEXT PRIMARY KEY,
+                source_type TEXT,
+                modified_at INTEGER,
+                metadata TEXT
+            );
+            CREATE TABLE entities (
+                id TEXT PRIMARY KEY,
+                entity_type TEXT,
+                name TEXT
+            );
+            CREATE TABLE entity_mentions (
+                id TEXT PRIMARY KEY,
+                entity_id TEXT,
+                document_id TEXT
+            );
+            INSERT INTO documents VALUES ('1', 'file_system', 1704067200, '{\"mime_type\": \"text/plain\"}');
+            INSERT INTO documents VALUES ('2', 'file_system', 1706745600, '{\"mime_type\": \"application/pdf\"}');
+            INSERT INTO documents VALUES ('3', 'email', 1706832000, '{\"mime_type\": \"text/plain\"}');
+            INSERT INTO entities VALUES ('e1', 'person', 'Alice');
+            INSERT INTO entities VALUES ('e2', 'person', 'Bob');
+            INSERT INTO entities VALUES ('e3', 'organization', 'Acme');
+            INSERT INTO entity_mentions VALUES ('m1', 'e1', '1');
+            INSERT INTO entity_mentions VALUES ('m2', 'e2', '1');
+            INSERT INTO entity_mentions VALUES ('m3', 'e1', '2');
+            INSERT INTO entity_mentions VALUES ('m4', 'e3', '3');",
+        ).unwrap();
+        conn
+    }
+
+    fn ids(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_document_facets() {
+        let conn = create_test_database();
+        let collector = FacetCollector::new();
+        let facets = collector.collect(&conn, &ids(&["1", "2", "3"]), DateInterval::Month).unwrap();
+
+        assert_eq!(facets.source_types[0].value, "file_system");
+        assert_eq!(facets.source_types[0].count, 2);
+        assert_eq!(facets.mime_types[0].value, "text/plain");
+        assert_eq!(facets.mime_types[0].count, 2);
+
+        // January 2024 and February 2024
+        assert_eq!(facets.modified_dates.len(), 2);
+        assert_eq!(facets.modified_dates[0].label, "2024-01");
+        assert_eq!(facets.modified_dates[1].count, 2);
+    }
+
+    #[test]
+    fn test_entity_facets_count_documents() {
+        let conn = create_test_database();
+        let collector = FacetCollector::new();
+        let facets = collector.collect(&conn, &ids(&["1", "2"]), DateInterval::Year).unwrap();
+
+        // Document 1 mentions two people but is only counted once
+        assert_eq!(facets.entity_types.len(), 1);
+        assert_eq!(facets.entity_types[0].value, "person");
+        assert_eq!(facets.entity_types[0].count, 2);
+
+        assert_eq!(facets.top_entities[0].name, "Alice");
+        assert_eq!(facets.top_entities[0].document_count, 2);
+    }
+
+    #[test]
+    fn test_empty_match_set() {
+        let conn = create_test_database();
+        let facets = FacetCollector::new().collect(&conn, &[], DateInterval::Day).unwrap();
+
+        assert!(facets.source_types.is_empty());
+        assert!(facets.top_entities.is_empty());
+    }
+}
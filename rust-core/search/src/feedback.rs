@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+use anyhow::{Result, anyhow};
+use rusqlite::{Connection, params};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::ranker::{PersonalizationEngine, RankingFeatures, RankingWeights};
+
+// Learned weights never drop a signal entirely
+const MIN_WEIGHT: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeedbackAction {
+    Shown,
+    Clicked,
+    Opened,
+    Dismissed,
+}
+
+impl FeedbackAction {
+    /// How strongly the action says the result was wanted
+    pub fn reward(&self) -> f64 {
+        match self {
+            FeedbackAction::Shown => 0.0,
+            FeedbackAction::Clicked => 0.5,
+            FeedbackAction::Opened => 1.0,
+            FeedbackAction::Dismissed => -1.0,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FeedbackAction::Shown => "shown",
+            FeedbackAction::Clicked => "clicked",
+            FeedbackAction::Opened => "opened",
+            FeedbackAction::Dismissed => "dismissed",
+        }
+    }
+
+    fn parse(action: &str) -> Result<Self> {
+        match action {
+            "shown" => Ok(FeedbackAction::Shown),
+            "clicked" => Ok(FeedbackAction::Clicked),
+            "opened" => Ok(FeedbackAction::Opened),
+            "dismissed" => Ok(FeedbackAction::Dismissed),
+            _ => Err(anyhow!("Unknown feedback action {}", action)),
+        }
+    }
+}
+
+/// What a user did with one search result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedbackEvent {
+    pub profile_id: String,
+    pub query: String,
+    pub document_id: String,
+    pub position: usize, // rank the result was shown at, from 0
+    pub action: FeedbackAction,
+    pub features: RankingFeatures, // ranking signals of the result when it was acted on
+    pub timestamp: i64,
+}
+
+/// Ranking state learned for one user profile
+#[derive(Debug, Clone, Default)]
+pub struct RankingProfile {
+    pub weights: Option<RankingWeights>, // the ranker's defaults until trained
+    pub personalization: PersonalizationEngine,
+}
+
+/// Feedback events and ranking profiles, kept in the `search_feedback` and
+/// `search_profiles` tables. Both are created with the first write.
+pub struct FeedbackStore;
+
+impl FeedbackStore {
+    fn create_tables(db: &Connection) -> Result<()> {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS search_feedback (
+                id TEXT PRIMARY KEY,
+                profile_id TEXT NOT NULL,
+                query TEXT NOT NULL,
+                document_id TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                features TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_search_feedback_profile ON search_feedback(profile_id);
+            CREATE TABLE IF NOT EXISTS search_profiles (
+                profile_id TEXT PRIMARY KEY,
+                weights TEXT,
+                personalization TEXT NOT NULL,
+                updated_at INTEGER NOT NULL
+            );",
+        )?;
+        Ok(())
+    }
+
+    fn table_exists(db: &Connection, table: &str) -> Result<bool> {
+        Ok(db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+            params![table],
+            |row| row.get(0),
+        )?)
+    }
+
+    pub fn record(db: &Connection, event: &FeedbackEvent) -> Result<()> {
+        Self::create_tables(db)?;
+        db.execute(
+            "INSERT INTO search_feedback (id, profile_id, query, document_id, position, action, features, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                Uuid::new_v4().to_string(),
+                event.profile_id,
+                event.query,
+                event.document_id,
+                event.position as i64,
+                event.action.as_str(),
+                serde_json::to_string(&event.features)?,
+                event.timestamp,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Events of a profile, oldest first
+    pub fn events(db: &Connection, profile_id: &str) -> Result<Vec<FeedbackEvent>> {
+        if !Self::table_exists(db, "search_feedback")? {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = db.prepare(
+            "SELECT query, document_id, position, action, features, created_at
+             FROM search_feedback WHERE profile_id = ?1 ORDER BY created_at, rowid",
+        )?;
+        let rows = stmt.query_map(params![profile_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, i64>(5)?,
+            ))
+        })?;
+
+        let mut events = Vec::new();
+        for row in rows {
+            let (query, document_id, position, action, features, timestamp) = row?;
+            events.push(FeedbackEvent {
+                profile_id: profile_id.to_string(),
+                query,
+                document_id,
+                position: position.max(0) as usize,
+                action: FeedbackAction::parse(&action)?,
+                features: serde_json::from_str(&features)?,
+                timestamp,
+            });
+        }
+
+        Ok(events)
+    }
+
+    /// The stored profile, or an empty one for a profile never seen
+    pub fn load_profile(db: &Connection, profile_id: &str) -> Result<RankingProfile> {
+        if !Self::table_exists(db, "search_profiles")? {
+            return Ok(RankingProfile::default());
+        }
+
+        let mut stmt = db.prepare("SELECT weights, personalization FROM search_profiles WHERE profile_id = ?1")?;
+        let mut rows = stmt.query(params![profile_id])?;
+
+        match rows.next()? {
+            Some(row) => {
+                let weights: Option<String> = row.get(0)?;
+                let personalization: String = row.get(1)?;
+                Ok(RankingProfile {
+                    weights: weights.map(|weights| serde_json::from_str(&weights)).transpose()?,
+                    personalization: serde_json::from_str(&personalization)?,
+                })
+            }
+            None => Ok(RankingProfile::default()),
+        }
+    }
+
+    pub fn save_profile(db: &Connection, profile_id: &str, profile: &RankingProfile) -> Result<()> {
+        Self::create_tables(db)?;
+        db.execute(
+            "INSERT OR REPLACE INTO search_profiles (profile_id, weights, personalization, updated_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                profile_id,
+                profile.weights.map(|weights| serde_json::to_string(&weights)).transpose()?,
+                serde_json::to_string(&profile.personalization)?,
+                chrono::Utc::now().timestamp(),
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+/// A result of a logged search with the strongest signal it received
+#[derive(Debug, Clone)]
+pub struct Judgment {
+    pub document_id: String,
+    pub features: RankingFeatures,
+    pub position: usize,
+    pub reward: f64,
+}
+
+/// The logged results of one query of one profile
+#[derive(Debug, Clone)]
+pub struct FeedbackSession {
+    pub query: String,
+    pub judgments: Vec<Judgment>,
+    pub last_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationScores {
+    pub pairs: usize,
+    pub pairwise_accuracy: f64, // share of preferred results ranked above less preferred ones
+    pub mrr: f64,               // mean reciprocal rank of the first wanted result
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackEvaluation {
+    pub training_sessions: usize,
+    pub evaluation_sessions: usize,
+    pub baseline: EvaluationScores,
+    pub tuned: EvaluationScores,
+    pub weights: RankingWeights,
+    pub applied: bool, // false when the tuned weights did worse on held-out sessions
+}
+
+/// Learns ranking weights from pairs of results where one got a stronger
+/// signal than the other (opened over clicked over ignored over
+/// dismissed), and checks them offline against the most recent sessions
+/// before they are used.
+#[derive(Debug, Clone)]
+pub struct WeightTrainer {
+    learning_rate: f64,
+    epochs: usize,
+    holdout_fraction: f64,
+    min_holdout_sessions: usize, // below this many sessions every session is used for both
+}
+
+impl Default for WeightTrainer {
+    fn default() -> Self {
+        Self {
+            learning_rate: 0.1,
+            epochs: 50,
+            holdout_fraction: 0.2,
+            min_holdout_sessions: 5,
+        }
+    }
+}
+
+impl WeightTrainer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Group events by query. A result's reward is its strongest action;
+    /// being shown only counts when nothing else happened.
+    pub fn sessions(&self, events: &[FeedbackEvent]) -> Vec<FeedbackSession> {
+        let mut sessions: Vec<FeedbackSession> = Vec::new();
+        let mut by_query: HashMap<String, usize> = HashMap::new();
+
+        for event in events {
+            let key = event.query.trim().to_lowercase();
+            let index = *by_query.entry(key).or_insert_with(|| {
+                sessions.push(FeedbackSession {
+                    query: event.query.clone(),
+                    judgments: Vec::new(),
+                    last_at: event.timestamp,
+                });
+                sessions.len() - 1
+            });
+            let session = &mut sessions[index];
+            session.last_at = session.last_at.max(event.timestamp);
+
+            let acted = event.action != FeedbackAction::Shown;
+            match session.judgments.iter_mut().find(|judgment| judgment.document_id == event.document_id) {
+                Some(judgment) => {
+                    judgment.position = judgment.position.min(event.position);
+                    judgment.features = event.features;
+                    if acted && (judgment.reward == 0.0 || event.action.reward() > judgment.reward) {
+                        judgment.reward = event.action.reward();
+                    }
+                }
+                None => session.judgments.push(Judgment {
+                    document_id: event.document_id.clone(),
+                    features: event.features,
+                    position: event.position,
+                    reward: event.action.reward(),
+                }),
+            }
+        }
+
+        sessions.sort_by_key(|session| session.last_at);
+        sessions
+    }
+
+    /// How well `weights` order the logged results
+    pub fn evaluate(&self, weights: &RankingWeights, sessions: &[FeedbackSession]) -> EvaluationScores {
+        let mut pairs = 0;
+        let mut correct = 0.0;
+        let mut reciprocal_ranks = Vec::new();
+
+        for session in sessions {
+            let scores: Vec<f64> = session.judgments.iter().map(|judgment| weights.score(&judgment.features)).collect();
+
+            for (i, preferred) in session.judgments.iter().enumerate() {
+                for (j, other) in session.judgments.iter().enumerate() {
+                    if preferred.reward > other.reward {
+                        pairs += 1;
+                        if scores[i] > scores[j] {
+                            correct += 1.0;
+                        } else if scores[i] == scores[j] {
+                            correct += 0.5;
+                        }
+                    }
+                }
+            }
+
+            // Ties keep the order the results were shown in
+            let mut order: Vec<usize> = (0..session.judgments.len()).collect();
+            order.sort_by(|&a, &b| {
+                scores[b]
+                    .partial_cmp(&scores[a])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then_with(|| session.judgments[a].position.cmp(&session.judgments[b].position))
+            });
+            if let Some(rank) = order.iter().position(|&i| session.judgments[i].reward > 0.0) {
+                reciprocal_ranks.push(1.0 / (rank + 1) as f64);
+            }
+        }
+
+        EvaluationScores {
+            pairs,
+            pairwise_accuracy: if pairs > 0 { correct / pairs as f64 } else { 0.0 },
+            mrr: if reciprocal_ranks.is_empty() {
+                0.0
+            } else {
+                reciprocal_ranks.iter().sum::<f64>() / reciprocal_ranks.len() as f64
+            },
+        }
+    }
+
+    /// Tune `baseline` on older sessions and compare both on the newest
+    /// ones. None when the feedback holds no preference between results.
+    pub fn train(&self, baseline: &RankingWeights, events: &[FeedbackEvent]) -> Option<FeedbackEvaluation> {
+        let sessions = self.sessions(events);
+
+        let holdout = if sessions.len() >= self.min_holdout_sessions {
+            ((sessions.len() as f64 * self.holdout_fraction).ceil() as usize).max(1)
+        } else {
+            0
+        };
+        let (training, evaluation) = if holdout > 0 {
+            sessions.split_at(sessions.len() - holdout)
+        } else {
+            (sessions.as_slice(), sessions.as_slice())
+        };
+
+        let differences: Vec<[f64; 3]> = training
+            .iter()
+            .flat_map(|session| {
+                session.judgments.iter().flat_map(move |preferred| {
+                    session
+                        .judgments
+                        .iter()
+                        .filter(move |other| preferred.reward > other.reward)
+                        .map(move |other| {
+                            [
+                                preferred.features.freshness - other.features.freshness,
+                                preferred.features.relevance - other.features.relevance,
+                                preferred.features.popularity - other.features.popularity,
+                            ]
+                        })
+                })
+            })
+            .collect();
+        if differences.is_empty() {
+            return None;
+        }
+
+        // Pairwise logistic regression: push each preferred result's score
+        // above the other's
+        let mut w = [baseline.freshness, baseline.relevance, baseline.popularity];
+        for _ in 0..self.epochs {
+            for difference in &differences {
+                let margin: f64 = w.iter().zip(difference).map(|(weight, d)| weight * d).sum();
+                let gradient = 1.0 / (1.0 + margin.exp());
+                for (weight, d) in w.iter_mut().zip(difference) {
+                    *weight = (*weight + self.learning_rate * gradient * d).max(MIN_WEIGHT);
+                }
+            }
+        }
+
+        let total: f64 = w.iter().sum();
+        let weights = RankingWeights {
+            freshness: w[0] / total,
+            relevance: w[1] / total,
+            popularity: w[2] / total,
+        };
+
+        let baseline_scores = self.evaluate(baseline, evaluation);
+        let tuned = self.evaluate(&weights, evaluation);
+
+        Some(FeedbackEvaluation {
+            training_sessions: training.len(),
+            evaluation_sessions: evaluation.len(),
+            applied: tuned.pairwise_accuracy >= baseline_scores.pairwise_accuracy && tuned.mrr >= baseline_scores.mrr,
+            baseline: baseline_scores,
+            tuned,
+            weights,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(freshness: f64, relevance: f64) -> RankingFeatures {
+        RankingFeatures {
+            freshness,
+            relevance,
+            popularity: 0.0,
+        }
+    }
+
+    fn event(query: &str, document_id: &str, position: usize, action: FeedbackAction, features: RankingFeatures, timestamp: i64) -> FeedbackEvent {
+        FeedbackEvent {
+            profile_id: "user".to_string(),
+            query: query.to_string(),
+            document_id: document_id.to_string(),
+            position,
+            action,
+            features,
+            timestamp,
+        }
+    }
+
+    /// Queries where the user always opens the newest result, which the
+    /// default weights rank below a better textual match
+    fn fresh_preferring_events(queries: usize) -> Vec<FeedbackEvent> {
+        let mut events = Vec::new();
+        for i in 0..queries {
+            let query = format!("query {}", i);
+            let t = i as i64 * 10;
+            events.push(event(&query, "match", 0, FeedbackAction::Shown, features(0.0, 1.0), t));
+            events.push(event(&query, "fresh", 1, FeedbackAction::Shown, features(1.0, 0.6), t));
+            events.push(event(&query, "fresh", 1, FeedbackAction::Opened, features(1.0, 0.6), t + 1));
+            events.push(event(&query, "match", 0, FeedbackAction::Dismissed, features(0.0, 1.0), t + 2));
+        }
+        events
+    }
+
+    #[test]
+    fn test_feedback_and_profiles_persist() {
+        let db = Connection::open_in_memory().unwrap();
+        assert!(FeedbackStore::events(&db, "user").unwrap().is_empty());
+        assert!(FeedbackStore::load_profile(&db, "user").unwrap().weights.is_none());
+
+        let events = fresh_preferring_events(2);
+        for event in &events {
+            FeedbackStore::record(&db, event).unwrap();
+        }
+        assert_eq!(FeedbackStore::events(&db, "user").unwrap(), events);
+        assert!(FeedbackStore::events(&db, "someone else").unwrap().is_empty());
+
+        let mut profile = RankingProfile {
+            weights: Some(RankingWeights { freshness: 0.5, relevance: 0.4, popularity: 0.1 }),
+            ..Default::default()
+        };
+        profile.personalization.update_preferences("application/pdf", 1.0);
+        profile.personalization.add_to_search_history("budget");
+        FeedbackStore::save_profile(&db, "user", &profile).unwrap();
+
+        let loaded = FeedbackStore::load_profile(&db, "user").unwrap();
+        assert_eq!(loaded.weights, profile.weights);
+        assert_eq!(loaded.personalization.preferences(), profile.personalization.preferences());
+        assert_eq!(loaded.personalization.search_history(), ["budget".to_string()]);
+    }
+
+    #[test]
+    fn test_sessions_keep_strongest_signal() {
+        let trainer = WeightTrainer::new();
+        let sessions = trainer.sessions(&fresh_preferring_events(1));
+
+        assert_eq!(sessions.len(), 1);
+        let rewards: Vec<(&str, f64)> = sessions[0]
+            .judgments
+            .iter()
+            .map(|judgment| (judgment.document_id.as_str(), judgment.reward))
+            .collect();
+        assert_eq!(rewards, vec![("match", -1.0), ("fresh", 1.0)]);
+    }
+
+    #[test]
+    fn test_training_learns_from_clicks() {
+        let trainer = WeightTrainer::new();
+        let baseline = RankingWeights { freshness: 0.2, relevance: 0.6, popularity: 0.2 };
+        let events = fresh_preferring_events(10);
+
+        let sessions = trainer.sessions(&events);
+        assert_eq!(trainer.evaluate(&baseline, &sessions).pairwise_accuracy, 0.0);
+
+        let evaluation = trainer.train(&baseline, &events).unwrap();
+        assert_eq!(evaluation.training_sessions, 8);
+        assert_eq!(evaluation.evaluation_sessions, 2);
+        assert!(evaluation.applied);
+        assert_eq!(evaluation.tuned.pairwise_accuracy, 1.0);
+        assert_eq!(evaluation.tuned.mrr, 1.0);
+        assert!(evaluation.weights.freshness > baseline.freshness);
+        let total = evaluation.weights.freshness + evaluation.weights.relevance + evaluation.weights.popularity;
+        assert!((total - 1.0).abs() < 1e-9);
+
+        // Only impressions, so nothing to learn from
+        let shown: Vec<FeedbackEvent> = events.into_iter().filter(|e| e.action == FeedbackAction::Shown).collect();
+        assert!(trainer.train(&baseline, &shown).is_none());
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/// Maximum number of edits allowed for a query term. Short terms get no
+/// slack at all, since one edit already turns them into unrelated words.
+pub fn max_edit_distance(term: &str) -> usize {
+    match term.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+/// Levenshtein automaton for a single term. States are rows of the edit
+/// distance matrix, so stepping through a dictionary in sorted order can
+/// reuse the rows of shared prefixes and abandon a prefix as soon as no
+/// extension of it can come within `max_distance`.
+#[derive(Debug, Clone)]
+pub struct LevenshteinAutomaton {
+    term: Vec<char>,
+    max_distance: usize,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(term: &str, max_distance: usize) -> Self {
+        Self {
+            term: term.chars().collect(),
+            max_distance,
+        }
+    }
+
+    pub fn start(&self) -> Vec<usize> {
+        (0..=self.term.len()).collect()
+    }
+
+    pub fn step(&self, state: &[usize], c: char) -> Vec<usize> {
+        let mut next = Vec::with_capacity(state.len());
+        next.push(state[0] + 1);
+
+        for (i, &term_char) in self.term.iter().enumerate() {
+            let substitution = state[i] + usize::from(term_char != c);
+            let insertion = next[i] + 1;
+            let deletion = state[i + 1] + 1;
+            next.push(substitution.min(insertion).min(deletion));
+        }
+
+        next
+    }
+
+    /// Edit distance of the input so far, if it is within the limit
+    pub fn distance(&self, state: &[usize]) -> Option<usize> {
+        state.last().copied().filter(|&distance| distance <= self.max_distance)
+    }
+
+    /// Whether any continuation of the input so far can still match
+    pub fn can_match(&self, state: &[usize]) -> bool {
+        state.iter().any(|&distance| distance <= self.max_distance)
+    }
+
+    /// Every dictionary term within the edit limit, with its distance.
+    /// Runs of terms sharing a dead prefix are skipped with a single seek.
+    pub fn intersect<V>(&self, dictionary: &BTreeMap<String, V>) -> Vec<(String, usize)> {
+        let mut matches = Vec::new();
+        let mut rows = vec![self.start()];
+        let mut prefix: Vec<char> = Vec::new();
+        let mut lower: Bound<String> = Bound::Unbounded;
+
+        while let Some((term, _)) = dictionary.range::<String, _>((lower.clone(), Bound::Unbounded)).next() {
+            let chars: Vec<char> = term.chars().collect();
+            let common = prefix.iter().zip(chars.iter()).take_while(|(a, b)| a == b).count();
+            rows.truncate(common + 1);
+            prefix.truncate(common);
+
+            let mut dead = false;
+            for &c in &chars[common..] {
+                let row = self.step(&rows[rows.len() - 1], c);
+                dead = !self.can_match(&row);
+                rows.push(row);
+                prefix.push(c);
+                if dead {
+                    break;
+                }
+            }
+
+            if dead {
+                lower = match prefix_successor(&prefix) {
+                    Some(successor) => Bound::Included(successor),
+                    None => break,
+                };
+            } else {
+                if let Some(distance) = self.distance(&rows[rows.len() - 1]) {
+                    matches.push((term.clone(), distance));
+                }
+                lower = Bound::Excluded(term.clone());
+            }
+        }
+
+        matches
+    }
+}
+
+/// Smallest string greater than every string starting with `prefix`
+fn prefix_successor(prefix: &[char]) -> Option<String> {
+    let mut chars = prefix.to_vec();
+
+    while let Some(last) = chars.pop() {
+        let next = match last {
+            '\u{D7FF}' => Some('\u{E000}'),
+            _ => char::from_u32(last as u32 + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, Clone)]
+pub struct FuzzyExpansion {
+    pub term: String,
+    pub distance: usize,
+}
+
+/// Expands query terms against the index vocabulary
+#[derive(Debug, Clone)]
+pub struct FuzzyExpander {
+    max_expansions: usize,
+}
+
+impl Default for FuzzyExpander {
+    fn default() -> Self {
+        Self {
+            max_expansions: 50,
+        }
+    }
+}
+
+impl FuzzyExpander {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Dictionary terms within the edit limit for `term`, closest first and
+    /// then by document frequency, capped at the expansion limit
+    pub fn expand(&self, term: &str, vocabulary: &BTreeMap<String, usize>) -> Vec<FuzzyExpansion> {
+        let automaton = LevenshteinAutomaton::new(term, max_edit_distance(term));
+
+        let mut matches = automaton.intersect(vocabulary);
+        matches.sort_by(|(a, a_distance), (b, b_distance)| {
+            a_distance
+                .cmp(b_distance)
+                .then_with(|| vocabulary[b].cmp(&vocabulary[a]))
+                .then_with(|| a.cmp(b))
+        });
+        matches.truncate(self.max_expansions);
+
+        matches
+            .into_iter()
+            .map(|(term, distance)| FuzzyExpansion { term, distance })
+            .collect()
+    }
+
+    pub fn set_max_expansions(&mut self, max_expansions: usize) {
+        self.max_expansions = max_expansions.max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vocabulary(terms: &[(&str, usize)]) -> BTreeMap<String, usize> {
+        terms.iter().map(|(term, df)| (term.to_string(), *df)).collect()
+    }
+
+    #[test]
+    fn test_automaton_matches_edit_distance() {
+        let automaton = LevenshteinAutomaton::new("budget", 2);
+        let dictionary = vocabulary(&[
+            ("budge", 1),
+            ("budget", 1),
+            ("budgets", 1),
+            ("bugdet", 1),
+            ("gadget", 1),
+            ("widget", 1),
+            ("zebra", 1),
+        ]);
+
+        let matches = automaton.intersect(&dictionary);
+        assert_eq!(
+            matches,
+            vec![
+                ("budge".to_string(), 1),
+                ("budget".to_string(), 0),
+                ("budgets".to_string(), 1),
+                ("bugdet".to_string(), 2),
+                ("gadget".to_string(), 2),
+                ("widget".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_edit_distance_by_length() {
+        assert_eq!(max_edit_distance("an"), 0);
+        assert_eq!(max_edit_distance("rview"), 1);
+        assert_eq!(max_edit_distance("reviw"), 1);
+        assert_eq!(max_edit_distance("quarterly"), 2);
+    }
+
+    #[test]
+    fn test_expansions_prefer_close_and_frequent_terms() {
+        let dictionary = vocabulary(&[("review", 3), ("reviews", 9), ("revie", 1), ("preview", 5)]);
+        let mut expander = FuzzyExpander::new();
+        expander.set_max_expansions(3);
+
+        let terms: Vec<String> = expander
+            .expand("reviw", &dictionary)
+            .into_iter()
+            .map(|expansion| expansion.term)
+            .collect();
+        assert_eq!(terms, vec!["review", "revie"]);
+
+        let terms: Vec<String> = expander
+            .expand("review", &dictionary)
+            .into_iter()
+            .map(|expansion| expansion.term)
+            .collect();
+        assert_eq!(terms, vec!["review", "reviews", "preview"]);
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(prefix_successor(&['a', 'b']), Some("ac".to_string()));
+        assert_eq!(prefix_successor(&['a', char::MAX]), Some("b".to_string()));
+        assert_eq!(prefix_successor(&[char::MAX]), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,236 @@
+use anyhow::Result;
+use rusqlite::{Connection, params};
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchHistoryEntry {
+    pub query: String,
+    pub profile_id: Option<String>,
+    pub result_count: usize, // total hits, not just the page returned
+    pub latency_ms: f64,
+    pub searched_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryCount {
+    pub query: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchAnalytics {
+    pub total_searches: usize,
+    pub distinct_queries: usize,
+    pub mean_latency_ms: f64,
+    pub top_queries: Vec<QueryCount>,
+    pub zero_result_queries: Vec<QueryCount>,
+    pub slowest_queries: Vec<SearchHistoryEntry>,
+}
+
+/// Searches as they were run, kept in the `search_history` table, which is
+/// created with the first search. Queries are grouped for reports by their
+/// trimmed, lowercased text, so "Budget " and "budget" count together.
+pub struct SearchHistory;
+
+impl SearchHistory {
+    fn create_table(db: &Connection) -> Result<()> {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS search_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                query TEXT NOT NULL,
+                normalized_query TEXT NOT NULL,
+                profile_id TEXT,
+                result_count INTEGER NOT NULL,
+                latency_ms REAL NOT NULL,
+                searched_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_search_history_searched_at ON search_history(searched_at);",
+        )?;
+        Ok(())
+    }
+
+    fn table_exists(db: &Connection) -> Result<bool> {
+        Ok(db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'search_history')",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn normalize(query: &str) -> String {
+        query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+    }
+
+    pub fn record(db: &Connection, entry: &SearchHistoryEntry) -> Result<()> {
+        Self::create_table(db)?;
+        db.execute(
+            "INSERT INTO search_history (query, normalized_query, profile_id, result_count, latency_ms, searched_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                entry.query,
+                Self::normalize(&entry.query),
+                entry.profile_id,
+                entry.result_count as i64,
+                entry.latency_ms,
+                entry.searched_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Most recent searches first, of one profile or of everyone
+    pub fn list(db: &Connection, profile_id: Option<&str>, limit: usize) -> Result<Vec<SearchHistoryEntry>> {
+        if !Self::table_exists(db)? {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = db.prepare(
+            "SELECT query, profile_id, result_count, latency_ms, searched_at FROM search_history
+             WHERE ?1 IS NULL OR profile_id = ?1
+             ORDER BY searched_at DESC, id DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![profile_id, limit as i64], Self::read_entry)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Delete the history of one profile, or all of it. Returns how many
+    /// searches were removed.
+    pub fn clear(db: &Connection, profile_id: Option<&str>) -> Result<usize> {
+        if !Self::table_exists(db)? {
+            return Ok(0);
+        }
+
+        Ok(db.execute("DELETE FROM search_history WHERE ?1 IS NULL OR profile_id = ?1", params![profile_id])?)
+    }
+
+    /// Aggregates over the whole history, with up to `limit` queries per list
+    pub fn analytics(db: &Connection, limit: usize) -> Result<SearchAnalytics> {
+        let mut analytics = SearchAnalytics {
+            total_searches: 0,
+            distinct_queries: 0,
+            mean_latency_ms: 0.0,
+            top_queries: Vec::new(),
+            zero_result_queries: Vec::new(),
+            slowest_queries: Vec::new(),
+        };
+        if !Self::table_exists(db)? {
+            return Ok(analytics);
+        }
+
+        let (total, distinct, mean_latency): (i64, i64, Option<f64>) = db.query_row(
+            "SELECT COUNT(*), COUNT(DISTINCT normalized_query), AVG(latency_ms) FROM search_history",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        analytics.total_searches = total as usize;
+        analytics.distinct_queries = distinct as usize;
+        analytics.mean_latency_ms = mean_latency.unwrap_or(0.0);
+
+        analytics.top_queries = Self::query_counts(db, "", limit)?;
+        analytics.zero_result_queries = Self::query_counts(db, "WHERE result_count = 0", limit)?;
+
+        let mut stmt = db.prepare(
+            "SELECT query, profile_id, result_count, latency_ms, searched_at FROM search_history
+             ORDER BY latency_ms DESC, id DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit as i64], Self::read_entry)?;
+        analytics.slowest_queries = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(analytics)
+    }
+
+    /// Most frequent queries, each shown as last typed
+    fn query_counts(db: &Connection, condition: &str, limit: usize) -> Result<Vec<QueryCount>> {
+        let sql = format!(
+            "SELECT (SELECT h2.query FROM search_history h2 WHERE h2.normalized_query = h.normalized_query
+                     ORDER BY h2.searched_at DESC, h2.id DESC LIMIT 1),
+                    COUNT(*) AS count
+             FROM search_history h {}
+             GROUP BY h.normalized_query
+             ORDER BY count DESC, h.normalized_query
+             LIMIT ?1",
+            condition
+        );
+        let mut stmt = db.prepare(&sql)?;
+        let rows = stmt.query_map(params![limit as i64], |row| {
+            Ok(QueryCount {
+                query: row.get(0)?,
+                count: row.get::<_, i64>(1)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    fn read_entry(row: &rusqlite::Row) -> rusqlite::Result<SearchHistoryEntry> {
+        Ok(SearchHistoryEntry {
+            query: row.get(0)?,
+            profile_id: row.get(1)?,
+            result_count: row.get::<_, i64>(2)? as usize,
+            latency_ms: row.get(3)?,
+            searched_at: row.get(4)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(query: &str, profile_id: Option<&str>, result_count: usize, latency_ms: f64, searched_at: i64) -> SearchHistoryEntry {
+        SearchHistoryEntry {
+            query: query.to_string(),
+            profile_id: profile_id.map(|id| id.to_string()),
+            result_count,
+            latency_ms,
+            searched_at,
+        }
+    }
+
+    fn history(db: &Connection) {
+        for entry in [
+            entry("budget", Some("ana"), 4, 2.0, 100),
+            entry("Budget ", Some("ben"), 4, 3.0, 200),
+            entry("budget", None, 5, 1.0, 300),
+            entry("zebra", Some("ana"), 0, 9.0, 400),
+            entry("lisbon trip", Some("ana"), 2, 4.0, 500),
+        ] {
+            SearchHistory::record(db, &entry).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_history_is_listed_newest_first_and_cleared() {
+        let db = Connection::open_in_memory().unwrap();
+        assert!(SearchHistory::list(&db, None, 10).unwrap().is_empty());
+        assert_eq!(SearchHistory::clear(&db, None).unwrap(), 0);
+        history(&db);
+
+        let all = SearchHistory::list(&db, None, 2).unwrap();
+        assert_eq!(all, vec![entry("lisbon trip", Some("ana"), 2, 4.0, 500), entry("zebra", Some("ana"), 0, 9.0, 400)]);
+        let queries: Vec<String> = SearchHistory::list(&db, Some("ana"), 10).unwrap().into_iter().map(|e| e.query).collect();
+        assert_eq!(queries, vec!["lisbon trip", "zebra", "budget"]);
+
+        assert_eq!(SearchHistory::clear(&db, Some("ana")).unwrap(), 3);
+        assert_eq!(SearchHistory::list(&db, None, 10).unwrap().len(), 2);
+        assert_eq!(SearchHistory::clear(&db, None).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_analytics() {
+        let db = Connection::open_in_memory().unwrap();
+        assert_eq!(SearchHistory::analytics(&db, 5).unwrap().total_searches, 0);
+        history(&db);
+
+        let analytics = SearchHistory::analytics(&db, 2).unwrap();
+        assert_eq!(analytics.total_searches, 5);
+        assert_eq!(analytics.distinct_queries, 3);
+        assert!((analytics.mean_latency_ms - 3.8).abs() < 1e-9);
+        assert_eq!(analytics.top_queries, vec![
+            QueryCount { query: "budget".to_string(), count: 3 },
+            QueryCount { query: "lisbon trip".to_string(), count: 1 },
+        ]);
+        assert_eq!(analytics.zero_result_queries, vec![QueryCount { query: "zebra".to_string(), count: 1 }]);
+        let slowest: Vec<&str> = analytics.slowest_queries.iter().map(|e| e.query.as_str()).collect();
+        assert_eq!(slowest, vec!["zebra", "lisbon trip"]);
+    }
+}
@@ -1,25 +1,254 @@
-use std::collections::HashMap;
-use anyhow::Result;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use rusqlite::{Connection, params};
 use serde::{Serialize, Deserialize};
+use tracing::{info, warn, debug};
 
-use crate::{IndexedDocument, SearchResult};
+use crate::IndexedDocument;
+use crate::query::{ParsedQuery, TermAlternative};
+use crate::segment::{DocumentCopy, MemorySegment, Segment};
+
+const SEGMENT_EXTENSION: &str = "seg";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bm25Parameters {
+    pub k1: f64,
+    pub b: f64,
+    pub title_weight: f64,
+    pub content_weight: f64,
+}
+
+impl Default for Bm25Parameters {
+    fn default() -> Self {
+        Self {
+            k1: 1.2,
+            b: 0.75,
+            title_weight: 2.0,
+            content_weight: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TermPosting {
+    pub title_positions: Vec<u32>,
+    pub content_positions: Vec<u32>,
+}
+
+impl TermPosting {
+    pub fn title_count(&self) -> u32 {
+        self.title_positions.len() as u32
+    }
+
+    pub fn content_count(&self) -> u32 {
+        self.content_positions.len() as u32
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocumentStats {
+    pub title_length: usize,
+    pub content_length: usize,
+    pub terms: Vec<String>, // distinct terms, used to remove the document again
+}
 
 #[derive(Debug, Clone)]
+pub struct MergePolicy {
+    pub max_segments: usize,     // merge once there are more segments than this
+    pub merge_factor: usize,     // number of smallest segments merged at a time
+    pub max_deleted_ratio: f64,  // rewrite segments with more tombstones than this
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        Self {
+            max_segments: 8,
+            merge_factor: 4,
+            max_deleted_ratio: 0.5,
+        }
+    }
+}
+
+/// A segment file written by `prepare_commit` and recorded in the caller's
+/// transaction, waiting for that transaction to commit
+#[derive(Debug)]
+pub struct PendingCommit {
+    segment: Option<(String, PathBuf)>,
+}
+
+enum DocumentLocation {
+    Buffer,
+    Segment(usize, u32),
+}
+
+/// How documents were indexed before a change, see `FullTextIndexer::checkpoint`
+pub struct IndexCheckpoint {
+    buffered: Vec<(String, Option<DocumentCopy>)>, // earlier buffered versions
+    tombstones: usize, // pending tombstones before the change
+}
+
+/// Full-text index made of immutable memory-mapped segments plus an
+/// in-memory buffer for recently indexed documents. Without a storage
+/// directory everything stays in the buffer.
+#[derive(Debug)]
 pub struct FullTextIndexer {
-    term_frequencies: HashMap<String, HashMap<String, f64>>, // term -> doc_id -> frequency
-    document_frequencies: HashMap<String, usize>, // term -> number of documents containing term
-    document_count: usize,
+    buffer: MemorySegment,
+    segments: Vec<Segment>,
+    vocabulary: BTreeMap<String, usize>, // term -> number of live documents containing term
+    total_title_length: usize,
+    total_content_length: usize,
+    directory: Option<PathBuf>,
+    next_generation: i64,
+    pending_tombstones: Vec<(String, String)>, // (segment id, document id) not yet in the manifest
+    surfaces: HashMap<String, HashMap<String, usize>>, // term -> word -> occurrences indexed as the term
+    pending_surfaces: HashMap<(String, String), i64>, // (term, word) count changes not yet in the manifest
+    merge_policy: MergePolicy,
+    flush_threshold: usize,
 }
 
 impl FullTextIndexer {
     pub fn new() -> Result<Self> {
         Ok(Self {
-            term_frequencies: HashMap::new(),
-            document_frequencies: HashMap::new(),
-            document_count: 0,
+            buffer: MemorySegment::new(),
+            segments: Vec::new(),
+            vocabulary: BTreeMap::new(),
+            total_title_length: 0,
+            total_content_length: 0,
+            directory: None,
+            next_generation: 1,
+            pending_tombstones: Vec::new(),
+            surfaces: HashMap::new(),
+            pending_surfaces: HashMap::new(),
+            merge_policy: MergePolicy::default(),
+            flush_threshold: 1000,
         })
     }
 
+    /// Open the persisted index in `directory`. The list of live segments and
+    /// their tombstones is read from the manifest tables in `db`; segment
+    /// files are only mapped, never parsed in full.
+    pub fn open(directory: &Path, db: &Connection) -> Result<Self> {
+        fs::create_dir_all(directory)?;
+        Self::create_manifest_tables(db)?;
+
+        let mut indexer = Self::new()?;
+        indexer.directory = Some(directory.to_path_buf());
+
+        let mut stmt = db.prepare("SELECT id, generation FROM search_index_segments ORDER BY generation")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+        for row in rows {
+            let (id, generation) = row?;
+            let path = Self::segment_path(directory, &id);
+            indexer.segments.push(Segment::open(&id, &path)?);
+            indexer.next_generation = indexer.next_generation.max(generation + 1);
+        }
+
+        let mut stmt = db.prepare("SELECT segment_id, document_id FROM search_index_tombstones")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (segment_id, document_id) = row?;
+            if let Some(segment) = indexer.segments.iter_mut().find(|s| s.id() == segment_id) {
+                if let Some(ordinal) = segment.find_document(&document_id) {
+                    segment.delete(ordinal);
+                }
+            }
+        }
+
+        let mut stmt = db.prepare("SELECT term, surface, count FROM search_index_surfaces")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?)))?;
+        for row in rows {
+            let (term, surface, count) = row?;
+            indexer.surfaces.entry(term).or_default().insert(surface, count.max(0) as usize);
+        }
+
+        // Files missing from the manifest belong to commits whose
+        // transaction never made it into SQLite
+        let live: HashSet<String> = indexer.segments.iter().map(|s| s.id().to_string()).collect();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+            if (extension == SEGMENT_EXTENSION || extension == "tmp") && !live.contains(&stem) {
+                warn!("Removing orphaned index segment {}", path.display());
+                let _ = fs::remove_file(&path);
+            }
+        }
+
+        // Collection statistics come from the segment headers and term
+        // tables, minus whatever the tombstones removed
+        for segment in &indexer.segments {
+            let (title_length, content_length) = segment.total_lengths();
+            indexer.total_title_length += title_length;
+            indexer.total_content_length += content_length;
+
+            for ordinal in 0..segment.term_count() {
+                *indexer.vocabulary.entry(segment.term(ordinal).to_string()).or_insert(0) +=
+                    segment.term_doc_freq(ordinal) as usize;
+            }
+
+            for ordinal in segment.deleted_documents() {
+                let (title_length, content_length) = segment.document_lengths(ordinal);
+                indexer.total_title_length -= title_length;
+                indexer.total_content_length -= content_length;
+                forget_terms(&mut indexer.vocabulary, segment.document_terms(ordinal));
+            }
+        }
+
+        info!(
+            "Opened search index with {} segments and {} documents",
+            indexer.segments.len(),
+            indexer.document_count()
+        );
+        Ok(indexer)
+    }
+
+    fn create_manifest_tables(db: &Connection) -> Result<()> {
+        db.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS search_index_segments (
+                id TEXT PRIMARY KEY,
+                generation INTEGER NOT NULL,
+                document_count INTEGER NOT NULL,
+                created_at INTEGER NOT NULL
+            )
+            "#,
+            [],
+        )?;
+
+        db.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS search_index_tombstones (
+                segment_id TEXT NOT NULL,
+                document_id TEXT NOT NULL,
+                PRIMARY KEY (segment_id, document_id)
+            )
+            "#,
+            [],
+        )?;
+
+        db.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS search_index_surfaces (
+                term TEXT NOT NULL,
+                surface TEXT NOT NULL,
+                count INTEGER NOT NULL,
+                PRIMARY KEY (term, surface)
+            )
+            "#,
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    fn segment_path(directory: &Path, id: &str) -> PathBuf {
+        directory.join(format!("{}.{}", id, SEGMENT_EXTENSION))
+    }
+
     pub async fn initialize(&self) -> Result<()> {
         // Initialize indexer - placeholder for now
         Ok(())
@@ -29,107 +258,764 @@ impl FullTextIndexer {
         // Remove existing document if it exists
         self.remove_document(&document.id).await?;
 
-        // Calculate term frequencies for this document
-        let mut term_counts = HashMap::new();
-        let mut total_terms = 0;
+        let stats = self.buffer.add_document(document);
+        remember_terms(&mut self.vocabulary, stats.terms.iter().map(String::as_str));
+        self.total_title_length += stats.title_length;
+        self.total_content_length += stats.content_length;
+
+        Ok(())
+    }
+
+    pub async fn remove_document(&mut self, document_id: &str) -> Result<()> {
+        self.remove_buffered(document_id);
+
+        // Segments are immutable, so older versions are tombstoned
+        for segment in self.segments.iter_mut() {
+            if let Some(ordinal) = segment.live_document(document_id) {
+                segment.delete(ordinal);
+
+                let (title_length, content_length) = segment.document_lengths(ordinal);
+                self.total_title_length -= title_length;
+                self.total_content_length -= content_length;
+                forget_terms(&mut self.vocabulary, segment.document_terms(ordinal));
 
-        for token in &document.tokens {
-            *term_counts.entry(token.clone()).or_insert(0) += 1;
-            total_terms += 1;
+                self.pending_tombstones.push((segment.id().to_string(), document_id.to_string()));
+            }
         }
 
-        // Calculate TF (term frequency) for each term
-        for (term, count) in term_counts {
-            let tf = count as f64 / total_terms as f64;
-            
-            // Update term frequencies
-            self.term_frequencies
-                .entry(term.clone())
-                .or_insert_with(HashMap::new)
-                .insert(document.id.clone(), tf);
+        Ok(())
+    }
+
+    fn remove_buffered(&mut self, document_id: &str) {
+        if let Some(stats) = self.buffer.remove_document(document_id) {
+            self.total_title_length -= stats.title_length;
+            self.total_content_length -= stats.content_length;
+            forget_terms(&mut self.vocabulary, stats.terms.iter().map(String::as_str));
+        }
+    }
+
+    /// Remember how the documents are indexed before they change, so that
+    /// `restore` can undo the change if it is never committed
+    pub fn checkpoint(&self, document_ids: &[String]) -> IndexCheckpoint {
+        IndexCheckpoint {
+            buffered: document_ids.iter().map(|id| (id.clone(), self.buffer.document(id))).collect(),
+            tombstones: self.pending_tombstones.len(),
+        }
+    }
+
+    /// Undo every change made since `checkpoint` to the documents it covers:
+    /// buffered versions go, their earlier buffered versions come back and
+    /// tombstones not yet committed are lifted
+    pub fn restore(&mut self, checkpoint: IndexCheckpoint) {
+        for (document_id, _) in &checkpoint.buffered {
+            self.remove_buffered(document_id);
+        }
+
+        for (segment_id, document_id) in self.pending_tombstones.split_off(checkpoint.tombstones) {
+            let segment = match self.segments.iter_mut().find(|segment| segment.id() == segment_id) {
+                Some(segment) => segment,
+                None => continue,
+            };
+            let ordinal = match segment.find_document(&document_id) {
+                Some(ordinal) if segment.undelete(ordinal) => ordinal,
+                _ => continue,
+            };
+
+            let (title_length, content_length) = segment.document_lengths(ordinal);
+            self.total_title_length += title_length;
+            self.total_content_length += content_length;
+            remember_terms(&mut self.vocabulary, segment.document_terms(ordinal));
+        }
 
-            // Update document frequencies
-            *self.document_frequencies.entry(term).or_insert(0) += 1;
+        for (document_id, previous) in checkpoint.buffered {
+            if let Some((stats, postings)) = previous.filter(|_| !self.buffer.documents.contains_key(&document_id)) {
+                let stats = self.buffer.add_postings(&document_id, stats, postings);
+                remember_terms(&mut self.vocabulary, stats.terms.iter().map(String::as_str));
+                self.total_title_length += stats.title_length;
+                self.total_content_length += stats.content_length;
+            }
+        }
+    }
+
+    /// Write buffered documents to a new segment and record it, together
+    /// with pending tombstones, in `tx`. The new state only becomes visible
+    /// once `finish_commit` is called after the transaction commits; if it
+    /// rolls back, call `abort_commit` instead.
+    pub fn prepare_commit(&self, tx: &Connection) -> Result<PendingCommit> {
+        let directory = self.directory
+            .as_ref()
+            .ok_or_else(|| anyhow!("Search index has no storage directory"))?;
+
+        Self::create_manifest_tables(tx)?;
+
+        let mut pending = PendingCommit { segment: None };
+
+        if !self.buffer.is_empty() {
+            let id = format!("seg-{:08}", self.next_generation);
+            let path = Self::segment_path(directory, &id);
+            self.buffer.write(&path)?;
+            pending.segment = Some((id.clone(), path));
+
+            let recorded = tx.execute(
+                "INSERT INTO search_index_segments (id, generation, document_count, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![id, self.next_generation, self.buffer.len() as i64, Utc::now().timestamp()],
+            );
+            if let Err(e) = recorded {
+                self.abort_commit(pending);
+                return Err(e.into());
+            }
+        }
+
+        for (segment_id, document_id) in &self.pending_tombstones {
+            let recorded = tx.execute(
+                "INSERT OR IGNORE INTO search_index_tombstones (segment_id, document_id) VALUES (?1, ?2)",
+                params![segment_id, document_id],
+            );
+            if let Err(e) = recorded {
+                self.abort_commit(pending);
+                return Err(e.into());
+            }
+        }
+
+        for ((term, surface), change) in &self.pending_surfaces {
+            let recorded = tx.execute(
+                "INSERT INTO search_index_surfaces (term, surface, count) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (term, surface) DO UPDATE SET count = count + excluded.count",
+                params![term, surface, change],
+            );
+            if let Err(e) = recorded {
+                self.abort_commit(pending);
+                return Err(e.into());
+            }
+        }
+
+        Ok(pending)
+    }
+
+    pub fn finish_commit(&mut self, pending: PendingCommit) -> Result<()> {
+        self.pending_tombstones.clear();
+        self.pending_surfaces.clear();
+
+        if let Some((id, path)) = pending.segment {
+            let segment = Segment::open(&id, &path)?;
+            self.segments.push(segment);
+            self.buffer = MemorySegment::new();
+            self.next_generation += 1;
         }
 
-        self.document_count += 1;
         Ok(())
     }
 
-    pub async fn remove_document(&mut self, document_id: &str) -> Result<()> {
-        // Remove document from term frequencies and update document frequencies
-        let mut terms_to_remove = Vec::new();
-
-        for (term, doc_frequencies) in &mut self.term_frequencies {
-            if doc_frequencies.remove(document_id).is_some() {
-                // Decrease document frequency for this term
-                if let Some(df) = self.document_frequencies.get_mut(term) {
-                    *df = df.saturating_sub(1);
-                    if *df == 0 {
-                        terms_to_remove.push(term.clone());
+    pub fn abort_commit(&self, pending: PendingCommit) {
+        if let Some((_, path)) = pending.segment {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Commit buffered documents and tombstones in a transaction of their own
+    pub fn commit(&mut self, db: &mut Connection) -> Result<()> {
+        let tx = db.transaction()?;
+        let pending = self.prepare_commit(&tx)?;
+
+        match tx.commit() {
+            Ok(()) => self.finish_commit(pending),
+            Err(e) => {
+                self.abort_commit(pending);
+                Err(e.into())
+            }
+        }
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.directory.is_some()
+    }
+
+    pub fn needs_flush(&self) -> bool {
+        self.is_persistent() && self.buffer.len() >= self.flush_threshold
+    }
+
+    pub fn has_uncommitted_changes(&self) -> bool {
+        !self.buffer.is_empty() || !self.pending_tombstones.is_empty() || !self.pending_surfaces.is_empty()
+    }
+
+    /// Count the words documents used for each term, as found by
+    /// `Analyzer::count_surface_forms`. Counts only grow: re-indexed and
+    /// removed documents keep theirs.
+    pub fn add_surface_forms(&mut self, forms: &HashMap<(String, String), usize>) {
+        for ((term, surface), count) in forms {
+            *self.surfaces.entry(term.clone()).or_default().entry(surface.clone()).or_insert(0) += count;
+            if self.is_persistent() {
+                *self.pending_surfaces.entry((term.clone(), surface.clone())).or_insert(0) += *count as i64;
+            }
+        }
+    }
+
+    /// Take back forms added for documents whose change never committed
+    pub fn remove_surface_forms(&mut self, forms: &HashMap<(String, String), usize>) {
+        for ((term, surface), count) in forms {
+            if let Some(words) = self.surfaces.get_mut(term) {
+                if let Some(total) = words.get_mut(surface) {
+                    *total = total.saturating_sub(*count);
+                    if *total == 0 {
+                        words.remove(surface);
                     }
                 }
-                
-                // Remove term entry if no documents contain it
-                if doc_frequencies.is_empty() {
-                    terms_to_remove.push(term.clone());
+                if words.is_empty() {
+                    self.surfaces.remove(term);
+                }
+            }
+
+            let key = (term.clone(), surface.clone());
+            if let Some(change) = self.pending_surfaces.get_mut(&key) {
+                *change -= *count as i64;
+                if *change == 0 {
+                    self.pending_surfaces.remove(&key);
                 }
             }
         }
+    }
+
+    /// The word most often indexed as `term`, if any was counted
+    pub fn surface_form(&self, term: &str) -> Option<&str> {
+        self.surfaces
+            .get(term)?
+            .iter()
+            .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(surface, _)| surface.as_str())
+    }
+
+    /// (indexed, removed) document ids the next commit will write. A
+    /// re-indexed document is in both.
+    pub fn uncommitted_documents(&self) -> (Vec<String>, Vec<String>) {
+        let indexed = self.buffer.documents.keys().cloned().collect();
+        let mut removed: Vec<String> = self.pending_tombstones
+            .iter()
+            .map(|(_, document_id)| document_id.clone())
+            .collect();
+        removed.sort_unstable();
+        removed.dedup();
+        (indexed, removed)
+    }
+
+    fn merge_candidates(&self) -> Vec<usize> {
+        let mut candidates: Vec<usize> = self.segments
+            .iter()
+            .enumerate()
+            .filter(|(_, segment)| {
+                segment.doc_count() > 0
+                    && segment.deleted_count() as f64 / segment.doc_count() as f64 > self.merge_policy.max_deleted_ratio
+            })
+            .map(|(index, _)| index)
+            .collect();
 
-        // Clean up empty entries
-        for term in terms_to_remove {
-            self.term_frequencies.remove(&term);
-            self.document_frequencies.remove(&term);
+        if self.segments.len() > self.merge_policy.max_segments {
+            let mut by_size: Vec<usize> = (0..self.segments.len())
+                .filter(|index| !candidates.contains(index))
+                .collect();
+            by_size.sort_by_key(|&index| self.segments[index].live_count());
+            candidates.extend(by_size.into_iter().take(self.merge_policy.merge_factor));
         }
 
-        if self.document_count > 0 {
-            self.document_count -= 1;
+        candidates.sort_unstable();
+        candidates
+    }
+
+    /// Merge small segments, and rewrite segments that are mostly
+    /// tombstones, according to the merge policy. Returns whether anything
+    /// was merged.
+    pub fn merge_segments(&mut self, db: &mut Connection) -> Result<bool> {
+        let directory = match &self.directory {
+            Some(directory) => directory.clone(),
+            None => return Ok(false),
+        };
+
+        let candidates = self.merge_candidates();
+        if candidates.is_empty() {
+            return Ok(false);
+        }
+
+        let mut merged = MemorySegment::new();
+        for &index in &candidates {
+            self.segments[index].copy_live_documents(&mut merged);
+        }
+
+        let id = format!("seg-{:08}", self.next_generation);
+        let path = Self::segment_path(&directory, &id);
+        if !merged.is_empty() {
+            merged.write(&path)?;
+        }
+
+        let old_ids: Vec<String> = candidates.iter().map(|&index| self.segments[index].id().to_string()).collect();
+        let recorded = Self::record_merge(db, &old_ids, &id, self.next_generation, &merged);
+        if let Err(e) = recorded {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+
+        for &index in candidates.iter().rev() {
+            let segment = self.segments.remove(index);
+            let old_path = segment.path().to_path_buf();
+            drop(segment);
+            let _ = fs::remove_file(old_path);
         }
+        self.pending_tombstones.retain(|(segment_id, _)| !old_ids.contains(segment_id));
 
+        if !merged.is_empty() {
+            self.segments.push(Segment::open(&id, &path)?);
+        }
+        self.next_generation += 1;
+
+        debug!("Merged {} index segments into {}", old_ids.len(), id);
+        Ok(true)
+    }
+
+    fn record_merge(
+        db: &mut Connection,
+        old_ids: &[String],
+        new_id: &str,
+        generation: i64,
+        merged: &MemorySegment,
+    ) -> Result<()> {
+        let tx = db.transaction()?;
+
+        for old_id in old_ids {
+            tx.execute("DELETE FROM search_index_segments WHERE id = ?1", [old_id])?;
+            tx.execute("DELETE FROM search_index_tombstones WHERE segment_id = ?1", [old_id])?;
+        }
+
+        if !merged.is_empty() {
+            tx.execute(
+                "INSERT INTO search_index_segments (id, generation, document_count, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![new_id, generation, merged.len() as i64, Utc::now().timestamp()],
+            )?;
+        }
+
+        tx.commit()?;
         Ok(())
     }
 
+    pub fn set_merge_policy(&mut self, policy: MergePolicy) {
+        self.merge_policy = policy;
+    }
+
+    pub fn set_flush_threshold(&mut self, threshold: usize) {
+        self.flush_threshold = threshold.max(1);
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn size_on_disk(&self) -> usize {
+        self.segments.iter().map(|segment| segment.size_on_disk()).sum()
+    }
+
+    /// Documents indexed since the last flush
+    pub fn buffered_document_count(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Documents deleted from segments, whose postings stay until a merge
+    pub fn deleted_document_count(&self) -> usize {
+        self.segments.iter().map(|segment| segment.deleted_count()).sum()
+    }
+
+    fn locate(&self, document_id: &str) -> Option<DocumentLocation> {
+        if self.buffer.documents.contains_key(document_id) {
+            return Some(DocumentLocation::Buffer);
+        }
+
+        self.segments
+            .iter()
+            .enumerate()
+            .find_map(|(index, segment)| {
+                segment.live_document(document_id).map(|ordinal| DocumentLocation::Segment(index, ordinal))
+            })
+    }
+
+    pub fn document_count(&self) -> usize {
+        self.buffer.len() + self.segments.iter().map(|segment| segment.live_count()).sum::<usize>()
+    }
+
+    pub fn contains_document(&self, document_id: &str) -> bool {
+        self.locate(document_id).is_some()
+    }
+
+    pub fn document_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.buffer.documents.keys().cloned().collect();
+        for segment in &self.segments {
+            ids.extend(segment.live_documents().map(|ordinal| segment.document_id(ordinal).to_string()));
+        }
+        ids
+    }
+
+    /// (title length, content length) in tokens
+    pub fn document_lengths(&self, document_id: &str) -> Option<(usize, usize)> {
+        match self.locate(document_id)? {
+            DocumentLocation::Buffer => self.buffer
+                .documents
+                .get(document_id)
+                .map(|stats| (stats.title_length, stats.content_length)),
+            DocumentLocation::Segment(index, ordinal) => Some(self.segments[index].document_lengths(ordinal)),
+        }
+    }
+
+    pub fn document_frequency(&self, term: &str) -> usize {
+        self.vocabulary.get(term).copied().unwrap_or(0)
+    }
+
+    /// Sorted term dictionary with live document frequencies
+    pub fn vocabulary(&self) -> &BTreeMap<String, usize> {
+        &self.vocabulary
+    }
+
+    pub fn average_title_length(&self) -> f64 {
+        let count = self.document_count();
+        if count == 0 {
+            return 0.0;
+        }
+        self.total_title_length as f64 / count as f64
+    }
+
+    pub fn average_content_length(&self) -> f64 {
+        let count = self.document_count();
+        if count == 0 {
+            return 0.0;
+        }
+        self.total_content_length as f64 / count as f64
+    }
+
     pub fn calculate_tf_idf(&self, term: &str, document_id: &str) -> f64 {
-        let tf = self.term_frequencies
-            .get(term)
-            .and_then(|docs| docs.get(document_id))
-            .copied()
-            .unwrap_or(0.0);
+        let posting = match self.posting(term, document_id) {
+            Some(posting) => posting,
+            None => return 0.0,
+        };
 
-        let df = self.document_frequencies.get(term).copied().unwrap_or(0) as f64;
+        let length = self.document_lengths(document_id)
+            .map(|(title_length, content_length)| title_length + content_length)
+            .unwrap_or(0);
+
+        let df = self.document_frequency(term) as f64;
         
-        if df == 0.0 || self.document_count == 0 {
+        if df == 0.0 || length == 0 {
             return 0.0;
         }
 
-        let idf = (self.document_count as f64 / df).ln();
+        let tf = (posting.title_count() + posting.content_count()) as f64 / length as f64;
+        let idf = (self.document_count() as f64 / df).ln();
         tf * idf
     }
 
+    /// BM25 inverse document frequency, floored at zero by the +1 so terms
+    /// present in every document still contribute
+    pub fn idf(&self, term: &str) -> f64 {
+        let n = self.document_count() as f64;
+        let df = self.document_frequency(term) as f64;
+
+        if df == 0.0 {
+            return 0.0;
+        }
+
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+
+    /// BM25F score of a single term: per-field term frequencies are length
+    /// normalized, weighted and summed before the k1 saturation is applied.
+    pub fn bm25_term_score(&self, term: &str, document_id: &str, params: &Bm25Parameters) -> f64 {
+        let posting = match self.posting(term, document_id) {
+            Some(posting) => posting,
+            None => return 0.0,
+        };
+        let (title_length, content_length) = match self.document_lengths(document_id) {
+            Some(lengths) => lengths,
+            None => return 0.0,
+        };
+
+        let normalize = |tf: u32, length: usize, average: f64| -> f64 {
+            if tf == 0 || average == 0.0 {
+                return 0.0;
+            }
+            tf as f64 / (1.0 - params.b + params.b * (length as f64 / average))
+        };
+
+        let tf = params.title_weight * normalize(posting.title_count(), title_length, self.average_title_length())
+            + params.content_weight * normalize(posting.content_count(), content_length, self.average_content_length());
+
+        if tf == 0.0 {
+            return 0.0;
+        }
+
+        self.idf(term) * (tf * (params.k1 + 1.0)) / (tf + params.k1)
+    }
+
+    pub fn score_document(&self, query_terms: &[String], document_id: &str, params: &Bm25Parameters) -> f64 {
+        query_terms
+            .iter()
+            .map(|term| self.bm25_term_score(term, document_id, params))
+            .sum()
+    }
+
+    /// Score of a document against groups of alternatives, one group per
+    /// query term. Each group contributes its best matching alternative;
+    /// None if there are no groups or some group has no alternative the
+    /// document matches.
+    pub fn score_alternatives(
+        &self,
+        groups: &[Vec<TermAlternative>],
+        document_id: &str,
+        params: &Bm25Parameters,
+    ) -> Option<f64> {
+        if groups.is_empty() {
+            return None;
+        }
+
+        let mut total = 0.0;
+
+        for group in groups {
+            let best = group
+                .iter()
+                .filter(|alternative| self.matches_alternative(alternative, document_id))
+                .map(|alternative| {
+                    alternative.weight * self.score_document(&alternative.terms, document_id, params)
+                })
+                .fold(None, |best: Option<f64>, score| Some(best.map_or(score, |b| b.max(score))))?;
+            total += best;
+        }
+
+        Some(total)
+    }
+
+    fn matches_alternative(&self, alternative: &TermAlternative, document_id: &str) -> bool {
+        if alternative.phrase {
+            self.matches_phrase(&alternative.terms, document_id)
+        } else {
+            alternative.terms.iter().all(|term| self.posting(term, document_id).is_some())
+        }
+    }
+
+    /// Candidate documents for groups of alternatives: those containing
+    /// every term of some alternative in each group. Phrase order is left
+    /// to `score_alternatives`.
+    pub fn documents_matching(&self, groups: &[Vec<TermAlternative>]) -> HashSet<String> {
+        let mut candidates: Option<HashSet<String>> = None;
+
+        for group in groups {
+            let mut matching: HashSet<String> = HashSet::new();
+            for alternative in group {
+                let mut documents: Option<HashSet<String>> = None;
+                for term in &alternative.terms {
+                    let containing: HashSet<String> = self.get_term_documents(term).into_iter().collect();
+                    documents = Some(match documents {
+                        Some(previous) => previous.intersection(&containing).cloned().collect(),
+                        None => containing,
+                    });
+                }
+                matching.extend(documents.unwrap_or_default());
+            }
+
+            candidates = Some(match candidates {
+                Some(previous) => previous.intersection(&matching).cloned().collect(),
+                None => matching,
+            });
+        }
+
+        candidates.unwrap_or_default()
+    }
+
     pub fn get_document_score(&self, query_terms: &[String], document_id: &str) -> f64 {
-        let mut score = 0.0;
-        
-        for term in query_terms {
-            score += self.calculate_tf_idf(term, document_id);
+        self.score_document(query_terms, document_id, &Bm25Parameters::default())
+    }
+
+    pub fn posting(&self, term: &str, document_id: &str) -> Option<Cow<'_, TermPosting>> {
+        match self.locate(document_id)? {
+            DocumentLocation::Buffer => self.buffer.posting(term, document_id).map(Cow::Borrowed),
+            DocumentLocation::Segment(index, ordinal) => {
+                let segment = &self.segments[index];
+                let term_ordinal = segment.find_term(term)?;
+                segment.posting(term_ordinal, ordinal).map(Cow::Owned)
+            }
+        }
+    }
+
+    /// Whether the terms appear consecutively, in order, in the title or the content
+    pub fn matches_phrase(&self, phrase: &[String], document_id: &str) -> bool {
+        match phrase.len() {
+            0 => true,
+            1 => self.posting(&phrase[0], document_id).is_some(),
+            _ => {
+                let postings: Option<Vec<Cow<'_, TermPosting>>> = phrase
+                    .iter()
+                    .map(|term| self.posting(term, document_id))
+                    .collect();
+
+                match postings {
+                    Some(postings) => {
+                        let title: Vec<&[u32]> = postings.iter().map(|p| p.title_positions.as_slice()).collect();
+                        let content: Vec<&[u32]> = postings.iter().map(|p| p.content_positions.as_slice()).collect();
+                        phrase_occurs(&title) || phrase_occurs(&content)
+                    }
+                    None => false,
+                }
+            }
+        }
+    }
+
+    /// FTS5 NEAR semantics: all terms occur in one field with at most
+    /// `distance` other tokens between the first and the last of them
+    pub fn matches_near(&self, terms: &[String], distance: usize, document_id: &str) -> bool {
+        let postings: Option<Vec<Cow<'_, TermPosting>>> = terms
+            .iter()
+            .map(|term| self.posting(term, document_id))
+            .collect();
+
+        let postings = match postings {
+            Some(postings) => postings,
+            None => return false,
+        };
+
+        let within = |lists: Vec<&[u32]>| match minimum_span(&lists) {
+            Some(span) => span as usize <= distance + terms.len(),
+            None => false,
+        };
+
+        within(postings.iter().map(|p| p.title_positions.as_slice()).collect())
+            || within(postings.iter().map(|p| p.content_positions.as_slice()).collect())
+    }
+
+    /// Positional phrase and NEAR constraints of a query. Plain terms are
+    /// left to the full-text match that produced the candidate.
+    pub fn matches_query(&self, query: &ParsedQuery, document_id: &str) -> bool {
+        query.phrases.iter().all(|phrase| self.matches_phrase(phrase, document_id))
+            && query.near.iter().all(|clause| self.matches_near(&clause.terms, clause.distance, document_id))
+    }
+
+    /// Ratio of matched distinct query terms to the length of the smallest
+    /// content window containing all of them: 1.0 when they are adjacent,
+    /// falling towards 0.0 as they spread out
+    pub fn proximity_score(&self, query_terms: &[String], document_id: &str) -> f64 {
+        let mut seen = HashSet::new();
+        let postings: Vec<Cow<'_, TermPosting>> = query_terms
+            .iter()
+            .filter(|term| seen.insert(term.as_str()))
+            .filter_map(|term| self.posting(term, document_id))
+            .filter(|posting| !posting.content_positions.is_empty())
+            .collect();
+
+        if postings.len() < 2 {
+            return 0.0;
+        }
+
+        let lists: Vec<&[u32]> = postings.iter().map(|p| p.content_positions.as_slice()).collect();
+        match minimum_span(&lists) {
+            Some(span) => lists.len() as f64 / span as f64,
+            None => 0.0,
         }
-        
-        score
     }
 
     pub async fn get_index_size(&self) -> Result<usize> {
-        Ok(self.term_frequencies.len())
+        Ok(self.vocabulary.len())
     }
 
     pub fn get_term_documents(&self, term: &str) -> Vec<String> {
-        self.term_frequencies
+        let mut documents: Vec<String> = self.buffer
+            .postings
             .get(term)
             .map(|docs| docs.keys().cloned().collect())
-            .unwrap_or_default()
+            .unwrap_or_default();
+
+        for segment in &self.segments {
+            if let Some(term_ordinal) = segment.find_term(term) {
+                documents.extend(
+                    segment.postings(term_ordinal).map(|(ordinal, _)| segment.document_id(ordinal).to_string())
+                );
+            }
+        }
+
+        documents
     }
 }
 
-#[derive(Debug, Clone)]
+fn remember_terms<'a, I>(vocabulary: &mut BTreeMap<String, usize>, terms: I)
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    for term in terms {
+        *vocabulary.entry(term.to_string()).or_insert(0) += 1;
+    }
+}
+
+fn forget_terms<'a, I>(vocabulary: &mut BTreeMap<String, usize>, terms: I)
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    for term in terms {
+        if let Some(df) = vocabulary.get_mut(term) {
+            *df = df.saturating_sub(1);
+            if *df == 0 {
+                vocabulary.remove(term);
+            }
+        }
+    }
+}
+
+/// Whether position lists (one per phrase term, sorted ascending) contain
+/// a run where term i sits at start + i
+fn phrase_occurs(positions: &[&[u32]]) -> bool {
+    let (first, rest) = match positions.split_first() {
+        Some(split) => split,
+        None => return false,
+    };
+
+    first.iter().any(|&start| {
+        rest.iter()
+            .enumerate()
+            .all(|(offset, list)| list.binary_search(&(start + offset as u32 + 1)).is_ok())
+    })
+}
+
+/// Length in tokens of the smallest window containing at least one position
+/// from every list, or None if any list is empty
+pub fn minimum_span(positions: &[&[u32]]) -> Option<u32> {
+    if positions.is_empty() || positions.iter().any(|list| list.is_empty()) {
+        return None;
+    }
+
+    let mut merged: Vec<(u32, usize)> = positions
+        .iter()
+        .enumerate()
+        .flat_map(|(index, list)| list.iter().map(move |&position| (position, index)))
+        .collect();
+    merged.sort_unstable();
+
+    let mut counts = vec![0usize; positions.len()];
+    let mut covered = 0;
+    let mut best: Option<u32> = None;
+    let mut left = 0;
+
+    for right in 0..merged.len() {
+        let (_, index) = merged[right];
+        if counts[index] == 0 {
+            covered += 1;
+        }
+        counts[index] += 1;
+
+        while covered == positions.len() {
+            let span = merged[right].0 - merged[left].0 + 1;
+            best = Some(best.map_or(span, |current| current.min(span)));
+
+            let (_, left_index) = merged[left];
+            counts[left_index] -= 1;
+            if counts[left_index] == 0 {
+                covered -= 1;
+            }
+            left += 1;
+        }
+    }
+
+    best
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct InvertedIndex {
     index: HashMap<String, Vec<DocumentPosting>>,
 }
@@ -143,14 +1029,12 @@ pub struct DocumentPosting {
 
 impl InvertedIndex {
     pub fn new() -> Self {
-        Self {
-            index: HashMap::new(),
-        }
+        Self::default()
     }
 
     pub fn add_document(&mut self, document: &IndexedDocument) {
         for (position, token) in document.tokens.iter().enumerate() {
-            let entry = self.index.entry(token.clone()).or_insert_with(Vec::new);
+            let entry = self.index.entry(token.clone()).or_default();
             
             // Find or create posting for this document
             if let Some(posting) = entry.iter_mut().find(|p| p.document_id == document.id) {
@@ -210,7 +1094,6 @@ impl InvertedIndex {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use uuid::Uuid;
 
     fn create_test_document(id: &str, content: &str) -> IndexedDocument {
         let tokens: Vec<String> = content
@@ -222,6 +1105,7 @@ mod tests {
             id: id.to_string(),
             title: format!("Document {}", id),
             content: content.to_string(),
+            title_tokens: vec!["document".to_string(), id.to_string()],
             tokens,
             entities: Vec::new(),
             metadata: serde_json::json!({}),
@@ -292,4 +1176,186 @@ mod tests {
         
         assert!(cat_score > dog_score);
     }
+
+    #[tokio::test]
+    async fn test_bm25_prefers_shorter_documents() {
+        let mut indexer = FullTextIndexer::new().unwrap();
+        
+        indexer.index_document(&create_test_document("1", "cat dog")).await.unwrap();
+        indexer.index_document(&create_test_document("2", "cat dog bird fish horse cow")).await.unwrap();
+        indexer.index_document(&create_test_document("3", "bird fish")).await.unwrap();
+        
+        let terms = vec!["cat".to_string()];
+        assert!(indexer.get_document_score(&terms, "1") > indexer.get_document_score(&terms, "2"));
+
+        // With no length normalization the two documents tie
+        let params = Bm25Parameters { b: 0.0, ..Default::default() };
+        let short = indexer.score_document(&terms, "1", &params);
+        let long = indexer.score_document(&terms, "2", &params);
+        assert!((short - long).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_bm25f_title_weight() {
+        let mut indexer = FullTextIndexer::new().unwrap();
+
+        let mut titled = create_test_document("1", "notes about the budget");
+        titled.title_tokens = vec!["budget".to_string()];
+        let untitled = create_test_document("2", "notes about the budget");
+
+        indexer.index_document(&titled).await.unwrap();
+        indexer.index_document(&untitled).await.unwrap();
+
+        let terms = vec!["budget".to_string()];
+        assert!(indexer.get_document_score(&terms, "1") > indexer.get_document_score(&terms, "2"));
+
+        let params = Bm25Parameters { title_weight: 0.0, ..Default::default() };
+        let titled_score = indexer.score_document(&terms, "1", &params);
+        let untitled_score = indexer.score_document(&terms, "2", &params);
+        assert!((titled_score - untitled_score).abs() < 0.2);
+    }
+
+    #[tokio::test]
+    async fn test_reindexing_keeps_statistics_consistent() {
+        let mut indexer = FullTextIndexer::new().unwrap();
+        let doc = create_test_document("1", "hello world");
+        
+        indexer.index_document(&doc).await.unwrap();
+        indexer.index_document(&doc).await.unwrap();
+        indexer.remove_document("missing").await.unwrap();
+        
+        assert_eq!(indexer.document_count(), 1);
+        assert_eq!(indexer.average_content_length(), 2.0);
+        assert_eq!(indexer.get_term_documents("hello"), vec!["1".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_phrase_and_near_matching() {
+        let mut indexer = FullTextIndexer::new().unwrap();
+        indexer.index_document(&create_test_document("1", "the quick brown fox jumps")).await.unwrap();
+        indexer.index_document(&create_test_document("2", "brown bears and a quick fox")).await.unwrap();
+
+        let phrase = vec!["quick".to_string(), "brown".to_string()];
+        assert!(indexer.matches_phrase(&phrase, "1"));
+        assert!(!indexer.matches_phrase(&phrase, "2"));
+
+        let near = vec!["quick".to_string(), "fox".to_string()];
+        assert!(indexer.matches_near(&near, 1, "1"));
+        assert!(indexer.matches_near(&near, 0, "2"));
+        assert!(!indexer.matches_near(&["brown".to_string(), "fox".to_string()], 2, "2"));
+
+        // Three terms with three other tokens between the first and the last
+        indexer.index_document(&create_test_document("3", "quick red brown and lazy fox")).await.unwrap();
+        let near = vec!["quick".to_string(), "brown".to_string(), "fox".to_string()];
+        assert!(indexer.matches_near(&near, 0, "1"));
+        assert!(indexer.matches_near(&near, 3, "3"));
+        assert!(!indexer.matches_near(&near, 2, "3"));
+    }
+
+    #[tokio::test]
+    async fn test_proximity_score() {
+        let mut indexer = FullTextIndexer::new().unwrap();
+        indexer.index_document(&create_test_document("1", "annual budget review")).await.unwrap();
+        indexer.index_document(&create_test_document("2", "budget notes for the upcoming annual review")).await.unwrap();
+
+        let terms = vec!["budget".to_string(), "review".to_string()];
+        let close = indexer.proximity_score(&terms, "1");
+        let far = indexer.proximity_score(&terms, "2");
+
+        assert!((close - 1.0).abs() < 1e-9);
+        assert!(far > 0.0 && far < close);
+        assert_eq!(indexer.proximity_score(&["budget".to_string()], "1"), 0.0);
+    }
+
+    #[test]
+    fn test_minimum_span() {
+        assert_eq!(minimum_span(&[&[1, 10], &[4, 12], &[11]]), Some(3));
+        assert_eq!(minimum_span(&[&[1], &[]]), None);
+    }
+
+    #[tokio::test]
+    async fn test_persisted_index_reopens_with_same_scores() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut db = Connection::open_in_memory().unwrap();
+        let terms = vec!["cat".to_string(), "dog".to_string()];
+
+        let mut indexer = FullTextIndexer::open(temp_dir.path(), &db).unwrap();
+        indexer.index_document(&create_test_document("1", "cat dog cat")).await.unwrap();
+        indexer.index_document(&create_test_document("2", "dog bird")).await.unwrap();
+        indexer.add_surface_forms(&HashMap::from([
+            (("dog".to_string(), "dogs".to_string()), 2),
+            (("dog".to_string(), "dog".to_string()), 1),
+        ]));
+        let expected = indexer.get_document_score(&terms, "1");
+        indexer.commit(&mut db).unwrap();
+
+        assert_eq!(indexer.segment_count(), 1);
+        assert!((indexer.get_document_score(&terms, "1") - expected).abs() < 1e-9);
+        drop(indexer);
+
+        let reopened = FullTextIndexer::open(temp_dir.path(), &db).unwrap();
+        assert_eq!(reopened.document_count(), 2);
+        assert_eq!(reopened.document_frequency("dog"), 2);
+        assert!((reopened.get_document_score(&terms, "1") - expected).abs() < 1e-9);
+        assert!(reopened.matches_phrase(&["cat".to_string(), "dog".to_string()], "1"));
+        assert_eq!(reopened.surface_form("dog"), Some("dogs"));
+        assert_eq!(reopened.surface_form("bird"), None);
+    }
+
+    #[tokio::test]
+    async fn test_persisted_deletes_and_uncommitted_segments() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut db = Connection::open_in_memory().unwrap();
+
+        let mut indexer = FullTextIndexer::open(temp_dir.path(), &db).unwrap();
+        indexer.index_document(&create_test_document("1", "hello world")).await.unwrap();
+        indexer.index_document(&create_test_document("2", "hello there")).await.unwrap();
+        indexer.commit(&mut db).unwrap();
+
+        indexer.remove_document("1").await.unwrap();
+        indexer.commit(&mut db).unwrap();
+
+        // A segment written for a transaction that rolled back is discarded
+        indexer.index_document(&create_test_document("3", "hello again")).await.unwrap();
+        {
+            let tx = db.transaction().unwrap();
+            indexer.prepare_commit(&tx).unwrap();
+            tx.rollback().unwrap();
+        }
+        drop(indexer);
+
+        let reopened = FullTextIndexer::open(temp_dir.path(), &db).unwrap();
+        assert_eq!(reopened.document_ids(), vec!["2".to_string()]);
+        assert_eq!(reopened.document_frequency("hello"), 1);
+        assert_eq!(reopened.document_frequency("world"), 0);
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_segment_merge() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut db = Connection::open_in_memory().unwrap();
+
+        let mut indexer = FullTextIndexer::open(temp_dir.path(), &db).unwrap();
+        indexer.set_merge_policy(MergePolicy { max_segments: 2, merge_factor: 2, max_deleted_ratio: 0.5 });
+
+        for i in 0..3 {
+            let id = i.to_string();
+            indexer.index_document(&create_test_document(&id, "shared words here")).await.unwrap();
+            indexer.commit(&mut db).unwrap();
+        }
+        indexer.remove_document("0").await.unwrap();
+        indexer.commit(&mut db).unwrap();
+        assert_eq!(indexer.segment_count(), 3);
+
+        assert!(indexer.merge_segments(&mut db).unwrap());
+        assert_eq!(indexer.segment_count(), 1);
+        assert!(!indexer.merge_segments(&mut db).unwrap());
+
+        let reopened = FullTextIndexer::open(temp_dir.path(), &db).unwrap();
+        let mut ids = reopened.document_ids();
+        ids.sort();
+        assert_eq!(ids, vec!["1".to_string(), "2".to_string()]);
+        assert_eq!(reopened.document_frequency("shared"), 2);
+    }
 }
\ No newline at end of file
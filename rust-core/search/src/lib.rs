@@ -1,22 +1,62 @@
 use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{Mutex, RwLock};
 use anyhow::{Result, anyhow};
 use serde::{Serialize, Deserialize};
 use rusqlite::Connection;
-use regex::Regex;
-use stemmer::Stemmer;
-use unicode_segmentation::UnicodeSegmentation;
-use ndarray::Array1;
-use tracing::{info, warn, error, debug};
-
+use tracing::{info, warn, debug};
+
+pub mod analyzer;
+pub mod cache;
+pub mod chunks;
+pub mod duplicates;
+pub mod entities;
+pub mod entity_query;
+pub mod evaluation;
+pub mod facets;
+pub mod feedback;
+pub mod fuzzy;
+pub mod history;
 pub mod indexer;
+pub mod pagination;
+pub mod pattern;
+pub mod query;
 pub mod ranker;
+pub mod saved_searches;
+pub mod segment;
 pub mod similarity;
-
+pub mod snippet;
+pub mod sort;
+pub mod stats;
+pub mod stream;
+pub mod suggest;
+pub mod synonyms;
+
+use analyzer::*;
+use cache::*;
+use chunks::*;
+use duplicates::*;
+use entities::*;
+use entity_query::*;
+use facets::*;
+use feedback::*;
+use fuzzy::*;
+use history::*;
 use indexer::*;
+use pagination::*;
+use pattern::*;
+use query::*;
 use ranker::*;
+use saved_searches::*;
 use similarity::*;
+use snippet::*;
+use sort::*;
+use stats::*;
+use stream::*;
+use suggest::*;
+use synonyms::*;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchQuery {
@@ -32,6 +72,10 @@ pub struct SearchFilters {
     pub date_range: Option<DateRange>,
     pub file_types: Option<Vec<String>>,
     pub source_types: Option<Vec<String>>,
+    #[serde(default)]
+    pub document_ids: Option<Vec<String>>, // only these documents; at most 500
+    #[serde(default)]
+    pub mentions: Option<Vec<Vec<String>>>, // entity ids; documents mentioning one of each group
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +93,34 @@ pub struct SearchOptions {
     pub fuzzy_matching: bool,
     pub semantic_search: bool,
     pub boost_recent: bool,
+    #[serde(default)]
+    pub include_facets: bool,
+    #[serde(default)]
+    pub facet_date_interval: DateInterval,
+    #[serde(default)]
+    pub search_after: Option<String>,
+    #[serde(default)]
+    pub bm25: Bm25Parameters,
+    #[serde(default)]
+    pub language: Option<String>, // ISO 639-1 code used to analyze the query text
+    #[serde(default)]
+    pub max_snippet_fragments: Option<usize>, // fragments per result, 1 when unset
+    #[serde(default)]
+    pub collapse_duplicates: bool, // keep only the best ranked of each near-duplicate group
+    #[serde(default)]
+    pub profile_id: Option<String>, // ranks and personalizes with what was learned for this profile
+    #[serde(default)]
+    pub diversity: Option<DiversityOptions>,
+    #[serde(default)]
+    pub max_passages: Option<usize>, // matching chunks per result, 3 when unset; 0 skips chunks
+    #[serde(default)]
+    pub query_mode: QueryMode,
+    #[serde(default)]
+    pub regex_timeout_ms: Option<u64>, // how long a regex scan may run, 2000 when unset
+    #[serde(default)]
+    pub sort: Vec<SortKey>, // applied in turn, ties by id; relevance when empty
+    #[serde(default)]
+    pub entity_expansion: Option<EntityExpansion>, // also match entities related to entity: references
 }
 
 impl Default for SearchOptions {
@@ -61,6 +133,20 @@ impl Default for SearchOptions {
             fuzzy_matching: false,
             semantic_search: false,
             boost_recent: true,
+            include_facets: false,
+            facet_date_interval: DateInterval::Month,
+            search_after: None,
+            bm25: Bm25Parameters::default(),
+            language: None,
+            max_snippet_fragments: None,
+            collapse_duplicates: false,
+            profile_id: None,
+            diversity: None,
+            max_passages: None,
+            query_mode: QueryMode::Terms,
+            regex_timeout_ms: None,
+            sort: Vec::new(),
+            entity_expansion: None,
         }
     }
 }
@@ -75,6 +161,26 @@ pub struct SearchResult {
     pub score: f64,
     pub metadata: serde_json::Value,
     pub highlights: Vec<TextHighlight>,
+    #[serde(default)]
+    pub fragments: Vec<SnippetFragment>,
+    #[serde(default)]
+    pub passages: Vec<ChunkPassage>, // best matching chunks, best first
+    #[serde(default)]
+    pub sort_values: Vec<SortValue>, // one per sort key, when not sorted by relevance
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResponse {
+    pub results: Vec<SearchResult>,
+    pub total_hits: usize,
+    pub total_hits_exact: bool, // false when fuzzy or semantic matches make the total an estimate
+    pub next_cursor: Option<String>,
+    pub facets: Option<SearchFacets>,
+    pub did_you_mean: Option<String>, // offered when the query matched nothing
+    #[serde(default)]
+    pub timed_out: bool, // a regex scan hit its time limit, so results are partial
+    #[serde(default)]
+    pub entities: Vec<EntityGroup>, // what the query's entity: references resolved to
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -84,6 +190,7 @@ pub enum SearchResultType {
     Chunk,
 }
 
+/// A matched word; offsets are in bytes and fall on character boundaries
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TextHighlight {
     pub start: usize,
@@ -96,6 +203,7 @@ pub struct IndexedDocument {
     pub id: String,
     pub title: String,
     pub content: String,
+    pub title_tokens: Vec<String>,
     pub tokens: Vec<String>,
     pub entities: Vec<IndexedEntity>,
     pub metadata: serde_json::Value,
@@ -110,59 +218,311 @@ pub struct IndexedEntity {
     pub properties: serde_json::Value,
 }
 
+// Terms of the source document used to select more-like-this candidates
+const MORE_LIKE_THIS_TERMS: usize = 25;
+
+// Time a regex scan may take when the query does not say
+const DEFAULT_REGEX_TIMEOUT_MS: u64 = 2000;
+
+/// A streamed search reports the ranked page after each stage that more
+/// stages follow, each replacing the one before, then facets, the final
+/// page and completion. A cancelled search reports only that it was
+/// cancelled, a failed one only the error.
 pub trait SearchCallback: Send + Sync {
     fn on_search_results(&self, results: Vec<SearchResult>);
     fn on_search_error(&self, error: String);
+    fn on_search_facets(&self, _facets: SearchFacets) {}
+    fn on_partial_results(&self, _stage: SearchStage, _results: Vec<SearchResult>) {}
+    fn on_search_complete(&self, _total_hits: usize) {}
+    fn on_search_cancelled(&self) {}
+}
+
+/// Notified after documents are indexed, once per saved search with new
+/// matches
+pub trait SavedSearchCallback: Send + Sync {
+    fn on_saved_search_matches(&self, alert: SavedSearchAlert);
+    fn on_saved_search_error(&self, _error: String) {}
+}
+
+/// Matches gathered for a query, before ranking
+struct Candidates {
+    results: Vec<SearchResult>,
+    match_ids: Option<Vec<String>>, // every match, when facets or the total need them
+    match_total: usize,
+    estimated: bool, // the total is a lower bound
+    timed_out: bool,
+    query_tokens: Vec<String>,
+    highlight_terms: HashSet<String>,
+}
+
+/// Where a streamed search reports its stages, and how it learns it was
+/// cancelled
+struct SearchStream<'a> {
+    callback: &'a dyn SearchCallback,
+    handle: &'a SearchHandle,
 }
 
 pub struct SearchEngine {
-    database: Arc<RwLock<Connection>>,
-    indexer: Arc<FullTextIndexer>,
+    database: Arc<Mutex<Connection>>,
+    indexer: Arc<RwLock<FullTextIndexer>>,
+    duplicates: Arc<RwLock<DuplicateDetector>>,
     ranker: Arc<SearchRanker>,
     similarity_engine: Arc<SimilarityEngine>,
-    stemmer: Stemmer,
+    facet_collector: FacetCollector,
+    entity_searcher: EntitySearcher,
+    fuzzy_expander: FuzzyExpander,
+    pattern_expander: PatternExpander,
+    suggester: QuerySuggester,
+    analyzers: AnalyzerRegistry,
+    snippet_generator: SnippetGenerator,
+    synonyms: Arc<RwLock<SynonymDictionary>>,
+    profiles: Arc<RwLock<HashMap<String, RankingProfile>>>, // loaded on first use
+    weight_trainer: WeightTrainer,
+    saved_search_callback: Arc<RwLock<Option<Box<dyn SavedSearchCallback>>>>,
+    saved_search_run: Arc<Mutex<()>>, // held while saved searches run for the callback
+    saved_searches_pending: Arc<AtomicBool>, // documents indexed since the last run started
+    query_cache: Arc<RwLock<QueryCache>>, // locked after the database, indexer and duplicates
+    index_path: Option<PathBuf>,
 }
 
 impl SearchEngine {
-    pub fn new(database: Arc<RwLock<Connection>>) -> Result<Self> {
-        let indexer = Arc::new(FullTextIndexer::new()?);
+    pub fn new(database: Arc<Mutex<Connection>>) -> Result<Self> {
+        let indexer = Arc::new(RwLock::new(FullTextIndexer::new()?));
+        let duplicates = Arc::new(RwLock::new(DuplicateDetector::new()));
         let ranker = Arc::new(SearchRanker::new());
         let similarity_engine = Arc::new(SimilarityEngine::new());
-        let stemmer = Stemmer::create(stemmer::Algorithm::English);
+        let facet_collector = FacetCollector::new();
+        let entity_searcher = EntitySearcher::new();
+        let fuzzy_expander = FuzzyExpander::new();
+        let pattern_expander = PatternExpander::new();
+        let suggester = QuerySuggester::new();
+        let analyzers = AnalyzerRegistry::new();
+        let snippet_generator = SnippetGenerator::new();
+        let synonyms = Arc::new(RwLock::new(SynonymDictionary::new()));
+        let profiles = Arc::new(RwLock::new(HashMap::new()));
+        let weight_trainer = WeightTrainer::new();
 
         Ok(Self {
             database,
             indexer,
+            duplicates,
             ranker,
             similarity_engine,
-            stemmer,
+            facet_collector,
+            entity_searcher,
+            fuzzy_expander,
+            pattern_expander,
+            suggester,
+            analyzers,
+            snippet_generator,
+            synonyms,
+            profiles,
+            weight_trainer,
+            saved_search_callback: Arc::new(RwLock::new(None)),
+            saved_search_run: Arc::new(Mutex::new(())),
+            saved_searches_pending: Arc::new(AtomicBool::new(false)),
+            query_cache: Arc::new(RwLock::new(QueryCache::new())),
+            index_path: None,
         })
     }
 
+    /// Search engine whose full-text index is persisted as segment files in
+    /// `index_path`, so startup does not have to re-tokenize every document
+    pub fn with_index_path(database: Arc<Mutex<Connection>>, index_path: PathBuf) -> Result<Self> {
+        let mut engine = Self::new(database)?;
+        engine.index_path = Some(index_path);
+        Ok(engine)
+    }
+
     pub async fn initialize(&self) -> Result<()> {
         info!("Initializing search engine");
+
+        {
+            let db = self.database.lock().await;
+            *self.synonyms.write().await = SynonymDictionary::load(&db)?;
+        }
         
-        // Initialize indexer
-        self.indexer.initialize().await?;
-        
-        // Build initial index from database
-        self.rebuild_index().await?;
+        match &self.index_path {
+            Some(index_path) => {
+                let (indexer, duplicates) = {
+                    let db = self.database.lock().await;
+                    (FullTextIndexer::open(index_path, &db)?, DuplicateDetector::load(&db)?)
+                };
+                *self.indexer.write().await = indexer;
+                *self.duplicates.write().await = duplicates;
+
+                // Catch up with documents written while the index was closed
+                self.reconcile_index().await?;
+            }
+            None => {
+                // Initialize indexer
+                self.indexer.read().await.initialize().await?;
+
+                // Build initial index from database
+                self.rebuild_index().await?;
+            }
+        }
         
         info!("Search engine initialized successfully");
         Ok(())
     }
 
+    async fn reconcile_index(&self) -> Result<()> {
+        {
+            let db = self.database.lock().await;
+            let mut indexer = self.indexer.write().await;
+            let mut duplicates = self.duplicates.write().await;
+
+            // Rows indexed before versions were kept have none and are
+            // indexed once more
+            Self::create_version_table(&db)?;
+            let mut stmt = db.prepare(
+                "SELECT d.id, d.modified_at IS NOT v.modified_at
+                 FROM documents d LEFT JOIN search_index_versions v ON v.document_id = d.id",
+            )?;
+            let rows: Vec<(String, bool)> = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            let stored: HashSet<String> = rows.iter().map(|(document_id, _)| document_id.clone()).collect();
+            Self::record_ingest_sequence(&db, &rows.iter().map(|(document_id, _)| document_id.clone()).collect::<Vec<_>>())?;
+
+            let indexed: HashSet<String> = indexer.document_ids().into_iter().collect();
+
+            let stale: Vec<&String> = indexed.difference(&stored).collect();
+            for document_id in &stale {
+                indexer.remove_document(document_id).await?;
+                duplicates.remove_document(document_id);
+            }
+
+            let missing: Vec<String> = stored.difference(&indexed).cloned().collect();
+            for document in self.load_documents(&db, &missing)? {
+                indexer.index_document(&document).await?;
+                indexer.add_surface_forms(&self.surface_forms(&document));
+                duplicates.add_document(&document);
+            }
+
+            // Rewritten in place, e.g. by INSERT OR REPLACE
+            let changed: Vec<String> = rows
+                .into_iter()
+                .filter(|(document_id, changed)| *changed && indexed.contains(document_id))
+                .map(|(document_id, _)| document_id)
+                .collect();
+            for document in self.load_documents(&db, &changed)? {
+                indexer.index_document(&document).await?;
+                indexer.add_surface_forms(&self.surface_forms(&document));
+                duplicates.add_document(&document);
+            }
+
+            // Indexed before signatures were kept
+            let unsigned: Vec<String> = indexed
+                .intersection(&stored)
+                .filter(|document_id| !duplicates.contains_document(document_id))
+                .cloned()
+                .collect();
+            for document in self.load_documents(&db, &unsigned)? {
+                duplicates.add_document(&document);
+            }
+
+            if !stale.is_empty() || !missing.is_empty() || !changed.is_empty() {
+                info!(
+                    "Search index reconciled: {} documents added, {} updated, {} removed",
+                    missing.len(),
+                    changed.len(),
+                    stale.len()
+                );
+            }
+        }
+
+        self.commit_index().await
+    }
+
     pub async fn search_documents(
         &self,
         query: &SearchQuery,
         callback: Box<dyn SearchCallback>,
     ) -> Result<()> {
+        self.stream_search(query, callback.as_ref(), &SearchHandle::new()).await
+    }
+
+    /// Search with results streamed to the callback as each stage
+    /// completes. Cancelling the handle stops the search at the next stage
+    /// boundary, which is not an error.
+    pub async fn stream_search(
+        &self,
+        query: &SearchQuery,
+        callback: &dyn SearchCallback,
+        handle: &SearchHandle,
+    ) -> Result<()> {
+        debug!("Streaming search with query: {}", query.text);
+
+        let stream = SearchStream { callback, handle };
+        match self.execute_recorded_search(query, Some(&stream)).await {
+            Ok(response) => {
+                if let Some(facets) = response.facets {
+                    callback.on_search_facets(facets);
+                }
+                callback.on_search_results(response.results);
+                callback.on_search_complete(response.total_hits);
+                Ok(())
+            }
+            Err(e) if e.is::<SearchCancelled>() => {
+                callback.on_search_cancelled();
+                Ok(())
+            }
+            Err(e) => {
+                callback.on_search_error(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn search(&self, query: &SearchQuery) -> Result<SearchResponse> {
         debug!("Searching documents with query: {}", query.text);
+        self.execute_recorded_search(query, None).await
+    }
 
-        let results = self.execute_search(query).await?;
-        callback.on_search_results(results);
-        
-        Ok(())
+    /// Run a search, or answer it from the query cache, and add it to the
+    /// search history. Later pages of the same search are not recorded
+    /// again, and a failure to record only costs the history entry, not
+    /// the results.
+    async fn execute_recorded_search(&self, query: &SearchQuery, stream: Option<&SearchStream<'_>>) -> Result<SearchResponse> {
+        let started = std::time::Instant::now();
+        let key = QueryCache::key(query);
+        let (cached, generation) = {
+            let mut cache = self.query_cache.write().await;
+            (cache.get(&key), cache.generation())
+        };
+        let response = match cached {
+            Some(response) => {
+                // The stages that check the handle are skipped
+                if let Some(stream) = stream {
+                    stream.handle.checkpoint()?;
+                }
+                response
+            }
+            None => {
+                let response = self.execute_search_with(query, stream).await?;
+                let dependencies = self.cache_dependencies(query).await;
+                self.query_cache.write().await.insert(key, &response, dependencies, generation);
+                response
+            }
+        };
+
+        if query.options.search_after.is_none() {
+            let entry = SearchHistoryEntry {
+                query: query.text.clone(),
+                profile_id: query.options.profile_id.clone(),
+                result_count: response.total_hits,
+                latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+                searched_at: chrono::Utc::now().timestamp(),
+            };
+            let db = self.database.lock().await;
+            if let Err(e) = SearchHistory::record(&db, &entry) {
+                warn!("Failed to record search history: {}", e);
+            }
+        }
+
+        Ok(response)
     }
 
     pub async fn search_entities(
@@ -178,20 +538,216 @@ impl SearchEngine {
         Ok(())
     }
 
-    async fn execute_search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>> {
+    /// The analyzed terms a query matches documents by, as far as the
+    /// index can tell which documents those are
+    async fn cache_dependencies(&self, query: &SearchQuery) -> CacheDependencies {
+        let analyzer = self.analyzers.analyzer(query.options.language.as_deref());
+        let parsed_query = ParsedQuery::parse(&query.text, |text| analyzer.analyze(text));
+        let query_tokens = parsed_query.all_terms();
+
+        let mut terms: HashSet<String> = query_tokens.iter().cloned().collect();
+        if !parsed_query.has_boolean_operators {
+            let term_groups = self.synonyms.read().await.expand(&query_tokens, |text| analyzer.analyze(text));
+            terms.extend(term_groups.into_iter().flatten().flat_map(|alternative| alternative.terms));
+        }
+
+        CacheDependencies {
+            broad: terms.is_empty()
+                || parsed_query.has_boolean_operators
+                || query.options.fuzzy_matching
+                || query.options.semantic_search
+                || query.options.query_mode != QueryMode::Terms
+                || query.filters.mentions.is_some()
+                || EntityQuery::parse(&query.text).has_references(),
+            terms,
+            profile_id: query.options.profile_id.clone(),
+        }
+    }
+
+    async fn execute_search(&self, query: &SearchQuery) -> Result<SearchResponse> {
+        self.execute_search_with(query, None).await
+    }
+
+    async fn execute_search_with(&self, query: &SearchQuery, stream: Option<&SearchStream<'_>>) -> Result<SearchResponse> {
         let mut results = Vec::new();
+        let latest_sequence = {
+            let db = self.database.lock().await;
+            Self::latest_ingest_sequence(&db)?
+        };
+        let page = PageRequest::from_options(&query.options, latest_sequence)?;
+        let snapshot = page.snapshot_filter();
+        if let Some(stream) = stream {
+            stream.handle.checkpoint()?;
+        }
 
-        // Tokenize and stem the query
-        let query_tokens = self.tokenize_and_stem(&query.text);
+        // Entity references leave the query text and require mentions
+        let resolved = self.resolve_entity_references(query).await?;
+        let (query, entity_groups) = match &resolved {
+            Some((resolved_query, groups)) => (resolved_query, groups.as_slice()),
+            None => (query, &[][..]),
+        };
+
+        // Tokenize and stem the query, keeping phrases and NEAR groups apart
+        let analyzer = self.analyzers.analyzer(query.options.language.as_deref());
+        let parsed_query = ParsedQuery::parse(&query.text, |text| analyzer.analyze(text));
+        let query_tokens = parsed_query.all_terms();
+        let profile = match query.options.profile_id.as_deref() {
+            Some(profile_id) => Some(self.ranking_profile(profile_id).await?),
+            None => None,
+        };
+
+        // Patterns are matched against index terms or the raw text rather
+        // than through FTS, in a single stage, and so are bare entity
+        // references through their mentions
+        if query.options.query_mode != QueryMode::Terms || (query.text.is_empty() && !entity_groups.is_empty()) {
+            let candidates = if query.text.is_empty() {
+                self.entity_candidates(query, entity_groups, snapshot, &page).await?
+            } else {
+                self.pattern_candidates(query, snapshot, &page).await?
+            };
+            if let Some(stream) = stream {
+                stream.handle.checkpoint()?;
+            }
+            return self.respond(candidates, query, entity_groups, profile.as_ref(), &page).await;
+        }
         
-        // Full-text search using SQLite FTS
-        let fts_results = self.fts_search(&query.text, &query.filters, &query.options).await?;
+        // Full-text search using SQLite FTS. Every mode returns enough
+        // top candidates to cover the requested page after merging.
+        let mut fts_results = self.fts_search(&query.text, &query.filters, &query.options.sort, snapshot, page.candidate_window()).await?;
+
+        // The index also matches analyzed terms, which catches inflections,
+        // folded accents and CJK bigrams the FTS tokenizer cannot. OR and
+        // NOT have no index equivalent, so those queries are left to FTS.
+        // Synonyms are expanded here, scored below the terms as typed.
+        let term_groups = if parsed_query.has_boolean_operators {
+            Vec::new()
+        } else {
+            self.synonyms.read().await.expand(&query_tokens, |text| analyzer.analyze(text))
+        };
+        let (index_results, index_ids) =
+            self.index_search(&term_groups, &query.filters, &query.options, snapshot, page.candidate_window()).await?;
+        fts_results.extend(index_results);
+
+        // Chunks rank long documents by their best passage rather than by
+        // their text as a whole, and find documents whose own row does not
+        // match
+        let chunk_hits = self.chunk_search(&query.text, &query.filters, snapshot, &query.options, page.candidate_window()).await?;
+        let chunk_scores = best_chunk_scores(&chunk_hits);
+        let seen: HashSet<String> = fts_results.iter().map(|result| result.id.clone()).collect();
+        let missing: Vec<String> = chunk_scores.keys().filter(|id| !seen.contains(*id)).cloned().collect();
+        if !missing.is_empty() {
+            let db = self.database.lock().await;
+            for document in self.load_documents(&db, &missing)? {
+                fts_results.push(SearchResult {
+                    id: document.id,
+                    result_type: SearchResultType::Document,
+                    title: document.title,
+                    content: Some(document.content),
+                    snippet: None,
+                    score: 0.0,
+                    metadata: document.metadata,
+                    highlights: Vec::new(),
+                    fragments: Vec::new(),
+                    passages: Vec::new(),
+                    sort_values: Vec::new(),
+                });
+            }
+        }
+
+        // Rescore candidates with BM25F plus a proximity boost from the
+        // in-memory index, and check phrase and NEAR constraints against
+        // stemmed positions. Documents not indexed yet keep SQLite's own
+        // bm25 score and phrase matching.
+        {
+            let indexer = self.indexer.read().await;
+            fts_results.retain(|result| {
+                !indexer.contains_document(&result.id) || indexer.matches_query(&parsed_query, &result.id)
+            });
+            for result in &mut fts_results {
+                if indexer.contains_document(&result.id) {
+                    let bm25 = indexer
+                        .score_alternatives(&term_groups, &result.id, &query.options.bm25)
+                        .unwrap_or_else(|| indexer.score_document(&query_tokens, &result.id, &query.options.bm25));
+                    let proximity = indexer.proximity_score(&query_tokens, &result.id);
+                    result.score = bm25 + self.ranker.proximity_weight() * proximity;
+                }
+                if let Some(passage) = chunk_scores.get(&result.id) {
+                    result.score += self.ranker.passage_weight() * passage;
+                }
+            }
+        }
         results.extend(fts_results);
 
+        // Facets need every match, not just the candidate window, and so
+        // does a total combining FTS and index matches
+        let match_ids = if query.options.include_facets || !index_ids.is_empty() {
+            let mut ids = self.fts_match_ids(&query.text, &query.filters, snapshot).await?;
+            let seen: HashSet<String> = ids.iter().cloned().collect();
+            ids.extend(index_ids.into_iter().filter(|id| !seen.contains(id)));
+            Some(ids)
+        } else {
+            None
+        };
+
+        let match_total = match match_ids {
+            Some(ref ids) => ids.len(),
+            None => self.fts_count(&query.text, &query.filters, snapshot).await?,
+        };
+
+        // Highlight synonyms as well as the query terms
+        let highlight_terms: HashSet<String> = query_tokens
+            .iter()
+            .cloned()
+            .chain(term_groups.iter().flatten().flat_map(|alternative| alternative.terms.iter().cloned()))
+            .collect();
+
+        if let Some(stream) = stream {
+            if query.options.fuzzy_matching || query.options.semantic_search {
+                let partial = self
+                    .partial_page(&results, &query_tokens, &highlight_terms, query, profile.as_ref(), &page)
+                    .await?;
+                stream.handle.checkpoint()?;
+                stream.callback.on_partial_results(SearchStage::Keyword, partial);
+            }
+        }
+
         // Fuzzy matching if enabled
         if query.options.fuzzy_matching {
-            let fuzzy_results = self.fuzzy_search(&query.text, &query.filters, &query.options).await?;
+            let indexer = self.indexer.read().await;
+            let expansions: Vec<Vec<TermAlternative>> = query_tokens
+                .iter()
+                .map(|term| {
+                    self.fuzzy_expander
+                        .expand(term, indexer.vocabulary())
+                        .into_iter()
+                        .map(|expansion| TermAlternative {
+                            terms: vec![expansion.term],
+                            phrase: false,
+                            weight: 1.0 / (1.0 + expansion.distance as f64),
+                        })
+                        .collect()
+                })
+                .collect();
+            drop(indexer);
+
+            let (fuzzy_results, _) = self.index_search(
+                &expansions,
+                &query.filters,
+                &query.options,
+                snapshot,
+                page.candidate_window(),
+            ).await?;
             results.extend(fuzzy_results);
+
+            if let Some(stream) = stream {
+                if query.options.semantic_search {
+                    let partial = self
+                        .partial_page(&results, &query_tokens, &highlight_terms, query, profile.as_ref(), &page)
+                        .await?;
+                    stream.handle.checkpoint()?;
+                    stream.callback.on_partial_results(SearchStage::Fuzzy, partial);
+                }
+            }
         }
 
         // Semantic search if enabled
@@ -199,387 +755,1905 @@ impl SearchEngine {
             let semantic_results = self.semantic_search(&query.text, &query.filters, &query.options).await?;
             results.extend(semantic_results);
         }
+        if let Some(stream) = stream {
+            stream.handle.checkpoint()?;
+        }
 
-        // Remove duplicates and rank results
-        results = self.deduplicate_results(results);
-        results = self.ranker.rank_results(results, &query_tokens, &query.options).await?;
+        // Other modes can only add to the FTS total, and we only ever see
+        // their top candidates, so the combined total is an estimate
+        let candidates = Candidates {
+            results,
+            match_ids,
+            match_total,
+            estimated: query.options.fuzzy_matching || query.options.semantic_search,
+            timed_out: false,
+            query_tokens,
+            highlight_terms,
+        };
+        self.respond(candidates, query, entity_groups, profile.as_ref(), &page).await
+    }
 
-        // Apply pagination
-        let limit = query.options.limit.unwrap_or(20);
-        let offset = query.options.offset.unwrap_or(0);
-        
-        if offset < results.len() {
-            let end = std::cmp::min(offset + limit, results.len());
-            results = results[offset..end].to_vec();
-        } else {
-            results.clear();
+    /// Rank candidates, count them, then page, highlight and attach passages
+    async fn respond(
+        &self,
+        candidates: Candidates,
+        query: &SearchQuery,
+        entity_groups: &[EntityGroup],
+        profile: Option<&RankingProfile>,
+        page: &PageRequest,
+    ) -> Result<SearchResponse> {
+        let Candidates { mut results, match_ids, match_total, estimated, timed_out, query_tokens, mut highlight_terms } = candidates;
+        if !entity_groups.is_empty() {
+            self.add_entity_scores(&mut results, entity_groups).await?;
+            let analyzer = self.analyzers.analyzer(query.options.language.as_deref());
+            for entity in entity_groups.iter().flat_map(|group| &group.entities) {
+                highlight_terms.extend(analyzer.analyze(&entity.name));
+            }
         }
+        let (mut results, candidate_count) = self.rank_candidates(results, &query_tokens, query, profile, page).await?;
+
+        // Collapsing and diversity limits only see the candidates, so
+        // dropping any makes the total an estimate unless every match was
+        // among them
+        let dropped = candidate_count - results.len();
+        let total_hits_exact = !estimated && (dropped == 0 || match_total <= candidate_count);
+        let total_hits = std::cmp::max(match_total.saturating_sub(dropped), results.len());
+
+        // Facets are counted over the full match set, before pagination
+        let facets = match match_ids.filter(|_| query.options.include_facets) {
+            Some(mut document_ids) => {
+                let seen: HashSet<String> = document_ids.iter().cloned().collect();
+                document_ids.extend(results.iter().filter(|r| !seen.contains(&r.id)).map(|r| r.id.clone()));
+
+                let db = self.database.lock().await;
+                Some(self.facet_collector.collect(&db, &document_ids, query.options.facet_date_interval)?)
+            }
+            None => None,
+        };
+
+        // Corrections are for words, not patterns
+        let did_you_mean = if total_hits == 0 && query.options.query_mode == QueryMode::Terms && !query.text.is_empty() {
+            self.suggest_queries(&query.text, 1).await?.into_iter().next().map(|s| s.text)
+        } else {
+            None
+        };
+
+        // Apply pagination
+        let ranked = results;
+        results = page.apply(&ranked);
+        let next_cursor = page.next_cursor(&ranked, &results, total_hits);
 
         // Generate snippets and highlights
         if query.options.include_snippets {
-            results = self.add_snippets_and_highlights(results, &query.text, &query.options).await?;
+            results = self.add_snippets_and_highlights(results, &highlight_terms, &query.options);
         }
-
-        Ok(results)
+        results = self.add_passages(results, &query.text, &highlight_terms, &query.options).await?;
+
+        Ok(SearchResponse {
+            results,
+            total_hits,
+            total_hits_exact,
+            next_cursor,
+            facets,
+            did_you_mean,
+            timed_out,
+            entities: entity_groups.to_vec(),
+        })
     }
 
-    async fn execute_entity_search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>> {
-        let db = self.database.read().await;
-        let mut results = Vec::new();
-
-        let mut sql = "SELECT id, entity_type, name, properties FROM entities WHERE 1=1".to_string();
-        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
-
-        // Add search condition
-        if !query.text.is_empty() {
-            sql.push_str(" AND name LIKE ?");
-            params.push(Box::new(format!("%{}%", query.text)));
+    /// Matches of a wildcard, regex or regex scan query. Wildcard and regex
+    /// words each expand to the most common index terms they match, and a
+    /// document must match every word; a pattern that matches more terms
+    /// than the expansion limit makes the total an estimate.
+    async fn pattern_candidates(&self, query: &SearchQuery, snapshot: Option<i64>, page: &PageRequest) -> Result<Candidates> {
+        let analyzer = self.analyzers.analyzer(query.options.language.as_deref());
+
+        if query.options.query_mode.expands_terms() {
+            let expansions = {
+                let indexer = self.indexer.read().await;
+                self.pattern_expander
+                    .expand(&query.text, query.options.query_mode, indexer.vocabulary(), |text| analyzer.analyze(text))?
+            };
+            let truncated = expansions.iter().any(|expansion| expansion.truncated);
+            let term_groups: Vec<Vec<TermAlternative>> = expansions
+                .iter()
+                .map(|expansion| expansion.terms.iter().map(|term| TermAlternative::exact(term)).collect())
+                .collect();
+            let (results, match_ids) =
+                self.index_search(&term_groups, &query.filters, &query.options, snapshot, page.candidate_window()).await?;
+
+            return Ok(Candidates {
+                results,
+                match_total: match_ids.len(),
+                match_ids: Some(match_ids),
+                estimated: truncated,
+                timed_out: false,
+                query_tokens: Vec::new(),
+                highlight_terms: expansions.into_iter().flat_map(|expansion| expansion.terms).collect(),
+            });
         }
 
-        // Add entity type filter
-        if let Some(ref entity_types) = query.filters.entity_types {
-            if !entity_types.is_empty() {
-                let placeholders = entity_types.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-                sql.push_str(&format!(" AND entity_type IN ({})", placeholders));
-                for entity_type in entity_types {
-                    params.push(Box::new(entity_type.clone()));
-                }
-            }
-        }
+        let timeout = std::time::Duration::from_millis(query.options.regex_timeout_ms.unwrap_or(DEFAULT_REGEX_TIMEOUT_MS));
+        let mut scanner = RegexScanner::new(&query.text, timeout)?;
 
-        sql.push_str(" ORDER BY name");
-        
-        let limit = query.options.limit.unwrap_or(20);
-        sql.push_str(&format!(" LIMIT {}", limit));
+        let db = self.database.lock().await;
+        let mut sql = "SELECT d.id, d.title, d.content, d.metadata FROM documents d WHERE 1 = 1".to_string();
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        Self::push_document_filters(&mut sql, &mut params, &query.filters, snapshot);
+        // A scan that times out keeps the first documents in the sort order
+        sql.push_str(&format!(" ORDER BY {}", order_by_sql(&query.options.sort, None)));
 
         let mut stmt = db.prepare(&sql)?;
         let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-        
-        let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| {
-            Ok(SearchResult {
-                id: row.get(0)?,
-                result_type: SearchResultType::Entity,
-                title: row.get(2)?, // name
-                content: None,
+        let mut rows = stmt.query(rusqlite::params_from_iter(param_refs))?;
+
+        let mut results = Vec::new();
+        let mut highlight_terms = HashSet::new();
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(0)?;
+            let title: String = row.get(1)?;
+            let content: String = row.get::<_, Option<String>>(2)?.unwrap_or_default();
+            let found = match scanner.scan(&id, &title, &content) {
+                Some(Some(found)) => found,
+                Some(None) => continue,
+                None => break,
+            };
+
+            // Matches in the title count double, as in the other modes
+            for text in &found.matched_text {
+                highlight_terms.extend(analyzer.analyze(text));
+            }
+            let metadata_str: String = row.get(3)?;
+            results.push(SearchResult {
+                id,
+                result_type: SearchResultType::Document,
+                title,
+                content: Some(content),
                 snippet: None,
-                score: 1.0, // TODO: Calculate relevance score
-                metadata: serde_json::from_str(row.get::<_, String>(3)?.as_str()).unwrap_or_default(),
+                score: (2 * found.title_matches + found.content_matches) as f64,
+                metadata: serde_json::from_str(&metadata_str).unwrap_or_default(),
                 highlights: Vec::new(),
-            })
-        })?;
-
-        for row in rows {
-            results.push(row?);
+                fragments: Vec::new(),
+                passages: Vec::new(),
+                sort_values: Vec::new(),
+            });
+        }
+        if scanner.timed_out() {
+            warn!("Regex scan for {} stopped after {:?} with {} matches", query.text, timeout, results.len());
         }
 
-        Ok(results)
+        let match_ids: Vec<String> = results.iter().map(|result| result.id.clone()).collect();
+        Ok(Candidates {
+            results,
+            match_total: match_ids.len(),
+            match_ids: Some(match_ids),
+            estimated: scanner.timed_out(),
+            timed_out: scanner.timed_out(),
+            query_tokens: Vec::new(),
+            highlight_terms,
+        })
     }
 
-    async fn fts_search(
-        &self,
-        query: &str,
-        filters: &SearchFilters,
-        options: &SearchOptions,
-    ) -> Result<Vec<SearchResult>> {
-        let db = self.database.read().await;
-        let mut results = Vec::new();
-
-        let mut sql = r#"
-            SELECT d.id, d.title, d.content, d.metadata, d.source_type, rank
-            FROM documents_fts 
-            JOIN documents d ON documents_fts.content_id = d.id
-            WHERE documents_fts MATCH ?
-        "#.to_string();
+    /// The query without its entity references, requiring a mention of
+    /// one entity of each, and what the references resolved to. None when
+    /// the query has no references.
+    async fn resolve_entity_references(&self, query: &SearchQuery) -> Result<Option<(SearchQuery, Vec<EntityGroup>)>> {
+        let entity_query = EntityQuery::parse(&query.text);
+        if !entity_query.has_references() {
+            return Ok(None);
+        }
 
-        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(query.to_string())];
+        let groups = {
+            let db = self.database.lock().await;
+            entity_query
+                .references
+                .iter()
+                .map(|reference| EntityResolver::resolve(&db, reference, query.options.entity_expansion.as_ref()))
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        let mut resolved = query.clone();
+        resolved.text = entity_query.text;
+        let mentions = resolved.filters.mentions.get_or_insert_with(Vec::new);
+        mentions.extend(groups.iter().map(|group| group.entity_ids()));
+        Ok(Some((resolved, groups)))
+    }
 
-        // Add filters
-        if let Some(ref source_types) = filters.source_types {
-            if !source_types.is_empty() {
-                let placeholders = source_types.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-                sql.push_str(&format!(" AND d.source_type IN ({})", placeholders));
-                for source_type in source_types {
-                    params.push(Box::new(source_type.clone()));
-                }
+    /// Documents mentioning the referenced entities, for a query that is
+    /// nothing but references. Their scores come from the mentions, added
+    /// in `respond` as for any other query.
+    async fn entity_candidates(
+        &self,
+        query: &SearchQuery,
+        entity_groups: &[EntityGroup],
+        snapshot: Option<i64>,
+        page: &PageRequest,
+    ) -> Result<Candidates> {
+        let db = self.database.lock().await;
+        let entity_ids: Vec<String> = entity_groups.iter().flat_map(|group| group.entity_ids()).collect();
+
+        let mut mentions = Vec::new();
+        if !entity_ids.is_empty() {
+            let mut sql = format!(
+                "SELECT m.document_id, m.entity_id FROM entity_mentions m JOIN documents d ON d.id = m.document_id
+                 WHERE m.entity_id IN ({})",
+                entity_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",")
+            );
+            let mut params: Vec<Box<dyn rusqlite::ToSql>> =
+                entity_ids.iter().map(|id| Box::new(id.clone()) as Box<dyn rusqlite::ToSql>).collect();
+            Self::push_document_filters(&mut sql, &mut params, &query.filters, snapshot);
+
+            let mut stmt = db.prepare(&sql)?;
+            let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+            let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                mentions.push(row?);
             }
         }
 
-        if let Some(ref date_range) = filters.date_range {
-            if let Some(start) = date_range.start {
-                sql.push_str(" AND d.modified_at >= ?");
-                params.push(Box::new(start.timestamp()));
-            }
-            if let Some(end) = date_range.end {
-                sql.push_str(" AND d.modified_at <= ?");
-                params.push(Box::new(end.timestamp()));
-            }
+        let mut scores: Vec<(String, f64)> = document_entity_scores(&mentions, entity_groups).into_iter().collect();
+        let match_ids: Vec<String> = scores.iter().map(|(id, _)| id.clone()).collect();
+        if is_custom(&query.options.sort) {
+            let values = Self::load_sort_values(&db, &query.options.sort, &scores)?;
+            scores.sort_by(|a, b| compare(&query.options.sort, (&values[&a.0], &a.0), (&values[&b.0], &b.0)));
+        } else {
+            scores.sort_by(|a, b| {
+                b.1.partial_cmp(&a.1)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then_with(|| a.0.cmp(&b.0))
+            });
         }
-
-        sql.push_str(" ORDER BY rank");
-        
-        let limit = options.limit.unwrap_or(20);
-        sql.push_str(&format!(" LIMIT {}", limit));
-
-        let mut stmt = db.prepare(&sql)?;
-        let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-        
-        let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| {
-            let metadata_str: String = row.get(3)?;
-            let metadata = serde_json::from_str(&metadata_str).unwrap_or_default();
-            
-            Ok(SearchResult {
-                id: row.get(0)?,
+        scores.truncate(page.candidate_window());
+
+        let window: Vec<String> = scores.into_iter().map(|(id, _)| id).collect();
+        let results = self
+            .load_documents(&db, &window)?
+            .into_iter()
+            .map(|document| SearchResult {
+                id: document.id,
                 result_type: SearchResultType::Document,
-                title: row.get(1)?,
-                content: Some(row.get(2)?),
-                snippet: None, // Will be generated later
-                score: row.get::<_, f64>(5)?,
-                metadata,
+                title: document.title,
+                content: Some(document.content),
+                snippet: None,
+                score: 0.0,
+                metadata: document.metadata,
                 highlights: Vec::new(),
+                fragments: Vec::new(),
+                passages: Vec::new(),
+                sort_values: Vec::new(),
             })
-        })?;
+            .collect();
+
+        Ok(Candidates {
+            results,
+            match_total: match_ids.len(),
+            match_ids: Some(match_ids),
+            estimated: false,
+            timed_out: false,
+            query_tokens: Vec::new(),
+            highlight_terms: HashSet::new(),
+        })
+    }
 
-        for row in rows {
-            results.push(row?);
+    /// Boost results by how strongly they mention the referenced entities
+    async fn add_entity_scores(&self, results: &mut [SearchResult], entity_groups: &[EntityGroup]) -> Result<()> {
+        let mut mentions = Vec::new();
+        {
+            let db = self.database.lock().await;
+            let ids: Vec<&str> = results.iter().map(|result| result.id.as_str()).collect();
+            for batch in ids.chunks(500) {
+                let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+                let sql = format!("SELECT document_id, entity_id FROM entity_mentions WHERE document_id IN ({})", placeholders);
+                let mut stmt = db.prepare(&sql)?;
+                let rows = stmt.query_map(rusqlite::params_from_iter(batch.iter()), |row| Ok((row.get(0)?, row.get(1)?)))?;
+                for row in rows {
+                    mentions.push(row?);
+                }
+            }
         }
 
-        Ok(results)
+        let scores = document_entity_scores(&mentions, entity_groups);
+        for result in results.iter_mut() {
+            if let Some(score) = scores.get(&result.id) {
+                result.score += self.ranker.entity_weight() * score;
+            }
+        }
+        Ok(())
     }
 
-    async fn fuzzy_search(
+    /// Deduplicate and rank candidates, then apply the profile's
+    /// preferences, diversity over the personalized order and duplicate
+    /// collapsing. Also returns how many candidates there were before
+    /// diversity and collapsing dropped any.
+    async fn rank_candidates(
         &self,
-        query: &str,
-        filters: &SearchFilters,
-        options: &SearchOptions,
-    ) -> Result<Vec<SearchResult>> {
-        let db = self.database.read().await;
-        let mut results = Vec::new();
+        results: Vec<SearchResult>,
+        query_tokens: &[String],
+        query: &SearchQuery,
+        profile: Option<&RankingProfile>,
+        page: &PageRequest,
+    ) -> Result<(Vec<SearchResult>, usize)> {
+        let results = self.deduplicate_results(results);
+        let ranker = self.profile_ranker(profile);
+        let mut results = ranker.rank_results(results, query_tokens, &query.options).await?;
+
+        if let Some(profile) = profile {
+            results = profile.personalization.personalize_results(results, query.options.profile_id.as_deref());
+        }
+        let candidate_count = results.len();
+        if let Some(diversity) = &query.options.diversity {
+            let analyzer = self.analyzers.analyzer(query.options.language.as_deref());
+            results = self.diversify_results(results, diversity, analyzer, page.seen()).await;
+        }
+        if query.options.collapse_duplicates {
+            self.collapse_duplicates(&mut results).await;
+        }
+        if is_custom(&query.options.sort) {
+            self.sort_results(&mut results, &query.options.sort).await?;
+        }
 
-        // Get all documents and perform fuzzy matching in memory
-        let mut sql = "SELECT id, title, content, metadata FROM documents WHERE 1=1".to_string();
-        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        Ok((results, candidate_count))
+    }
 
-        // Add filters (similar to FTS search)
-        if let Some(ref source_types) = filters.source_types {
-            if !source_types.is_empty() {
-                let placeholders = source_types.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-                sql.push_str(&format!(" AND source_type IN ({})", placeholders));
-                for source_type in source_types {
-                    params.push(Box::new(source_type.clone()));
-                }
-            }
+    /// Order ranked results by the query's sort keys, keeping each result's
+    /// values on it
+    async fn sort_results(&self, results: &mut [SearchResult], sort: &[SortKey]) -> Result<()> {
+        let scores: Vec<(String, f64)> = results.iter().map(|result| (result.id.clone(), result.score)).collect();
+        let mut values = {
+            let db = self.database.lock().await;
+            Self::load_sort_values(&db, sort, &scores)?
+        };
+        for result in results.iter_mut() {
+            result.sort_values = values.remove(&result.id).unwrap_or_else(|| vec![SortValue::Missing; sort.len()]);
         }
+        results.sort_by(|a, b| compare(sort, (&a.sort_values, &a.id), (&b.sort_values, &b.id)));
+        Ok(())
+    }
 
-        let limit = options.limit.unwrap_or(100); // Get more for fuzzy filtering
-        sql.push_str(&format!(" LIMIT {}", limit));
-
-        let mut stmt = db.prepare(&sql)?;
-        let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-        
-        let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| {
-            let title: String = row.get(1)?;
-            let content: String = row.get(2)?;
-            let metadata_str: String = row.get(3)?;
-            let metadata = serde_json::from_str(&metadata_str).unwrap_or_default();
-            
-            // Calculate fuzzy similarity
-            let title_similarity = strsim::jaro_winkler(query, &title);
-            let content_similarity = strsim::jaro_winkler(query, &content);
-            let max_similarity = title_similarity.max(content_similarity);
-            
-            if max_similarity > 0.3 { // Threshold for fuzzy matching
-                Ok(Some(SearchResult {
-                    id: row.get(0)?,
-                    result_type: SearchResultType::Document,
-                    title,
-                    content: Some(content),
-                    snippet: None,
-                    score: max_similarity,
-                    metadata,
-                    highlights: Vec::new(),
-                }))
-            } else {
-                Ok(None)
+    /// Each document's value for every sort key, relevance being its score
+    fn load_sort_values(db: &Connection, sort: &[SortKey], scores: &[(String, f64)]) -> Result<HashMap<String, Vec<SortValue>>> {
+        let columns: Vec<&str> = sort.iter().filter_map(|key| key.column()).collect();
+        let mut values: HashMap<String, Vec<SortValue>> = HashMap::new();
+        for batch in scores.chunks(500) {
+            let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let sql = format!(
+                "SELECT d.id{} FROM documents d WHERE d.id IN ({})",
+                columns.iter().map(|column| format!(", {}", column)).collect::<String>(),
+                placeholders
+            );
+
+            let mut stmt = db.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(batch.iter().map(|(id, _)| id)))?;
+            let mut batch_values: HashMap<String, Vec<SortValue>> = HashMap::new();
+            while let Some(row) = rows.next()? {
+                let row_values = (1..=columns.len())
+                    .map(|i| Ok(SortValue::from_sql(row.get_ref(i)?)))
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                batch_values.insert(row.get(0)?, row_values);
             }
-        })?;
 
-        for row_result in rows {
-            if let Ok(Some(result)) = row_result {
-                results.push(result);
+            for (id, score) in batch {
+                let mut row_values = batch_values.remove(id).unwrap_or_default().into_iter();
+                let document_values = sort
+                    .iter()
+                    .map(|key| match key.field {
+                        SortField::Relevance => SortValue::Number(*score),
+                        _ => row_values.next().unwrap_or(SortValue::Missing),
+                    })
+                    .collect();
+                values.insert(id.clone(), document_values);
             }
         }
-
-        // Sort by similarity score
-        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
-
-        Ok(results)
+        Ok(values)
     }
 
-    async fn semantic_search(
+    /// The page as it would be if the search stopped at the current stage
+    async fn partial_page(
         &self,
-        query: &str,
-        _filters: &SearchFilters,
-        _options: &SearchOptions,
+        results: &[SearchResult],
+        query_tokens: &[String],
+        highlight_terms: &HashSet<String>,
+        query: &SearchQuery,
+        profile: Option<&RankingProfile>,
+        page: &PageRequest,
     ) -> Result<Vec<SearchResult>> {
-        // TODO: Implement semantic search using embeddings
-        // For now, return empty results
-        info!("Semantic search not yet implemented");
-        Ok(Vec::new())
+        let (ranked, _) = self.rank_candidates(results.to_vec(), query_tokens, query, profile, page).await?;
+        let mut partial = page.apply(&ranked);
+        if query.options.include_snippets {
+            partial = self.add_snippets_and_highlights(partial, highlight_terms, &query.options);
+        }
+        self.add_passages(partial, &query.text, highlight_terms, &query.options).await
     }
 
-    fn deduplicate_results(&self, mut results: Vec<SearchResult>) -> Vec<SearchResult> {
+    /// "Did you mean" corrections for a query, best first
+    pub async fn suggest_queries(&self, query: &str, limit: usize) -> Result<Vec<QuerySuggestion>> {
+        let indexer = self.indexer.read().await;
+        Ok(self.suggester.suggest(query, &indexer, |text| self.tokenize_and_stem(text), limit))
+    }
+
+    /// Search box completions for the text typed so far
+    pub async fn autocomplete(&self, text: &str, limit: usize) -> Result<Vec<Completion>> {
+        let db = self.database.lock().await;
+        let indexer = self.indexer.read().await;
+        self.suggester.complete(&db, &indexer, text, limit)
+    }
+
+    pub async fn add_synonyms(&self, rule: SynonymRule) -> Result<SynonymEntry> {
+        let db = self.database.lock().await;
+        let entry = self.synonyms.write().await.add(&db, rule)?;
+        self.query_cache.write().await.clear();
+        Ok(entry)
+    }
+
+    pub async fn remove_synonyms(&self, id: &str) -> Result<bool> {
+        let db = self.database.lock().await;
+        let removed = self.synonyms.write().await.remove(&db, id)?;
+        self.query_cache.write().await.clear();
+        Ok(removed)
+    }
+
+    pub async fn list_synonyms(&self) -> Vec<SynonymEntry> {
+        self.synonyms.read().await.entries().to_vec()
+    }
+
+    /// Groups of near-duplicate documents at `threshold` similarity, or
+    /// at the configured threshold when None
+    pub async fn duplicate_groups(&self, threshold: Option<f64>) -> Vec<DuplicateGroup> {
+        self.duplicates.read().await.groups(threshold)
+    }
+
+    pub async fn set_duplicate_threshold(&self, threshold: f64) {
+        self.duplicates.write().await.set_threshold(threshold);
+        self.query_cache.write().await.clear();
+    }
+
+    /// Bound the query cache by the serialized size of its responses. Zero
+    /// turns caching off. Cached responses are dropped.
+    pub async fn set_query_cache_limit(&self, max_bytes: usize) {
+        *self.query_cache.write().await = QueryCache::with_max_bytes(max_bytes);
+    }
+
+    pub async fn query_cache_stats(&self) -> CacheStats {
+        self.query_cache.read().await.stats()
+    }
+
+    /// Record what a user did with a search result. Clicks, opens and
+    /// dismissals also move the profile's personalization preferences;
+    /// ranking weights only change when the profile is trained.
+    pub async fn record_feedback(
+        &self,
+        profile_id: &str,
+        query: &str,
+        document_id: &str,
+        position: usize,
+        action: FeedbackAction,
+    ) -> Result<()> {
+        let db = self.database.lock().await;
+        let document = self
+            .load_documents(&db, &[document_id.to_string()])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Document not found: {}", document_id))?;
+
+        // The query is analyzed as the document was, to match its terms
+        let language = document.metadata.get("language").and_then(|l| l.as_str()).map(|l| l.to_string());
+        let query_terms = self.analyze(query, language.as_deref());
+        let result = SearchResult {
+            id: document.id,
+            result_type: SearchResultType::Document,
+            title: document.title,
+            content: Some(document.content),
+            snippet: None,
+            score: 0.0,
+            metadata: document.metadata,
+            highlights: Vec::new(),
+            fragments: Vec::new(),
+            passages: Vec::new(),
+            sort_values: Vec::new(),
+        };
+
+        FeedbackStore::record(&db, &FeedbackEvent {
+            profile_id: profile_id.to_string(),
+            query: query.to_string(),
+            document_id: document_id.to_string(),
+            position,
+            action,
+            features: self.ranker.ranking_features(&result, &query_terms),
+            timestamp: chrono::Utc::now().timestamp(),
+        })?;
+
+        if action != FeedbackAction::Shown {
+            let mut profiles = self.profiles.write().await;
+            let profile = Self::cached_profile(&mut profiles, &db, profile_id)?;
+
+            for key in PersonalizationEngine::preference_keys(&result.metadata) {
+                profile.personalization.update_preferences(&key, action.reward());
+            }
+            let repeated = profile.personalization.search_history().last().map(|last| last.as_str()) == Some(query);
+            if action != FeedbackAction::Dismissed && !repeated {
+                profile.personalization.add_to_search_history(query);
+            }
+
+            FeedbackStore::save_profile(&db, profile_id, profile)?;
+            self.query_cache.write().await.invalidate_profile(profile_id);
+        }
+
+        Ok(())
+    }
+
+    /// Tune a profile's ranking weights on its recorded feedback. The new
+    /// weights are evaluated offline against the profile's most recent
+    /// searches and kept only if they rank those no worse than the current
+    /// ones. None when the feedback has nothing to learn from.
+    pub async fn train_ranking(&self, profile_id: &str) -> Result<Option<FeedbackEvaluation>> {
+        let db = self.database.lock().await;
+        let events = FeedbackStore::events(&db, profile_id)?;
+
+        let mut profiles = self.profiles.write().await;
+        let profile = Self::cached_profile(&mut profiles, &db, profile_id)?;
+        let baseline = profile.weights.unwrap_or_else(|| self.ranker.weights());
+
+        let evaluation = self.weight_trainer.train(&baseline, &events);
+        if let Some(evaluation) = evaluation.as_ref().filter(|evaluation| evaluation.applied) {
+            profile.weights = Some(evaluation.weights);
+            FeedbackStore::save_profile(&db, profile_id, profile)?;
+            self.query_cache.write().await.invalidate_profile(profile_id);
+            info!("Ranking weights for profile {} tuned on {} searches", profile_id, evaluation.training_sessions);
+        }
+
+        Ok(evaluation)
+    }
+
+    pub async fn ranking_profile(&self, profile_id: &str) -> Result<RankingProfile> {
+        let db = self.database.lock().await;
+        let mut profiles = self.profiles.write().await;
+        Ok(Self::cached_profile(&mut profiles, &db, profile_id)?.clone())
+    }
+
+    /// The ranker with a profile's learned weights, or the default one
+    fn profile_ranker(&self, profile: Option<&RankingProfile>) -> Arc<SearchRanker> {
+        match profile.and_then(|profile| profile.weights) {
+            Some(weights) => {
+                let mut ranker = (*self.ranker).clone();
+                ranker.set_ranking_weights(weights);
+                Arc::new(ranker)
+            }
+            None => self.ranker.clone(),
+        }
+    }
+
+    /// Cap results per type and source, then reorder by maximal marginal
+    /// relevance if asked, comparing results by TF-IDF cosine similarity.
+    /// `seen` results, from earlier pages, keep their place at the front.
+    async fn diversify_results(
+        &self,
+        results: Vec<SearchResult>,
+        options: &DiversityOptions,
+        analyzer: &Analyzer,
+        seen: &[String],
+    ) -> Vec<SearchResult> {
+        let mut diversifier = ResultDiversifier::new();
+        diversifier.set_diversity_limits(
+            options.max_per_type.unwrap_or(usize::MAX),
+            options.max_per_source.unwrap_or(usize::MAX),
+        );
+        let results = diversifier.diversify_results(results);
+
+        let lambda = match options.mmr_lambda {
+            Some(lambda) => lambda,
+            None => return results,
+        };
+
+        let indexer = self.indexer.read().await;
+        let vectors: Vec<HashMap<String, f64>> = results
+            .iter()
+            .map(|result| {
+                let mut terms = analyzer.analyze(&result.title);
+                terms.extend(analyzer.analyze(result.content.as_deref().unwrap_or_default()));
+                self.similarity_engine.tfidf_vector(&terms, |term| indexer.idf(term))
+            })
+            .collect();
+
+        diversifier.mmr_rerank(results, lambda, seen, |a, b| {
+            self.similarity_engine.tfidf_cosine_similarity(&vectors[a], &vectors[b])
+        })
+    }
+
+    fn cached_profile<'a>(
+        profiles: &'a mut HashMap<String, RankingProfile>,
+        db: &Connection,
+        profile_id: &str,
+    ) -> Result<&'a mut RankingProfile> {
+        Ok(match profiles.entry(profile_id.to_string()) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => entry.insert(FeedbackStore::load_profile(db, profile_id)?),
+        })
+    }
+
+    /// Save `query` under `name`. Only documents ingested from now on are
+    /// reported as its new matches.
+    pub async fn save_search(&self, name: &str, query: &SearchQuery) -> Result<SavedSearch> {
+        let db = self.database.lock().await;
+        SavedSearchStore::save(&db, name, query, chrono::Utc::now().timestamp())
+    }
+
+    pub async fn list_saved_searches(&self) -> Result<Vec<SavedSearch>> {
+        let db = self.database.lock().await;
+        SavedSearchStore::list(&db)
+    }
+
+    pub async fn delete_saved_search(&self, id: &str) -> Result<bool> {
+        let db = self.database.lock().await;
+        SavedSearchStore::delete(&db, id)
+    }
+
+    /// Run saved searches automatically after each batch of documents
+    /// indexed with `index_documents_with`
+    pub async fn set_saved_search_callback(&self, callback: Box<dyn SavedSearchCallback>) {
+        *self.saved_search_callback.write().await = Some(callback);
+    }
+
+    /// Run every saved search over the documents ingested since its last
+    /// run. Returns an alert for each search with new matches, best first;
+    /// a document is only ever reported once per saved search.
+    pub async fn run_saved_searches(&self) -> Result<Vec<SavedSearchAlert>> {
+        let mut alerts = Vec::new();
+
+        for saved in self.list_saved_searches().await? {
+            let run_at = chrono::Utc::now().timestamp();
+            let mut candidates = {
+                let db = self.database.lock().await;
+                SavedSearchStore::new_documents(&db, &saved)?
+            };
+            if let Some(ref allowed) = saved.query.filters.document_ids {
+                candidates.retain(|id| allowed.contains(id));
+            }
+
+            let mut results = Vec::new();
+            for batch in candidates.chunks(500) {
+                let mut query = saved.query.clone();
+                query.filters.document_ids = Some(batch.to_vec());
+                query.options.offset = Some(0);
+                query.options.limit = Some(batch.len());
+                query.options.search_after = None;
+                query.options.include_facets = false;
+                results.extend(self.execute_search(&query).await?.results);
+            }
+            results.sort_by(|a, b| {
+                b.score.partial_cmp(&a.score)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then_with(|| a.id.cmp(&b.id))
+            });
+
+            let matched: HashSet<String> = results.iter().map(|result| result.id.clone()).collect();
+            let recorded = {
+                let db = self.database.lock().await;
+                SavedSearchStore::record_run(&db, &saved.id, run_at, &matched)?
+            };
+
+            if recorded && !results.is_empty() {
+                alerts.push(SavedSearchAlert {
+                    saved_search_id: saved.id,
+                    name: saved.name,
+                    results,
+                });
+            }
+        }
+
+        Ok(alerts)
+    }
+
+    /// Run saved searches for the callback. Batches indexed while a run is
+    /// underway are left to one more run when it finishes, rather than each
+    /// running every saved search again.
+    async fn notify_saved_searches(&self) {
+        let callback = self.saved_search_callback.read().await;
+        let callback = match callback.as_ref() {
+            Some(callback) => callback,
+            None => return,
+        };
+
+        self.saved_searches_pending.store(true, Ordering::SeqCst);
+        loop {
+            let running = match self.saved_search_run.try_lock() {
+                Ok(running) => running,
+                Err(_) => return,
+            };
+            while self.saved_searches_pending.swap(false, Ordering::SeqCst) {
+                match self.run_saved_searches().await {
+                    Ok(alerts) => {
+                        for alert in alerts {
+                            callback.on_saved_search_matches(alert);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to run saved searches: {}", e);
+                        callback.on_saved_search_error(e.to_string());
+                    }
+                }
+            }
+            drop(running);
+
+            // A batch may have finished between the last run and the unlock
+            if !self.saved_searches_pending.load(Ordering::SeqCst) {
+                return;
+            }
+        }
+    }
+
+    /// Documents related to `document_id`, most similar first. Candidates
+    /// share the source's most distinctive terms and are ranked by TF-IDF
+    /// cosine similarity, averaged with embedding similarity when both
+    /// documents have chunk embeddings. Near-duplicates of the source are
+    /// left out.
+    pub async fn find_similar(&self, document_id: &str, limit: usize) -> Result<Vec<SearchResult>> {
+        let db = self.database.lock().await;
+        let indexer = self.indexer.read().await;
+
+        let source = self
+            .load_documents(&db, &[document_id.to_string()])?
+            .pop()
+            .ok_or_else(|| anyhow!("Document not found: {}", document_id))?;
+        let document_terms = |document: &IndexedDocument| -> Vec<String> {
+            document.title_tokens.iter().chain(document.tokens.iter()).cloned().collect()
+        };
+        let source_vector = self
+            .similarity_engine
+            .tfidf_vector(&document_terms(&source), |term| indexer.idf(term));
+
+        // The most distinctive terms of the source select the candidates
+        let mut key_terms: Vec<(&String, &f64)> = source_vector.iter().collect();
+        key_terms.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.0.cmp(b.0)));
+        key_terms.truncate(MORE_LIKE_THIS_TERMS);
+
+        let bm25 = Bm25Parameters::default();
+        let mut candidate_scores: HashMap<String, f64> = HashMap::new();
+        for (term, weight) in key_terms {
+            for candidate in indexer.get_term_documents(term) {
+                if candidate != document_id {
+                    let score = weight * indexer.bm25_term_score(term, &candidate, &bm25);
+                    *candidate_scores.entry(candidate).or_insert(0.0) += score;
+                }
+            }
+        }
+        let mut candidates: Vec<(String, f64)> = candidate_scores.into_iter().collect();
+        candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.0.cmp(&b.0)));
+        candidates.truncate((limit * 5).max(50));
+
+        let candidate_ids: Vec<String> = candidates.into_iter().map(|(id, _)| id).collect();
+        let documents = self.load_documents(&db, &candidate_ids)?;
+        let mut embeddings = self.load_embeddings(&db, &candidate_ids)?;
+        let source_embedding = self.load_embeddings(&db, &[document_id.to_string()])?.remove(document_id);
+
+        let mut results = Vec::new();
+        for document in documents {
+            let vector = self
+                .similarity_engine
+                .tfidf_vector(&document_terms(&document), |term| indexer.idf(term));
+            let mut similarity = self.similarity_engine.tfidf_cosine_similarity(&source_vector, &vector);
+
+            if let (Some(source_embedding), Some(embedding)) = (&source_embedding, embeddings.remove(&document.id)) {
+                if let Ok(vector_similarity) = self.similarity_engine.cosine_similarity(source_embedding, &embedding) {
+                    similarity = (similarity + vector_similarity) / 2.0;
+                }
+            }
+
+            if similarity <= 0.0 || self.similarity_engine.is_near_duplicate(similarity) {
+                continue;
+            }
+
+            let snippet = self
+                .snippet_generator
+                .fragments(&document.content, &[], &HashSet::new(), 1)
+                .first()
+                .map(|fragment| self.snippet_generator.snippet(&document.content, fragment));
+
+            results.push(SearchResult {
+                id: document.id,
+                result_type: SearchResultType::Document,
+                title: document.title,
+                content: Some(document.content),
+                snippet,
+                score: similarity,
+                metadata: document.metadata,
+                highlights: Vec::new(),
+                fragments: Vec::new(),
+                passages: Vec::new(),
+                sort_values: Vec::new(),
+            });
+        }
+
+        results.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        results.truncate(limit);
+
+        Ok(results)
+    }
+
+    /// Mean chunk embedding per document, for documents that have any.
+    /// Embeddings are stored as little-endian f32 arrays.
+    fn load_embeddings(&self, db: &Connection, ids: &[String]) -> Result<HashMap<String, Vec<f32>>> {
+        let has_chunks: bool = db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'document_chunks')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_chunks {
+            return Ok(HashMap::new());
+        }
+
+        let mut chunks: HashMap<String, Vec<Vec<f32>>> = HashMap::new();
+        for batch in ids.chunks(500) {
+            let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let sql = format!(
+                "SELECT document_id, embedding FROM document_chunks
+                 WHERE embedding IS NOT NULL AND document_id IN ({})
+                 ORDER BY document_id, chunk_index",
+                placeholders
+            );
+            let mut stmt = db.prepare(&sql)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(batch.iter()), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?;
+            for row in rows {
+                let (document_id, blob) = row?;
+                let embedding: Vec<f32> = blob
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect();
+                if !embedding.is_empty() {
+                    chunks.entry(document_id).or_default().push(embedding);
+                }
+            }
+        }
+
+        Ok(chunks
+            .into_iter()
+            .filter_map(|(document_id, chunks)| Some((document_id, self.similarity_engine.mean_embedding(&chunks)?)))
+            .collect())
+    }
+
+    async fn execute_entity_search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>> {
+        let db = self.database.lock().await;
+
+        self.entity_searcher.search(
+            &db,
+            &query.text,
+            query.filters.entity_types.as_deref(),
+            query.options.offset.unwrap_or(0),
+            query.options.limit.unwrap_or(20),
+        )
+    }
+
+    fn fts_from_clause(
+        query: &str,
+        filters: &SearchFilters,
+        snapshot: Option<i64>,
+    ) -> (String, Vec<Box<dyn rusqlite::ToSql>>) {
+        let mut sql = r#"
+            FROM documents_fts 
+            JOIN documents d ON documents_fts.content_id = d.id
+            WHERE documents_fts MATCH ?
+        "#.to_string();
+
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(query.to_string())];
+        Self::push_document_filters(&mut sql, &mut params, filters, snapshot);
+
+        (sql, params)
+    }
+
+    /// Append filter conditions on the documents table, aliased as `d`
+    fn push_document_filters(
+        sql: &mut String,
+        params: &mut Vec<Box<dyn rusqlite::ToSql>>,
+        filters: &SearchFilters,
+        snapshot: Option<i64>,
+    ) {
+        // Add filters
+        if let Some(ref source_types) = filters.source_types {
+            if !source_types.is_empty() {
+                let placeholders = source_types.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+                sql.push_str(&format!(" AND d.source_type IN ({})", placeholders));
+                for source_type in source_types {
+                    params.push(Box::new(source_type.clone()));
+                }
+            }
+        }
+
+        if let Some(ref date_range) = filters.date_range {
+            if let Some(start) = date_range.start {
+                sql.push_str(" AND d.modified_at >= ?");
+                params.push(Box::new(start.timestamp()));
+            }
+            if let Some(end) = date_range.end {
+                sql.push_str(" AND d.modified_at <= ?");
+                params.push(Box::new(end.timestamp()));
+            }
+        }
+
+        // One subquery per group, each satisfied by any of its entities. A
+        // group that resolved to no entity matches nothing.
+        for group in filters.mentions.iter().flatten() {
+            if group.is_empty() {
+                sql.push_str(" AND 0");
+                continue;
+            }
+            let placeholders = group.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            sql.push_str(&format!(
+                " AND d.id IN (SELECT document_id FROM entity_mentions WHERE entity_id IN ({}))",
+                placeholders
+            ));
+            for entity_id in group {
+                params.push(Box::new(entity_id.clone()));
+            }
+        }
+
+        if let Some(ref document_ids) = filters.document_ids {
+            let placeholders = document_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            sql.push_str(&format!(" AND d.id IN ({})", placeholders));
+            for document_id in document_ids {
+                params.push(Box::new(document_id.clone()));
+            }
+        }
+
+        // Pin every page to the documents that existed for the first one.
+        // Documents re-ingested since keep their first sequence number.
+        if let Some(snapshot) = snapshot {
+            sql.push_str(" AND d.id NOT IN (SELECT document_id FROM search_ingest_sequence WHERE sequence > ?)");
+            params.push(Box::new(snapshot));
+        }
+    }
+
+    async fn fts_search(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        sort: &[SortKey],
+        snapshot: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let db = self.database.lock().await;
+        let mut results = Vec::new();
+
+        let (from_clause, params) = Self::fts_from_clause(query, filters, snapshot);
+        let mut sql = format!(
+            "SELECT d.id, d.title, d.content, d.metadata, d.source_type, rank {}",
+            from_clause
+        );
+
+        // A custom sort picks the candidates by its own keys, so the window
+        // holds the first documents in that order rather than the best ranked
+        if is_custom(sort) {
+            sql.push_str(&format!(" ORDER BY {}", order_by_sql(sort, Some("rank"))));
+        } else {
+            sql.push_str(" ORDER BY rank, d.id");
+        }
+        sql.push_str(&format!(" LIMIT {}", limit));
+
+        let mut stmt = db.prepare(&sql)?;
+        let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        
+        let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| {
+            let metadata_str: String = row.get(3)?;
+            let metadata = serde_json::from_str(&metadata_str).unwrap_or_default();
+            
+            Ok(SearchResult {
+                id: row.get(0)?,
+                result_type: SearchResultType::Document,
+                title: row.get(1)?,
+                content: Some(row.get(2)?),
+                snippet: None, // Will be generated later
+                score: -row.get::<_, f64>(5)?, // FTS5 rank is negated bm25, lower is better
+                metadata,
+                highlights: Vec::new(),
+                fragments: Vec::new(),
+                passages: Vec::new(),
+                sort_values: Vec::new(),
+            })
+        })?;
+
+        for row in rows {
+            results.push(row?);
+        }
+
+        Ok(results)
+    }
+
+    /// Best matching chunks of documents that pass the filters, best
+    /// first. Empty when chunks are not indexed or the query skips them.
+    async fn chunk_search(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        snapshot: Option<i64>,
+        options: &SearchOptions,
+        limit: usize,
+    ) -> Result<Vec<ChunkHit>> {
+        if options.max_passages == Some(0) {
+            return Ok(Vec::new());
+        }
+        let db = self.database.lock().await;
+        if !Self::chunks_indexed(&db)? {
+            return Ok(Vec::new());
+        }
+
+        let mut sql = r#"
+            SELECT c.document_id, c.id, c.chunk_index, c.start_position, c.end_position, c.content, rank
+            FROM document_chunks_fts
+            JOIN document_chunks c ON document_chunks_fts.chunk_id = c.id
+            JOIN documents d ON c.document_id = d.id
+            WHERE document_chunks_fts MATCH ?
+        "#.to_string();
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(query.to_string())];
+        Self::push_document_filters(&mut sql, &mut params, filters, snapshot);
+        sql.push_str(&format!(" ORDER BY rank, c.id LIMIT {}", limit));
+
+        let mut stmt = db.prepare(&sql)?;
+        let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), Self::read_chunk_hit)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Attach each result's best matching chunks, with a fragment of each
+    /// around the query terms
+    async fn add_passages(
+        &self,
+        mut results: Vec<SearchResult>,
+        query: &str,
+        query_terms: &HashSet<String>,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let per_document = options.max_passages.unwrap_or(DEFAULT_MAX_PASSAGES);
+        if per_document == 0 || results.is_empty() || options.query_mode != QueryMode::Terms {
+            return Ok(results);
+        }
+        let db = self.database.lock().await;
+        if !Self::chunks_indexed(&db)? {
+            return Ok(results);
+        }
+
+        // Results are a page, well under the 500 ids SQLite allows at once
+        let ids: Vec<&str> = results.iter().map(|result| result.id.as_str()).collect();
+        let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+        let sql = format!(
+            "SELECT c.document_id, c.id, c.chunk_index, c.start_position, c.end_position, c.content, rank
+             FROM document_chunks_fts
+             JOIN document_chunks c ON document_chunks_fts.chunk_id = c.id
+             WHERE document_chunks_fts MATCH ? AND c.document_id IN ({})
+             ORDER BY rank, c.id",
+            placeholders
+        );
+        let mut stmt = db.prepare(&sql)?;
+        let params = std::iter::once(query).chain(ids.iter().copied());
+        let hits = stmt
+            .query_map(rusqlite::params_from_iter(params), Self::read_chunk_hit)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut groups = group_by_document(hits, per_document);
+
+        for result in &mut results {
+            let Some(hits) = groups.remove(&result.id) else { continue };
+            let language = result.metadata.get("language").and_then(|value| value.as_str());
+            let analyzer = self.analyzers.analyzer(language);
+
+            result.passages = hits
+                .into_iter()
+                .map(|hit| {
+                    let tokens = analyzer.analyze_with_offsets(&hit.content);
+                    let mut fragment = self.snippet_generator.fragments(&hit.content, &tokens, query_terms, 1).into_iter().next();
+                    if !options.highlight_matches {
+                        if let Some(fragment) = &mut fragment {
+                            fragment.highlights.clear();
+                        }
+                    }
+                    ChunkPassage {
+                        chunk_id: hit.chunk_id,
+                        chunk_index: hit.chunk_index,
+                        start_position: hit.start_position,
+                        end_position: hit.end_position,
+                        score: hit.score,
+                        fragment,
+                    }
+                })
+                .collect();
+        }
+
+        Ok(results)
+    }
+
+    fn chunks_indexed(db: &Connection) -> Result<bool> {
+        Ok(db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'document_chunks_fts')",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn read_chunk_hit(row: &rusqlite::Row) -> rusqlite::Result<ChunkHit> {
+        Ok(ChunkHit {
+            document_id: row.get(0)?,
+            chunk_id: row.get(1)?,
+            chunk_index: row.get(2)?,
+            start_position: row.get(3)?,
+            end_position: row.get(4)?,
+            content: row.get(5)?,
+            score: -row.get::<_, f64>(6)?, // FTS5 rank is negated bm25, lower is better
+        })
+    }
+
+    async fn fts_count(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        snapshot: Option<i64>,
+    ) -> Result<usize> {
+        let db = self.database.lock().await;
+
+        let (from_clause, params) = Self::fts_from_clause(query, filters, snapshot);
+        let sql = format!("SELECT COUNT(*) {}", from_clause);
+
+        let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let count: i64 = db.query_row(&sql, rusqlite::params_from_iter(param_refs), |row| row.get(0))?;
+
+        Ok(count as usize)
+    }
+
+    async fn fts_match_ids(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        snapshot: Option<i64>,
+    ) -> Result<Vec<String>> {
+        let db = self.database.lock().await;
+
+        let (from_clause, params) = Self::fts_from_clause(query, filters, snapshot);
+        let sql = format!("SELECT d.id {}", from_clause);
+
+        let mut stmt = db.prepare(&sql)?;
+        let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| row.get::<_, String>(0))?;
+
+        let mut ids = Vec::new();
+        for row in rows {
+            ids.push(row?);
+        }
+
+        Ok(ids)
+    }
+
+    /// Search the in-memory index. Each group lists the alternatives for
+    /// one query term (the term itself, its fuzzy expansions or synonyms);
+    /// documents must match an alternative from every group and are scored
+    /// with BM25F, discounted by each matched alternative's weight. Returns
+    /// the top `window` results and the ids of every match passing the
+    /// filters.
+    async fn index_search(
+        &self,
+        term_groups: &[Vec<TermAlternative>],
+        filters: &SearchFilters,
+        options: &SearchOptions,
+        snapshot: Option<i64>,
+        window: usize,
+    ) -> Result<(Vec<SearchResult>, Vec<String>)> {
+        if term_groups.is_empty() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        let db = self.database.lock().await;
+        let indexer = self.indexer.read().await;
+
+        // Documents matching some alternative of every group, with phrase
+        // alternatives checked against positions while scoring
+        let mut candidate_scores: HashMap<String, f64> = indexer
+            .documents_matching(term_groups)
+            .into_iter()
+            .filter_map(|document_id| {
+                let score = indexer.score_alternatives(term_groups, &document_id, &options.bm25)?;
+                Some((document_id, score))
+            })
+            .collect();
+        let candidates: Vec<String> = candidate_scores.keys().cloned().collect();
+
+        // Apply filters on the documents table
+        let mut matching_ids = Vec::new();
+        for batch in candidates.chunks(500) {
+            let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let mut sql = format!("SELECT d.id FROM documents d WHERE d.id IN ({})", placeholders);
+            let mut params: Vec<Box<dyn rusqlite::ToSql>> = batch
+                .iter()
+                .map(|id| Box::new(id.clone()) as Box<dyn rusqlite::ToSql>)
+                .collect();
+            Self::push_document_filters(&mut sql, &mut params, filters, snapshot);
+
+            let mut stmt = db.prepare(&sql)?;
+            let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+            let rows = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| row.get::<_, String>(0))?;
+            for row in rows {
+                matching_ids.push(row?);
+            }
+        }
+
+        let mut scores: Vec<(String, f64)> = matching_ids
+            .iter()
+            .filter_map(|document_id| Some((document_id.clone(), candidate_scores.remove(document_id)?)))
+            .collect();
+
+        if is_custom(&options.sort) {
+            let values = Self::load_sort_values(&db, &options.sort, &scores)?;
+            let no_values = Vec::new();
+            let values_of = |id: &String| values.get(id).unwrap_or(&no_values).as_slice();
+            scores.sort_by(|a, b| compare(&options.sort, (values_of(&a.0), &a.0), (values_of(&b.0), &b.0)));
+        } else {
+            scores.sort_by(|a, b| {
+                b.1.partial_cmp(&a.1)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then_with(|| a.0.cmp(&b.0))
+            });
+        }
+        scores.truncate(window);
+
+        let mut results = Vec::with_capacity(scores.len());
+        for batch in scores.chunks(500) {
+            let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let sql = format!(
+                "SELECT id, title, content, metadata FROM documents WHERE id IN ({})",
+                placeholders
+            );
+
+            let mut stmt = db.prepare(&sql)?;
+            let mut rows: HashMap<String, SearchResult> = HashMap::new();
+            let mapped = stmt.query_map(rusqlite::params_from_iter(batch.iter().map(|(id, _)| id)), |row| {
+                let metadata_str: String = row.get(3)?;
+                let metadata = serde_json::from_str(&metadata_str).unwrap_or_default();
+
+                Ok(SearchResult {
+                    id: row.get(0)?,
+                    result_type: SearchResultType::Document,
+                    title: row.get(1)?,
+                    content: Some(row.get(2)?),
+                    snippet: None,
+                    score: 0.0,
+                    metadata,
+                    highlights: Vec::new(),
+                    fragments: Vec::new(),
+                    passages: Vec::new(),
+                    sort_values: Vec::new(),
+                })
+            })?;
+            for row in mapped {
+                let result = row?;
+                rows.insert(result.id.clone(), result);
+            }
+
+            for (document_id, score) in batch {
+                if let Some(mut result) = rows.remove(document_id) {
+                    result.score = *score;
+                    results.push(result);
+                }
+            }
+        }
+
+        Ok((results, matching_ids))
+    }
+
+    async fn semantic_search(
+        &self,
+        _query: &str,
+        _filters: &SearchFilters,
+        _options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        // TODO: Implement semantic search using embeddings
+        // For now, return empty results
+        info!("Semantic search not yet implemented");
+        Ok(Vec::new())
+    }
+
+    /// Drop results that are near-duplicates of a better ranked result,
+    /// listing them under `duplicate_ids` in the kept result's metadata.
+    /// Returns how many were dropped.
+    async fn collapse_duplicates(&self, results: &mut Vec<SearchResult>) -> usize {
+        let duplicates = self.duplicates.read().await;
+        let mut kept: Vec<SearchResult> = Vec::with_capacity(results.len());
+        let mut representative: HashMap<String, usize> = HashMap::new();
+        let before = results.len();
+
+        for result in results.drain(..) {
+            if let Some(&index) = representative.get(&result.id) {
+                if let Some(metadata) = kept[index].metadata.as_object_mut() {
+                    let ids = metadata.entry("duplicate_ids").or_insert_with(|| serde_json::json!([]));
+                    if let Some(ids) = ids.as_array_mut() {
+                        ids.push(serde_json::Value::String(result.id));
+                    }
+                }
+                continue;
+            }
+
+            for (duplicate_id, _) in duplicates.duplicates_of(&result.id, None) {
+                representative.entry(duplicate_id).or_insert(kept.len());
+            }
+            kept.push(result);
+        }
+
+        *results = kept;
+        before - results.len()
+    }
+
+    fn deduplicate_results(&self, mut results: Vec<SearchResult>) -> Vec<SearchResult> {
         let mut seen_ids = HashSet::new();
         results.retain(|result| seen_ids.insert(result.id.clone()));
         results
     }
 
-    async fn add_snippets_and_highlights(
+    fn add_snippets_and_highlights(
         &self,
         mut results: Vec<SearchResult>,
-        query: &str,
+        query_terms: &HashSet<String>,
         options: &SearchOptions,
-    ) -> Result<Vec<SearchResult>> {
-        let query_terms: Vec<&str> = query.split_whitespace().collect();
-        
+    ) -> Vec<SearchResult> {
+        let max_fragments = options.max_snippet_fragments.unwrap_or(1);
+
         for result in &mut results {
             if let Some(ref content) = result.content {
-                // Generate snippet
-                result.snippet = Some(self.generate_snippet(content, query, 200));
-                
+                // Content is analyzed in the document's language, like the index
+                let language = result.metadata.get("language").and_then(|value| value.as_str());
+                let tokens = self.analyzers.analyzer(language).analyze_with_offsets(content);
+
+                result.fragments = self.snippet_generator.fragments(content, &tokens, query_terms, max_fragments);
+                result.snippet = result
+                    .fragments
+                    .first()
+                    .map(|fragment| self.snippet_generator.snippet(content, fragment));
+
                 // Generate highlights if enabled
                 if options.highlight_matches {
-                    result.highlights = self.generate_highlights(content, &query_terms);
+                    result.highlights = self.snippet_generator.highlights(content, &tokens, query_terms);
+                } else {
+                    for fragment in &mut result.fragments {
+                        fragment.highlights.clear();
+                    }
                 }
             }
         }
-        
-        Ok(results)
+
+        results
+    }
+
+    fn tokenize_and_stem(&self, text: &str) -> Vec<String> {
+        self.analyzers.default_analyzer().analyze(text)
+    }
+
+    /// Index terms for text in the given language, falling back to the
+    /// default analyzer when the language is unknown or unsupported
+    pub fn analyze(&self, text: &str, language: Option<&str>) -> Vec<String> {
+        self.analyzers.analyzer(language).analyze(text)
+    }
+
+    /// Index one document whose row is already stored. Saved searches are
+    /// left for the next batch or `run_saved_searches` to report.
+    pub async fn index_document(&self, document: &IndexedDocument) -> Result<()> {
+        let needs_flush = {
+            let db = self.database.lock().await;
+            let mut indexer = self.indexer.write().await;
+            let ids = [document.id.clone()];
+            Self::record_ingest_sequence(&db, &ids)?;
+            self.invalidate_cached_searches(&indexer, &ids).await;
+            indexer.index_document(document).await?;
+            indexer.add_surface_forms(&self.surface_forms(document));
+            self.duplicates.write().await.add_document(document);
+            self.invalidate_cached_searches(&indexer, &ids).await;
+            indexer.needs_flush()
+        };
+
+        if needs_flush {
+            self.commit_index().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Index documents and write their rows with `write_rows` in one SQLite
+    /// transaction, so the persisted index never disagrees with the
+    /// documents table after a crash. Saved searches run once for the
+    /// whole batch.
+    pub async fn index_documents_with<F>(&self, documents: &[IndexedDocument], write_rows: F) -> Result<()>
+    where
+        F: FnOnce(&rusqlite::Transaction) -> Result<()>,
+    {
+        self.write_and_index_documents(documents, write_rows).await?;
+        self.notify_saved_searches().await;
+        Ok(())
+    }
+
+    async fn write_and_index_documents<F>(&self, documents: &[IndexedDocument], write_rows: F) -> Result<()>
+    where
+        F: FnOnce(&rusqlite::Transaction) -> Result<()>,
+    {
+        let mut db = self.database.lock().await;
+        let mut indexer = self.indexer.write().await;
+        let mut duplicates = self.duplicates.write().await;
+
+        let tx = db.transaction()?;
+        write_rows(&tx)?;
+
+        let ids: Vec<String> = documents.iter().map(|document| document.id.clone()).collect();
+        Self::record_ingest_sequence(&tx, &ids)?;
+
+        // Earlier versions of the documents come back if the rows roll back
+        let checkpoint = indexer.checkpoint(&ids);
+        let signatures = duplicates.checkpoint(&ids);
+
+        let mut surfaces = HashMap::new();
+        for document in documents {
+            for (form, count) in self.surface_forms(document) {
+                *surfaces.entry(form).or_insert(0) += count;
+            }
+        }
+
+        self.invalidate_cached_searches(&indexer, &ids).await;
+        let mut indexed = Ok(());
+        for document in documents {
+            indexed = indexer.index_document(document).await;
+            if indexed.is_err() {
+                break;
+            }
+            duplicates.add_document(document);
+        }
+        indexer.add_surface_forms(&surfaces);
+
+        let prepared = indexed.and_then(|()| {
+            if !indexer.is_persistent() {
+                return Ok(None);
+            }
+            duplicates.save(&tx)?;
+            Self::record_index_versions(&tx, &indexer)?;
+            indexer.prepare_commit(&tx).map(Some)
+        });
+        let committed = match prepared {
+            Ok(pending) => match tx.commit() {
+                Ok(()) => Ok(pending),
+                Err(e) => {
+                    if let Some(pending) = pending {
+                        indexer.abort_commit(pending);
+                    }
+                    Err(e.into())
+                }
+            },
+            Err(e) => {
+                drop(tx);
+                Err(e)
+            }
+        };
+
+        let pending = match committed {
+            Ok(pending) => pending,
+            Err(e) => {
+                indexer.restore(checkpoint);
+                indexer.remove_surface_forms(&surfaces);
+                duplicates.restore(signatures);
+                return Err(e);
+            }
+        };
+        self.invalidate_cached_searches(&indexer, &ids).await;
+
+        if let Some(pending) = pending {
+            indexer.finish_commit(pending)?;
+            duplicates.mark_saved();
+            indexer.merge_segments(&mut db)?;
+        }
+        Ok(())
+    }
+
+    pub async fn remove_document(&self, document_id: &str) -> Result<()> {
+        let mut indexer = self.indexer.write().await;
+        self.invalidate_cached_searches(&indexer, &[document_id.to_string()]).await;
+        indexer.remove_document(document_id).await?;
+        self.duplicates.write().await.remove_document(document_id);
+        Ok(())
+    }
+
+    /// Words of a document counted under the terms they analyze to
+    fn surface_forms(&self, document: &IndexedDocument) -> HashMap<(String, String), usize> {
+        let analyzer = self.analyzers.analyzer(document.metadata.get("language").and_then(|l| l.as_str()));
+        let mut forms = HashMap::new();
+        analyzer.count_surface_forms(&document.title, &mut forms);
+        analyzer.count_surface_forms(&document.content, &mut forms);
+        forms
+    }
+
+    /// Drop cached searches the documents were returned by or whose terms
+    /// they contain. Called before and after the index changes, so both the
+    /// old and the new text of a document count.
+    async fn invalidate_cached_searches(&self, indexer: &FullTextIndexer, document_ids: &[String]) {
+        self.query_cache
+            .write()
+            .await
+            .invalidate_documents(document_ids, |term, id| indexer.posting(term, id).is_some());
+    }
+
+    /// Write buffered documents and deletions to the persisted index and
+    /// merge segments if needed. Does nothing for an in-memory index.
+    pub async fn commit_index(&self) -> Result<()> {
+        let mut db = self.database.lock().await;
+        let mut indexer = self.indexer.write().await;
+
+        if !indexer.is_persistent() {
+            return Ok(());
+        }
+
+        if indexer.has_uncommitted_changes() {
+            let tx = db.transaction()?;
+            Self::record_index_versions(&tx, &indexer)?;
+            let pending = indexer.prepare_commit(&tx)?;
+            match tx.commit() {
+                Ok(()) => indexer.finish_commit(pending)?,
+                Err(e) => {
+                    indexer.abort_commit(pending);
+                    return Err(e.into());
+                }
+            }
+        }
+        indexer.merge_segments(&mut db)?;
+
+        let mut duplicates = self.duplicates.write().await;
+        if duplicates.has_unsaved_changes() {
+            let tx = db.transaction()?;
+            duplicates.save(&tx)?;
+            tx.commit()?;
+            duplicates.mark_saved();
+        }
+        Ok(())
+    }
+
+    fn create_sequence_table(db: &Connection) -> Result<()> {
+        db.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS search_ingest_sequence (
+                sequence INTEGER PRIMARY KEY AUTOINCREMENT,
+                document_id TEXT NOT NULL UNIQUE
+            )
+            "#,
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Number documents in the order they are first ingested, so a search
+    /// can be pinned to the documents that existed when it started. Sequence
+    /// numbers never repeat, and re-ingesting a document keeps its number.
+    fn record_ingest_sequence(db: &Connection, document_ids: &[String]) -> Result<()> {
+        Self::create_sequence_table(db)?;
+        for document_id in document_ids {
+            db.execute("INSERT OR IGNORE INTO search_ingest_sequence (document_id) VALUES (?1)", [document_id])?;
+        }
+        Ok(())
     }
 
-    fn generate_snippet(&self, content: &str, query: &str, max_length: usize) -> String {
-        let query_lower = query.to_lowercase();
-        let content_lower = content.to_lowercase();
-        
-        // Find the position of the query in the content
-        if let Some(pos) = content_lower.find(&query_lower) {
-            let start = pos.saturating_sub(max_length / 2);
-            let end = std::cmp::min(content.len(), start + max_length);
-            
-            let mut snippet = content[start..end].to_string();
-            
-            // Add ellipsis if truncated
-            if start > 0 {
-                snippet = format!("...{}", snippet);
-            }
-            if end < content.len() {
-                snippet = format!("{}...", snippet);
-            }
-            
-            snippet
-        } else {
-            // If query not found, return beginning of content
-            let end = std::cmp::min(content.len(), max_length);
-            let mut snippet = content[..end].to_string();
-            if end < content.len() {
-                snippet = format!("{}...", snippet);
-            }
-            snippet
+    /// The newest ingest sequence number, the snapshot of a new search
+    fn latest_ingest_sequence(db: &Connection) -> Result<i64> {
+        Self::create_sequence_table(db)?;
+        Ok(db.query_row("SELECT COALESCE(MAX(sequence), 0) FROM search_ingest_sequence", [], |row| row.get(0))?)
+    }
+
+    fn create_version_table(db: &Connection) -> Result<()> {
+        db.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS search_index_versions (
+                document_id TEXT PRIMARY KEY,
+                modified_at INTEGER
+            )
+            "#,
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Record the stored modified_at of the documents the next commit writes
+    /// to the persisted index, so startup can tell which rows changed while
+    /// the index was closed
+    fn record_index_versions(tx: &Connection, indexer: &FullTextIndexer) -> Result<()> {
+        Self::create_version_table(tx)?;
+
+        let (indexed, removed) = indexer.uncommitted_documents();
+        for document_id in &removed {
+            tx.execute("DELETE FROM search_index_versions WHERE document_id = ?1", [document_id])?;
         }
+        for document_id in &indexed {
+            tx.execute(
+                "INSERT OR REPLACE INTO search_index_versions (document_id, modified_at)
+                 SELECT id, modified_at FROM documents WHERE id = ?1",
+                [document_id],
+            )?;
+        }
+        Ok(())
     }
 
-    fn generate_highlights(&self, content: &str, query_terms: &[&str]) -> Vec<TextHighlight> {
-        let mut highlights = Vec::new();
-        let content_lower = content.to_lowercase();
-        
-        for term in query_terms {
-            let term_lower = term.to_lowercase();
-            let mut start = 0;
-            
-            while let Some(pos) = content_lower[start..].find(&term_lower) {
-                let actual_pos = start + pos;
-                highlights.push(TextHighlight {
-                    start: actual_pos,
-                    end: actual_pos + term.len(),
-                    text: term.to_string(),
-                });
-                start = actual_pos + term.len();
+    /// Documents with the given ids, analyzed as for indexing. Ids that do
+    /// not exist are skipped.
+    fn load_documents(&self, db: &Connection, ids: &[String]) -> Result<Vec<IndexedDocument>> {
+        let mut documents = Vec::with_capacity(ids.len());
+
+        for batch in ids.chunks(500) {
+            let placeholders = batch.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let sql = format!(
+                "SELECT id, title, content, metadata FROM documents WHERE id IN ({})",
+                placeholders
+            );
+            let mut stmt = db.prepare(&sql)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(batch.iter()), |row| self.read_document(row))?;
+            for row in rows {
+                documents.push(row?);
             }
         }
+
+        Ok(documents)
+    }
+
+    fn read_document(&self, row: &rusqlite::Row) -> rusqlite::Result<IndexedDocument> {
+        let title: String = row.get(1)?;
+        let content: String = row.get(2)?;
+        let metadata_str: String = row.get(3)?;
+        let metadata: serde_json::Value = serde_json::from_str(&metadata_str).unwrap_or_default();
+
+        // Documents are analyzed in the language detected at ingestion
+        let analyzer = self.analyzers.analyzer(metadata.get("language").and_then(|l| l.as_str()));
+
+        Ok(IndexedDocument {
+            id: row.get(0)?,
+            title_tokens: analyzer.analyze(&title),
+            title,
+            content: content.clone(),
+            tokens: analyzer.analyze(&content),
+            entities: Vec::new(), // TODO: Load entities
+            metadata,
+            embedding: None, // TODO: Generate embeddings
+        })
+    }
+
+    pub async fn rebuild_index(&self) -> Result<()> {
+        info!("Rebuilding search index");
         
-        // Sort highlights by position
-        highlights.sort_by_key(|h| h.start);
-        
-        // Remove overlapping highlights
-        let mut filtered_highlights = Vec::new();
-        for highlight in highlights {
-            let overlaps = filtered_highlights.iter().any(|existing: &TextHighlight| {
-                highlight.start < existing.end && highlight.end > existing.start
-            });
-            
-            if !overlaps {
-                filtered_highlights.push(highlight);
+        {
+            let db = self.database.lock().await;
+            let mut stmt = db.prepare("SELECT id, title, content, metadata FROM documents")?;
+
+            let rows = stmt.query_map([], |row| self.read_document(row))?;
+
+            let mut ids = Vec::new();
+            for row in rows {
+                let document = row?;
+                let mut indexer = self.indexer.write().await;
+                indexer.index_document(&document).await?;
+                indexer.add_surface_forms(&self.surface_forms(&document));
+                self.duplicates.write().await.add_document(&document);
+                ids.push(document.id);
             }
+            Self::record_ingest_sequence(&db, &ids)?;
         }
+
+        self.commit_index().await?;
+        self.query_cache.write().await.clear();
         
-        filtered_highlights
+        info!("Search index rebuilt successfully");
+        Ok(())
     }
 
-    fn tokenize_and_stem(&self, text: &str) -> Vec<String> {
-        text.unicode_words()
-            .map(|word| word.to_lowercase())
-            .map(|word| self.stemmer.stem(&word).to_string())
-            .collect()
+    /// Past searches, most recent first, of one profile or of everyone
+    pub async fn search_history(&self, profile_id: Option<&str>, limit: usize) -> Result<Vec<SearchHistoryEntry>> {
+        let db = self.database.lock().await;
+        SearchHistory::list(&db, profile_id, limit)
     }
 
-    pub async fn index_document(&self, document: &IndexedDocument) -> Result<()> {
-        self.indexer.index_document(document).await
+    /// Returns how many searches were removed
+    pub async fn clear_search_history(&self, profile_id: Option<&str>) -> Result<usize> {
+        let db = self.database.lock().await;
+        SearchHistory::clear(&db, profile_id)
     }
 
-    pub async fn remove_document(&self, document_id: &str) -> Result<()> {
-        self.indexer.remove_document(document_id).await
+    /// Index contents, sizes and how far the index is behind the
+    /// documents table, with the `top_terms` most common terms
+    pub async fn index_stats(&self, top_terms: usize) -> Result<IndexStats> {
+        let db = self.database.lock().await;
+        let indexer = self.indexer.read().await;
+
+        let mut stmt = db.prepare("SELECT id FROM documents")?;
+        let stored: HashSet<String> = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let indexed: HashSet<String> = indexer.document_ids().into_iter().collect();
+        let database_bytes: i64 = db.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let fields = field_stats(&indexer);
+        let total_tokens: usize = fields.iter().map(|field| field.total_tokens).sum();
+        let document_count = indexer.document_count();
+
+        Ok(IndexStats {
+            document_count,
+            stored_documents: stored.len(),
+            term_count: indexer.vocabulary().len(),
+            average_document_length: if document_count == 0 { 0.0 } else { total_tokens as f64 / document_count as f64 },
+            fields,
+            top_terms: stats::top_terms(&indexer, "", top_terms),
+            segment_count: indexer.segment_count(),
+            buffered_documents: indexer.buffered_document_count(),
+            index_bytes: indexer.size_on_disk(),
+            database_bytes: database_bytes as usize,
+            unindexed_documents: stored.difference(&indexed).count(),
+            orphaned_documents: indexed.difference(&stored).count(),
+            deleted_documents: indexer.deleted_document_count(),
+        })
     }
 
-    pub async fn rebuild_index(&self) -> Result<()> {
-        info!("Rebuilding search index");
-        
-        let db = self.database.read().await;
-        let mut stmt = db.prepare("SELECT id, title, content, metadata FROM documents")?;
-        
-        let rows = stmt.query_map([], |row| {
-            let content: String = row.get(2)?;
-            let metadata_str: String = row.get(3)?;
-            let metadata = serde_json::from_str(&metadata_str).unwrap_or_default();
-            
-            Ok(IndexedDocument {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                content: content.clone(),
-                tokens: self.tokenize_and_stem(&content),
-                entities: Vec::new(), // TODO: Load entities
-                metadata,
-                embedding: None, // TODO: Generate embeddings
-            })
-        })?;
+    /// Index terms starting with a prefix, most common first. Terms are
+    /// analyzed, so they are lowercased, folded and stemmed; the prefix is
+    /// lowercased and folded to match.
+    pub async fn explore_terms(&self, prefix: &str, limit: usize) -> Vec<TermStats> {
+        let indexer = self.indexer.read().await;
+        stats::top_terms(&indexer, &fold(&prefix.to_lowercase()), limit)
+    }
 
-        for row in rows {
-            let document = row?;
-            self.indexer.index_document(&document).await?;
+    /// How a document scores for a terms query: BM25F per term, the
+    /// proximity, passage and entity boosts, and the ranker's weighted
+    /// signals. Fuzzy and semantic matches are not explained.
+    pub async fn explain(&self, query: &SearchQuery, document_id: &str) -> Result<ScoreExplanation> {
+        if query.options.query_mode != QueryMode::Terms {
+            return Err(anyhow!("Only term queries can be explained, not {:?}", query.options.query_mode));
         }
-        
-        info!("Search index rebuilt successfully");
-        Ok(())
+
+        let resolved = self.resolve_entity_references(query).await?;
+        let (query, entity_groups) = match &resolved {
+            Some((resolved_query, groups)) => (resolved_query, groups.as_slice()),
+            None => (query, &[][..]),
+        };
+        let analyzer = self.analyzers.analyzer(query.options.language.as_deref());
+        let parsed_query = ParsedQuery::parse(&query.text, |text| analyzer.analyze(text));
+        let query_tokens = parsed_query.all_terms();
+        let term_groups = if parsed_query.has_boolean_operators {
+            Vec::new()
+        } else {
+            self.synonyms.read().await.expand(&query_tokens, |text| analyzer.analyze(text))
+        };
+        let profile = match query.options.profile_id.as_deref() {
+            Some(profile_id) => Some(self.ranking_profile(profile_id).await?),
+            None => None,
+        };
+
+        // The document as the query and its filters see it
+        let mut filters = query.filters.clone();
+        filters.document_ids = Some(vec![document_id.to_string()]);
+        let fts_result = if query.text.is_empty() {
+            None
+        } else {
+            self.fts_search(&query.text, &filters, &[], None, 1).await?.pop()
+        };
+        let (mut index_results, _) = self.index_search(&term_groups, &filters, &query.options, None, 1).await?;
+        let matched = if query.text.is_empty() {
+            let db = self.database.lock().await;
+            let mut sql = "SELECT COUNT(*) FROM documents d WHERE 1 = 1".to_string();
+            let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+            Self::push_document_filters(&mut sql, &mut params, &filters, None);
+            let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+            db.query_row(&sql, rusqlite::params_from_iter(param_refs), |row| row.get::<_, i64>(0))? > 0
+        } else {
+            let indexer = self.indexer.read().await;
+            (fts_result.is_some() || !index_results.is_empty())
+                && (!indexer.contains_document(document_id) || indexer.matches_query(&parsed_query, document_id))
+        };
+
+        let mut result = match fts_result.or_else(|| index_results.pop()) {
+            Some(result) => result,
+            None => {
+                let db = self.database.lock().await;
+                let document = self
+                    .load_documents(&db, &[document_id.to_string()])?
+                    .pop()
+                    .ok_or_else(|| anyhow!("Document not found: {}", document_id))?;
+                SearchResult {
+                    id: document.id,
+                    result_type: SearchResultType::Document,
+                    title: document.title,
+                    content: Some(document.content),
+                    snippet: None,
+                    score: 0.0,
+                    metadata: document.metadata,
+                    highlights: Vec::new(),
+                    fragments: Vec::new(),
+                    passages: Vec::new(),
+                    sort_values: Vec::new(),
+                }
+            }
+        };
+        let mut explanation = ScoreExplanation::new(document_id, matched);
+
+        {
+            let indexer = self.indexer.read().await;
+            if indexer.contains_document(document_id) {
+                let params = &query.options.bm25;
+                let bm25 = indexer
+                    .score_alternatives(&term_groups, document_id, params)
+                    .unwrap_or_else(|| indexer.score_document(&query_tokens, document_id, params));
+                let detail = format!("k1 {}, b {}, title weight {}, content weight {}", params.k1, params.b, params.title_weight, params.content_weight);
+                explanation.add("bm25", bm25, Some(detail));
+                explanation.terms = query_tokens
+                    .iter()
+                    .map(|term| {
+                        let posting = indexer.posting(term, document_id);
+                        TermContribution {
+                            term: term.clone(),
+                            document_frequency: indexer.document_frequency(term),
+                            idf: indexer.idf(term),
+                            title_frequency: posting.as_ref().map_or(0, |posting| posting.title_count()),
+                            content_frequency: posting.as_ref().map_or(0, |posting| posting.content_count()),
+                            score: indexer.bm25_term_score(term, document_id, params),
+                        }
+                    })
+                    .collect();
+                let proximity = indexer.proximity_score(&query_tokens, document_id);
+                explanation.add("proximity", self.ranker.proximity_weight() * proximity, None);
+            } else {
+                explanation.add("fts", result.score, Some("SQLite bm25; the document is not indexed yet".to_string()));
+            }
+        }
+
+        let page = PageRequest::from_options(&query.options, i64::MAX)?;
+        let chunk_hits = if query.text.is_empty() {
+            Vec::new()
+        } else {
+            self.chunk_search(&query.text, &query.filters, None, &query.options, page.candidate_window()).await?
+        };
+        if let Some(passage) = best_chunk_scores(&chunk_hits).get(document_id) {
+            let best = chunk_hits.iter().find(|hit| hit.document_id == document_id).map(|hit| format!("chunk {}", hit.chunk_index));
+            explanation.add("passage", self.ranker.passage_weight() * passage, best);
+        }
+
+        if !entity_groups.is_empty() {
+            result.score = 0.0;
+            let mut scored = [result.clone()];
+            self.add_entity_scores(&mut scored, entity_groups).await?;
+            let references: Vec<&str> = entity_groups.iter().map(|group| group.reference.as_str()).collect();
+            explanation.add("entities", scored[0].score, Some(references.join(", ")));
+        }
+
+        let ranker = self.profile_ranker(profile.as_ref());
+        let features = ranker.ranking_features(&result, &query_tokens);
+        let weights = ranker.weights();
+        if query.options.boost_recent {
+            explanation.add("freshness", weights.freshness * features.freshness, None);
+        }
+        explanation.add("query_match", weights.relevance * features.relevance, None);
+        explanation.add("popularity", weights.popularity * features.popularity, None);
+
+        if let Some(profile) = &profile {
+            result.score = 0.0;
+            let personalized = profile.personalization.personalize_results(vec![result], query.options.profile_id.as_deref());
+            explanation.add("personalization", personalized[0].score, query.options.profile_id.clone());
+        }
+
+        Ok(explanation)
     }
 
     pub async fn get_search_statistics(&self) -> Result<serde_json::Value> {
-        let db = self.database.read().await;
+        let index = self.index_stats(10).await?;
+        let db = self.database.lock().await;
         
         let document_count: i64 = db.query_row("SELECT COUNT(*) FROM documents", [], |row| row.get(0))?;
         let entity_count: i64 = db.query_row("SELECT COUNT(*) FROM entities", [], |row| row.get(0))?;
+        let queries = SearchHistory::analytics(&db, 10)?;
+        let cache = self.query_cache_stats().await;
         
         Ok(serde_json::json!({
             "document_count": document_count,
             "entity_count": entity_count,
-            "index_size": self.indexer.get_index_size().await?,
+            "index_size": self.indexer.read().await.get_index_size().await?,
+            "queries": queries,
+            "query_cache": cache,
+            "index": index,
         }))
     }
 }
@@ -591,9 +2665,7 @@ mod tests {
     use tempfile::TempDir;
 
     async fn create_test_search_engine() -> SearchEngine {
-        let temp_dir = TempDir::new().unwrap();
-        let db_path = temp_dir.path().join("test.db");
-        let conn = Connection::open(db_path).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
         
         // Create test tables
         conn.execute(
@@ -606,10 +2678,57 @@ mod tests {
             [],
         ).unwrap();
         
-        let db = Arc::new(RwLock::new(conn));
+        let db = Arc::new(Mutex::new(conn));
         SearchEngine::new(db).unwrap()
     }
 
+    async fn create_fts_test_search_engine(documents: &[(&str, &str, &str)]) -> SearchEngine {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE documents (
+                id TEXT PRIMARY KEY,
+                source_type TEXT NOT NULL DEFAULT 'file_system',
+                ingested_at INTEGER NOT NULL DEFAULT 0,
+                modified_at INTEGER NOT NULL DEFAULT 0,
+                metadata TEXT NOT NULL DEFAULT '{}',
+                title TEXT NOT NULL,
+                content TEXT
+            );
+            CREATE VIRTUAL TABLE documents_fts USING fts5(title, content, content_id UNINDEXED);",
+        ).unwrap();
+
+        for (id, title, content) in documents {
+            conn.execute(
+                "INSERT INTO documents (id, title, content) VALUES (?1, ?2, ?3)",
+                rusqlite::params![id, title, content],
+            ).unwrap();
+            conn.execute(
+                "INSERT INTO documents_fts (title, content, content_id) VALUES (?1, ?2, ?3)",
+                rusqlite::params![title, content, id],
+            ).unwrap();
+        }
+
+        let engine = SearchEngine::new(Arc::new(Mutex::new(conn))).unwrap();
+        engine.rebuild_index().await.unwrap();
+        engine
+    }
+
+    fn create_test_query(text: &str, options: SearchOptions) -> SearchQuery {
+        SearchQuery {
+            text: text.to_string(),
+            filters: SearchFilters {
+                entity_types: None,
+                document_types: None,
+                date_range: None,
+                file_types: None,
+                source_types: None,
+                document_ids: None,
+                mentions: None,
+            },
+            options,
+        }
+    }
+
     #[tokio::test]
     async fn test_search_engine_creation() {
         let engine = create_test_search_engine().await;
@@ -629,7 +2748,13 @@ mod tests {
     fn test_snippet_generation() {
         let engine = tokio::runtime::Runtime::new().unwrap().block_on(create_test_search_engine());
         let content = "This is a long document with multiple sentences. The search query should be highlighted in the snippet. This continues for much longer.";
-        let snippet = engine.generate_snippet(content, "search query", 50);
+        let tokens = engine.analyzers.default_analyzer().analyze_with_offsets(content);
+        let query_terms: HashSet<String> = engine.tokenize_and_stem("search query").into_iter().collect();
+
+        let mut generator = SnippetGenerator::new();
+        generator.set_fragment_length(50);
+        let fragments = generator.fragments(content, &tokens, &query_terms, 1);
+        let snippet = generator.snippet(content, &fragments[0]);
         
         assert!(snippet.contains("search query"));
         assert!(snippet.len() <= 60); // Account for ellipsis
@@ -639,10 +2764,1138 @@ mod tests {
     fn test_highlight_generation() {
         let engine = tokio::runtime::Runtime::new().unwrap().block_on(create_test_search_engine());
         let content = "This is a test document with test content for testing.";
-        let highlights = engine.generate_highlights(content, &["test", "content"]);
+        let tokens = engine.analyzers.default_analyzer().analyze_with_offsets(content);
+        let query_terms: HashSet<String> = engine.tokenize_and_stem("test content").into_iter().collect();
+        let highlights = engine.snippet_generator.highlights(content, &tokens, &query_terms);
         
         assert!(!highlights.is_empty());
         assert!(highlights.iter().any(|h| h.text == "test"));
         assert!(highlights.iter().any(|h| h.text == "content"));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_pagination_and_total_hits() {
+        let documents: Vec<(String, String, String)> = (0..5)
+            .map(|i| (i.to_string(), format!("Report {}", i), "quarterly report".to_string()))
+            .collect();
+        let documents: Vec<(&str, &str, &str)> = documents
+            .iter()
+            .map(|(id, title, content)| (id.as_str(), title.as_str(), content.as_str()))
+            .collect();
+        let engine = create_fts_test_search_engine(&documents).await;
+
+        let options = SearchOptions {
+            limit: Some(2),
+            offset: Some(2),
+            include_snippets: false,
+            ..Default::default()
+        };
+        let response = engine.search(&create_test_query("report", options)).await.unwrap();
+
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.total_hits, 5);
+        assert!(response.total_hits_exact);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_paging_visits_every_hit_once() {
+        let documents: Vec<(String, String, String)> = (0..5)
+            .map(|i| (i.to_string(), format!("Report {}", i), "quarterly report".to_string()))
+            .collect();
+        let documents: Vec<(&str, &str, &str)> = documents
+            .iter()
+            .map(|(id, title, content)| (id.as_str(), title.as_str(), content.as_str()))
+            .collect();
+        let engine = create_fts_test_search_engine(&documents).await;
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let options = SearchOptions {
+                limit: Some(2),
+                include_snippets: false,
+                search_after: cursor.clone(),
+                ..Default::default()
+            };
+            let response = engine.search(&create_test_query("report", options)).await.unwrap();
+            seen.extend(response.results.into_iter().map(|r| r.id));
+
+            match response.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        seen.sort();
+        assert_eq!(seen, vec!["0", "1", "2", "3", "4"]);
+    }
+
+    async fn reingest(engine: &SearchEngine, id: &str, content: &str) {
+        let document = IndexedDocument {
+            id: id.to_string(),
+            title: format!("Report {}", id),
+            content: content.to_string(),
+            title_tokens: engine.tokenize_and_stem(&format!("Report {}", id)),
+            tokens: engine.tokenize_and_stem(content),
+            entities: Vec::new(),
+            metadata: serde_json::json!({}),
+            embedding: None,
+        };
+        engine.index_documents_with(&[document], |tx| {
+            tx.execute(
+                "INSERT OR REPLACE INTO documents (id, title, content, ingested_at) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![id, format!("Report {}", id), content, chrono::Utc::now().timestamp()],
+            )?;
+            tx.execute("DELETE FROM documents_fts WHERE content_id = ?1", [id])?;
+            tx.execute(
+                "INSERT INTO documents_fts (title, content, content_id) VALUES (?1, ?2, ?3)",
+                rusqlite::params![format!("Report {}", id), content, id],
+            )?;
+            Ok(())
+        }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cursor_paging_is_stable_across_ingestion() {
+        let documents: Vec<(String, String, String)> = (0..5)
+            .map(|i| (i.to_string(), format!("Report {}", i), "quarterly report".to_string()))
+            .collect();
+        let documents: Vec<(&str, &str, &str)> = documents
+            .iter()
+            .map(|(id, title, content)| (id.as_str(), title.as_str(), content.as_str()))
+            .collect();
+        let engine = create_fts_test_search_engine(&documents).await;
+        let options = SearchOptions {
+            limit: Some(2),
+            include_snippets: false,
+            ..Default::default()
+        };
+
+        let first = engine.search(&create_test_query("report", options.clone())).await.unwrap();
+        let mut seen: Vec<String> = first.results.iter().map(|r| r.id.clone()).collect();
+        let mut cursor = first.next_cursor;
+        assert_eq!(seen.len(), 2);
+
+        let unseen = (0..5).map(|i| i.to_string()).find(|id| !seen.contains(id)).unwrap();
+        loop {
+            // A new document outranks everything, a seen one is rewritten to
+            // rank first and an unseen one is rewritten in place
+            ingest(&engine, &format!("new-{}", seen.len()), "Report", "report report report report").await.unwrap();
+            reingest(&engine, &seen[0], "report report report report report").await;
+            reingest(&engine, &unseen, "quarterly report, revised").await;
+
+            let mut next_page = create_test_query("report", options.clone());
+            next_page.options.search_after = cursor.clone();
+            let response = engine.search(&next_page).await.unwrap();
+            seen.extend(response.results.into_iter().map(|r| r.id));
+
+            match response.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        seen.sort();
+        assert_eq!(seen, vec!["0", "1", "2", "3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn test_proximity_and_phrase_queries() {
+        let engine = create_fts_test_search_engine(&[
+            ("far", "Notes", "the budget was discussed before the yearly planning review"),
+            ("near", "Notes", "the yearly planning budget review was discussed before"),
+        ]).await;
+
+        let options = SearchOptions {
+            include_snippets: false,
+            boost_recent: false,
+            ..Default::default()
+        };
+
+        let response = engine.search(&create_test_query("budget review", options.clone())).await.unwrap();
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.results[0].id, "near");
+
+        let response = engine.search(&create_test_query("\"budget review\"", options.clone())).await.unwrap();
+        let ids: Vec<String> = response.results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["near"]);
+
+        let response = engine.search(&create_test_query("NEAR(budget review, 2)", options)).await.unwrap();
+        let ids: Vec<String> = response.results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["near"]);
+    }
+
+    #[tokio::test]
+    async fn test_facets_count_every_match_not_just_the_page() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget", "annual budget review"),
+            ("2", "Budget", "budget notes"),
+            ("3", "Budget", "travel budget"),
+            ("4", "Garden", "seeds and compost"),
+        ]).await;
+        {
+            let db = engine.database.lock().await;
+            db.execute_batch(
+                "CREATE TABLE entities (id TEXT PRIMARY KEY, entity_type TEXT NOT NULL, name TEXT NOT NULL);
+                 CREATE TABLE entity_mentions (id TEXT PRIMARY KEY, entity_id TEXT NOT NULL, document_id TEXT NOT NULL);
+                 UPDATE documents SET source_type = 'email' WHERE id = '3';",
+            ).unwrap();
+        }
+
+        let options = SearchOptions { include_facets: true, include_snippets: false, limit: Some(1), ..Default::default() };
+        let response = engine.search(&create_test_query("budget", options)).await.unwrap();
+        assert_eq!(response.results.len(), 1);
+
+        let facets = response.facets.unwrap();
+        let sources: Vec<(&str, usize)> = facets.source_types.iter().map(|b| (b.value.as_str(), b.count)).collect();
+        assert_eq!(sources, vec![("file_system", 2), ("email", 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_persisted_index_catches_up_on_startup() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let index_path = temp_dir.path().join("index");
+
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE documents (id TEXT PRIMARY KEY, title TEXT, content TEXT, metadata TEXT, modified_at INTEGER);
+             INSERT INTO documents VALUES ('1', 'Budget', 'annual budget review', '{}', 1);",
+        ).unwrap();
+
+        let engine = SearchEngine::with_index_path(Arc::new(Mutex::new(conn)), index_path.clone()).unwrap();
+        engine.initialize().await.unwrap();
+
+        let document = IndexedDocument {
+            id: "2".to_string(),
+            title: "Plan".to_string(),
+            content: "budget plan".to_string(),
+            title_tokens: engine.tokenize_and_stem("Plan"),
+            tokens: engine.tokenize_and_stem("budget plan"),
+            entities: Vec::new(),
+            metadata: serde_json::json!({}),
+            embedding: None,
+        };
+        engine.index_documents_with(&[document], |tx| {
+            tx.execute("INSERT INTO documents VALUES ('2', 'Plan', 'budget plan', '{}', 1)", [])?;
+            Ok(())
+        }).await.unwrap();
+        drop(engine);
+
+        // Written while the engine was not running
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute("DELETE FROM documents WHERE id = '1'", []).unwrap();
+        conn.execute("INSERT INTO documents VALUES ('3', 'Review', 'quarterly review', '{}', 1)", []).unwrap();
+        conn.execute("INSERT OR REPLACE INTO documents VALUES ('2', 'Plan', 'budget forecast', '{}', 2)", []).unwrap();
+
+        let engine = SearchEngine::with_index_path(Arc::new(Mutex::new(conn)), index_path).unwrap();
+        engine.initialize().await.unwrap();
+
+        let indexer = engine.indexer.read().await;
+        let mut ids = indexer.document_ids();
+        ids.sort();
+        assert_eq!(ids, vec!["2".to_string(), "3".to_string()]);
+        assert!(indexer.posting("forecast", "2").is_some());
+        assert!(indexer.posting("plan", "2").is_some());
+        assert_eq!(indexer.document_frequency("budget"), 1);
+        assert!(!indexer.has_uncommitted_changes());
+    }
+
+    #[tokio::test]
+    async fn test_failed_commit_keeps_previous_version_indexed() {
+        let temp_dir = TempDir::new().unwrap();
+
+        for index_path in [None, Some(temp_dir.path().join("index"))] {
+            // A deferred foreign key makes the commit itself fail
+            let conn = Connection::open_in_memory().unwrap();
+            conn.execute_batch(
+                "PRAGMA foreign_keys = ON;
+                 CREATE TABLE documents (id TEXT PRIMARY KEY, title TEXT, content TEXT, metadata TEXT, modified_at INTEGER);
+                 CREATE TABLE folders (id TEXT PRIMARY KEY);
+                 CREATE TABLE placements (folder_id TEXT REFERENCES folders(id) DEFERRABLE INITIALLY DEFERRED);
+                 INSERT INTO documents VALUES ('1', 'Budget', 'annual budget review', '{}', 1);
+                 INSERT INTO documents VALUES ('2', 'Budget', 'annual budget review', '{}', 1);",
+            ).unwrap();
+            let database = Arc::new(Mutex::new(conn));
+            let engine = match &index_path {
+                Some(index_path) => SearchEngine::with_index_path(database, index_path.clone()).unwrap(),
+                None => SearchEngine::new(database).unwrap(),
+            };
+            engine.initialize().await.unwrap();
+
+            let document = IndexedDocument {
+                id: "1".to_string(),
+                title: "Garden".to_string(),
+                content: "seeds and compost".to_string(),
+                title_tokens: engine.tokenize_and_stem("Garden"),
+                tokens: engine.tokenize_and_stem("seeds and compost"),
+                entities: Vec::new(),
+                metadata: serde_json::json!({}),
+                embedding: None,
+            };
+            let result = engine.index_documents_with(&[document], |tx| {
+                tx.execute("UPDATE documents SET title = 'Garden', content = 'seeds and compost', modified_at = 2 WHERE id = '1'", [])?;
+                tx.execute("INSERT INTO placements VALUES ('missing')", [])?;
+                Ok(())
+            }).await;
+            assert!(result.is_err());
+
+            let db = engine.database.lock().await;
+            let content: String = db.query_row("SELECT content FROM documents WHERE id = '1'", [], |row| row.get(0)).unwrap();
+            assert_eq!(content, "annual budget review");
+
+            let indexer = engine.indexer.read().await;
+            assert!(indexer.posting("budget", "1").is_some());
+            assert!(indexer.posting("seed", "1").is_none());
+            assert_eq!(indexer.document_frequency("budget"), 2);
+            assert_eq!(indexer.has_uncommitted_changes(), index_path.is_none());
+
+            let duplicates = engine.duplicates.read().await;
+            assert_eq!(duplicates.similarity("1", "2"), Some(1.0));
+            assert_eq!(duplicates.has_unsaved_changes(), index_path.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fuzzy_search_expands_misspelled_terms() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget", "annual budget review"),
+            ("2", "Budget", "budget notes"),
+            ("3", "Gadgets", "gadget reviews"),
+        ]).await;
+
+        let options = SearchOptions {
+            fuzzy_matching: true,
+            include_snippets: false,
+            boost_recent: false,
+            ..Default::default()
+        };
+
+        let response = engine.search(&create_test_query("budgte reviw", options.clone())).await.unwrap();
+        let ids: Vec<String> = response.results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["1"]);
+        assert!(!response.total_hits_exact);
+
+        // Two letter terms must match exactly
+        let response = engine.search(&create_test_query("bu", options)).await.unwrap();
+        assert!(response.results.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_empty_results_offer_did_you_mean() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget", "quarterly budget review"),
+        ]).await;
+
+        let options = SearchOptions {
+            include_snippets: false,
+            ..Default::default()
+        };
+
+        let response = engine.search(&create_test_query("quartrly", options.clone())).await.unwrap();
+        assert!(response.results.is_empty());
+        assert_eq!(response.did_you_mean.as_deref(), Some("quarterly"));
+
+        let response = engine.search(&create_test_query("quarterly", options)).await.unwrap();
+        assert_eq!(response.results.len(), 1);
+        assert!(response.did_you_mean.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_documents_analyzed_in_their_language() {
+        let engine = create_fts_test_search_engine(&[
+            ("de", "Häuser", "Die Häuser der Stadt"),
+            ("zh", "北京", "北京大学的图书馆"),
+            ("en", "Houses", "houses in the city"),
+        ]).await;
+        {
+            let db = engine.database.lock().await;
+            db.execute("UPDATE documents SET metadata = '{\"language\": \"de\"}' WHERE id = 'de'", []).unwrap();
+            db.execute("UPDATE documents SET metadata = '{\"language\": \"zh\"}' WHERE id = 'zh'", []).unwrap();
+        }
+        engine.rebuild_index().await.unwrap();
+
+        let options = SearchOptions {
+            include_snippets: false,
+            language: Some("de".to_string()),
+            ..Default::default()
+        };
+        let response = engine.search(&create_test_query("Hausern", options)).await.unwrap();
+        let ids: Vec<String> = response.results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["de"]);
+        assert_eq!(response.total_hits, 1);
+
+        // FTS sees one token for the whole run of ideographs; the index
+        // matches its bigrams
+        let options = SearchOptions {
+            include_snippets: false,
+            ..Default::default()
+        };
+        let response = engine.search(&create_test_query("大学", options)).await.unwrap();
+        let ids: Vec<String> = response.results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["zh"]);
+    }
+
+    #[tokio::test]
+    async fn test_synonyms_expand_queries() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "PO 4471", "PO approved by finance"),
+            ("2", "Supplier invoice", "purchase order approved by finance"),
+            ("3", "Order history", "order placed for purchase next month"),
+            ("4", "Cluster upgrade", "kubernetes cluster upgrade notes"),
+        ]).await;
+        engine.add_synonyms(SynonymRule::Equivalent {
+            terms: vec!["PO".to_string(), "purchase order".to_string()],
+        }).await.unwrap();
+        engine.add_synonyms(SynonymRule::OneWay {
+            from: "k8s".to_string(),
+            to: vec!["kubernetes".to_string()],
+        }).await.unwrap();
+
+        let options = SearchOptions {
+            include_snippets: false,
+            boost_recent: false,
+            ..Default::default()
+        };
+
+        // Exact matches rank above synonym matches, and multi-word
+        // synonyms only match as phrases
+        let response = engine.search(&create_test_query("PO approved", options.clone())).await.unwrap();
+        let ids: Vec<String> = response.results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+        assert_eq!(response.total_hits, 2);
+
+        let response = engine.search(&create_test_query("purchase order", options.clone())).await.unwrap();
+        let ids: Vec<String> = response.results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.contains(&"1".to_string()));
+
+        let response = engine.search(&create_test_query("k8s", options.clone())).await.unwrap();
+        let ids: Vec<String> = response.results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["4"]);
+
+        // One-way rules do not apply in reverse
+        let response = engine.search(&create_test_query("kubernetes", options)).await.unwrap();
+        assert_eq!(response.results.len(), 1);
+        assert_eq!(engine.list_synonyms().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_snippets_on_multibyte_content() {
+        let content = format!("{} Das Budget für Straßenbau wurde genehmigt. {}", "Überblick ".repeat(40), "Ärger ".repeat(40));
+        let engine = create_fts_test_search_engine(&[("1", "Haushalt", content.as_str())]).await;
+
+        let options = SearchOptions {
+            boost_recent: false,
+            max_snippet_fragments: Some(2),
+            ..Default::default()
+        };
+        let response = engine.search(&create_test_query("budget", options)).await.unwrap();
+
+        let result = &response.results[0];
+        let snippet = result.snippet.as_deref().unwrap();
+        assert!(snippet.starts_with("...") && snippet.ends_with("..."));
+        assert!(snippet.contains("Budget für Straßenbau"));
+        assert_eq!(result.fragments.len(), 1);
+        assert_eq!(result.highlights.len(), 1);
+        assert_eq!(&content[result.highlights[0].start..result.highlights[0].end], "Budget");
+    }
+
+    #[tokio::test]
+    async fn test_find_similar_skips_near_duplicates() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget review", "quarterly budget review for the finance team"),
+            ("2", "Budget review", "quarterly budget review for the finance team"),
+            ("3", "Finance budget", "the finance team budget planning session"),
+            ("4", "Garden", "tomato planting schedule"),
+            ("5", "Team offsite", "finance team offsite agenda"),
+        ]).await;
+
+        let results = engine.find_similar("1", 10).await.unwrap();
+        let ids: Vec<String> = results.iter().map(|r| r.id.clone()).collect();
+        assert_eq!(ids, vec!["3", "5"]);
+        assert!(results[0].snippet.is_some());
+
+        // Embeddings are blended in when both documents have them
+        {
+            let db = engine.database.lock().await;
+            db.execute_batch(
+                "CREATE TABLE document_chunks (
+                    id TEXT PRIMARY KEY, document_id TEXT NOT NULL, content TEXT NOT NULL,
+                    chunk_index INTEGER NOT NULL, embedding BLOB
+                );",
+            ).unwrap();
+            let embedding = |values: &[f32]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>();
+            for (id, document_id, values) in [("c1", "1", [1.0, 0.0]), ("c3", "3", [0.0, 1.0]), ("c5", "5", [1.0, 0.0])] {
+                db.execute(
+                    "INSERT INTO document_chunks VALUES (?1, ?2, '', 0, ?3)",
+                    rusqlite::params![id, document_id, embedding(&values)],
+                ).unwrap();
+            }
+        }
+        let results = engine.find_similar("1", 10).await.unwrap();
+        let ids: Vec<String> = results.iter().map(|r| r.id.clone()).collect();
+        assert_eq!(ids, vec!["5", "3"]);
+
+        assert!(engine.find_similar("missing", 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_collapse_near_duplicates() {
+        let report = "quarterly report on revenue growth in every region with details on costs hiring \
+            plans supplier contracts marketing spend and the outlook for next year";
+        let edited = report.replace("every region", "each region");
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Quarterly report", report),
+            ("2", "Quarterly report (copy)", edited.as_str()),
+            ("3", "Revenue notes", "revenue by region and quarterly targets"),
+        ]).await;
+        engine.set_duplicate_threshold(0.6).await;
+
+        let groups = engine.duplicate_groups(None).await;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].document_ids, vec!["1", "2"]);
+
+        let options = SearchOptions {
+            include_snippets: false,
+            boost_recent: false,
+            collapse_duplicates: true,
+            ..Default::default()
+        };
+        let response = engine.search(&create_test_query("quarterly revenue", options)).await.unwrap();
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.total_hits, 2);
+        let kept = response.results.iter().find(|r| r.id == "1" || r.id == "2").unwrap();
+        assert_eq!(kept.metadata["duplicate_ids"].as_array().unwrap().len(), 1);
+
+        engine.remove_document("2").await.unwrap();
+        assert!(engine.duplicate_groups(None).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_feedback_tunes_profile_weights() {
+        let engine = create_fts_test_search_engine(&[
+            ("old", "Budget plan", "last year's budget plan"),
+            ("new", "Notes", "notes on the budget"),
+        ]).await;
+        {
+            let db = engine.database.lock().await;
+            let now = chrono::Utc::now().timestamp();
+            db.execute("UPDATE documents SET metadata = ?1 WHERE id = 'old'", rusqlite::params![
+                serde_json::json!({ "modified_at": now - 400 * 86_400, "mime_type": "text/plain" }).to_string()
+            ]).unwrap();
+            db.execute("UPDATE documents SET metadata = ?1 WHERE id = 'new'", rusqlite::params![
+                serde_json::json!({ "modified_at": now, "mime_type": "application/pdf" }).to_string()
+            ]).unwrap();
+        }
+
+        assert!(engine.record_feedback("user", "budget", "missing", 0, FeedbackAction::Shown).await.is_err());
+        assert!(engine.train_ranking("user").await.unwrap().is_none());
+
+        // The user keeps opening the recent notes over the better title match
+        for query in ["budget", "budget notes", "the budget"] {
+            engine.record_feedback("user", query, "old", 0, FeedbackAction::Shown).await.unwrap();
+            engine.record_feedback("user", query, "new", 1, FeedbackAction::Shown).await.unwrap();
+            engine.record_feedback("user", query, "new", 1, FeedbackAction::Opened).await.unwrap();
+            engine.record_feedback("user", query, "old", 0, FeedbackAction::Dismissed).await.unwrap();
+        }
+
+        let evaluation = engine.train_ranking("user").await.unwrap().unwrap();
+        assert!(evaluation.applied);
+        assert!(evaluation.tuned.pairwise_accuracy > evaluation.baseline.pairwise_accuracy);
+        assert!(evaluation.weights.freshness > engine.ranker.weights().freshness);
+
+        // The profile survives a restart
+        let restarted = SearchEngine::new(engine.database.clone()).unwrap();
+        let profile = restarted.ranking_profile("user").await.unwrap();
+        let weights = profile.weights.unwrap();
+        assert!((weights.freshness - evaluation.weights.freshness).abs() < 1e-9);
+        assert!(profile.personalization.preferences()["application/pdf"] > 0.0);
+        assert!(profile.personalization.preferences()["text/plain"] < 0.0);
+        assert_eq!(profile.personalization.search_history().len(), 3);
+
+        let options = SearchOptions { profile_id: Some("user".to_string()), ..Default::default() };
+        let response = restarted.search(&create_test_query("budget", options)).await.unwrap();
+        assert_eq!(response.results.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_personalizes_then_diversifies() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget review", "quarterly budget review for the finance team"),
+            ("2", "Budget review", "quarterly budget review for the finance team, budget notes"),
+            ("3", "Garden Budget", "money for tomatoes, compost, seeds and new garden tools this spring"),
+            ("4", "Travel Budget", "money for flights and hotels on the lisbon trip"),
+        ]).await;
+        {
+            let db = engine.database.lock().await;
+            db.execute_batch(
+                "UPDATE documents SET metadata = '{\"mime_type\": \"text/plain\"}';
+                 UPDATE documents SET metadata = '{\"mime_type\": \"text/plain\", \"source_type\": \"email\"}' WHERE id = '4';",
+            ).unwrap();
+        }
+        let ids = |response: &SearchResponse| response.results.iter().map(|r| r.id.clone()).collect::<Vec<_>>();
+
+        let ranked = engine.search(&create_test_query("budget", SearchOptions::default())).await.unwrap();
+        assert_eq!(ids(&ranked), vec!["2", "1", "3", "4"]);
+
+        // "1" repeats "2", so MMR moves it behind the other topics. "3"
+        // shares less with "2" than the shorter "4" does.
+        let mmr = || Some(DiversityOptions { mmr_lambda: Some(0.3), ..Default::default() });
+        let options = SearchOptions { diversity: mmr(), ..Default::default() };
+        let diversified = engine.search(&create_test_query("budget", options)).await.unwrap();
+        assert_eq!(ids(&diversified), vec!["2", "3", "4", "1"]);
+        assert!(diversified.results.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        // The profile keeps opening email, which lifts "4" over "3"
+        for _ in 0..5 {
+            engine.record_feedback("user", "budget", "4", 3, FeedbackAction::Opened).await.unwrap();
+        }
+        let options = SearchOptions { profile_id: Some("user".to_string()), ..Default::default() };
+        let personalized = engine.search(&create_test_query("budget", options)).await.unwrap();
+        assert_eq!(ids(&personalized), vec!["2", "1", "4", "3"]);
+
+        // Diversity applies to the personalized order, so "4" stays ahead
+        // of "3" where plain MMR put it behind
+        let options = SearchOptions { profile_id: Some("user".to_string()), diversity: mmr(), ..Default::default() };
+        let both = engine.search(&create_test_query("budget", options)).await.unwrap();
+        assert_eq!(ids(&both), vec!["2", "4", "3", "1"]);
+        assert_ne!(ids(&both), ids(&diversified));
+
+        let options = SearchOptions {
+            diversity: Some(DiversityOptions { max_per_source: Some(1), ..Default::default() }),
+            ..Default::default()
+        };
+        let limited = engine.search(&create_test_query("budget", options)).await.unwrap();
+        assert_eq!(ids(&limited), vec!["2", "4"]);
+        assert_eq!(limited.total_hits, 2);
+    }
+
+    #[tokio::test]
+    async fn test_diversified_pages_follow_one_mmr_order() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget review", "quarterly budget review for the finance team"),
+            ("2", "Budget review", "quarterly budget review for the finance team, budget notes"),
+            ("3", "Garden Budget", "money for tomatoes, compost, seeds and new garden tools this spring"),
+            ("4", "Travel Budget", "money for flights and hotels on the lisbon trip"),
+        ]).await;
+        let diversity = Some(DiversityOptions { mmr_lambda: Some(0.3), ..Default::default() });
+        let options = SearchOptions { diversity, include_snippets: false, ..Default::default() };
+        let ids = |results: &[SearchResult]| results.iter().map(|r| r.id.clone()).collect::<Vec<_>>();
+
+        let whole = engine.search(&create_test_query("budget", options.clone())).await.unwrap();
+        assert_eq!(ids(&whole.results), vec!["2", "3", "4", "1"]);
+
+        // Page by page, the first page already sees past its own results
+        // and later pages skip what earlier ones returned
+        for limit in [1, 2, 3] {
+            let mut paged = Vec::new();
+            let mut cursor = None;
+            loop {
+                let mut query = create_test_query("budget", SearchOptions { limit: Some(limit), ..options.clone() });
+                query.options.search_after = cursor;
+                let response = engine.search(&query).await.unwrap();
+                paged.extend(ids(&response.results));
+                cursor = response.next_cursor;
+                if cursor.is_none() {
+                    break;
+                }
+            }
+            assert_eq!(paged, ids(&whole.results), "limit {}", limit);
+        }
+
+        let offset = SearchOptions { limit: Some(2), offset: Some(1), ..options };
+        let response = engine.search(&create_test_query("budget", offset)).await.unwrap();
+        assert_eq!(ids(&response.results), vec!["3", "4"]);
+    }
+
+    struct CollectingAlerts(Arc<std::sync::Mutex<Vec<SavedSearchAlert>>>);
+
+    impl SavedSearchCallback for CollectingAlerts {
+        fn on_saved_search_matches(&self, alert: SavedSearchAlert) {
+            self.0.lock().unwrap().push(alert);
+        }
+    }
+
+    async fn ingest(engine: &SearchEngine, id: &str, title: &str, content: &str) -> Result<()> {
+        ingest_batch(engine, &[(id, title, content)]).await
+    }
+
+    async fn ingest_batch(engine: &SearchEngine, documents: &[(&str, &str, &str)]) -> Result<()> {
+        let indexed: Vec<IndexedDocument> = documents
+            .iter()
+            .map(|&(id, title, content)| IndexedDocument {
+                id: id.to_string(),
+                title: title.to_string(),
+                content: content.to_string(),
+                title_tokens: engine.tokenize_and_stem(title),
+                tokens: engine.tokenize_and_stem(content),
+                entities: Vec::new(),
+                metadata: serde_json::json!({}),
+                embedding: None,
+            })
+            .collect();
+        engine.index_documents_with(&indexed, |tx| {
+            for &(id, title, content) in documents {
+                tx.execute(
+                    "INSERT INTO documents (id, title, content, ingested_at) VALUES (?1, ?2, ?3, ?4)",
+                    rusqlite::params![id, title, content, chrono::Utc::now().timestamp()],
+                )?;
+                tx.execute(
+                    "INSERT INTO documents_fts (title, content, content_id) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![title, content, id],
+                )?;
+            }
+            Ok(())
+        }).await
+    }
+
+    #[tokio::test]
+    async fn test_saved_searches_alert_on_new_matches() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget review", "quarterly budget review"),
+        ]).await;
+        let alerts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        engine.set_saved_search_callback(Box::new(CollectingAlerts(alerts.clone()))).await;
+
+        let saved = engine.save_search("Budgets", &create_test_query("budget", SearchOptions::default())).await.unwrap();
+        engine.save_search("Gardens", &create_test_query("garden", SearchOptions::default())).await.unwrap();
+        assert_eq!(engine.list_saved_searches().await.unwrap().len(), 2);
+
+        // Only the new budget document is reported, not the one saved before
+        ingest(&engine, "2", "Budget plan", "next year's budget").await.unwrap();
+        ingest(&engine, "3", "Packing list", "passport and chargers").await.unwrap();
+        {
+            let alerts = alerts.lock().unwrap();
+            assert_eq!(alerts.len(), 1);
+            assert_eq!(alerts[0].saved_search_id, saved.id);
+            let ids: Vec<&str> = alerts[0].results.iter().map(|r| r.id.as_str()).collect();
+            assert_eq!(ids, vec!["2"]);
+        }
+
+        // Nothing new since the last run
+        assert!(engine.run_saved_searches().await.unwrap().is_empty());
+
+        assert!(engine.delete_saved_search(&saved.id).await.unwrap());
+        ingest(&engine, "4", "Budget notes", "budget notes").await.unwrap();
+        assert_eq!(alerts.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_saved_searches_run_once_per_batch() {
+        let engine = create_fts_test_search_engine(&[]).await;
+        let alerts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        engine.set_saved_search_callback(Box::new(CollectingAlerts(alerts.clone()))).await;
+        engine.save_search("Budgets", &create_test_query("budget", SearchOptions::default())).await.unwrap();
+
+        ingest_batch(&engine, &[
+            ("1", "Budget review", "quarterly budget review"),
+            ("2", "Budget plan", "next year's budget"),
+            ("3", "Packing list", "passport and chargers"),
+        ]).await.unwrap();
+        {
+            let alerts = alerts.lock().unwrap();
+            assert_eq!(alerts.len(), 1);
+            let mut ids: Vec<&str> = alerts[0].results.iter().map(|r| r.id.as_str()).collect();
+            ids.sort();
+            assert_eq!(ids, vec!["1", "2"]);
+        }
+
+        // Single documents indexed on their own wait for the next run
+        {
+            let db = engine.database.lock().await;
+            db.execute(
+                "INSERT INTO documents (id, title, content, ingested_at) VALUES ('4', 'Budget notes', 'budget notes', ?1)",
+                [chrono::Utc::now().timestamp()],
+            ).unwrap();
+            db.execute("INSERT INTO documents_fts (title, content, content_id) VALUES ('Budget notes', 'budget notes', '4')", []).unwrap();
+        }
+        let document = IndexedDocument {
+            id: "4".to_string(),
+            title: "Budget notes".to_string(),
+            content: "budget notes".to_string(),
+            title_tokens: engine.tokenize_and_stem("Budget notes"),
+            tokens: engine.tokenize_and_stem("budget notes"),
+            entities: Vec::new(),
+            metadata: serde_json::json!({}),
+            embedding: None,
+        };
+        engine.index_document(&document).await.unwrap();
+        assert_eq!(alerts.lock().unwrap().len(), 1);
+
+        let pending = engine.run_saved_searches().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].results[0].id, "4");
+    }
+
+    #[tokio::test]
+    async fn test_searches_are_recorded_in_history() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget review", "quarterly budget review"),
+            ("2", "Budget plan", "next year's budget"),
+        ]).await;
+
+        let options = SearchOptions { limit: Some(1), profile_id: Some("ana".to_string()), ..Default::default() };
+        let first = engine.search(&create_test_query("budget", options.clone())).await.unwrap();
+        // Following a cursor continues the same search rather than starting a new one
+        let mut next_page = create_test_query("budget", options);
+        next_page.options.search_after = first.next_cursor.clone();
+        engine.search(&next_page).await.unwrap();
+        engine.search(&create_test_query("zebra", SearchOptions::default())).await.unwrap();
+
+        let history = engine.search_history(None, 10).await.unwrap();
+        let queries: Vec<(&str, usize)> = history.iter().map(|e| (e.query.as_str(), e.result_count)).collect();
+        assert_eq!(queries, vec![("zebra", 0), ("budget", 2)]);
+        assert_eq!(engine.search_history(Some("ana"), 10).await.unwrap().len(), 1);
+
+        assert_eq!(engine.clear_search_history(Some("ana")).await.unwrap(), 1);
+        assert_eq!(engine.search_history(None, 10).await.unwrap().len(), 1);
+    }
+
+    /// Records what a streamed search reported, and cancels it after the
+    /// first partial page if asked to
+    struct StreamEvents {
+        events: Arc<std::sync::Mutex<Vec<String>>>,
+        cancel_after_partial: Option<SearchHandle>,
+    }
+
+    impl StreamEvents {
+        fn push(&self, event: String) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    impl SearchCallback for StreamEvents {
+        fn on_search_results(&self, results: Vec<SearchResult>) {
+            let ids: Vec<String> = results.into_iter().map(|r| r.id).collect();
+            self.push(format!("results {}", ids.join(",")));
+        }
+
+        fn on_search_error(&self, error: String) {
+            self.push(format!("error {}", error));
+        }
+
+        fn on_partial_results(&self, stage: SearchStage, results: Vec<SearchResult>) {
+            let ids: Vec<String> = results.into_iter().map(|r| r.id).collect();
+            self.push(format!("{:?} {}", stage, ids.join(",")));
+            if let Some(handle) = &self.cancel_after_partial {
+                handle.cancel();
+            }
+        }
+
+        fn on_search_complete(&self, total_hits: usize) {
+            self.push(format!("complete {}", total_hits));
+        }
+
+        fn on_search_cancelled(&self) {
+            self.push("cancelled".to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_search_reports_each_stage_then_completes() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget", "annual budget review"),
+            ("2", "Gadgets", "gadget reviews"),
+        ]).await;
+        let options = SearchOptions { fuzzy_matching: true, include_snippets: false, ..Default::default() };
+        let query = create_test_query("budget", options);
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback = StreamEvents { events: events.clone(), cancel_after_partial: None };
+        engine.stream_search(&query, &callback, &SearchHandle::new()).await.unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["Keyword 1", "results 1,2", "complete 2"]);
+
+        // Without later stages there is nothing partial to report
+        let plain = create_test_query("budget", SearchOptions { include_snippets: false, ..Default::default() });
+        events.lock().unwrap().clear();
+        engine.stream_search(&plain, &callback, &SearchHandle::new()).await.unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["results 1", "complete 1"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_stream_search_stops_at_the_next_stage() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget", "annual budget review"),
+        ]).await;
+        let query = create_test_query("budget", SearchOptions { fuzzy_matching: true, ..Default::default() });
+
+        let handle = SearchHandle::new();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback = StreamEvents { events: events.clone(), cancel_after_partial: Some(handle.clone()) };
+        engine.stream_search(&query, &callback, &handle).await.unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["Keyword 1", "cancelled"]);
+        assert!(engine.search_history(None, 10).await.unwrap().is_empty());
+
+        // A search cancelled before it starts reports nothing else
+        events.lock().unwrap().clear();
+        engine.stream_search(&query, &callback, &handle).await.unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["cancelled"]);
+
+        // Nor is it answered from the query cache
+        engine.search(&query).await.unwrap();
+        events.lock().unwrap().clear();
+        engine.stream_search(&query, &callback, &handle).await.unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["cancelled"]);
+        assert_eq!(engine.search_history(None, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_cache_is_invalidated_by_matching_documents() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget review", "quarterly budget review"),
+        ]).await;
+        let query = create_test_query("budget", SearchOptions { include_snippets: false, ..Default::default() });
+        let ids = |response: SearchResponse| response.results.into_iter().map(|r| r.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(engine.search(&query).await.unwrap()), vec!["1"]);
+        assert_eq!(ids(engine.search(&query).await.unwrap()), vec!["1"]);
+        let stats = engine.query_cache_stats().await;
+        assert_eq!((stats.entries, stats.hits, stats.misses), (1, 1, 1));
+
+        // A document the query cannot match leaves the cached response alone
+        ingest(&engine, "2", "Packing list", "passport and chargers").await.unwrap();
+        assert_eq!(engine.query_cache_stats().await.entries, 1);
+
+        ingest(&engine, "3", "Budget plan", "next year's budget").await.unwrap();
+        assert_eq!(engine.query_cache_stats().await.entries, 0);
+        let mut found = ids(engine.search(&query).await.unwrap());
+        found.sort();
+        assert_eq!(found, vec!["1", "3"]);
+
+        engine.remove_document("3").await.unwrap();
+        assert_eq!(engine.query_cache_stats().await.entries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_results_carry_their_best_matching_chunks() {
+        let chunks = [
+            "flights and hotels were booked early in the spring",
+            "lisbon trams and more lisbon trams",
+            "the last day in lisbon was spent packing",
+        ];
+        let content = chunks.join(" ");
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Travel report", &content),
+            ("2", "Postcard", "greetings from lisbon"),
+        ]).await;
+        {
+            let db = engine.database.lock().await;
+            db.execute_batch(
+                "CREATE TABLE document_chunks (
+                    id TEXT PRIMARY KEY, document_id TEXT NOT NULL, content TEXT NOT NULL, chunk_index INTEGER NOT NULL,
+                    start_position INTEGER NOT NULL, end_position INTEGER NOT NULL, embedding BLOB
+                );
+                CREATE VIRTUAL TABLE document_chunks_fts USING fts5(content, chunk_id UNINDEXED);",
+            ).unwrap();
+            let mut start = 0;
+            for (index, chunk) in chunks.iter().enumerate() {
+                let id = format!("1-{}", index);
+                db.execute(
+                    "INSERT INTO document_chunks VALUES (?1, '1', ?2, ?3, ?4, ?5, NULL)",
+                    rusqlite::params![id, chunk, index as u32, start, start + chunk.len()],
+                ).unwrap();
+                db.execute("INSERT INTO document_chunks_fts (content, chunk_id) VALUES (?1, ?2)", rusqlite::params![chunk, id]).unwrap();
+                start += chunk.len() + 1;
+            }
+        }
+
+        let options = SearchOptions { include_snippets: false, ..Default::default() };
+        let response = engine.search(&create_test_query("lisbon", options.clone())).await.unwrap();
+        let report = response.results.iter().find(|r| r.id == "1").unwrap();
+        let passages: Vec<(u32, u32)> = report.passages.iter().map(|p| (p.chunk_index, p.start_position)).collect();
+        assert_eq!(passages, vec![(1, 51), (2, 86)]);
+        assert_eq!(&content[51..report.passages[0].end_position as usize], chunks[1]);
+        let fragment = report.passages[0].fragment.as_ref().unwrap();
+        assert_eq!(fragment.highlights.len(), 2);
+        assert!(response.results.iter().find(|r| r.id == "2").unwrap().passages.is_empty());
+
+        let one = SearchOptions { max_passages: Some(1), ..options.clone() };
+        let response = engine.search(&create_test_query("lisbon", one)).await.unwrap();
+        assert_eq!(response.results.iter().find(|r| r.id == "1").unwrap().passages.len(), 1);
+
+        let none = SearchOptions { max_passages: Some(0), ..options };
+        let response = engine.search(&create_test_query("lisbon", none)).await.unwrap();
+        assert!(response.results.iter().all(|r| r.passages.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_regex_and_scan_query_modes() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Invoice INV-2024", "invoice for the budget review"),
+            ("2", "Budgie care", "seed and water for the budgie"),
+            ("3", "Invoice INV-2023", "paid in full"),
+        ]).await;
+        let search = |text: &str, query_mode: QueryMode, regex_timeout_ms: Option<u64>| {
+            let options = SearchOptions { query_mode, regex_timeout_ms, include_snippets: false, ..Default::default() };
+            let query = create_test_query(text, options);
+            let engine = &engine;
+            async move { engine.search(&query).await.unwrap() }
+        };
+        let ids = |response: &SearchResponse| {
+            let mut ids: Vec<String> = response.results.iter().map(|r| r.id.clone()).collect();
+            ids.sort();
+            ids
+        };
+
+        let response = search("budg*", QueryMode::Wildcard, None).await;
+        assert_eq!(ids(&response), vec!["1", "2"]);
+        assert!(response.total_hits_exact);
+        assert_eq!(ids(&search("bud?et invoic*", QueryMode::Wildcard, None).await), vec!["1"]);
+        // Plain words are analyzed, so the plural still finds "budget"
+        assert_eq!(ids(&search("budgets invoic*", QueryMode::Wildcard, None).await), vec!["1"]);
+        assert_eq!(ids(&search("Büd*", QueryMode::Wildcard, None).await), vec!["1", "2"]);
+        assert_eq!(ids(&search("inv-202[34]", QueryMode::Regex, None).await), Vec::<String>::new());
+        assert_eq!(ids(&search("202[3-4]", QueryMode::Regex, None).await), vec!["1", "3"]);
+
+        // Scans see the raw text, punctuation and case included
+        let response = search(r"INV-\d{4}", QueryMode::RegexScan, None).await;
+        assert_eq!(ids(&response), vec!["1", "3"]);
+        assert!(!response.timed_out && response.did_you_mean.is_none());
+
+        let response = search(r"INV-\d{4}", QueryMode::RegexScan, Some(0)).await;
+        assert!(response.timed_out && !response.total_hits_exact);
+        assert!(response.results.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_multi_key_sort_pages_in_order() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "budget a", "budget"),
+            ("2", "Budget b", "budget budget budget"),
+            ("3", "budget c", "budget"),
+            ("4", "budget d", "budget notes"),
+            ("5", "budget e", "budget"),
+        ]).await;
+        {
+            let db = engine.database.lock().await;
+            for (id, modified_at, metadata) in [
+                ("1", 300, r#"{"file_size": 10}"#),
+                ("2", 100, r#"{"file_size": 20}"#),
+                ("3", 200, r#"{"file_size": 10}"#),
+                ("4", 400, "{}"),
+                ("5", 100, r#"{"file_size": 10}"#),
+            ] {
+                db.execute(
+                    "UPDATE documents SET modified_at = ?1, metadata = ?2 WHERE id = ?3",
+                    rusqlite::params![modified_at, metadata, id],
+                ).unwrap();
+            }
+        }
+
+        let sort = vec![
+            SortKey::new(SortField::FileSize, SortOrder::Descending),
+            SortKey::new(SortField::ModifiedAt, SortOrder::Ascending),
+        ];
+        let mut query = create_test_query("budget", SearchOptions { limit: Some(2), sort, include_snippets: false, ..Default::default() });
+        let mut ids = Vec::new();
+        loop {
+            let response = engine.search(&query).await.unwrap();
+            ids.extend(response.results.iter().map(|r| r.id.clone()));
+            match response.next_cursor {
+                Some(cursor) => query.options.search_after = Some(cursor),
+                None => break,
+            }
+        }
+        // Document 4 has no size and comes last
+        assert_eq!(ids, vec!["2", "5", "3", "1", "4"]);
+
+        let options = SearchOptions {
+            sort: vec![SortKey::new(SortField::Title, SortOrder::Descending)],
+            include_snippets: false,
+            ..Default::default()
+        };
+        let response = engine.search(&create_test_query("budget", options)).await.unwrap();
+        let titles: Vec<&str> = response.results.iter().map(|r| r.title.as_str()).collect();
+        assert_eq!(titles, vec!["budget e", "budget d", "budget c", "Budget b", "budget a"]);
+        assert_eq!(response.results[0].sort_values, vec![SortValue::Text("budget e".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_entity_references_expand_to_related_entities() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Acme contract", "contract budget signed with Acme Corp"),
+            ("2", "Kickoff notes", "Ann Smith presented the budget"),
+            ("3", "Paris trip", "flights to Paris"),
+            ("4", "Acme memo", "Acme Corp holiday schedule"),
+        ]).await;
+        {
+            let db = engine.database.lock().await;
+            db.execute_batch(
+                "CREATE TABLE entities (id TEXT PRIMARY KEY, entity_type TEXT NOT NULL, name TEXT NOT NULL);
+                 CREATE TABLE entity_mentions (id TEXT PRIMARY KEY, entity_id TEXT NOT NULL, document_id TEXT NOT NULL);
+                 CREATE TABLE relationships (
+                     id TEXT PRIMARY KEY, source_entity_id TEXT NOT NULL, target_entity_id TEXT NOT NULL,
+                     relationship_type TEXT NOT NULL, strength REAL NOT NULL DEFAULT 1.0
+                 );
+                 INSERT INTO entities VALUES ('acme', 'organization', 'Acme Corp');
+                 INSERT INTO entities VALUES ('ann', 'person', 'Ann Smith');
+                 INSERT INTO entities VALUES ('paris', 'location', 'Paris');
+                 INSERT INTO entity_mentions VALUES ('m1', 'acme', '1');
+                 INSERT INTO entity_mentions VALUES ('m2', 'ann', '2');
+                 INSERT INTO entity_mentions VALUES ('m3', 'paris', '3');
+                 INSERT INTO entity_mentions VALUES ('m4', 'acme', '4');
+                 INSERT INTO relationships VALUES ('r1', 'ann', 'acme', 'works_for', 1.0);
+                 INSERT INTO relationships VALUES ('r2', 'acme', 'paris', 'located_in', 1.0);",
+            ).unwrap();
+        }
+        let options = SearchOptions { include_snippets: false, ..Default::default() };
+        let ids = |response: &SearchResponse| response.results.iter().map(|r| r.id.clone()).collect::<Vec<_>>();
+
+        let response = engine.search(&create_test_query(r#"entity:"Acme Corp""#, options.clone())).await.unwrap();
+        let mut found = ids(&response);
+        found.sort();
+        assert_eq!(found, vec!["1", "4"]);
+        assert_eq!(response.entities[0].entity_ids(), vec!["acme"]);
+
+        // Text narrows the mentioning documents
+        let response = engine.search(&create_test_query("budget entity:acme", options.clone())).await.unwrap();
+        assert_eq!(ids(&response), vec!["1"]);
+
+        // People related to Acme count, below Acme itself
+        let expansion = EntityExpansion { entity_types: Some(vec!["person".to_string()]), ..Default::default() };
+        let expanded = SearchOptions { entity_expansion: Some(expansion), ..options.clone() };
+        let response = engine.search(&create_test_query("budget entity:acme", expanded)).await.unwrap();
+        assert_eq!(ids(&response), vec!["1", "2"]);
+        assert_eq!(response.entities[0].entity_ids(), vec!["acme", "ann"]);
+
+        let response = engine.search(&create_test_query("entity:nobody", options)).await.unwrap();
+        assert!(response.results.is_empty() && response.entities[0].entities.is_empty());
+        assert!(response.did_you_mean.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_index_stats_and_explain() {
+        let engine = create_fts_test_search_engine(&[
+            ("1", "Budget review", "the quarterly budget review"),
+            ("2", "Garden", "tomatoes and budget notes"),
+            ("3", "Travel", "flights to lisbon"),
+        ]).await;
+
+        let stats = engine.index_stats(1).await.unwrap();
+        assert_eq!((stats.document_count, stats.stored_documents), (3, 3));
+        assert_eq!(stats.top_terms[0].term, "budget");
+        assert_eq!(stats.top_terms[0].document_frequency, 2);
+        assert_eq!(stats.fields.iter().map(|f| f.field.as_str()).collect::<Vec<_>>(), vec!["title", "content"]);
+        assert!(stats.average_document_length > 0.0 && stats.database_bytes > 0);
+        assert_eq!((stats.unindexed_documents, stats.orphaned_documents), (0, 0));
+
+        let terms: Vec<String> = engine.explore_terms("Fl", 10).await.into_iter().map(|t| t.term).collect();
+        assert_eq!(terms, vec!["flight"]);
+        // Accented prefixes find the folded terms
+        let terms: Vec<String> = engine.explore_terms("LÍS", 10).await.into_iter().map(|t| t.term).collect();
+        assert_eq!(terms, vec!["lisbon"]);
+
+        let query = create_test_query("budget review", SearchOptions { include_snippets: false, ..Default::default() });
+        let response = engine.search(&query).await.unwrap();
+        let explanation = engine.explain(&query, "1").await.unwrap();
+        assert!(explanation.matched);
+        assert!((explanation.score - response.results[0].score).abs() < 1e-9);
+        assert_eq!(explanation.components[0].name, "bm25");
+        assert_eq!(explanation.terms.iter().map(|t| t.term.as_str()).collect::<Vec<_>>(), vec!["budget", "review"]);
+        assert_eq!(explanation.terms[0].title_frequency, 1);
+
+        assert!(!engine.explain(&query, "3").await.unwrap().matched);
+        assert!(engine.explain(&query, "missing").await.is_err());
+    }
+}
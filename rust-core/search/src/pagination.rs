@@ -0,0 +1,232 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+use crate::{SearchOptions, SearchResult};
+
+/// Fewest candidates a diversified search reranks, so every page of it up
+/// to this depth is cut from the same MMR order
+const MMR_CANDIDATES: usize = 100;
+
+/// Opaque search-after position handed back to callers as `next_cursor`.
+/// Pages that follow a cursor only see documents first ingested at or
+/// before the snapshot, an ingest sequence number taken by the first page,
+/// and skip the documents earlier pages returned. Scores are recomputed on
+/// every page and may shift, so they are not used to find the position.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchCursor {
+    pub snapshot: i64,
+    pub position: usize,
+    pub seen: Vec<String>, // ids returned by earlier pages, in order
+}
+
+impl SearchCursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_string(self).unwrap_or_default();
+        json.bytes().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self> {
+        if !cursor.len().is_multiple_of(2) {
+            return Err(anyhow!("Invalid search cursor"));
+        }
+
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+            .collect::<std::result::Result<Vec<u8>, _>>()
+            .map_err(|_| anyhow!("Invalid search cursor"))?;
+
+        serde_json::from_slice(&bytes).map_err(|_| anyhow!("Invalid search cursor"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PageRequest {
+    pub offset: usize,
+    pub limit: usize,
+    pub cursor: Option<SearchCursor>,
+    pub snapshot: i64,
+    pub min_window: usize,
+}
+
+impl PageRequest {
+    /// `latest_sequence` is the newest ingest sequence number, which becomes
+    /// the snapshot unless a cursor brings one from an earlier page
+    pub fn from_options(options: &SearchOptions, latest_sequence: i64) -> Result<Self> {
+        let limit = options.limit.unwrap_or(20);
+        let min_window = match options.diversity.as_ref().and_then(|diversity| diversity.mmr_lambda) {
+            Some(_) => MMR_CANDIDATES,
+            None => 0,
+        };
+
+        match options.search_after.as_deref() {
+            Some(encoded) => {
+                let cursor = SearchCursor::decode(encoded)?;
+                Ok(Self {
+                    offset: cursor.position,
+                    limit,
+                    snapshot: cursor.snapshot,
+                    cursor: Some(cursor),
+                    min_window,
+                })
+            }
+            None => Ok(Self {
+                offset: options.offset.unwrap_or(0),
+                limit,
+                cursor: None,
+                snapshot: latest_sequence,
+                min_window,
+            }),
+        }
+    }
+
+    /// Snapshot bound to push down into SQL, applied from the first page on
+    /// so every page of a search sees the same documents
+    pub fn snapshot_filter(&self) -> Option<i64> {
+        Some(self.snapshot)
+    }
+
+    /// Number of top-ranked candidates each search mode must return so the
+    /// requested page can be cut from the merged ranking.
+    pub fn candidate_window(&self) -> usize {
+        std::cmp::max(self.offset + self.limit, self.min_window)
+    }
+
+    /// Ids earlier pages returned, in order
+    pub fn seen(&self) -> &[String] {
+        self.cursor.as_ref().map(|cursor| cursor.seen.as_slice()).unwrap_or_default()
+    }
+
+    pub fn apply(&self, results: &[SearchResult]) -> Vec<SearchResult> {
+        match &self.cursor {
+            Some(cursor) => {
+                let seen: HashSet<&str> = cursor.seen.iter().map(String::as_str).collect();
+                results
+                    .iter()
+                    .filter(|result| !seen.contains(result.id.as_str()))
+                    .take(self.limit)
+                    .cloned()
+                    .collect()
+            }
+            None => results.iter().skip(self.offset).take(self.limit).cloned().collect(),
+        }
+    }
+
+    /// Cursor for the page after `page`, which was cut from `ranked`
+    pub fn next_cursor(&self, ranked: &[SearchResult], page: &[SearchResult], total_hits: usize) -> Option<String> {
+        let position = self.offset + page.len();
+
+        if page.is_empty() || page.len() < self.limit || position >= total_hits {
+            return None;
+        }
+
+        // An offset page also skipped the results ranked before it
+        let seen = match &self.cursor {
+            Some(cursor) => cursor.seen.iter().cloned().chain(page.iter().map(|result| result.id.clone())).collect(),
+            None => ranked.iter().take(position).map(|result| result.id.clone()).collect(),
+        };
+
+        Some(SearchCursor {
+            snapshot: self.snapshot,
+            position,
+            seen,
+        }.encode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchResultType;
+
+    fn create_test_result(id: &str, score: f64) -> SearchResult {
+        SearchResult {
+            id: id.to_string(),
+            result_type: SearchResultType::Document,
+            title: format!("Document {}", id),
+            content: None,
+            snippet: None,
+            score,
+            metadata: serde_json::json!({}),
+            highlights: Vec::new(),
+            fragments: Vec::new(),
+            passages: Vec::new(),
+            sort_values: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = SearchCursor {
+            snapshot: 42,
+            position: 2,
+            seen: vec!["doc-ü".to_string(), "b".to_string()],
+        };
+
+        let decoded = SearchCursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded, cursor);
+        assert!(SearchCursor::decode("not a cursor").is_err());
+    }
+
+    #[test]
+    fn test_offset_pagination() {
+        let options = SearchOptions {
+            limit: Some(2),
+            offset: Some(2),
+            ..Default::default()
+        };
+        let page = PageRequest::from_options(&options, 7).unwrap();
+        assert_eq!(page.candidate_window(), 4);
+        assert_eq!(page.snapshot_filter(), Some(7));
+
+        let results: Vec<SearchResult> = (0..5).map(|i| create_test_result(&i.to_string(), 5.0 - i as f64)).collect();
+        let paged = page.apply(&results);
+        let ids: Vec<&str> = paged.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "3"]);
+
+        // The cursor after an offset page also covers the skipped results
+        let cursor = SearchCursor::decode(&page.next_cursor(&results, &paged, results.len()).unwrap()).unwrap();
+        assert_eq!(cursor.seen, vec!["0", "1", "2", "3"]);
+    }
+
+    #[test]
+    fn test_search_after_skips_seen_results() {
+        let first = PageRequest::from_options(&SearchOptions {
+            limit: Some(2),
+            ..Default::default()
+        }, 3).unwrap();
+
+        let results: Vec<SearchResult> = vec![
+            create_test_result("a", 3.0),
+            create_test_result("b", 2.0),
+            create_test_result("c", 2.0),
+            create_test_result("d", 1.0),
+        ];
+
+        let page_one = first.apply(&results);
+        let cursor = first.next_cursor(&results, &page_one, results.len()).unwrap();
+
+        // Later ingestion does not move the snapshot of a cursor page
+        let second = PageRequest::from_options(&SearchOptions {
+            limit: Some(2),
+            search_after: Some(cursor),
+            ..Default::default()
+        }, 9).unwrap();
+        assert_eq!(second.snapshot_filter(), Some(3));
+
+        // Scores are recomputed on every page, so a seen result that now
+        // ranks lower is still skipped
+        let shifted = vec![
+            create_test_result("c", 3.0),
+            create_test_result("d", 2.5),
+            create_test_result("a", 1.0),
+            create_test_result("b", 0.5),
+        ];
+        let page_two = second.apply(&shifted);
+        let ids: Vec<&str> = page_two.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["c", "d"]);
+        assert!(second.next_cursor(&shifted, &page_two, results.len()).is_none());
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use regex::{Regex, RegexBuilder};
+use serde::{Serialize, Deserialize};
+
+use crate::analyzer::fold;
+
+// Compiled size allowed for a user supplied regex, well above anything
+// typed by hand but far below what a pathological repetition compiles to
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// How the query text is matched
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMode {
+    #[default]
+    Terms, // FTS5 syntax: terms, phrases, NEAR, OR and NOT
+    Wildcard,  // each word may use * and ?, matched against index terms
+    Regex,     // each word is a regex matched against whole index terms
+    RegexScan, // the whole text is a regex run over titles and contents
+}
+
+impl QueryMode {
+    /// Modes that expand the query against the term dictionary
+    pub fn expands_terms(&self) -> bool {
+        matches!(self, QueryMode::Wildcard | QueryMode::Regex)
+    }
+}
+
+/// Index terms a pattern expanded to, most common first
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternExpansion {
+    pub pattern: String,
+    pub terms: Vec<String>,
+    pub truncated: bool, // more terms matched than the expansion limit
+}
+
+/// Expands wildcard and regex patterns against the index vocabulary.
+/// Index terms are lowercased and stemmed, so patterns match stems:
+/// `budg*` finds "budgets" through the term "budget".
+#[derive(Debug, Clone)]
+pub struct PatternExpander {
+    max_expansions: usize,
+}
+
+impl Default for PatternExpander {
+    fn default() -> Self {
+        Self {
+            max_expansions: 100,
+        }
+    }
+}
+
+impl PatternExpander {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expand each whitespace separated word of `text`. Words without
+    /// wildcards in wildcard mode are analyzed like any query term, giving
+    /// one expansion per index term, so `budgets report*` still matches
+    /// "budget".
+    pub fn expand<F>(
+        &self,
+        text: &str,
+        mode: QueryMode,
+        vocabulary: &BTreeMap<String, usize>,
+        analyze: F,
+    ) -> Result<Vec<PatternExpansion>>
+    where
+        F: Fn(&str) -> Vec<String>,
+    {
+        let mut expansions = Vec::new();
+        for word in text.split_whitespace() {
+            let (regex, prefix) = match mode {
+                QueryMode::Wildcard if !Self::is_wildcard(word) => {
+                    expansions.extend(analyze(word).into_iter().map(|term| PatternExpansion {
+                        pattern: word.to_string(),
+                        terms: vec![term],
+                        truncated: false,
+                    }));
+                    continue;
+                }
+                QueryMode::Wildcard => (Self::wildcard_regex(word)?, Self::literal_prefix(word)),
+                QueryMode::Regex => (Self::term_regex(word)?, String::new()),
+                _ => return Err(anyhow!("{:?} queries are not expanded against terms", mode)),
+            };
+            expansions.push(self.expand_regex(word, &regex, &prefix, vocabulary));
+        }
+        Ok(expansions)
+    }
+
+    pub fn set_max_expansions(&mut self, max_expansions: usize) {
+        self.max_expansions = max_expansions.max(1);
+    }
+
+    fn expand_regex(&self, pattern: &str, regex: &Regex, prefix: &str, vocabulary: &BTreeMap<String, usize>) -> PatternExpansion {
+        // Terms sharing the literal prefix are contiguous in the dictionary
+        let mut matches: Vec<(&String, usize)> = vocabulary
+            .range(prefix.to_string()..)
+            .take_while(|(term, _)| term.starts_with(prefix))
+            .filter(|(term, _)| regex.is_match(term))
+            .map(|(term, frequency)| (term, *frequency))
+            .collect();
+        matches.sort_by(|(a, a_frequency), (b, b_frequency)| b_frequency.cmp(a_frequency).then_with(|| a.cmp(b)));
+
+        let truncated = matches.len() > self.max_expansions;
+        matches.truncate(self.max_expansions);
+        PatternExpansion {
+            pattern: pattern.to_string(),
+            terms: matches.into_iter().map(|(term, _)| term.clone()).collect(),
+            truncated,
+        }
+    }
+
+    fn is_wildcard(word: &str) -> bool {
+        word.contains(['*', '?'])
+    }
+
+    // Index terms are folded, so the literal parts of a wildcard are too
+    fn wildcard_regex(pattern: &str) -> Result<Regex> {
+        let mut regex = String::from("^");
+        for c in fold(&pattern.to_lowercase()).chars() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        Self::compile(&regex)
+    }
+
+    fn term_regex(pattern: &str) -> Result<Regex> {
+        Self::compile(&format!("^(?:{})$", pattern))
+    }
+
+    fn literal_prefix(pattern: &str) -> String {
+        fold(&pattern.to_lowercase()).chars().take_while(|c| *c != '*' && *c != '?').collect()
+    }
+
+    fn compile(pattern: &str) -> Result<Regex> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|e| anyhow!("Invalid pattern {}: {}", pattern, e))
+    }
+}
+
+/// Matches of a regex in one document's title and content
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanMatch {
+    pub document_id: String,
+    pub title_matches: usize,
+    pub content_matches: usize,
+    pub matched_text: Vec<String>, // distinct matches, in order of appearance
+}
+
+/// Runs a regex over raw document text, giving up at a deadline. Rust
+/// regexes match in linear time, so one document cannot overrun the
+/// deadline by more than the time to scan it once.
+#[derive(Debug, Clone)]
+pub struct RegexScanner {
+    regex: Regex,
+    deadline: Instant,
+    timed_out: bool,
+}
+
+impl RegexScanner {
+    pub fn new(pattern: &str, timeout: Duration) -> Result<Self> {
+        let regex = RegexBuilder::new(pattern)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|e| anyhow!("Invalid regex {}: {}", pattern, e))?;
+
+        Ok(Self {
+            regex,
+            deadline: Instant::now() + timeout,
+            timed_out: false,
+        })
+    }
+
+    /// None once the deadline has passed; the document was not scanned
+    pub fn scan(&mut self, document_id: &str, title: &str, content: &str) -> Option<Option<ScanMatch>> {
+        if self.timed_out || Instant::now() >= self.deadline {
+            self.timed_out = true;
+            return None;
+        }
+
+        let mut matched_text: Vec<String> = Vec::new();
+        let mut count = |text: &str| {
+            let mut matches = 0;
+            for found in self.regex.find_iter(text).filter(|found| !found.as_str().is_empty()) {
+                matches += 1;
+                if !matched_text.iter().any(|seen| seen == found.as_str()) {
+                    matched_text.push(found.as_str().to_string());
+                }
+            }
+            matches
+        };
+        let title_matches = count(title);
+        let content_matches = count(content);
+
+        if title_matches + content_matches == 0 {
+            return Some(None);
+        }
+        Some(Some(ScanMatch {
+            document_id: document_id.to_string(),
+            title_matches,
+            content_matches,
+            matched_text,
+        }))
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vocabulary(terms: &[(&str, usize)]) -> BTreeMap<String, usize> {
+        terms.iter().map(|(term, df)| (term.to_string(), *df)).collect()
+    }
+
+    // Lowercases, drops "the" and strips a plural "s"
+    fn analyze(text: &str) -> Vec<String> {
+        text.split_whitespace()
+            .map(|word| word.to_lowercase())
+            .filter(|word| word != "the")
+            .map(|word| word.strip_suffix('s').map(str::to_string).unwrap_or(word))
+            .collect()
+    }
+
+    #[test]
+    fn test_wildcards_expand_most_common_terms_first() {
+        let vocabulary = vocabulary(&[("budget", 5), ("budgie", 9), ("bud", 2), ("rebudget", 1), ("report", 4)]);
+        let expander = PatternExpander::new();
+
+        let expansions = expander.expand("Budg* rep?rt", QueryMode::Wildcard, &vocabulary, analyze).unwrap();
+        assert_eq!(expansions[0].terms, vec!["budgie", "budget"]);
+        assert_eq!(expansions[1].terms, vec!["report"]);
+        assert!(!expansions[0].truncated);
+
+        // Dots are literal in wildcards
+        assert!(expander.expand("bud.*", QueryMode::Wildcard, &vocabulary, analyze).unwrap()[0].terms.is_empty());
+
+        let mut limited = PatternExpander::new();
+        limited.set_max_expansions(1);
+        let expansions = limited.expand("*bud*", QueryMode::Wildcard, &vocabulary, analyze).unwrap();
+        assert_eq!(expansions[0].terms, vec!["budgie"]);
+        assert!(expansions[0].truncated);
+    }
+
+    #[test]
+    fn test_wildcard_mode_analyzes_plain_words_and_folds_patterns() {
+        let vocabulary = vocabulary(&[("budget", 5), ("report", 4), ("resume", 2), ("resumption", 1)]);
+        let expander = PatternExpander::new();
+
+        // Plain words become the index terms the analyzer makes of them
+        let expansions = expander.expand("the Budgets report*", QueryMode::Wildcard, &vocabulary, analyze).unwrap();
+        let terms: Vec<&Vec<String>> = expansions.iter().map(|expansion| &expansion.terms).collect();
+        assert_eq!(terms, vec![&vec!["budget".to_string()], &vec!["report".to_string()]]);
+        assert_eq!(expansions[0].pattern, "Budgets");
+
+        let expansions = expander.expand("Résu*", QueryMode::Wildcard, &vocabulary, analyze).unwrap();
+        assert_eq!(expansions[0].terms, vec!["resume", "resumption"]);
+    }
+
+    #[test]
+    fn test_regex_matches_whole_terms() {
+        let vocabulary = vocabulary(&[("budget", 5), ("rebudget", 1), ("2023", 3), ("2024", 2), ("20245", 1)]);
+        let expander = PatternExpander::new();
+
+        let expansions = expander.expand("202[34] budget", QueryMode::Regex, &vocabulary, analyze).unwrap();
+        assert_eq!(expansions[0].terms, vec!["2023", "2024"]);
+        assert_eq!(expansions[1].terms, vec!["budget"]);
+        assert!(expander.expand("(unclosed", QueryMode::Regex, &vocabulary, analyze).is_err());
+        assert!(expander.expand("budget", QueryMode::Terms, &vocabulary, analyze).is_err());
+    }
+
+    #[test]
+    fn test_regex_scanner_counts_matches_until_the_deadline() {
+        let mut scanner = RegexScanner::new(r"INV-\d{4}", Duration::from_secs(60)).unwrap();
+        let found = scanner.scan("1", "Invoice INV-2024", "Paid INV-2024 and INV-2025").unwrap().unwrap();
+        assert_eq!((found.title_matches, found.content_matches), (1, 2));
+        assert_eq!(found.matched_text, vec!["INV-2024", "INV-2025"]);
+        assert_eq!(scanner.scan("2", "Receipt", "no invoice number"), Some(None));
+        assert!(!scanner.timed_out());
+
+        let mut expired = RegexScanner::new("INV", Duration::ZERO).unwrap();
+        assert_eq!(expired.scan("1", "INV", ""), None);
+        assert!(expired.timed_out());
+        assert!(RegexScanner::new("a{1000}{1000}", Duration::from_secs(1)).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use serde::{Serialize, Deserialize};
+
+// FTS5's default NEAR distance when the query does not give one
+const DEFAULT_NEAR_DISTANCE: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NearClause {
+    pub terms: Vec<String>,
+    pub distance: usize,
+}
+
+/// One way of satisfying a query term in the index: the term itself, a
+/// fuzzy expansion or a synonym. Several terms are either all required
+/// (`phrase == false`) or required as consecutive words.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TermAlternative {
+    pub terms: Vec<String>,
+    pub phrase: bool,
+    pub weight: f64, // multiplier on the BM25F score of the matched terms
+}
+
+impl TermAlternative {
+    pub fn exact(term: &str) -> Self {
+        Self {
+            terms: vec![term.to_string()],
+            phrase: false,
+            weight: 1.0,
+        }
+    }
+}
+
+/// Query text split into the parts the positional index can answer.
+/// The syntax is the subset of FTS5 query syntax that carries positional
+/// meaning: `"quoted phrases"` and `NEAR(term term, k)` groups. Everything
+/// else is treated as plain terms.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParsedQuery {
+    pub terms: Vec<String>,
+    pub phrases: Vec<Vec<String>>,
+    pub near: Vec<NearClause>,
+    pub has_boolean_operators: bool, // OR or NOT, which select documents the terms alone do not
+}
+
+impl ParsedQuery {
+    pub fn parse<F>(text: &str, analyze: F) -> Self
+    where
+        F: Fn(&str) -> Vec<String>,
+    {
+        let mut parsed = ParsedQuery::default();
+        let mut free_text = String::new();
+        let mut rest = text;
+
+        while !rest.is_empty() {
+            if let Some(after_quote) = rest.strip_prefix('"') {
+                let end = after_quote.find('"').unwrap_or(after_quote.len());
+                let phrase = analyze(&after_quote[..end]);
+                match phrase.len() {
+                    0 => {}
+                    1 => parsed.terms.extend(phrase),
+                    _ => parsed.phrases.push(phrase),
+                }
+                rest = after_quote.get(end + 1..).unwrap_or("");
+            } else if let Some(after_near) = rest.strip_prefix("NEAR(") {
+                let end = after_near.find(')').unwrap_or(after_near.len());
+                parsed.near.push(Self::parse_near(&after_near[..end], &analyze));
+                rest = after_near.get(end + 1..).unwrap_or("");
+            } else {
+                let next = rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+                free_text.push_str(&rest[..next]);
+                rest = &rest[next..];
+            }
+        }
+
+        // Boolean operators are FTS5 syntax, not search terms
+        let words: Vec<&str> = free_text
+            .split_whitespace()
+            .filter(|word| {
+                if matches!(*word, "OR" | "NOT") {
+                    parsed.has_boolean_operators = true;
+                }
+                !matches!(*word, "AND" | "OR" | "NOT")
+            })
+            .collect();
+        parsed.terms.extend(analyze(&words.join(" ")));
+
+        parsed
+    }
+
+    fn parse_near<F>(body: &str, analyze: &F) -> NearClause
+    where
+        F: Fn(&str) -> Vec<String>,
+    {
+        let (terms, distance) = match body.rsplit_once(',') {
+            Some((terms, distance)) => match distance.trim().parse::<usize>() {
+                Ok(distance) => (terms, distance),
+                Err(_) => (body, DEFAULT_NEAR_DISTANCE),
+            },
+            None => (body, DEFAULT_NEAR_DISTANCE),
+        };
+
+        NearClause {
+            terms: analyze(&terms.replace('"', " ")),
+            distance,
+        }
+    }
+
+    /// Every distinct term in the query, in order of first appearance
+    pub fn all_terms(&self) -> Vec<String> {
+        let mut seen = std::collections::HashSet::new();
+        self.terms
+            .iter()
+            .chain(self.phrases.iter().flatten())
+            .chain(self.near.iter().flat_map(|clause| clause.terms.iter()))
+            .filter(|term| seen.insert(term.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    pub fn has_positional_constraints(&self) -> bool {
+        !self.phrases.is_empty() || !self.near.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(text: &str) -> Vec<String> {
+        text.split_whitespace().map(|word| word.to_lowercase()).collect()
+    }
+
+    #[test]
+    fn test_plain_terms() {
+        let parsed = ParsedQuery::parse("budget AND review", analyze);
+        assert_eq!(parsed.terms, vec!["budget", "review"]);
+        assert!(!parsed.has_positional_constraints());
+        assert!(!parsed.has_boolean_operators);
+        assert!(ParsedQuery::parse("budget NOT review", analyze).has_boolean_operators);
+    }
+
+    #[test]
+    fn test_phrase_and_near() {
+        let parsed = ParsedQuery::parse("\"Quick Brown\" fox NEAR(annual review, 3) \"solo\"", analyze);
+
+        assert_eq!(parsed.phrases, vec![vec!["quick".to_string(), "brown".to_string()]]);
+        assert_eq!(parsed.near.len(), 1);
+        assert_eq!(parsed.near[0].terms, vec!["annual", "review"]);
+        assert_eq!(parsed.near[0].distance, 3);
+        assert_eq!(parsed.terms, vec!["solo", "fox"]);
+
+        assert_eq!(
+            parsed.all_terms(),
+            vec!["solo", "fox", "quick", "brown", "annual", "review"]
+        );
+    }
+
+    #[test]
+    fn test_near_default_distance() {
+        let parsed = ParsedQuery::parse("NEAR(alpha beta)", analyze);
+        assert_eq!(parsed.near[0].distance, DEFAULT_NEAR_DISTANCE);
+    }
+}
@@ -1,25 +1,80 @@
 use std::collections::HashMap;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
 
 use crate::{SearchResult, SearchOptions};
 
+/// Weights of the ranking signals added to a result's base score. They
+/// always sum to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RankingWeights {
+    pub freshness: f64,
+    pub relevance: f64,
+    pub popularity: f64,
+}
+
+/// The ranking signals of one result, before weighting
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct RankingFeatures {
+    pub freshness: f64,
+    pub relevance: f64,
+    pub popularity: f64,
+}
+
+impl RankingWeights {
+    pub fn score(&self, features: &RankingFeatures) -> f64 {
+        self.freshness * features.freshness
+            + self.relevance * features.relevance
+            + self.popularity * features.popularity
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SearchRanker {
     // Configuration for ranking algorithms
     freshness_weight: f64,
     relevance_weight: f64,
     popularity_weight: f64,
+    proximity_weight: f64,
+    passage_weight: f64,
+    entity_weight: f64,
 }
 
-impl SearchRanker {
-    pub fn new() -> Self {
+impl Default for SearchRanker {
+    fn default() -> Self {
         Self {
             freshness_weight: 0.2,
             relevance_weight: 0.6,
             popularity_weight: 0.2,
+            proximity_weight: 0.5,
+            passage_weight: 0.5,
+            entity_weight: 0.5,
         }
     }
+}
+
+impl SearchRanker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Weight applied to the positional proximity score of a match
+    pub fn proximity_weight(&self) -> f64 {
+        self.proximity_weight
+    }
+
+    /// Weight applied to a document's best matching chunk, relative to the
+    /// best chunk of the query
+    pub fn passage_weight(&self) -> f64 {
+        self.passage_weight
+    }
+
+    /// Weight applied to how strongly a document mentions the entities a
+    /// query refers to, 1 per reference for the entity itself
+    pub fn entity_weight(&self) -> f64 {
+        self.entity_weight
+    }
 
     pub async fn rank_results(
         &self,
@@ -32,8 +87,12 @@ impl SearchRanker {
             result.score = self.calculate_ranking_score(result, query_terms, options).await?;
         }
 
-        // Sort by score (highest first)
-        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        // Sort by score (highest first), ties broken by id so pages are stable
+        results.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.id.cmp(&b.id))
+        });
 
         Ok(results)
     }
@@ -44,20 +103,31 @@ impl SearchRanker {
         query_terms: &[String],
         options: &SearchOptions,
     ) -> Result<f64> {
-        let mut score = result.score; // Base relevance score
+        let mut features = self.ranking_features(result, query_terms);
 
-        // Apply freshness boost if enabled
-        if options.boost_recent {
-            score += self.calculate_freshness_score(result) * self.freshness_weight;
+        // Freshness only counts when boosting recent documents
+        if !options.boost_recent {
+            features.freshness = 0.0;
         }
 
-        // Apply query-specific scoring
-        score += self.calculate_query_match_score(result, query_terms) * self.relevance_weight;
+        Ok(result.score + self.weights().score(&features))
+    }
 
-        // Apply popularity score (based on metadata)
-        score += self.calculate_popularity_score(result) * self.popularity_weight;
+    /// Signals of a result for the given query, as weighted by `weights`
+    pub fn ranking_features(&self, result: &SearchResult, query_terms: &[String]) -> RankingFeatures {
+        RankingFeatures {
+            freshness: self.calculate_freshness_score(result),
+            relevance: self.calculate_query_match_score(result, query_terms),
+            popularity: self.calculate_popularity_score(result),
+        }
+    }
 
-        Ok(score)
+    pub fn weights(&self) -> RankingWeights {
+        RankingWeights {
+            freshness: self.freshness_weight,
+            relevance: self.relevance_weight,
+            popularity: self.popularity_weight,
+        }
     }
 
     fn calculate_freshness_score(&self, result: &SearchResult) -> f64 {
@@ -161,6 +231,34 @@ impl SearchRanker {
             self.popularity_weight = popularity / total;
         }
     }
+
+    pub fn set_ranking_weights(&mut self, weights: RankingWeights) {
+        self.set_weights(weights.freshness, weights.relevance, weights.popularity);
+    }
+
+    pub fn set_proximity_weight(&mut self, weight: f64) {
+        self.proximity_weight = weight.max(0.0);
+    }
+
+    pub fn set_passage_weight(&mut self, weight: f64) {
+        self.passage_weight = weight.max(0.0);
+    }
+
+    pub fn set_entity_weight(&mut self, weight: f64) {
+        self.entity_weight = weight.max(0.0);
+    }
+}
+
+/// Per-query diversification. Limits left unset do not apply; without
+/// `mmr_lambda` the ranking order is kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiversityOptions {
+    #[serde(default)]
+    pub max_per_type: Option<usize>,
+    #[serde(default)]
+    pub max_per_source: Option<usize>,
+    #[serde(default)]
+    pub mmr_lambda: Option<f64>, // 1 ranks by relevance alone, 0 by novelty alone
 }
 
 #[derive(Debug, Clone)]
@@ -169,13 +267,19 @@ pub struct ResultDiversifier {
     max_results_per_source: usize,
 }
 
-impl ResultDiversifier {
-    pub fn new() -> Self {
+impl Default for ResultDiversifier {
+    fn default() -> Self {
         Self {
             max_results_per_type: 10,
             max_results_per_source: 5,
         }
     }
+}
+
+impl ResultDiversifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
     pub fn diversify_results(&self, results: Vec<SearchResult>) -> Vec<SearchResult> {
         let mut diversified = Vec::new();
@@ -208,9 +312,68 @@ impl ResultDiversifier {
         self.max_results_per_type = max_per_type;
         self.max_results_per_source = max_per_source;
     }
+
+    /// Reorder by maximal marginal relevance. Each pick maximizes
+    /// `lambda * relevance - (1 - lambda) * similarity` to the closest
+    /// result already picked, relevance being the score over the top score
+    /// so it does not depend on how many candidates there are.
+    /// `similarity` takes indexes into `results`. Results whose ids are in
+    /// `picked`, which earlier pages returned, go first in that order so
+    /// later picks are diversified against them. Scores are replaced by the
+    /// pick's value, which never increases after the `picked` results.
+    pub fn mmr_rerank<F>(&self, results: Vec<SearchResult>, lambda: f64, picked: &[String], similarity: F) -> Vec<SearchResult>
+    where
+        F: Fn(usize, usize) -> f64,
+    {
+        let lambda = lambda.clamp(0.0, 1.0);
+        let max = results.iter().map(|result| result.score).fold(f64::NEG_INFINITY, f64::max);
+        let relevance: Vec<f64> = results
+            .iter()
+            .map(|result| if max > 0.0 { result.score / max } else { 1.0 })
+            .collect();
+
+        let mut closest = vec![0.0; results.len()];
+        let mut remaining: Vec<usize> = (0..results.len()).collect();
+        let mut picks = Vec::with_capacity(results.len());
+        let mut seeds = picked.iter().filter_map(|id| results.iter().position(|result| &result.id == id));
+
+        while !remaining.is_empty() {
+            let value = |i: usize| lambda * relevance[i] - (1.0 - lambda) * closest[i];
+            let position = seeds
+                .by_ref()
+                .find_map(|seed| remaining.iter().position(|&i| i == seed))
+                .or_else(|| {
+                    (0..remaining.len()).max_by(|&a, &b| {
+                        let (a, b) = (remaining[a], remaining[b]);
+                        value(a)
+                            .partial_cmp(&value(b))
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                            .then_with(|| results[b].id.cmp(&results[a].id))
+                    })
+                })
+                .unwrap_or(0);
+            let picked = remaining.remove(position);
+            picks.push((picked, value(picked)));
+
+            for &i in &remaining {
+                closest[i] = f64::max(closest[i], similarity(picked, i));
+            }
+        }
+
+        let mut slots: Vec<Option<SearchResult>> = results.into_iter().map(Some).collect();
+        picks
+            .into_iter()
+            .filter_map(|(i, value)| {
+                slots[i].take().map(|mut result| {
+                    result.score = value;
+                    result
+                })
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PersonalizationEngine {
     user_preferences: HashMap<String, f64>,
     search_history: Vec<String>,
@@ -238,8 +401,12 @@ impl PersonalizationEngine {
             result.score += self.calculate_personalization_score(result);
         }
 
-        // Re-sort results
-        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        // Re-sort results, ties broken by id so pages are stable
+        results.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.id.cmp(&b.id))
+        });
 
         results
     }
@@ -282,6 +449,24 @@ impl PersonalizationEngine {
         self.user_preferences.insert(result_type.to_string(), updated);
     }
 
+    /// Metadata values preferences are kept for: the result's MIME type and
+    /// source type
+    pub fn preference_keys(result_metadata: &serde_json::Value) -> Vec<String> {
+        ["mime_type", "source_type"]
+            .iter()
+            .filter_map(|key| result_metadata.get(*key).and_then(|value| value.as_str()))
+            .map(|value| value.to_string())
+            .collect()
+    }
+
+    pub fn preferences(&self) -> &HashMap<String, f64> {
+        &self.user_preferences
+    }
+
+    pub fn search_history(&self) -> &[String] {
+        &self.search_history
+    }
+
     pub fn add_to_search_history(&mut self, query: &str) {
         self.search_history.push(query.to_string());
         
@@ -314,6 +499,9 @@ mod tests {
                 "modified_at": Utc::now().timestamp()
             }),
             highlights: Vec::new(),
+            fragments: Vec::new(),
+            passages: Vec::new(),
+            sort_values: Vec::new(),
         }
     }
 
@@ -358,6 +546,33 @@ mod tests {
         assert!(!diversified.is_empty());
     }
 
+    #[test]
+    fn test_mmr_rerank_promotes_novel_results() {
+        let diversifier = ResultDiversifier::new();
+        let results = vec![
+            create_test_result("a", "budget", 1.0),
+            create_test_result("b", "budget copy", 0.9),
+            create_test_result("c", "garden", 0.5),
+        ];
+        // "a" and "b" cover the same topic
+        let similarity = |i: usize, j: usize| if i + j == 1 { 0.95 } else { 0.0 };
+
+        let reranked = diversifier.mmr_rerank(results.clone(), 0.5, &[], similarity);
+        let ids: Vec<&str> = reranked.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "c", "b"]);
+        assert!(reranked.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        // Relevance alone keeps the ranking
+        let reranked = diversifier.mmr_rerank(results.clone(), 1.0, &[], similarity);
+        let ids: Vec<&str> = reranked.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+
+        // Results an earlier page picked count against their neighbours
+        let reranked = diversifier.mmr_rerank(results, 0.5, &["b".to_string()], similarity);
+        let ids: Vec<&str> = reranked.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "c", "a"]);
+    }
+
     #[test]
     fn test_personalization() {
         let mut engine = PersonalizationEngine::new();
@@ -394,6 +609,9 @@ mod tests {
             score: 0.5,
             metadata: json!({}),
             highlights: Vec::new(),
+            fragments: Vec::new(),
+            passages: Vec::new(),
+            sort_values: Vec::new(),
         };
         
         let query_terms = vec!["test".to_string(), "document".to_string()];
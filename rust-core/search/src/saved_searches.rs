@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+use anyhow::{Result, anyhow};
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::{SearchQuery, SearchResult};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSearch {
+    pub id: String,
+    pub name: String,
+    pub query: SearchQuery,
+    pub created_at: i64,
+    pub last_run_at: i64, // documents ingested from here on are new matches
+}
+
+/// Documents that newly match a saved search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSearchAlert {
+    pub saved_search_id: String,
+    pub name: String,
+    pub results: Vec<SearchResult>,
+}
+
+/// Saved searches and the documents already reported for each, kept in the
+/// `search_saved` and `search_saved_matches` tables. Both are created with
+/// the first saved search.
+pub struct SavedSearchStore;
+
+impl SavedSearchStore {
+    fn create_tables(db: &Connection) -> Result<()> {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS search_saved (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                query TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                last_run_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS search_saved_matches (
+                saved_search_id TEXT NOT NULL,
+                document_id TEXT NOT NULL,
+                matched_at INTEGER NOT NULL,
+                PRIMARY KEY (saved_search_id, document_id)
+            );",
+        )?;
+        Ok(())
+    }
+
+    fn table_exists(db: &Connection) -> Result<bool> {
+        Ok(db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'search_saved')",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Only documents ingested after `now` will be reported for it
+    pub fn save(db: &Connection, name: &str, query: &SearchQuery, now: i64) -> Result<SavedSearch> {
+        if name.trim().is_empty() {
+            return Err(anyhow!("A saved search needs a name"));
+        }
+        Self::create_tables(db)?;
+
+        let saved = SavedSearch {
+            id: Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            query: query.clone(),
+            created_at: now,
+            last_run_at: now,
+        };
+        db.execute(
+            "INSERT INTO search_saved (id, name, query, created_at, last_run_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![saved.id, saved.name, serde_json::to_string(&saved.query)?, saved.created_at, saved.last_run_at],
+        )?;
+
+        Ok(saved)
+    }
+
+    pub fn list(db: &Connection) -> Result<Vec<SavedSearch>> {
+        if !Self::table_exists(db)? {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = db.prepare(
+            "SELECT id, name, query, created_at, last_run_at FROM search_saved ORDER BY created_at, rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        })?;
+
+        let mut saved_searches = Vec::new();
+        for row in rows {
+            let (id, name, query, created_at, last_run_at) = row?;
+            saved_searches.push(SavedSearch {
+                id,
+                name,
+                query: serde_json::from_str(&query)?,
+                created_at,
+                last_run_at,
+            });
+        }
+
+        Ok(saved_searches)
+    }
+
+    /// Returns false if there was no saved search with that id
+    pub fn delete(db: &Connection, id: &str) -> Result<bool> {
+        if !Self::table_exists(db)? {
+            return Ok(false);
+        }
+
+        db.execute("DELETE FROM search_saved_matches WHERE saved_search_id = ?1", params![id])?;
+        Ok(db.execute("DELETE FROM search_saved WHERE id = ?1", params![id])? > 0)
+    }
+
+    /// Documents ingested since the last run that were not reported yet.
+    /// The run time is inclusive, so documents ingested in the same second
+    /// as a run are not missed.
+    pub fn new_documents(db: &Connection, saved: &SavedSearch) -> Result<Vec<String>> {
+        let mut stmt = db.prepare(
+            "SELECT d.id FROM documents d
+             WHERE d.ingested_at >= ?1
+               AND NOT EXISTS (
+                   SELECT 1 FROM search_saved_matches m
+                   WHERE m.saved_search_id = ?2 AND m.document_id = d.id
+               )
+             ORDER BY d.id",
+        )?;
+        let ids = stmt
+            .query_map(params![saved.last_run_at, saved.id], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(ids)
+    }
+
+    /// Remember the run and the documents it reported. Returns false if the
+    /// saved search was deleted in the meantime.
+    pub fn record_run(db: &Connection, id: &str, run_at: i64, matched: &HashSet<String>) -> Result<bool> {
+        let exists = db
+            .query_row("SELECT 1 FROM search_saved WHERE id = ?1", params![id], |_| Ok(()))
+            .optional()?
+            .is_some();
+        if !exists {
+            return Ok(false);
+        }
+
+        db.execute("UPDATE search_saved SET last_run_at = ?1 WHERE id = ?2", params![run_at, id])?;
+        for document_id in matched {
+            db.execute(
+                "INSERT OR IGNORE INTO search_saved_matches (saved_search_id, document_id, matched_at) VALUES (?1, ?2, ?3)",
+                params![id, document_id, run_at],
+            )?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SearchFilters, SearchOptions};
+
+    fn create_db() -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE documents (id TEXT PRIMARY KEY, ingested_at INTEGER NOT NULL);
+             INSERT INTO documents VALUES ('old', 100), ('same', 200), ('new', 300);",
+        ).unwrap();
+        db
+    }
+
+    fn query(text: &str) -> SearchQuery {
+        SearchQuery {
+            text: text.to_string(),
+            filters: SearchFilters {
+                entity_types: None,
+                document_types: None,
+                date_range: None,
+                file_types: None,
+                source_types: Some(vec!["email".to_string()]),
+                document_ids: None,
+                mentions: None,
+            },
+            options: SearchOptions { limit: Some(5), fuzzy_matching: true, ..Default::default() },
+        }
+    }
+
+    #[test]
+    fn test_saved_searches_persist_with_their_query() {
+        let db = create_db();
+        assert!(SavedSearchStore::list(&db).unwrap().is_empty());
+        assert!(!SavedSearchStore::delete(&db, "missing").unwrap());
+        assert!(SavedSearchStore::save(&db, " ", &query("budget"), 0).is_err());
+
+        let saved = SavedSearchStore::save(&db, "Budgets", &query("budget"), 200).unwrap();
+        let listed = SavedSearchStore::list(&db).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, saved.id);
+        assert_eq!(listed[0].query.text, "budget");
+        assert_eq!(listed[0].query.filters.source_types, Some(vec!["email".to_string()]));
+        assert!(listed[0].query.options.fuzzy_matching);
+
+        assert!(SavedSearchStore::delete(&db, &saved.id).unwrap());
+        assert!(SavedSearchStore::list(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_new_documents_since_last_run() {
+        let db = create_db();
+        let saved = SavedSearchStore::save(&db, "Budgets", &query("budget"), 200).unwrap();
+        assert_eq!(SavedSearchStore::new_documents(&db, &saved).unwrap(), vec!["new", "same"]);
+
+        // Reported documents are not reported again, even within the run's second
+        let matched: HashSet<String> = ["new".to_string()].into_iter().collect();
+        assert!(SavedSearchStore::record_run(&db, &saved.id, 300, &matched).unwrap());
+        let saved = SavedSearchStore::list(&db).unwrap().remove(0);
+        assert_eq!(saved.last_run_at, 300);
+        assert!(SavedSearchStore::new_documents(&db, &saved).unwrap().is_empty());
+
+        SavedSearchStore::delete(&db, &saved.id).unwrap();
+        assert!(!SavedSearchStore::record_run(&db, &saved.id, 400, &HashSet::new()).unwrap());
+    }
+}
@@ -0,0 +1,583 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use memmap2::Mmap;
+
+use crate::IndexedDocument;
+use crate::indexer::{DocumentStats, TermPosting};
+
+// Segment file layout (all integers little endian):
+//
+//   header      magic, counts, field length totals and table offsets
+//   doc table   one fixed-size entry per document, sorted by document id
+//   term table  one fixed-size entry per term, sorted by term
+//   data        ids, term text, per-document term lists, postings, positions
+//
+// Fixed-size tables let lookups binary search the mapped file directly, so
+// opening a segment costs one mmap rather than a parse.
+const MAGIC: &[u8; 8] = b"AOSEG001";
+const HEADER_SIZE: usize = 64;
+const DOC_ENTRY_SIZE: usize = 32;
+const TERM_ENTRY_SIZE: usize = 24;
+const POSTING_ENTRY_SIZE: usize = 20;
+
+/// A document's stats with its postings, term by term
+pub type DocumentCopy = (DocumentStats, Vec<(String, TermPosting)>);
+
+/// Mutable segment holding recently indexed documents until they are
+/// written to disk
+#[derive(Debug, Clone, Default)]
+pub struct MemorySegment {
+    pub postings: HashMap<String, HashMap<String, TermPosting>>, // term -> doc_id -> positions per field
+    pub documents: HashMap<String, DocumentStats>,
+}
+
+impl MemorySegment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn add_document(&mut self, document: &IndexedDocument) -> &DocumentStats {
+        // Record token positions per field; raw counts are their lengths
+        let mut postings: HashMap<String, TermPosting> = HashMap::new();
+
+        for (position, token) in document.title_tokens.iter().enumerate() {
+            postings.entry(token.clone()).or_default().title_positions.push(position as u32);
+        }
+        for (position, token) in document.tokens.iter().enumerate() {
+            postings.entry(token.clone()).or_default().content_positions.push(position as u32);
+        }
+
+        let stats = DocumentStats {
+            title_length: document.title_tokens.len(),
+            content_length: document.tokens.len(),
+            terms: postings.keys().cloned().collect(),
+        };
+
+        self.add_postings(&document.id, stats, postings.into_iter().collect())
+    }
+
+    pub fn add_postings(
+        &mut self,
+        document_id: &str,
+        stats: DocumentStats,
+        postings: Vec<(String, TermPosting)>,
+    ) -> &DocumentStats {
+        for (term, posting) in postings {
+            self.postings
+                .entry(term)
+                .or_default()
+                .insert(document_id.to_string(), posting);
+        }
+
+        self.documents.entry(document_id.to_string()).or_insert(stats)
+    }
+
+    pub fn remove_document(&mut self, document_id: &str) -> Option<DocumentStats> {
+        let stats = self.documents.remove(document_id)?;
+
+        for term in &stats.terms {
+            if let Some(doc_postings) = self.postings.get_mut(term) {
+                doc_postings.remove(document_id);
+                // Remove term entry if no documents contain it
+                if doc_postings.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+
+        Some(stats)
+    }
+
+    pub fn posting(&self, term: &str, document_id: &str) -> Option<&TermPosting> {
+        self.postings.get(term).and_then(|docs| docs.get(document_id))
+    }
+
+    /// A copy of a document's stats and postings, to add back later
+    pub fn document(&self, document_id: &str) -> Option<DocumentCopy> {
+        let stats = self.documents.get(document_id)?;
+        let postings = stats.terms
+            .iter()
+            .filter_map(|term| Some((term.clone(), self.posting(term, document_id)?.clone())))
+            .collect();
+        Some((stats.clone(), postings))
+    }
+
+    /// Write the segment to `path`. The file is written under a temporary
+    /// name, synced and renamed so a crash never leaves a partial segment.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut doc_ids: Vec<&String> = self.documents.keys().collect();
+        doc_ids.sort();
+        let doc_ordinals: HashMap<&str, u32> = doc_ids
+            .iter()
+            .enumerate()
+            .map(|(ordinal, id)| (id.as_str(), ordinal as u32))
+            .collect();
+
+        let mut terms: Vec<&String> = self.postings.keys().collect();
+        terms.sort();
+        let term_ordinals: HashMap<&str, u32> = terms
+            .iter()
+            .enumerate()
+            .map(|(ordinal, term)| (term.as_str(), ordinal as u32))
+            .collect();
+
+        let docs_offset = HEADER_SIZE;
+        let terms_offset = docs_offset + doc_ids.len() * DOC_ENTRY_SIZE;
+        let data_offset = terms_offset + terms.len() * TERM_ENTRY_SIZE;
+
+        let mut data: Vec<u8> = Vec::new();
+        let mut doc_table: Vec<u8> = Vec::with_capacity(doc_ids.len() * DOC_ENTRY_SIZE);
+        let mut term_table: Vec<u8> = Vec::with_capacity(terms.len() * TERM_ENTRY_SIZE);
+        let mut total_title_length = 0u64;
+        let mut total_content_length = 0u64;
+
+        for id in &doc_ids {
+            let stats = &self.documents[*id];
+
+            let id_offset = (data_offset + data.len()) as u64;
+            data.extend_from_slice(id.as_bytes());
+
+            let mut document_terms: Vec<u32> = stats.terms
+                .iter()
+                .filter_map(|term| term_ordinals.get(term.as_str()).copied())
+                .collect();
+            document_terms.sort_unstable();
+
+            let document_terms_offset = (data_offset + data.len()) as u64;
+            for ordinal in &document_terms {
+                data.extend_from_slice(&ordinal.to_le_bytes());
+            }
+
+            doc_table.extend_from_slice(&id_offset.to_le_bytes());
+            doc_table.extend_from_slice(&(id.len() as u32).to_le_bytes());
+            doc_table.extend_from_slice(&(stats.title_length as u32).to_le_bytes());
+            doc_table.extend_from_slice(&(stats.content_length as u32).to_le_bytes());
+            doc_table.extend_from_slice(&document_terms_offset.to_le_bytes());
+            doc_table.extend_from_slice(&(document_terms.len() as u32).to_le_bytes());
+
+            total_title_length += stats.title_length as u64;
+            total_content_length += stats.content_length as u64;
+        }
+
+        for term in &terms {
+            let text_offset = (data_offset + data.len()) as u64;
+            data.extend_from_slice(term.as_bytes());
+
+            let mut entries: Vec<(u32, &TermPosting)> = self.postings[*term]
+                .iter()
+                .filter_map(|(id, posting)| doc_ordinals.get(id.as_str()).map(|&ordinal| (ordinal, posting)))
+                .collect();
+            entries.sort_by_key(|(ordinal, _)| *ordinal);
+
+            let mut positions_offsets = Vec::with_capacity(entries.len());
+            for (_, posting) in &entries {
+                positions_offsets.push((data_offset + data.len()) as u64);
+                for position in posting.title_positions.iter().chain(posting.content_positions.iter()) {
+                    data.extend_from_slice(&position.to_le_bytes());
+                }
+            }
+
+            let postings_offset = (data_offset + data.len()) as u64;
+            for ((ordinal, posting), positions_offset) in entries.iter().zip(positions_offsets) {
+                data.extend_from_slice(&ordinal.to_le_bytes());
+                data.extend_from_slice(&posting.title_count().to_le_bytes());
+                data.extend_from_slice(&posting.content_count().to_le_bytes());
+                data.extend_from_slice(&positions_offset.to_le_bytes());
+            }
+
+            term_table.extend_from_slice(&text_offset.to_le_bytes());
+            term_table.extend_from_slice(&(term.len() as u32).to_le_bytes());
+            term_table.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+            term_table.extend_from_slice(&postings_offset.to_le_bytes());
+        }
+
+        let mut header: Vec<u8> = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&(doc_ids.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(terms.len() as u32).to_le_bytes());
+        header.extend_from_slice(&total_title_length.to_le_bytes());
+        header.extend_from_slice(&total_content_length.to_le_bytes());
+        header.extend_from_slice(&(docs_offset as u64).to_le_bytes());
+        header.extend_from_slice(&(terms_offset as u64).to_le_bytes());
+        header.extend_from_slice(&(data_offset as u64).to_le_bytes());
+        header.resize(HEADER_SIZE, 0);
+
+        let temp_path = path.with_extension("tmp");
+        {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(&header)?;
+            file.write_all(&doc_table)?;
+            file.write_all(&term_table)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+        }
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+}
+
+/// Immutable, memory-mapped segment. Deleted documents are tombstoned by
+/// ordinal and physically dropped when the segment is merged.
+#[derive(Debug)]
+pub struct Segment {
+    id: String,
+    path: PathBuf,
+    mmap: Mmap,
+    doc_count: u32,
+    term_count: u32,
+    docs_offset: usize,
+    terms_offset: usize,
+    deleted: HashSet<u32>,
+}
+
+impl Segment {
+    pub fn open(id: &str, path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: segment files are written once under a temporary name and
+        // never modified after the rename, so the mapping cannot change
+        // underneath us.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_SIZE || &mmap[..MAGIC.len()] != MAGIC {
+            return Err(anyhow!("Invalid index segment: {}", path.display()));
+        }
+
+        let mut segment = Self {
+            id: id.to_string(),
+            path: path.to_path_buf(),
+            mmap,
+            doc_count: 0,
+            term_count: 0,
+            docs_offset: 0,
+            terms_offset: 0,
+            deleted: HashSet::new(),
+        };
+
+        segment.doc_count = segment.read_u32(8);
+        segment.term_count = segment.read_u32(12);
+        segment.docs_offset = segment.read_u64(32) as usize;
+        segment.terms_offset = segment.read_u64(40) as usize;
+
+        let docs_end = segment.docs_offset + segment.doc_count as usize * DOC_ENTRY_SIZE;
+        let terms_end = segment.terms_offset + segment.term_count as usize * TERM_ENTRY_SIZE;
+        if docs_end > segment.mmap.len() || terms_end > segment.mmap.len() {
+            return Err(anyhow!("Truncated index segment: {}", path.display()));
+        }
+
+        Ok(segment)
+    }
+
+    // Out-of-range reads return zero/empty rather than panicking; open()
+    // has already checked the fixed tables, so this only guards against
+    // corrupt offsets in the data region.
+    fn read_u32(&self, offset: usize) -> u32 {
+        self.mmap
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .unwrap_or(0)
+    }
+
+    fn read_u64(&self, offset: usize) -> u64 {
+        self.mmap
+            .get(offset..offset + 8)
+            .map(|bytes| {
+                let mut buffer = [0u8; 8];
+                buffer.copy_from_slice(bytes);
+                u64::from_le_bytes(buffer)
+            })
+            .unwrap_or(0)
+    }
+
+    fn read_str(&self, offset: u64, len: u32) -> &str {
+        let start = offset as usize;
+        self.mmap
+            .get(start..start + len as usize)
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+            .unwrap_or("")
+    }
+
+    fn read_positions(&self, offset: u64, count: u32) -> Vec<u32> {
+        (0..count as usize)
+            .map(|i| self.read_u32(offset as usize + i * 4))
+            .collect()
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size_on_disk(&self) -> usize {
+        self.mmap.len()
+    }
+
+    pub fn doc_count(&self) -> u32 {
+        self.doc_count
+    }
+
+    pub fn term_count(&self) -> u32 {
+        self.term_count
+    }
+
+    /// Total (title, content) token counts over every document written to
+    /// the segment, including tombstoned ones
+    pub fn total_lengths(&self) -> (usize, usize) {
+        (self.read_u64(16) as usize, self.read_u64(24) as usize)
+    }
+
+    pub fn deleted_documents(&self) -> impl Iterator<Item = u32> + '_ {
+        self.deleted.iter().copied()
+    }
+
+    pub fn deleted_count(&self) -> usize {
+        self.deleted.len()
+    }
+
+    pub fn live_count(&self) -> usize {
+        self.doc_count as usize - self.deleted.len()
+    }
+
+    pub fn is_deleted(&self, ordinal: u32) -> bool {
+        self.deleted.contains(&ordinal)
+    }
+
+    /// Tombstone a document; returns false if it was already deleted
+    pub fn delete(&mut self, ordinal: u32) -> bool {
+        ordinal < self.doc_count && self.deleted.insert(ordinal)
+    }
+
+    /// Lift a tombstone that was never committed; returns false if the
+    /// document was live
+    pub fn undelete(&mut self, ordinal: u32) -> bool {
+        self.deleted.remove(&ordinal)
+    }
+
+    pub fn document_id(&self, ordinal: u32) -> &str {
+        let entry = self.docs_offset + ordinal as usize * DOC_ENTRY_SIZE;
+        self.read_str(self.read_u64(entry), self.read_u32(entry + 8))
+    }
+
+    /// (title length, content length) in tokens
+    pub fn document_lengths(&self, ordinal: u32) -> (usize, usize) {
+        let entry = self.docs_offset + ordinal as usize * DOC_ENTRY_SIZE;
+        (self.read_u32(entry + 12) as usize, self.read_u32(entry + 16) as usize)
+    }
+
+    pub fn document_terms(&self, ordinal: u32) -> Vec<&str> {
+        let entry = self.docs_offset + ordinal as usize * DOC_ENTRY_SIZE;
+        let offset = self.read_u64(entry + 20) as usize;
+        let count = self.read_u32(entry + 28) as usize;
+
+        (0..count)
+            .map(|i| self.term(self.read_u32(offset + i * 4)))
+            .collect()
+    }
+
+    pub fn find_document(&self, document_id: &str) -> Option<u32> {
+        Self::binary_search(self.doc_count, |ordinal| self.document_id(ordinal).cmp(document_id))
+    }
+
+    pub fn live_document(&self, document_id: &str) -> Option<u32> {
+        self.find_document(document_id).filter(|ordinal| !self.is_deleted(*ordinal))
+    }
+
+    pub fn live_documents(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.doc_count).filter(move |ordinal| !self.is_deleted(*ordinal))
+    }
+
+    pub fn term(&self, ordinal: u32) -> &str {
+        let entry = self.terms_offset + ordinal as usize * TERM_ENTRY_SIZE;
+        self.read_str(self.read_u64(entry), self.read_u32(entry + 8))
+    }
+
+    /// Number of documents in the segment containing the term, including
+    /// tombstoned ones
+    pub fn term_doc_freq(&self, ordinal: u32) -> u32 {
+        let entry = self.terms_offset + ordinal as usize * TERM_ENTRY_SIZE;
+        self.read_u32(entry + 12)
+    }
+
+    pub fn find_term(&self, term: &str) -> Option<u32> {
+        Self::binary_search(self.term_count, |ordinal| self.term(ordinal).cmp(term))
+    }
+
+    pub fn terms(&self) -> impl Iterator<Item = &str> + '_ {
+        (0..self.term_count).map(move |ordinal| self.term(ordinal))
+    }
+
+    fn posting_entry(&self, term_ordinal: u32, index: u32) -> (u32, TermPosting) {
+        let term_entry = self.terms_offset + term_ordinal as usize * TERM_ENTRY_SIZE;
+        let postings_offset = self.read_u64(term_entry + 16) as usize;
+        let entry = postings_offset + index as usize * POSTING_ENTRY_SIZE;
+
+        let doc_ordinal = self.read_u32(entry);
+        let title_count = self.read_u32(entry + 4);
+        let content_count = self.read_u32(entry + 8);
+        let positions_offset = self.read_u64(entry + 12);
+
+        let mut positions = self.read_positions(positions_offset, title_count + content_count);
+        let content_positions = positions.split_off(title_count as usize);
+
+        (doc_ordinal, TermPosting {
+            title_positions: positions,
+            content_positions,
+        })
+    }
+
+    fn posting_doc_ordinal(&self, term_ordinal: u32, index: u32) -> u32 {
+        let term_entry = self.terms_offset + term_ordinal as usize * TERM_ENTRY_SIZE;
+        let postings_offset = self.read_u64(term_entry + 16) as usize;
+        self.read_u32(postings_offset + index as usize * POSTING_ENTRY_SIZE)
+    }
+
+    pub fn posting(&self, term_ordinal: u32, doc_ordinal: u32) -> Option<TermPosting> {
+        let index = Self::binary_search(self.term_doc_freq(term_ordinal), |index| {
+            self.posting_doc_ordinal(term_ordinal, index).cmp(&doc_ordinal)
+        })?;
+        Some(self.posting_entry(term_ordinal, index).1)
+    }
+
+    /// Live postings of a term as (document ordinal, positions)
+    pub fn postings(&self, term_ordinal: u32) -> impl Iterator<Item = (u32, TermPosting)> + '_ {
+        (0..self.term_doc_freq(term_ordinal))
+            .map(move |index| self.posting_entry(term_ordinal, index))
+            .filter(move |(doc_ordinal, _)| !self.is_deleted(*doc_ordinal))
+    }
+
+    /// Copy the live documents of this segment into a memory segment, used
+    /// when merging segments
+    pub fn copy_live_documents(&self, target: &mut MemorySegment) {
+        for doc_ordinal in self.live_documents() {
+            let (title_length, content_length) = self.document_lengths(doc_ordinal);
+            let terms: Vec<String> = self.document_terms(doc_ordinal).into_iter().map(String::from).collect();
+
+            let postings: Vec<(String, TermPosting)> = terms
+                .iter()
+                .filter_map(|term| {
+                    let term_ordinal = self.find_term(term)?;
+                    Some((term.clone(), self.posting(term_ordinal, doc_ordinal)?))
+                })
+                .collect();
+
+            target.add_postings(self.document_id(doc_ordinal), DocumentStats {
+                title_length,
+                content_length,
+                terms,
+            }, postings);
+        }
+    }
+
+    fn binary_search<F>(len: u32, mut compare: F) -> Option<u32>
+    where
+        F: FnMut(u32) -> std::cmp::Ordering,
+    {
+        let (mut low, mut high) = (0u32, len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match compare(mid) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn create_test_document(id: &str, title: &str, content: &str) -> IndexedDocument {
+        IndexedDocument {
+            id: id.to_string(),
+            title: title.to_string(),
+            content: content.to_string(),
+            title_tokens: title.split_whitespace().map(|w| w.to_lowercase()).collect(),
+            tokens: content.split_whitespace().map(|w| w.to_lowercase()).collect(),
+            entities: Vec::new(),
+            metadata: serde_json::json!({}),
+            embedding: None,
+        }
+    }
+
+    #[test]
+    fn test_segment_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("seg-1.seg");
+
+        let mut memory = MemorySegment::new();
+        memory.add_document(&create_test_document("b", "Budget", "annual budget review"));
+        memory.add_document(&create_test_document("a", "Notes", "review the notes"));
+        memory.write(&path).unwrap();
+
+        let segment = Segment::open("seg-1", &path).unwrap();
+        assert_eq!(segment.doc_count(), 2);
+        assert_eq!(segment.document_id(0), "a");
+        assert_eq!(segment.document_lengths(1), (1, 3));
+
+        let doc = segment.find_document("b").unwrap();
+        let term = segment.find_term("budget").unwrap();
+        assert_eq!(segment.term_doc_freq(term), 1);
+
+        let posting = segment.posting(term, doc).unwrap();
+        assert_eq!(posting.title_positions, vec![0]);
+        assert_eq!(posting.content_positions, vec![1]);
+
+        let mut terms = segment.document_terms(doc);
+        terms.sort();
+        assert_eq!(terms, vec!["annual", "budget", "review"]);
+        assert!(segment.find_term("missing").is_none());
+    }
+
+    #[test]
+    fn test_tombstones_hide_postings() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("seg-1.seg");
+
+        let mut memory = MemorySegment::new();
+        memory.add_document(&create_test_document("1", "One", "shared words"));
+        memory.add_document(&create_test_document("2", "Two", "shared terms"));
+        memory.write(&path).unwrap();
+
+        let mut segment = Segment::open("seg-1", &path).unwrap();
+        let ordinal = segment.find_document("1").unwrap();
+        assert!(segment.delete(ordinal));
+        assert!(!segment.delete(ordinal));
+        assert!(segment.live_document("1").is_none());
+
+        let term = segment.find_term("shared").unwrap();
+        let live: Vec<u32> = segment.postings(term).map(|(doc, _)| doc).collect();
+        assert_eq!(live, vec![segment.find_document("2").unwrap()]);
+
+        let mut merged = MemorySegment::new();
+        segment.copy_live_documents(&mut merged);
+        assert_eq!(merged.len(), 1);
+        assert!(merged.posting("shared", "2").is_some());
+    }
+
+    #[test]
+    fn test_rejects_invalid_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("bad.seg");
+        fs::write(&path, b"not a segment").unwrap();
+
+        assert!(Segment::open("bad", &path).is_err());
+    }
+}
@@ -2,20 +2,30 @@ use anyhow::Result;
 use ndarray::Array1;
 use strsim;
 
+use crate::analyzer::Language;
+
 #[derive(Debug, Clone)]
 pub struct SimilarityEngine {
     // Configuration for similarity calculations
     text_similarity_threshold: f64,
     vector_similarity_threshold: f64,
+    near_duplicate_threshold: f64,
 }
 
-impl SimilarityEngine {
-    pub fn new() -> Self {
+impl Default for SimilarityEngine {
+    fn default() -> Self {
         Self {
             text_similarity_threshold: 0.3,
             vector_similarity_threshold: 0.7,
+            near_duplicate_threshold: 0.9,
         }
     }
+}
+
+impl SimilarityEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
     /// Calculate cosine similarity between two vectors
     pub fn cosine_similarity(&self, a: &[f32], b: &[f32]) -> Result<f64> {
@@ -97,6 +107,45 @@ impl SimilarityEngine {
         dot_product / (norm_a.sqrt() * norm_b.sqrt())
     }
 
+    /// TF-IDF weights of a token list, with inverse document frequencies
+    /// supplied by the caller (usually the full-text index)
+    pub fn tfidf_vector<F>(&self, tokens: &[String], idf: F) -> std::collections::HashMap<String, f64>
+    where
+        F: Fn(&str) -> f64,
+    {
+        let mut vector = TextPreprocessor::term_frequency(tokens);
+        for (term, weight) in vector.iter_mut() {
+            *weight *= idf(term);
+        }
+        vector.retain(|_, weight| *weight > 0.0);
+        vector
+    }
+
+    /// Document embedding as the mean of its chunk embeddings. Chunks
+    /// with a different dimension from the first are ignored.
+    pub fn mean_embedding(&self, chunks: &[Vec<f32>]) -> Option<Vec<f32>> {
+        let dimension = chunks.first()?.len();
+        let matching: Vec<&Vec<f32>> = chunks.iter().filter(|chunk| chunk.len() == dimension).collect();
+
+        let mut mean = vec![0.0f32; dimension];
+        for chunk in &matching {
+            for (sum, value) in mean.iter_mut().zip(chunk.iter()) {
+                *sum += value;
+            }
+        }
+        for value in mean.iter_mut() {
+            *value /= matching.len() as f32;
+        }
+
+        Some(mean)
+    }
+
+    /// Whether a similarity score is high enough to treat two documents
+    /// as copies of each other rather than related
+    pub fn is_near_duplicate(&self, similarity: f64) -> bool {
+        similarity >= self.near_duplicate_threshold
+    }
+
     /// Calculate semantic similarity using string distance metrics
     pub fn semantic_text_similarity(&self, text_a: &str, text_b: &str) -> f64 {
         // Combine multiple string similarity metrics
@@ -105,7 +154,7 @@ impl SimilarityEngine {
         let normalized_levenshtein = strsim::normalized_levenshtein(text_a, text_b);
 
         // Weighted average of different metrics
-        (jaro_winkler * 0.4 + sorensen_dice * 0.4 + normalized_levenshtein * 0.2)
+        jaro_winkler * 0.4 + sorensen_dice * 0.4 + normalized_levenshtein * 0.2
     }
 
     /// Find similar documents based on content similarity
@@ -131,6 +180,7 @@ impl SimilarityEngine {
     }
 
     /// Calculate BM25 score for document ranking
+    #[allow(clippy::too_many_arguments)]
     pub fn bm25_score(
         &self,
         query_terms: &[String],
@@ -204,11 +254,11 @@ impl SimilarityEngine {
         let mut matrix = vec![vec![0; len2 + 1]; len1 + 1];
 
         // Initialize first row and column
-        for i in 0..=len1 {
-            matrix[i][0] = i;
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[0] = i;
         }
-        for j in 0..=len2 {
-            matrix[0][j] = j;
+        for (j, cell) in matrix[0].iter_mut().enumerate() {
+            *cell = j;
         }
 
         let chars1: Vec<char> = s1.chars().collect();
@@ -243,6 +293,10 @@ impl SimilarityEngine {
         }
     }
 
+    pub fn set_near_duplicate_threshold(&mut self, threshold: f64) {
+        self.near_duplicate_threshold = threshold.clamp(0.0, 1.0);
+    }
+
     pub fn set_thresholds(&mut self, text_threshold: f64, vector_threshold: f64) {
         self.text_similarity_threshold = text_threshold.clamp(0.0, 1.0);
         self.vector_similarity_threshold = vector_threshold.clamp(0.0, 1.0);
@@ -253,19 +307,17 @@ impl SimilarityEngine {
 pub struct TextPreprocessor;
 
 impl TextPreprocessor {
-    /// Remove common stop words
+    /// Remove common English stop words
     pub fn remove_stop_words(tokens: &[String]) -> Vec<String> {
-        let stop_words = vec![
-            "the", "a", "an", "and", "or", "but", "in", "on", "at", "to", "for", "of", "with",
-            "by", "is", "are", "was", "were", "be", "been", "being", "have", "has", "had",
-            "do", "does", "did", "will", "would", "could", "should", "may", "might", "must",
-            "this", "that", "these", "those", "i", "you", "he", "she", "it", "we", "they",
-        ];
-        
-        let stop_words_set: std::collections::HashSet<_> = stop_words.iter().collect();
+        Self::remove_stop_words_for(tokens, Language::English)
+    }
+
+    /// Remove the stop words of the given language
+    pub fn remove_stop_words_for(tokens: &[String], language: Language) -> Vec<String> {
+        let stop_words_set: std::collections::HashSet<_> = language.stop_words().iter().copied().collect();
         
         tokens.iter()
-            .filter(|token| !stop_words_set.contains(&token.as_str()))
+            .filter(|token| !stop_words_set.contains(token.as_str()))
             .cloned()
             .collect()
     }
@@ -370,7 +422,7 @@ mod tests {
         
         // Check that scores are in 0-1 range
         for &score in &scores {
-            assert!(score >= 0.0 && score <= 1.0);
+            assert!((0.0..=1.0).contains(&score));
         }
         
         // Check that the order is preserved (relatively)
@@ -439,4 +491,18 @@ mod tests {
         
         assert!(score > 0.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tfidf_vectors_and_mean_embedding() {
+        let engine = SimilarityEngine::new();
+        let tokens: Vec<String> = ["budget", "budget", "the"].iter().map(|t| t.to_string()).collect();
+
+        let vector = engine.tfidf_vector(&tokens, |term| if term == "the" { 0.0 } else { 2.0 });
+        assert_eq!(vector.len(), 1);
+        assert!((vector["budget"] - 4.0 / 3.0).abs() < 1e-9);
+
+        let mean = engine.mean_embedding(&[vec![1.0, 0.0], vec![0.0, 1.0], vec![5.0]]).unwrap();
+        assert_eq!(mean, vec![0.5, 0.5]);
+        assert!(engine.mean_embedding(&[]).is_none());
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashSet;
+use serde::{Serialize, Deserialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::TextHighlight;
+use crate::analyzer::AnalyzedToken;
+
+/// A passage of a document around query matches. `start` and `end` are
+/// byte offsets into the content, always on character boundaries, and the
+/// highlights are relative to `text`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnippetFragment {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+    pub score: f64, // share of distinct query terms the fragment contains
+    pub highlights: Vec<TextHighlight>,
+}
+
+/// A run of matches that fits in one fragment
+#[derive(Debug, Clone, Copy)]
+struct Passage {
+    first: usize,
+    last: usize,
+    distinct_terms: usize,
+    matches: usize,
+}
+
+/// Picks the passages of a document that best cover the query and marks
+/// the matched words. Works on analyzed tokens, so inflected forms and
+/// folded accents are highlighted, while terms inside longer words ("cat"
+/// in "category") are not.
+#[derive(Debug, Clone)]
+pub struct SnippetGenerator {
+    fragment_length: usize, // in characters
+}
+
+impl Default for SnippetGenerator {
+    fn default() -> Self {
+        Self {
+            fragment_length: 200,
+        }
+    }
+}
+
+impl SnippetGenerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_fragment_length(&mut self, fragment_length: usize) {
+        self.fragment_length = fragment_length.max(1);
+    }
+
+    /// Whole matched words of `content`, with overlapping matches (CJK
+    /// bigrams) merged
+    pub fn highlights(&self, content: &str, tokens: &[AnalyzedToken], query_terms: &HashSet<String>) -> Vec<TextHighlight> {
+        merge_highlights(content, tokens.iter().filter(|token| query_terms.contains(&token.term)))
+    }
+
+    /// Up to `max_fragments` non-overlapping fragments, best first. A
+    /// fragment's score is the share of distinct query terms it contains;
+    /// ties go to the one with more matches, then the earlier one. Without
+    /// any match the opening of the document is returned.
+    pub fn fragments(
+        &self,
+        content: &str,
+        tokens: &[AnalyzedToken],
+        query_terms: &HashSet<String>,
+        max_fragments: usize,
+    ) -> Vec<SnippetFragment> {
+        let matches: Vec<&AnalyzedToken> = tokens.iter().filter(|token| query_terms.contains(&token.term)).collect();
+        if matches.is_empty() {
+            return self.leading_fragment(content).into_iter().collect();
+        }
+
+        // Character offset of every match, to measure passages in characters
+        let mut char_offsets = Vec::with_capacity(matches.len());
+        let (mut byte, mut chars) = (0, 0);
+        for token in &matches {
+            chars += content[byte..token.start].chars().count();
+            byte = token.start;
+            char_offsets.push((chars, chars + content[token.start..token.end].chars().count()));
+        }
+
+        let mut passages: Vec<Passage> = (0..matches.len())
+            .map(|first| {
+                let mut last = first;
+                while last + 1 < matches.len()
+                    && char_offsets[last + 1].1 - char_offsets[first].0 <= self.fragment_length
+                {
+                    last += 1;
+                }
+                let distinct: HashSet<&str> = matches[first..=last].iter().map(|token| token.term.as_str()).collect();
+
+                Passage {
+                    first,
+                    last,
+                    distinct_terms: distinct.len(),
+                    matches: last - first + 1,
+                }
+            })
+            .collect();
+        passages.sort_by(|a, b| {
+            b.distinct_terms
+                .cmp(&a.distinct_terms)
+                .then_with(|| b.matches.cmp(&a.matches))
+                .then_with(|| a.first.cmp(&b.first))
+        });
+
+        let boundaries = word_boundaries(content);
+        let mut fragments: Vec<SnippetFragment> = Vec::new();
+
+        for passage in passages {
+            if fragments.len() >= max_fragments.max(1) {
+                break;
+            }
+
+            let span_start = matches[passage.first].start;
+            let span_end = matches[passage.last].end;
+            let span_chars = char_offsets[passage.last].1 - char_offsets[passage.first].0;
+
+            // Spread the remaining length around the matches, then shrink
+            // to whole words
+            let context = self.fragment_length.saturating_sub(span_chars);
+            let mut start = step_back(content, span_start, context / 2);
+            let right = context - content[start..span_start].chars().count();
+            let mut end = step_forward(content, span_end, right);
+            start = snap_forward(&boundaries, start).min(span_start);
+            end = snap_back(&boundaries, end).max(span_end);
+            start += content[start..span_start].len() - content[start..span_start].trim_start().len();
+            end -= content[span_end..end].len() - content[span_end..end].trim_end().len();
+
+            let overlaps = fragments.iter().any(|fragment| start < fragment.end && end > fragment.start);
+            if overlaps {
+                continue;
+            }
+
+            let highlights = merge_highlights(content, matches[passage.first..=passage.last].iter().copied())
+                .into_iter()
+                .map(|highlight| TextHighlight {
+                    start: highlight.start - start,
+                    end: highlight.end - start,
+                    text: highlight.text,
+                })
+                .collect();
+
+            fragments.push(SnippetFragment {
+                text: content[start..end].to_string(),
+                start,
+                end,
+                score: passage.distinct_terms as f64 / query_terms.len().max(1) as f64,
+                highlights,
+            });
+        }
+
+        fragments
+    }
+
+    /// The best fragment as plain text, with ellipses where the content
+    /// was cut
+    pub fn snippet(&self, content: &str, fragment: &SnippetFragment) -> String {
+        let mut snippet = fragment.text.clone();
+
+        if !content[..fragment.start].trim().is_empty() {
+            snippet = format!("...{}", snippet);
+        }
+        if !content[fragment.end..].trim().is_empty() {
+            snippet = format!("{}...", snippet);
+        }
+
+        snippet
+    }
+
+    fn leading_fragment(&self, content: &str) -> Option<SnippetFragment> {
+        if content.trim().is_empty() {
+            return None;
+        }
+
+        let mut end = step_forward(content, 0, self.fragment_length);
+        if end < content.len() {
+            let boundary = snap_back(&word_boundaries(content), end);
+            // A single word longer than the fragment is cut mid-word
+            if boundary > 0 {
+                end = boundary;
+            }
+        }
+        end = content[..end].trim_end().len();
+
+        Some(SnippetFragment {
+            text: content[..end].to_string(),
+            start: 0,
+            end,
+            score: 0.0,
+            highlights: Vec::new(),
+        })
+    }
+}
+
+fn merge_highlights<'a>(content: &str, matches: impl Iterator<Item = &'a AnalyzedToken>) -> Vec<TextHighlight> {
+    let mut highlights: Vec<TextHighlight> = Vec::new();
+
+    for token in matches {
+        match highlights.last_mut() {
+            Some(last) if token.start < last.end => {
+                last.end = last.end.max(token.end);
+                last.text = content[last.start..last.end].to_string();
+            }
+            _ => highlights.push(TextHighlight {
+                start: token.start,
+                end: token.end,
+                text: content[token.start..token.end].to_string(),
+            }),
+        }
+    }
+
+    highlights
+}
+
+/// Byte offsets where words and the gaps between them start, plus the end
+fn word_boundaries(content: &str) -> Vec<usize> {
+    let mut boundaries: Vec<usize> = content.split_word_bound_indices().map(|(offset, _)| offset).collect();
+    boundaries.push(content.len());
+    boundaries
+}
+
+fn snap_forward(boundaries: &[usize], offset: usize) -> usize {
+    let index = boundaries.partition_point(|&boundary| boundary < offset);
+    boundaries.get(index).copied().unwrap_or(offset)
+}
+
+fn snap_back(boundaries: &[usize], offset: usize) -> usize {
+    let index = boundaries.partition_point(|&boundary| boundary <= offset);
+    index.checked_sub(1).map(|i| boundaries[i]).unwrap_or(0)
+}
+
+/// Offset `chars` characters before `offset`, never splitting a grapheme
+fn step_back(content: &str, offset: usize, chars: usize) -> usize {
+    let mut start = offset;
+    let mut taken = 0;
+
+    for (index, grapheme) in content[..offset].grapheme_indices(true).rev() {
+        taken += grapheme.chars().count();
+        if taken > chars {
+            break;
+        }
+        start = index;
+    }
+
+    start
+}
+
+/// Offset `chars` characters after `offset`, never splitting a grapheme
+fn step_forward(content: &str, offset: usize, chars: usize) -> usize {
+    let mut end = offset;
+    let mut taken = 0;
+
+    for grapheme in content[offset..].graphemes(true) {
+        taken += grapheme.chars().count();
+        if taken > chars {
+            break;
+        }
+        end += grapheme.len();
+    }
+
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::{Analyzer, Language};
+
+    fn terms(analyzer: &Analyzer, query: &str) -> HashSet<String> {
+        analyzer.analyze(query).into_iter().collect()
+    }
+
+    #[test]
+    fn test_multibyte_content_is_cut_on_char_boundaries() {
+        let analyzer = Analyzer::new(Language::English);
+        let mut generator = SnippetGenerator::new();
+        generator.set_fragment_length(12);
+
+        let content = "ééééé ééééé ééééé café ñandú ééééé ééééé ééééé";
+        let tokens = analyzer.analyze_with_offsets(content);
+        let fragments = generator.fragments(content, &tokens, &terms(&analyzer, "cafe"), 1);
+
+        assert_eq!(fragments.len(), 1);
+        assert!(fragments[0].text.contains("café"));
+        assert_eq!(&content[fragments[0].start..fragments[0].end], fragments[0].text);
+        let snippet = generator.snippet(content, &fragments[0]);
+        assert!(snippet.starts_with("...") && snippet.ends_with("..."));
+
+        // No match: the opening of the document, cut at a word
+        let fragments = generator.fragments(content, &tokens, &terms(&analyzer, "zebra"), 1);
+        assert_eq!(fragments[0].text, "ééééé ééééé");
+    }
+
+    #[test]
+    fn test_highlights_whole_stemmed_words() {
+        let analyzer = Analyzer::new(Language::English);
+        let generator = SnippetGenerator::new();
+
+        let content = "The cat sat on the category list while cats watched";
+        let tokens = analyzer.analyze_with_offsets(content);
+        let highlights = generator.highlights(content, &tokens, &terms(&analyzer, "cat"));
+
+        let texts: Vec<&str> = highlights.iter().map(|highlight| highlight.text.as_str()).collect();
+        assert_eq!(texts, vec!["cat", "cats"]);
+        assert_eq!(&content[highlights[1].start..highlights[1].end], "cats");
+    }
+
+    #[test]
+    fn test_best_passage_and_multiple_fragments() {
+        let analyzer = Analyzer::new(Language::English);
+        let mut generator = SnippetGenerator::new();
+        generator.set_fragment_length(40);
+
+        let content = "Budget notes from the first meeting. Nothing else was decided on that day at all, \
+            and the rest of the afternoon went to planning. The budget review for the quarter closed \
+            on time. Later the budget was published.";
+        let tokens = analyzer.analyze_with_offsets(content);
+        let query_terms = terms(&analyzer, "budget review");
+
+        let fragments = generator.fragments(content, &tokens, &query_terms, 3);
+        assert!(fragments[0].text.contains("budget review"));
+        assert_eq!(fragments[0].score, 1.0);
+        assert_eq!(fragments.len(), 3);
+        assert!(fragments.iter().all(|fragment| fragment.text.chars().count() <= 40));
+
+        let highlighted: Vec<&str> = fragments[0].highlights.iter().map(|highlight| {
+            &fragments[0].text[highlight.start..highlight.end]
+        }).collect();
+        assert_eq!(highlighted, vec!["budget", "review"]);
+    }
+}
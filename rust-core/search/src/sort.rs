@@ -0,0 +1,167 @@
+use std::cmp::Ordering;
+use serde::{Serialize, Deserialize};
+
+/// What results can be ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Relevance,
+    ModifiedAt,
+    IngestedAt,
+    Title,     // case-insensitive
+    FileSize,  // metadata file_size
+    WordCount, // metadata word_count
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Ascending,
+    #[default]
+    Descending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortKey {
+    pub field: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+impl SortKey {
+    pub fn new(field: SortField, order: SortOrder) -> Self {
+        Self { field, order }
+    }
+
+    /// Expression for the field over `documents d`, None for relevance,
+    /// which only the ranking knows
+    pub fn column(&self) -> Option<&'static str> {
+        match self.field {
+            SortField::Relevance => None,
+            SortField::ModifiedAt => Some("d.modified_at"),
+            SortField::IngestedAt => Some("d.ingested_at"),
+            SortField::Title => Some("d.title"),
+            SortField::FileSize => Some("json_extract(d.metadata, '$.file_size')"),
+            SortField::WordCount => Some("json_extract(d.metadata, '$.word_count')"),
+        }
+    }
+}
+
+/// A result's value for one sort key. Missing values sort last in either
+/// order, as they do in SQL through `IS NULL`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortValue {
+    Number(f64),
+    Text(String),
+    Missing,
+}
+
+impl SortValue {
+    pub fn from_sql(value: rusqlite::types::ValueRef<'_>) -> Self {
+        use rusqlite::types::ValueRef;
+        match value {
+            ValueRef::Integer(n) => SortValue::Number(n as f64),
+            ValueRef::Real(n) => SortValue::Number(n),
+            ValueRef::Text(text) => SortValue::Text(String::from_utf8_lossy(text).into_owned()),
+            ValueRef::Null | ValueRef::Blob(_) => SortValue::Missing,
+        }
+    }
+
+    fn compare(&self, other: &SortValue, order: SortOrder) -> Ordering {
+        let ordering = match (self, other) {
+            (SortValue::Missing, SortValue::Missing) => return Ordering::Equal,
+            (SortValue::Missing, _) => return Ordering::Greater,
+            (_, SortValue::Missing) => return Ordering::Less,
+            (SortValue::Number(a), SortValue::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            // NOCASE folds ASCII only, and so does this
+            (SortValue::Text(a), SortValue::Text(b)) => a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()),
+            (SortValue::Number(_), SortValue::Text(_)) => Ordering::Less,
+            (SortValue::Text(_), SortValue::Number(_)) => Ordering::Greater,
+        };
+        match order {
+            SortOrder::Ascending => ordering,
+            SortOrder::Descending => ordering.reverse(),
+        }
+    }
+}
+
+/// Whether the keys order results other than by score, best first
+pub fn is_custom(keys: &[SortKey]) -> bool {
+    keys.iter().any(|key| *key != SortKey::new(SortField::Relevance, SortOrder::Descending))
+}
+
+/// ORDER BY terms for the keys, then the id so equal values keep one order
+/// on every page. `rank` stands in for relevance where FTS ranks the rows
+/// and relevance is skipped without it.
+pub fn order_by_sql(keys: &[SortKey], rank: Option<&str>) -> String {
+    let mut terms = Vec::new();
+    for key in keys {
+        let direction = match key.order {
+            SortOrder::Ascending => "ASC",
+            SortOrder::Descending => "DESC",
+        };
+        match key.column() {
+            Some(column) => {
+                let collation = if key.field == SortField::Title { " COLLATE NOCASE" } else { "" };
+                terms.push(format!("({} IS NULL), {}{} {}", column, column, collation, direction));
+            }
+            // FTS5 rank is lower for better matches
+            None => {
+                if let Some(rank) = rank {
+                    let direction = if key.order == SortOrder::Descending { "ASC" } else { "DESC" };
+                    terms.push(format!("{} {}", rank, direction));
+                }
+            }
+        }
+    }
+    terms.push("d.id".to_string());
+    terms.join(", ")
+}
+
+/// Compare two results' values for the keys, then their ids
+pub fn compare(keys: &[SortKey], a: (&[SortValue], &str), b: (&[SortValue], &str)) -> Ordering {
+    keys.iter()
+        .zip(a.0.iter().zip(b.0.iter()))
+        .map(|(key, (a_value, b_value))| a_value.compare(b_value, key.order))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or_else(|| a.1.cmp(b.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_orders_each_key_then_id() {
+        let keys = [
+            SortKey::new(SortField::FileSize, SortOrder::Descending),
+            SortKey::new(SortField::Title, SortOrder::Ascending),
+        ];
+        let mut rows = [
+            (vec![SortValue::Number(10.0), SortValue::Text("b".to_string())], "1"),
+            (vec![SortValue::Missing, SortValue::Text("a".to_string())], "2"),
+            (vec![SortValue::Number(10.0), SortValue::Text("A".to_string())], "4"),
+            (vec![SortValue::Number(10.0), SortValue::Text("a".to_string())], "3"),
+            (vec![SortValue::Number(20.0), SortValue::Text("z".to_string())], "5"),
+        ];
+        rows.sort_by(|a, b| compare(&keys, (&a.0, a.1), (&b.0, b.1)));
+
+        let ids: Vec<&str> = rows.iter().map(|row| row.1).collect();
+        assert_eq!(ids, vec!["5", "3", "4", "1", "2"]);
+    }
+
+    #[test]
+    fn test_order_by_sql_puts_missing_values_last() {
+        let keys = [
+            SortKey::new(SortField::WordCount, SortOrder::Ascending),
+            SortKey::new(SortField::Relevance, SortOrder::Descending),
+        ];
+        assert_eq!(
+            order_by_sql(&keys, Some("rank")),
+            "(json_extract(d.metadata, '$.word_count') IS NULL), json_extract(d.metadata, '$.word_count') ASC, rank ASC, d.id",
+        );
+        assert_eq!(order_by_sql(&keys[1..], None), "d.id");
+        assert!(!is_custom(&keys[1..]) && is_custom(&keys));
+    }
+}
@@ -0,0 +1,199 @@
+use serde::{Serialize, Deserialize};
+
+use crate::indexer::FullTextIndexer;
+
+/// A term of the index and how it is spread over documents and fields
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TermStats {
+    pub term: String,
+    pub document_frequency: usize,
+    pub idf: f64,
+    pub title_frequency: usize, // occurrences in titles, over all documents
+    pub content_frequency: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldStats {
+    pub field: String,
+    pub total_tokens: usize,
+    pub average_length: f64,
+    pub empty_documents: usize,
+}
+
+/// What the index holds and how far it is behind the documents table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexStats {
+    pub document_count: usize, // indexed and live
+    pub stored_documents: usize,
+    pub term_count: usize,
+    pub average_document_length: f64, // tokens, title and content together
+    pub fields: Vec<FieldStats>,
+    pub top_terms: Vec<TermStats>,
+    pub segment_count: usize,
+    pub buffered_documents: usize, // indexed but not yet flushed to a segment
+    pub index_bytes: usize,        // segments on disk
+    pub database_bytes: usize,
+    pub unindexed_documents: usize, // stored but missing from the index
+    pub orphaned_documents: usize,  // indexed but no longer stored
+    pub deleted_documents: usize,   // removed from segments, awaiting a merge
+}
+
+/// Terms starting with `prefix`, most common first, ties by term
+pub fn top_terms(indexer: &FullTextIndexer, prefix: &str, limit: usize) -> Vec<TermStats> {
+    let mut terms: Vec<(&String, usize)> = indexer
+        .vocabulary()
+        .range(prefix.to_string()..)
+        .take_while(|(term, _)| term.starts_with(prefix))
+        .map(|(term, frequency)| (term, *frequency))
+        .collect();
+    terms.sort_by(|(a, a_frequency), (b, b_frequency)| b_frequency.cmp(a_frequency).then_with(|| a.cmp(b)));
+    terms.truncate(limit);
+
+    terms
+        .into_iter()
+        .map(|(term, document_frequency)| {
+            let (title_frequency, content_frequency) = indexer
+                .get_term_documents(term)
+                .iter()
+                .filter_map(|document_id| indexer.posting(term, document_id))
+                .fold((0, 0), |(title, content), posting| {
+                    (title + posting.title_count() as usize, content + posting.content_count() as usize)
+                });
+            TermStats {
+                term: term.clone(),
+                document_frequency,
+                idf: indexer.idf(term),
+                title_frequency,
+                content_frequency,
+            }
+        })
+        .collect()
+}
+
+/// Token counts of the title and content fields
+pub fn field_stats(indexer: &FullTextIndexer) -> Vec<FieldStats> {
+    let lengths: Vec<(usize, usize)> = indexer
+        .document_ids()
+        .iter()
+        .filter_map(|document_id| indexer.document_lengths(document_id))
+        .collect();
+
+    let field = |name: &str, length: fn(&(usize, usize)) -> usize| {
+        let total_tokens: usize = lengths.iter().map(length).sum();
+        FieldStats {
+            field: name.to_string(),
+            total_tokens,
+            average_length: if lengths.is_empty() { 0.0 } else { total_tokens as f64 / lengths.len() as f64 },
+            empty_documents: lengths.iter().filter(|lengths| length(lengths) == 0).count(),
+        }
+    };
+    vec![field("title", |lengths| lengths.0), field("content", |lengths| lengths.1)]
+}
+
+/// One part of a result's score
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreComponent {
+    pub name: String,
+    pub value: f64, // already weighted; the components sum to the score
+    pub detail: Option<String>,
+}
+
+/// A query term's BM25F contribution to a document's score
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TermContribution {
+    pub term: String,
+    pub document_frequency: usize,
+    pub idf: f64,
+    pub title_frequency: u32,
+    pub content_frequency: u32,
+    pub score: f64,
+}
+
+/// How a document scored for a query, stage by stage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoreExplanation {
+    pub document_id: String,
+    pub matched: bool, // whether the query and its filters select the document at all
+    pub score: f64,
+    pub components: Vec<ScoreComponent>,
+    pub terms: Vec<TermContribution>,
+}
+
+impl ScoreExplanation {
+    pub fn new(document_id: &str, matched: bool) -> Self {
+        Self {
+            document_id: document_id.to_string(),
+            matched,
+            score: 0.0,
+            components: Vec::new(),
+            terms: Vec::new(),
+        }
+    }
+
+    /// Record a component, leaving out those that did not contribute
+    pub fn add(&mut self, name: &str, value: f64, detail: Option<String>) {
+        if value == 0.0 {
+            return;
+        }
+        self.score += value;
+        self.components.push(ScoreComponent {
+            name: name.to_string(),
+            value,
+            detail,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IndexedDocument;
+
+    fn document(id: &str, title: &str, content: &str) -> IndexedDocument {
+        let tokens = |text: &str| text.split_whitespace().map(str::to_string).collect();
+        IndexedDocument {
+            id: id.to_string(),
+            title: title.to_string(),
+            content: content.to_string(),
+            title_tokens: tokens(title),
+            tokens: tokens(content),
+            entities: Vec::new(),
+            metadata: serde_json::json!({}),
+            embedding: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_top_terms_and_field_stats() {
+        let mut indexer = FullTextIndexer::new().unwrap();
+        indexer.index_document(&document("1", "budget", "budget review budget")).await.unwrap();
+        indexer.index_document(&document("2", "", "budget plan")).await.unwrap();
+        indexer.index_document(&document("3", "bud", "garden")).await.unwrap();
+
+        let terms = top_terms(&indexer, "bud", 10);
+        let listed: Vec<(&str, usize, usize, usize)> = terms
+            .iter()
+            .map(|t| (t.term.as_str(), t.document_frequency, t.title_frequency, t.content_frequency))
+            .collect();
+        assert_eq!(listed, vec![("budget", 2, 1, 3), ("bud", 1, 1, 0)]);
+        assert!(terms[0].idf < terms[1].idf);
+        assert_eq!(top_terms(&indexer, "", 1)[0].term, "budget");
+
+        let fields = field_stats(&indexer);
+        assert_eq!((fields[0].total_tokens, fields[0].empty_documents), (2, 1));
+        assert_eq!(fields[1].total_tokens, 6);
+        assert_eq!(fields[1].average_length, 2.0);
+    }
+
+    #[test]
+    fn test_explanation_sums_its_components() {
+        let mut explanation = ScoreExplanation::new("1", true);
+        explanation.add("bm25", 2.5, None);
+        explanation.add("proximity", 0.0, None);
+        explanation.add("freshness", 0.25, Some("modified 3 days ago".to_string()));
+
+        assert_eq!(explanation.score, 2.75);
+        let names: Vec<&str> = explanation.components.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["bm25", "freshness"]);
+    }
+}
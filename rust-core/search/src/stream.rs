@@ -0,0 +1,71 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::{Serialize, Deserialize};
+
+/// Stages of a search, in the order their results arrive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchStage {
+    Keyword, // FTS and the in-memory index, with synonyms
+    Fuzzy,
+    Semantic,
+}
+
+/// Cancels a streamed search. Clones share the same search, so one can be
+/// handed to the search and another kept by whoever may cancel it.
+#[derive(Debug, Clone, Default)]
+pub struct SearchHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl SearchHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The search stops at the next stage boundary
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn checkpoint(&self) -> Result<(), SearchCancelled> {
+        if self.is_cancelled() {
+            Err(SearchCancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returned by a search whose handle was cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchCancelled;
+
+impl fmt::Display for SearchCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Search cancelled")
+    }
+}
+
+impl std::error::Error for SearchCancelled {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancelling_a_clone_cancels_the_search() {
+        let handle = SearchHandle::new();
+        let held = handle.clone();
+        assert!(handle.checkpoint().is_ok());
+
+        held.cancel();
+        assert!(handle.is_cancelled());
+        assert_eq!(handle.checkpoint(), Err(SearchCancelled));
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::{Serialize, Deserialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::fuzzy::FuzzyExpander;
+use crate::indexer::FullTextIndexer;
+
+// Upper bound on vocabulary entries visited for very short prefixes
+const MAX_PREFIX_SCAN: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuerySuggestion {
+    pub text: String,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompletionKind {
+    Entity,
+    Title,
+    Term,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Completion {
+    pub text: String,
+    pub kind: CompletionKind,
+    pub score: f64, // 0.0 - 1.0, comparable across kinds
+}
+
+#[derive(Debug, Clone)]
+struct Correction {
+    term: String,
+    weight: f64,
+}
+
+/// "Did you mean" corrections and search box completions drawn from the
+/// full-text index vocabulary, document titles and entity names
+#[derive(Debug, Clone)]
+pub struct QuerySuggester {
+    expander: FuzzyExpander,
+    candidates_per_word: usize,
+}
+
+impl Default for QuerySuggester {
+    fn default() -> Self {
+        let mut expander = FuzzyExpander::new();
+        expander.set_max_expansions(10);
+
+        Self {
+            expander,
+            candidates_per_word: 3,
+        }
+    }
+}
+
+impl QuerySuggester {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Corrected versions of `query`. Words whose analyzed term is missing
+    /// from the index are replaced by close vocabulary terms, weighted by
+    /// document frequency and edit distance; a suggestion is only offered
+    /// if some document contains all of its terms. Replacements read as the
+    /// word most often indexed as the term ("quarterly" for "quartrly"),
+    /// or as the term itself when no words were counted.
+    pub fn suggest<F>(
+        &self,
+        query: &str,
+        indexer: &FullTextIndexer,
+        analyze: F,
+        limit: usize,
+    ) -> Vec<QuerySuggestion>
+    where
+        F: Fn(&str) -> Vec<String>,
+    {
+        let words: Vec<String> = query.unicode_words().map(|word| word.to_lowercase()).collect();
+        let terms: Vec<Option<String>> = words.iter().map(|word| analyze(word).into_iter().next()).collect();
+
+        let corrections: Vec<Vec<Correction>> = terms
+            .iter()
+            .map(|term| match term {
+                Some(term) if indexer.document_frequency(term) == 0 => self.expander
+                    .expand(term, indexer.vocabulary())
+                    .into_iter()
+                    .take(self.candidates_per_word)
+                    .map(|expansion| Correction {
+                        weight: (1.0 + indexer.document_frequency(&expansion.term) as f64).ln()
+                            / (1.0 + expansion.distance as f64),
+                        term: expansion.term,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            })
+            .collect();
+
+        if corrections.iter().all(|candidates| candidates.is_empty()) {
+            return Vec::new();
+        }
+
+        // The best correction for every word, then variants swapping in the
+        // runner-up candidates one word at a time
+        let best: Vec<usize> = vec![0; words.len()];
+        let mut variants = vec![best.clone()];
+        for (index, candidates) in corrections.iter().enumerate() {
+            for alternative in 1..candidates.len() {
+                let mut variant = best.clone();
+                variant[index] = alternative;
+                variants.push(variant);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut suggestions: Vec<QuerySuggestion> = variants
+            .into_iter()
+            .filter_map(|choice| {
+                let mut text = Vec::with_capacity(words.len());
+                let mut query_terms = Vec::new();
+                let mut weights = Vec::new();
+
+                for (index, word) in words.iter().enumerate() {
+                    match corrections[index].get(choice[index]) {
+                        Some(correction) => {
+                            text.push(indexer.surface_form(&correction.term).unwrap_or(&correction.term).to_string());
+                            query_terms.push(correction.term.clone());
+                            weights.push(correction.weight);
+                        }
+                        None => {
+                            text.push(word.clone());
+                            if let Some(term) = terms[index].as_ref().filter(|t| indexer.document_frequency(t) > 0) {
+                                query_terms.push(term.clone());
+                            }
+                        }
+                    }
+                }
+
+                if !Self::co_occur(indexer, &query_terms) {
+                    return None;
+                }
+
+                let text = text.join(" ");
+                if !seen.insert(text.clone()) {
+                    return None;
+                }
+
+                Some(QuerySuggestion {
+                    text,
+                    score: weights.iter().sum::<f64>() / weights.len() as f64,
+                })
+            })
+            .collect();
+
+        suggestions.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.text.cmp(&b.text))
+        });
+        suggestions.truncate(limit);
+        suggestions
+    }
+
+    fn co_occur(indexer: &FullTextIndexer, terms: &[String]) -> bool {
+        let mut documents: Option<HashSet<String>> = None;
+
+        for term in terms {
+            let matching: HashSet<String> = indexer.get_term_documents(term).into_iter().collect();
+            let remaining: HashSet<String> = match documents {
+                Some(previous) => previous.intersection(&matching).cloned().collect(),
+                None => matching,
+            };
+            if remaining.is_empty() {
+                return false;
+            }
+            documents = Some(remaining);
+        }
+
+        documents.is_some()
+    }
+
+    /// Vocabulary terms starting with `prefix`, most frequent first
+    pub fn complete_term(&self, prefix: &str, vocabulary: &BTreeMap<String, usize>, limit: usize) -> Vec<(String, usize)> {
+        let mut terms: Vec<(String, usize)> = vocabulary
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(term, _)| term.starts_with(prefix))
+            .take(MAX_PREFIX_SCAN)
+            .map(|(term, df)| (term.clone(), *df))
+            .collect();
+
+        terms.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        terms.truncate(limit);
+        terms
+    }
+
+    /// Completions for the text typed so far. Entity names and titles are
+    /// matched against the whole input; vocabulary terms complete the last
+    /// word and keep the words before it.
+    pub fn complete(
+        &self,
+        db: &Connection,
+        indexer: &FullTextIndexer,
+        text: &str,
+        limit: usize,
+    ) -> Result<Vec<Completion>> {
+        let input = text.trim_start().to_lowercase();
+        if input.trim().is_empty() || limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut completions = Vec::new();
+        let starts_with = format!("{}%", escape_like(input.trim_end()));
+        let word_starts_with = format!("% {}%", escape_like(input.trim_end()));
+
+        let mut stmt = db.prepare(
+            "SELECT e.name, COUNT(m.id) AS mentions
+             FROM entities e
+             LEFT JOIN entity_mentions m ON m.entity_id = e.id
+             WHERE e.name LIKE ?1 ESCAPE '\\' OR e.name LIKE ?2 ESCAPE '\\'
+             GROUP BY e.id
+             ORDER BY mentions DESC, e.name
+             LIMIT ?3",
+        )?;
+        let entities: Vec<(String, i64)> = stmt
+            .query_map(rusqlite::params![starts_with, word_starts_with, limit as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let max_mentions = entities.iter().map(|(_, mentions)| *mentions).max().unwrap_or(0).max(1);
+        for (name, mentions) in entities {
+            completions.push(Completion {
+                text: name,
+                kind: CompletionKind::Entity,
+                score: 0.5 + 0.5 * mentions as f64 / max_mentions as f64,
+            });
+        }
+
+        let mut stmt = db.prepare(
+            "SELECT title, title LIKE ?1 ESCAPE '\\' AS at_start
+             FROM documents
+             WHERE title LIKE ?1 ESCAPE '\\' OR title LIKE ?2 ESCAPE '\\'
+             ORDER BY at_start DESC, modified_at DESC
+             LIMIT ?3",
+        )?;
+        let titles = stmt.query_map(rusqlite::params![starts_with, word_starts_with, limit as i64], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?))
+        })?;
+        for row in titles {
+            let (title, at_start) = row?;
+            completions.push(Completion {
+                text: title,
+                kind: CompletionKind::Title,
+                score: if at_start { 0.9 } else { 0.6 },
+            });
+        }
+
+        // Only complete a word that is still being typed
+        if !input.ends_with(char::is_whitespace) {
+            let (head, last) = match input.rsplit_once(char::is_whitespace) {
+                Some((head, last)) => (format!("{} ", head.trim_end()), last),
+                None => (String::new(), input.as_str()),
+            };
+
+            let terms = self.complete_term(last, indexer.vocabulary(), limit);
+            let max_df = terms.iter().map(|(_, df)| *df).max().unwrap_or(1).max(1);
+            for (term, df) in terms {
+                completions.push(Completion {
+                    text: format!("{}{}", head, term),
+                    kind: CompletionKind::Term,
+                    score: 0.8 * df as f64 / max_df as f64,
+                });
+            }
+        }
+
+        let mut seen = HashSet::new();
+        completions.retain(|completion| seen.insert(completion.text.to_lowercase()));
+        completions.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        completions.truncate(limit);
+
+        Ok(completions)
+    }
+}
+
+/// `text` with LIKE wildcards escaped, for patterns using ESCAPE '\'
+pub(crate) fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::IndexedDocument;
+
+    fn analyze(text: &str) -> Vec<String> {
+        text.split_whitespace().map(|word| word.to_lowercase()).collect()
+    }
+
+    async fn create_test_indexer(documents: &[(&str, &str)]) -> FullTextIndexer {
+        let mut indexer = FullTextIndexer::new().unwrap();
+        for (id, content) in documents {
+            indexer.index_document(&IndexedDocument {
+                id: id.to_string(),
+                title: String::new(),
+                content: content.to_string(),
+                title_tokens: Vec::new(),
+                tokens: analyze(content),
+                entities: Vec::new(),
+                metadata: serde_json::json!({}),
+                embedding: None,
+            }).await.unwrap();
+        }
+        indexer
+    }
+
+    #[tokio::test]
+    async fn test_did_you_mean() {
+        let indexer = create_test_indexer(&[
+            ("1", "quarterly budget review"),
+            ("2", "budget planning"),
+            ("3", "budgets for marketing"),
+        ]).await;
+        let suggester = QuerySuggester::new();
+
+        let suggestions = suggester.suggest("Quartrly budgte", &indexer, analyze, 5);
+        assert_eq!(suggestions[0].text, "quarterly budget");
+
+        // Nothing to correct
+        assert!(suggester.suggest("budget review", &indexer, analyze, 5).is_empty());
+
+        // Corrections that never occur together are not offered
+        let suggestions = suggester.suggest("marketng planing", &indexer, analyze, 5);
+        assert!(suggestions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_did_you_mean_shows_words_rather_than_stems() {
+        let mut indexer = create_test_indexer(&[("1", "quarter budget"), ("2", "quarter")]).await;
+        let suggester = QuerySuggester::new();
+        assert_eq!(suggester.suggest("quartr", &indexer, analyze, 1)[0].text, "quarter");
+
+        indexer.add_surface_forms(&HashMap::from([
+            (("quarter".to_string(), "quarterly".to_string()), 2),
+            (("quarter".to_string(), "quarters".to_string()), 1),
+        ]));
+        assert_eq!(suggester.suggest("quartr", &indexer, analyze, 1)[0].text, "quarterly");
+    }
+
+    #[tokio::test]
+    async fn test_term_completion_prefers_frequent_terms() {
+        let indexer = create_test_indexer(&[
+            ("1", "budget review"),
+            ("2", "budget planning"),
+            ("3", "budgets"),
+        ]).await;
+        let suggester = QuerySuggester::new();
+
+        let terms = suggester.complete_term("budg", indexer.vocabulary(), 5);
+        assert_eq!(terms, vec![("budget".to_string(), 2), ("budgets".to_string(), 1)]);
+        assert!(suggester.complete_term("zz", indexer.vocabulary(), 5).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_completions_across_sources() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE documents (id TEXT PRIMARY KEY, title TEXT, modified_at INTEGER);
+             CREATE TABLE entities (id TEXT PRIMARY KEY, entity_type TEXT, name TEXT);
+             CREATE TABLE entity_mentions (id TEXT PRIMARY KEY, entity_id TEXT, document_id TEXT);
+             INSERT INTO documents VALUES ('1', 'Budget 2024', 1);
+             INSERT INTO documents VALUES ('2', 'Annual budget notes', 2);
+             INSERT INTO documents VALUES ('3', '100% done', 3);
+             INSERT INTO entities VALUES ('e1', 'organization', 'Budget Office');
+             INSERT INTO entity_mentions VALUES ('m1', 'e1', '1');",
+        ).unwrap();
+        let indexer = create_test_indexer(&[("1", "budget review"), ("2", "budget notes")]).await;
+        let suggester = QuerySuggester::new();
+
+        let completions = suggester.complete(&db, &indexer, "Budg", 10).unwrap();
+        let texts: Vec<&str> = completions.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["Budget Office", "Budget 2024", "budget", "Annual budget notes"]);
+        assert_eq!(completions[0].kind, CompletionKind::Entity);
+
+        let completions = suggester.complete(&db, &indexer, "annual budget r", 10).unwrap();
+        assert_eq!(completions[0].text, "annual budget review");
+        assert_eq!(completions[0].kind, CompletionKind::Term);
+
+        // LIKE wildcards in the input are matched literally
+        let completions = suggester.complete(&db, &indexer, "100%", 10).unwrap();
+        assert_eq!(completions.len(), 1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,312 @@
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use rusqlite::{Connection, params};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::query::TermAlternative;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SynonymRule {
+    /// Every term stands for every other ("k8s", "kubernetes")
+    Equivalent { terms: Vec<String> },
+    /// `from` also finds `to`, but not the other way round ("PO" finds
+    /// "purchase order" without "order" finding "PO")
+    OneWay { from: String, to: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SynonymEntry {
+    pub id: String,
+    pub rule: SynonymRule,
+}
+
+/// Analyzed rule input and the alternatives it expands to
+struct Expansion {
+    input: Vec<String>,
+    outputs: Vec<Vec<String>>,
+}
+
+/// User-maintained synonyms and abbreviations, kept in the
+/// `search_synonyms` table and applied to analyzed query terms. Rules are
+/// stored as written and analyzed at query time, so they follow the
+/// query's language.
+pub struct SynonymDictionary {
+    entries: Vec<SynonymEntry>,
+    expansion_weight: f64,
+}
+
+impl Default for SynonymDictionary {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            expansion_weight: 0.8,
+        }
+    }
+}
+
+impl SynonymDictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(db: &Connection) -> Result<Self> {
+        let mut dictionary = Self::new();
+
+        // The table is created with the first rule
+        let exists: bool = db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'search_synonyms')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(dictionary);
+        }
+
+        let mut stmt = db.prepare("SELECT id, kind, source, terms FROM search_synonyms ORDER BY created_at, rowid")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        for row in rows {
+            let (id, kind, source, terms) = row?;
+            let terms: Vec<String> = serde_json::from_str(&terms)?;
+
+            let rule = match (kind.as_str(), source) {
+                ("equivalent", _) => SynonymRule::Equivalent { terms },
+                ("one_way", Some(from)) => SynonymRule::OneWay { from, to: terms },
+                _ => return Err(anyhow!("Invalid synonym rule {}", id)),
+            };
+            dictionary.entries.push(SynonymEntry { id, rule });
+        }
+
+        Ok(dictionary)
+    }
+
+    fn create_table(db: &Connection) -> Result<()> {
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS search_synonyms (
+                id TEXT PRIMARY KEY,
+                kind TEXT NOT NULL,
+                source TEXT,
+                terms TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn add(&mut self, db: &Connection, rule: SynonymRule) -> Result<SynonymEntry> {
+        let (kind, source, terms) = match &rule {
+            SynonymRule::Equivalent { terms } if terms.len() >= 2 => ("equivalent", None, terms),
+            SynonymRule::OneWay { from, to } if !from.trim().is_empty() && !to.is_empty() => {
+                ("one_way", Some(from.as_str()), to)
+            }
+            _ => return Err(anyhow!("A synonym rule needs at least two terms")),
+        };
+        if terms.iter().any(|term| term.trim().is_empty()) {
+            return Err(anyhow!("Synonym terms must not be empty"));
+        }
+
+        Self::create_table(db)?;
+
+        let entry = SynonymEntry {
+            id: Uuid::new_v4().to_string(),
+            rule: rule.clone(),
+        };
+        db.execute(
+            "INSERT INTO search_synonyms (id, kind, source, terms, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![entry.id, kind, source, serde_json::to_string(terms)?, Utc::now().timestamp()],
+        )?;
+
+        self.entries.push(entry.clone());
+        Ok(entry)
+    }
+
+    /// Returns false if there was no rule with that id
+    pub fn remove(&mut self, db: &Connection, id: &str) -> Result<bool> {
+        Self::create_table(db)?;
+        let removed = db.execute("DELETE FROM search_synonyms WHERE id = ?1", params![id])? > 0;
+        self.entries.retain(|entry| entry.id != id);
+        Ok(removed)
+    }
+
+    pub fn entries(&self) -> &[SynonymEntry] {
+        &self.entries
+    }
+
+    pub fn set_expansion_weight(&mut self, weight: f64) {
+        self.expansion_weight = weight.clamp(0.0, 1.0);
+    }
+
+    /// Group analyzed query terms into alternatives: the terms as typed,
+    /// plus any synonyms of them at a reduced weight. Rule inputs are
+    /// matched greedily, longest first, so "purchase order" is expanded as
+    /// a whole rather than word by word; multi-word synonyms must appear as
+    /// phrases.
+    pub fn expand<F>(&self, terms: &[String], analyze: F) -> Vec<Vec<TermAlternative>>
+    where
+        F: Fn(&str) -> Vec<String>,
+    {
+        let expansions = self.analyzed_expansions(&analyze);
+        let mut groups = Vec::with_capacity(terms.len());
+        let mut position = 0;
+
+        while position < terms.len() {
+            let remaining = &terms[position..];
+            let longest = expansions
+                .iter()
+                .filter(|expansion| remaining.starts_with(&expansion.input))
+                .map(|expansion| expansion.input.len())
+                .max();
+
+            let length = match longest {
+                Some(length) => length,
+                None => {
+                    groups.push(vec![TermAlternative::exact(&terms[position])]);
+                    position += 1;
+                    continue;
+                }
+            };
+
+            let span = &terms[position..position + length];
+            let mut group = vec![TermAlternative {
+                terms: span.to_vec(),
+                phrase: false,
+                weight: 1.0,
+            }];
+            for expansion in expansions.iter().filter(|expansion| expansion.input == span) {
+                for output in &expansion.outputs {
+                    if output.as_slice() == span || group.iter().any(|alternative| &alternative.terms == output) {
+                        continue;
+                    }
+                    group.push(TermAlternative {
+                        terms: output.clone(),
+                        phrase: output.len() > 1,
+                        weight: self.expansion_weight,
+                    });
+                }
+            }
+
+            groups.push(group);
+            position += length;
+        }
+
+        groups
+    }
+
+    fn analyzed_expansions<F>(&self, analyze: &F) -> Vec<Expansion>
+    where
+        F: Fn(&str) -> Vec<String>,
+    {
+        let mut expansions = Vec::new();
+
+        for entry in &self.entries {
+            match &entry.rule {
+                SynonymRule::Equivalent { terms } => {
+                    let analyzed: Vec<Vec<String>> = terms.iter().map(|term| analyze(term)).collect();
+                    for (i, input) in analyzed.iter().enumerate() {
+                        let outputs = analyzed
+                            .iter()
+                            .enumerate()
+                            .filter(|(j, _)| *j != i)
+                            .map(|(_, output)| output.clone())
+                            .collect();
+                        expansions.push(Expansion { input: input.clone(), outputs });
+                    }
+                }
+                SynonymRule::OneWay { from, to } => {
+                    expansions.push(Expansion {
+                        input: analyze(from),
+                        outputs: to.iter().map(|term| analyze(term)).collect(),
+                    });
+                }
+            }
+        }
+
+        // A rule made entirely of stop words analyzes to nothing
+        for expansion in &mut expansions {
+            expansion.outputs.retain(|output| !output.is_empty());
+        }
+        expansions.retain(|expansion| !expansion.input.is_empty() && !expansion.outputs.is_empty());
+        expansions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(text: &str) -> Vec<String> {
+        text.split_whitespace().map(|word| word.to_lowercase()).collect()
+    }
+
+    fn terms(text: &str) -> Vec<String> {
+        analyze(text)
+    }
+
+    fn dictionary(db: &Connection) -> SynonymDictionary {
+        let mut dictionary = SynonymDictionary::load(db).unwrap();
+        dictionary
+            .add(db, SynonymRule::Equivalent { terms: vec!["PO".to_string(), "purchase order".to_string()] })
+            .unwrap();
+        dictionary
+            .add(db, SynonymRule::OneWay { from: "k8s".to_string(), to: vec!["kubernetes".to_string()] })
+            .unwrap();
+        dictionary
+    }
+
+    #[test]
+    fn test_rules_persist() {
+        let db = Connection::open_in_memory().unwrap();
+        let mut dictionary = dictionary(&db);
+
+        let reloaded = SynonymDictionary::load(&db).unwrap();
+        assert_eq!(reloaded.entries(), dictionary.entries());
+
+        let id = dictionary.entries()[0].id.clone();
+        assert!(dictionary.remove(&db, &id).unwrap());
+        assert!(!dictionary.remove(&db, &id).unwrap());
+        assert_eq!(SynonymDictionary::load(&db).unwrap().entries().len(), 1);
+
+        assert!(dictionary.add(&db, SynonymRule::Equivalent { terms: vec!["alone".to_string()] }).is_err());
+    }
+
+    #[test]
+    fn test_equivalent_sets_expand_both_ways() {
+        let db = Connection::open_in_memory().unwrap();
+        let dictionary = dictionary(&db);
+
+        let groups = dictionary.expand(&terms("open po"), analyze);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0], vec![TermAlternative::exact("open")]);
+        assert_eq!(groups[1][1].terms, terms("purchase order"));
+        assert!(groups[1][1].phrase);
+        assert_eq!(groups[1][1].weight, 0.8);
+
+        // The longer input is matched as one unit
+        let groups = dictionary.expand(&terms("purchase order status"), analyze);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0][0].terms, terms("purchase order"));
+        assert_eq!(groups[0][1].terms, terms("po"));
+    }
+
+    #[test]
+    fn test_one_way_rules() {
+        let db = Connection::open_in_memory().unwrap();
+        let dictionary = dictionary(&db);
+
+        let groups = dictionary.expand(&terms("k8s"), analyze);
+        assert_eq!(groups[0].len(), 2);
+        assert_eq!(groups[0][1].terms, terms("kubernetes"));
+
+        let groups = dictionary.expand(&terms("kubernetes"), analyze);
+        assert_eq!(groups, vec![vec![TermAlternative::exact("kubernetes")]]);
+    }
+}